@@ -5,11 +5,16 @@
 
 mod analyze;
 mod echo;
+mod quic_bench;
+mod replay;
+mod scenario;
 mod sink;
 mod source;
 mod udp_proxy;
 
+use clap::builder::PossibleValuesParser;
 use clap::{Parser, Subcommand};
+use slipstream_quic::CONGESTION_CONTROL_NAMES;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -137,7 +142,7 @@ enum Command {
         log: String,
     },
 
-    /// Run as UDP proxy with delay/jitter simulation
+    /// Run as UDP proxy with delay/jitter/loss simulation
     UdpProxy {
         /// Listen address (host:port)
         #[arg(long)]
@@ -147,13 +152,28 @@ enum Command {
         #[arg(long)]
         upstream: SocketAddr,
 
+        /// Network condition preset; explicit flags override its values
+        #[arg(
+            long,
+            value_parser = PossibleValuesParser::new(scenario::SCENARIO_NAMES.iter().copied())
+        )]
+        scenario: Option<String>,
+
         /// Base delay in milliseconds
-        #[arg(long, default_value = "0")]
-        delay_ms: f64,
+        #[arg(long)]
+        delay_ms: Option<f64>,
 
         /// Jitter standard deviation in milliseconds
-        #[arg(long, default_value = "0")]
-        jitter_ms: f64,
+        #[arg(long)]
+        jitter_ms: Option<f64>,
+
+        /// Fraction of packets to drop 0.0-1.0
+        #[arg(long)]
+        loss_rate: Option<f64>,
+
+        /// Bottleneck rate per direction in kbit/s (0 = unlimited)
+        #[arg(long)]
+        rate_kbps: Option<u64>,
 
         /// Delay distribution (normal or uniform)
         #[arg(long, default_value = "normal")]
@@ -168,14 +188,79 @@ enum Command {
         seed: Option<u64>,
 
         /// Target reorder rate 0.0-1.0 (0 disables reordering)
-        #[arg(long, default_value = "0")]
-        reorder_rate: f64,
+        #[arg(long)]
+        reorder_rate: Option<f64>,
 
         /// Log file path (use - for stdout)
         #[arg(long, default_value = "-")]
         log: String,
     },
 
+    /// Replay captured DNS queries against a server
+    Replay {
+        /// Capture to replay (udp-proxy log or client --query-log output)
+        #[arg(long)]
+        log: PathBuf,
+
+        /// Server DNS address (host:port)
+        #[arg(long)]
+        target: SocketAddr,
+
+        /// Timing scale (2 = twice as fast, 0 = as fast as possible)
+        #[arg(long, default_value = "1")]
+        speed: f64,
+
+        /// Tunnel domain used to synthesize queries from query-log captures
+        #[arg(long)]
+        domain: Option<String>,
+
+        /// Time to wait for outstanding responses after the last query
+        #[arg(long, default_value = "1000")]
+        drain_ms: u64,
+
+        /// Report file path (use - for stdout)
+        #[arg(long, default_value = "-")]
+        report: String,
+    },
+
+    /// Measure the bare QUIC transport over loopback UDP (no DNS, no binaries)
+    QuicBench {
+        /// Server certificate (PEM)
+        #[arg(long, default_value = "fixtures/certs/cert.pem")]
+        cert: String,
+
+        /// Server private key (PEM)
+        #[arg(long, default_value = "fixtures/certs/key.pem")]
+        key: String,
+
+        /// Congestion control for both endpoints
+        #[arg(
+            long,
+            value_parser = PossibleValuesParser::new(CONGESTION_CONTROL_NAMES.iter().copied())
+        )]
+        congestion_control: Option<String>,
+
+        /// Bytes to transfer for the throughput phase
+        #[arg(long, default_value = "10485760")]
+        bytes: u64,
+
+        /// Sequential ping round trips for the latency phase
+        #[arg(long, default_value = "100")]
+        pings: u64,
+
+        /// Payload size of each ping
+        #[arg(long, default_value = "64")]
+        ping_bytes: usize,
+
+        /// Overall timeout in seconds
+        #[arg(long, default_value = "60")]
+        timeout: u64,
+
+        /// Report file path (use - for stdout)
+        #[arg(long, default_value = "-")]
+        report: String,
+    },
+
     /// Calculate E2E throughput from two log files
     E2eReport {
         /// Label for the output
@@ -438,27 +523,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Command::UdpProxy {
             listen,
             upstream,
+            scenario,
             delay_ms,
             jitter_ms,
+            loss_rate,
+            rate_kbps,
             dist,
             max_packets,
             seed,
             reorder_rate,
             log,
         } => {
+            let preset = scenario.as_deref().and_then(scenario::find);
+            let conditions = scenario::Scenario {
+                name: preset.map_or("custom", |p| p.name),
+                delay_ms: delay_ms.or(preset.map(|p| p.delay_ms)).unwrap_or(0.0),
+                jitter_ms: jitter_ms.or(preset.map(|p| p.jitter_ms)).unwrap_or(0.0),
+                loss_rate: loss_rate.or(preset.map(|p| p.loss_rate)).unwrap_or(0.0),
+                rate_kbps: rate_kbps.or(preset.map(|p| p.rate_kbps)).unwrap_or(0),
+                reorder_rate: reorder_rate
+                    .or(preset.map(|p| p.reorder_rate))
+                    .unwrap_or(0.0),
+            };
             udp_proxy::run(
                 listen,
                 upstream,
                 &log,
-                delay_ms,
-                jitter_ms,
+                &conditions,
                 &dist,
                 max_packets,
                 seed,
-                reorder_rate,
             )
             .await?;
         }
+        Command::Replay {
+            log,
+            target,
+            speed,
+            domain,
+            drain_ms,
+            report,
+        } => {
+            replay::run(&log, target, speed, domain.as_deref(), drain_ms, &report).await?;
+        }
+        Command::QuicBench {
+            cert,
+            key,
+            congestion_control,
+            bytes,
+            pings,
+            ping_bytes,
+            timeout,
+            report,
+        } => {
+            let options = quic_bench::Options {
+                cert: &cert,
+                key: &key,
+                congestion_control: congestion_control.as_deref(),
+                bytes,
+                pings,
+                ping_bytes,
+                timeout: Duration::from_secs(timeout),
+            };
+            quic_bench::run(&options, &report).await?;
+        }
         Command::E2eReport {
             label,
             start_log,
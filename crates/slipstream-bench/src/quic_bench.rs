@@ -0,0 +1,271 @@
+//! QUIC transport benchmark over loopback UDP.
+//!
+//! Drives slipstream_quic's Client and Server directly, with no DNS encoding
+//! and neither binary's runtime loop, so tquic-layer regressions can be told
+//! apart from tunnel overhead. Both endpoints run on the calling task: pings
+//! are echoed on bidirectional streams and bulk data is sunk from one
+//! unidirectional stream.
+
+use crate::{now_ts, summarize, LogWriter};
+use slipstream_quic::{parse_congestion_control, Client, ClientConnection, Config, Server};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::time::sleep;
+
+const SERVER_NAME: &str = "quic-bench";
+/// Longest wait when neither endpoint has a timer armed.
+const IDLE_WAIT: Duration = Duration::from_millis(50);
+const WRITE_CHUNK: usize = 64 * 1024;
+
+pub struct Options<'a> {
+    pub cert: &'a str,
+    pub key: &'a str,
+    pub congestion_control: Option<&'a str>,
+    pub bytes: u64,
+    pub pings: u64,
+    pub ping_bytes: usize,
+    pub timeout: Duration,
+}
+
+/// Result summary event.
+#[derive(serde::Serialize)]
+struct QuicBenchLogEvent {
+    ts: f64,
+    event: &'static str,
+    cc: &'static str,
+    handshake_ms: f64,
+    pings: usize,
+    rtt_min_ms: f64,
+    rtt_p50_ms: f64,
+    rtt_p99_ms: f64,
+    rtt_max_ms: f64,
+    bytes: u64,
+    secs: f64,
+    mib_s: f64,
+}
+
+/// Server-side view of the bulk stream.
+#[derive(Default)]
+struct Sink {
+    received: u64,
+    finished: bool,
+}
+
+/// Both endpoints and the sockets between them.
+struct Loopback {
+    client_socket: UdpSocket,
+    server_socket: UdpSocket,
+    conn: ClientConnection,
+    server: Server,
+    client_buf: Vec<u8>,
+    server_buf: Vec<u8>,
+    sink: Sink,
+    deadline: Instant,
+}
+
+impl Loopback {
+    /// Flush both endpoints, then wait for a packet or the next timer and let
+    /// the server side answer whatever arrived.
+    async fn step(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if Instant::now() >= self.deadline {
+            return Err("timed out".into());
+        }
+        if self.conn.is_closing() {
+            return Err(match self.conn.close_cause() {
+                Some(cause) => format!("connection {}", cause).into(),
+                None => "connection closed".into(),
+            });
+        }
+        for (packet, to) in self.conn.poll_send() {
+            self.client_socket.send_to(&packet, to).await?;
+        }
+        for (packet, to) in self.server.poll_send() {
+            self.server_socket.send_to(&packet, to).await?;
+        }
+
+        let wait = self
+            .conn
+            .timeout()
+            .into_iter()
+            .chain(self.server.timeout())
+            .min()
+            .unwrap_or(IDLE_WAIT)
+            .min(IDLE_WAIT);
+        tokio::select! {
+            received = self.client_socket.recv_from(&mut self.client_buf) => {
+                let (len, from) = received?;
+                let _ = self.conn.recv(&self.client_buf[..len], from);
+            }
+            received = self.server_socket.recv_from(&mut self.server_buf) => {
+                let (len, from) = received?;
+                let _ = self.server.recv(&self.server_buf[..len], from);
+            }
+            _ = sleep(wait) => {
+                self.conn.on_timeout();
+                self.server.on_timeout();
+            }
+        }
+        while let Ok((len, from)) = self.client_socket.try_recv_from(&mut self.client_buf) {
+            let _ = self.conn.recv(&self.client_buf[..len], from);
+        }
+        while let Ok((len, from)) = self.server_socket.try_recv_from(&mut self.server_buf) {
+            let _ = self.server.recv(&self.server_buf[..len], from);
+        }
+
+        self.serve();
+        Ok(())
+    }
+
+    /// Echo bidirectional streams and count bytes on unidirectional ones.
+    fn serve(&mut self) {
+        let mut buf = [0u8; WRITE_CHUNK];
+        for conn_id in self.server.ready_connections() {
+            for stream_id in self.server.readable_streams(conn_id) {
+                while let Ok((len, fin)) = self.server.stream_read(conn_id, stream_id, &mut buf) {
+                    if stream_id & 0x2 != 0 {
+                        self.sink.received += len as u64;
+                        self.sink.finished |= fin;
+                    } else if len > 0 || fin {
+                        let _ = self
+                            .server
+                            .stream_write(conn_id, stream_id, &buf[..len], fin);
+                    }
+                    if fin || len == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub async fn run(
+    options: &Options<'_>,
+    report_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut report = LogWriter::open(report_path)?;
+
+    let mut config = Config::new()
+        .with_multipath(false)
+        .with_tls(options.cert, options.key);
+    if let Some(name) = options.congestion_control {
+        config = config.with_congestion_control(parse_congestion_control(name)?);
+    }
+    let cc = config.congestion_control.name();
+
+    let server_socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let client_socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let server_addr = server_socket.local_addr()?;
+    let client_addr: SocketAddr = client_socket.local_addr()?;
+    let server = Server::new(server_addr, config.clone())?;
+    let conn = Client::new(config)?.connect(client_addr, server_addr, SERVER_NAME)?;
+    let mut loopback = Loopback {
+        client_socket,
+        server_socket,
+        conn,
+        server,
+        client_buf: vec![0u8; 65535],
+        server_buf: vec![0u8; 65535],
+        sink: Sink::default(),
+        deadline: Instant::now() + options.timeout,
+    };
+
+    let started = Instant::now();
+    while !loopback.conn.is_ready() {
+        loopback.step().await?;
+    }
+    let handshake = started.elapsed();
+
+    // Latency: one ping at a time, each on a fresh stream
+    let payload = vec![b'p'; options.ping_bytes.max(1)];
+    let mut rtts = Vec::with_capacity(options.pings as usize);
+    let mut read_buf = vec![0u8; WRITE_CHUNK];
+    for _ in 0..options.pings {
+        let stream_id = loopback.conn.open_bi()?;
+        let sent_at = Instant::now();
+        loopback.conn.stream_write(stream_id, &payload, true)?;
+        let mut echoed = false;
+        while !echoed {
+            loopback.step().await?;
+            while let Ok((len, fin)) = loopback.conn.stream_read(stream_id, &mut read_buf) {
+                echoed |= fin;
+                if fin || len == 0 {
+                    break;
+                }
+            }
+        }
+        rtts.push(sent_at.elapsed());
+    }
+
+    // Throughput: one unidirectional stream, timed until the server saw FIN
+    let chunk = vec![b'a'; WRITE_CHUNK];
+    let stream_id = loopback.conn.open_uni()?;
+    let transfer_started = Instant::now();
+    let mut written = 0u64;
+    let mut fin_sent = false;
+    while !loopback.sink.finished {
+        while written < options.bytes {
+            let len = (options.bytes - written).min(WRITE_CHUNK as u64) as usize;
+            match loopback.conn.stream_write(stream_id, &chunk[..len], false) {
+                Ok(n) if n > 0 => written += n as u64,
+                _ => break,
+            }
+        }
+        if written == options.bytes && !fin_sent {
+            fin_sent = loopback.conn.stream_write(stream_id, &[], true).is_ok();
+        }
+        loopback.step().await?;
+    }
+    let secs = transfer_started.elapsed().as_secs_f64();
+    let _ = loopback.conn.close(0, "bench done");
+    for (packet, to) in loopback.conn.poll_send() {
+        let _ = loopback.client_socket.send_to(&packet, to).await;
+    }
+
+    rtts.sort();
+    let ms = |duration: Option<&Duration>| duration.map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+    let percentile =
+        |p: usize| ms(rtts.get((rtts.len() * p / 100).min(rtts.len().saturating_sub(1))));
+    let total = loopback.sink.received;
+    let event = QuicBenchLogEvent {
+        ts: now_ts(),
+        event: "quic_bench_done",
+        cc,
+        handshake_ms: handshake.as_secs_f64() * 1000.0,
+        pings: rtts.len(),
+        rtt_min_ms: ms(rtts.first()),
+        rtt_p50_ms: percentile(50),
+        rtt_p99_ms: percentile(99),
+        rtt_max_ms: ms(rtts.last()),
+        bytes: total,
+        secs,
+        mib_s: if secs > 0.0 {
+            total as f64 / (1024.0 * 1024.0) / secs
+        } else {
+            0.0
+        },
+    };
+    let line = serde_json::to_string(&event).unwrap_or_default();
+    match &mut report {
+        LogWriter::Stdout => println!("{}", line),
+        LogWriter::File(f) => {
+            let _ = writeln!(f, "{}", line);
+            let _ = f.flush();
+        }
+    }
+    eprintln!(
+        "quic-bench: cc={} handshake={:.2}ms rtt p50={:.3}ms p99={:.3}ms",
+        cc, event.handshake_ms, event.rtt_p50_ms, event.rtt_p99_ms
+    );
+    summarize("quic transport", total, secs);
+    if total < options.bytes {
+        return Err(format!(
+            "server received {} bytes, expected {}",
+            total, options.bytes
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+//! Replay captured DNS queries against a server.
+//!
+//! Accepts either udp-proxy capture logs (client_to_server packets with hex
+//! payloads are re-sent verbatim) or client query logs (payloads are not logged,
+//! so each query is re-synthesized with a random payload of the same QNAME
+//! length under --domain). Responses are counted per RCODE.
+
+use crate::{now_ts, LogWriter};
+use rand::prelude::*;
+use serde_json::Value;
+use slipstream_dns::{
+    build_qname, encode_query, max_payload_len_for_domain, QueryParams, CLASS_IN, EDNS_UDP_PAYLOAD,
+    RR_TXT,
+};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// A query to re-send, with its offset from the first captured query.
+struct ReplayQuery {
+    offset: f64,
+    packet: Vec<u8>,
+}
+
+/// Replay summary event.
+#[derive(serde::Serialize)]
+struct ReplayLogEvent {
+    ts: f64,
+    event: &'static str,
+    sent: u64,
+    received: u64,
+    secs: f64,
+    rcodes: BTreeMap<u8, u64>,
+}
+
+pub async fn run(
+    log: &Path,
+    target: SocketAddr,
+    speed: f64,
+    domain: Option<&str>,
+    drain_ms: u64,
+    report_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let queries = load_queries(log, domain)?;
+    if queries.is_empty() {
+        return Err(format!("No replayable queries in {}", log.display()).into());
+    }
+    let mut report = LogWriter::open(report_path)?;
+
+    let bind_addr = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    eprintln!(
+        "Replaying {} queries from {} to {} (speed {})",
+        queries.len(),
+        log.display(),
+        target,
+        speed
+    );
+
+    let start = Instant::now();
+    let mut sent = 0u64;
+    let mut received = 0u64;
+    let mut rcodes: BTreeMap<u8, u64> = BTreeMap::new();
+    let mut buf = vec![0u8; 65535];
+
+    for query in &queries {
+        // speed <= 0 replays as fast as possible.
+        if speed > 0.0 {
+            let due = start + Duration::from_secs_f64(query.offset / speed);
+            while Instant::now() < due {
+                let wait = due.saturating_duration_since(Instant::now());
+                if let Ok(Ok((len, _))) =
+                    tokio::time::timeout(wait, socket.recv_from(&mut buf)).await
+                {
+                    count_response(&buf[..len], &mut received, &mut rcodes);
+                }
+            }
+        }
+        socket.send_to(&query.packet, target).await?;
+        sent += 1;
+    }
+
+    let drain_deadline = Instant::now() + Duration::from_millis(drain_ms);
+    while received < sent {
+        let wait = drain_deadline.saturating_duration_since(Instant::now());
+        if wait.is_zero() {
+            break;
+        }
+        match tokio::time::timeout(wait, socket.recv_from(&mut buf)).await {
+            Ok(Ok((len, _))) => count_response(&buf[..len], &mut received, &mut rcodes),
+            Ok(Err(e)) => tracing::warn!("UDP recv error: {}", e),
+            Err(_) => break,
+        }
+    }
+
+    let secs = start.elapsed().as_secs_f64();
+    let event = ReplayLogEvent {
+        ts: now_ts(),
+        event: "replay_done",
+        sent,
+        received,
+        secs,
+        rcodes,
+    };
+    let line = serde_json::to_string(&event).unwrap_or_default();
+    match &mut report {
+        LogWriter::Stdout => println!("{}", line),
+        LogWriter::File(f) => {
+            let _ = writeln!(f, "{}", line);
+            let _ = f.flush();
+        }
+    }
+    eprintln!(
+        "replay: sent={} received={} secs={:.3}",
+        sent, received, secs
+    );
+    Ok(())
+}
+
+fn count_response(packet: &[u8], received: &mut u64, rcodes: &mut BTreeMap<u8, u64>) {
+    if packet.len() < 12 {
+        return;
+    }
+    *received += 1;
+    *rcodes.entry(packet[3] & 0x0f).or_insert(0) += 1;
+}
+
+fn load_queries(
+    path: &Path,
+    domain: Option<&str>,
+) -> Result<Vec<ReplayQuery>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut rng = StdRng::from_entropy();
+    let mut first_ts = None;
+    let mut queries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let Ok(record) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let Some(ts) = record["ts"].as_f64() else {
+            continue;
+        };
+        let packet = if record["direction"] == "client_to_server" {
+            match record["hex"].as_str() {
+                Some(hex) => hex::decode(hex)?,
+                None => continue,
+            }
+        } else if record["event"] == "query" {
+            let Some(qname_len) = record["qname_len"].as_u64() else {
+                continue;
+            };
+            let domain = domain.ok_or("--domain is required to replay query-log captures")?;
+            synthesize_query(domain, qname_len as usize, &mut rng)?
+        } else {
+            continue;
+        };
+        let first_ts = *first_ts.get_or_insert(ts);
+        queries.push(ReplayQuery {
+            offset: (ts - first_ts).max(0.0),
+            packet,
+        });
+    }
+    Ok(queries)
+}
+
+/// Build a TXT query whose QNAME is as close to `qname_len` as the domain allows.
+fn synthesize_query(
+    domain: &str,
+    qname_len: usize,
+    rng: &mut StdRng,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut payload_len = max_payload_len_for_domain(domain)?;
+    let qname = loop {
+        let mut payload = vec![0u8; payload_len];
+        rng.fill_bytes(&mut payload);
+        let qname = build_qname(&payload, domain)?;
+        if qname.len() <= qname_len || payload_len <= 1 {
+            break qname;
+        }
+        payload_len -= 1;
+    };
+    let packet = encode_query(&QueryParams {
+        id: rng.gen(),
+        qname: &qname,
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
+    })?;
+    Ok(packet)
+}
@@ -0,0 +1,63 @@
+//! Named network condition presets for the UDP proxy.
+//!
+//! Each preset bundles the delay, jitter, loss, rate and reordering observed on a
+//! class of real networks, so runs can be reproduced with a single flag instead of
+//! a long list of knobs. Values are one-way and apply to each direction.
+
+/// Network conditions applied by the UDP proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct Scenario {
+    pub name: &'static str,
+    /// Base one-way delay in milliseconds.
+    pub delay_ms: f64,
+    /// Jitter standard deviation in milliseconds.
+    pub jitter_ms: f64,
+    /// Fraction of packets dropped, 0.0-1.0.
+    pub loss_rate: f64,
+    /// Bottleneck rate in kbit/s (0 = unlimited).
+    pub rate_kbps: u64,
+    /// Target reorder rate, 0.0-1.0.
+    pub reorder_rate: f64,
+}
+
+/// Built-in presets, selectable with `udp-proxy --scenario`.
+pub const SCENARIOS: &[Scenario] = &[
+    // Shared hotel/café access point: moderate RTT, bursty jitter from airtime
+    // contention and a captive-portal resolver that drops under load.
+    Scenario {
+        name: "hotel-wifi",
+        delay_ms: 40.0,
+        jitter_ms: 25.0,
+        loss_rate: 0.02,
+        rate_kbps: 2000,
+        reorder_rate: 0.01,
+    },
+    // Enterprise forwarder chain: short, stable RTT but a per-client query rate
+    // limit that caps throughput well below the link speed.
+    Scenario {
+        name: "corp-dns",
+        delay_ms: 15.0,
+        jitter_ms: 5.0,
+        loss_rate: 0.005,
+        rate_kbps: 512,
+        reorder_rate: 0.0,
+    },
+    // Geostationary uplink: ~600 ms RTT before resolver time, low jitter and
+    // loss that mostly comes from weather fades.
+    Scenario {
+        name: "satellite",
+        delay_ms: 300.0,
+        jitter_ms: 20.0,
+        loss_rate: 0.01,
+        rate_kbps: 1000,
+        reorder_rate: 0.0,
+    },
+];
+
+/// Names of the built-in presets, for argument parsing.
+pub const SCENARIO_NAMES: &[&str] = &["hotel-wifi", "corp-dns", "satellite"];
+
+/// Look up a preset by name.
+pub fn find(name: &str) -> Option<Scenario> {
+    SCENARIOS.iter().find(|s| s.name == name).copied()
+}
@@ -4,8 +4,10 @@
 //! Features:
 //! - Delay distribution from sorted pool (prevents natural reordering)
 //! - Controlled reordering via periodic adjacent swaps
+//! - Random loss and a per-direction bottleneck rate
 //! - JSON logging of all packets
 
+use crate::scenario::Scenario;
 use crate::{now_ts, LogWriter};
 use rand::prelude::*;
 use rand_distr::{Distribution, Normal, Uniform};
@@ -317,16 +319,40 @@ struct ProxyLogEvent {
     delay_ms: f64,
 }
 
+/// Bottleneck link that serializes packets at a fixed rate, per direction.
+struct RateLimiter {
+    bytes_per_sec: f64,
+    free_at: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+    fn new(rate_kbps: u64) -> Self {
+        Self {
+            bytes_per_sec: rate_kbps as f64 * 1000.0 / 8.0,
+            free_at: HashMap::new(),
+        }
+    }
+
+    /// Time at which a packet received at `now` has finished crossing the link.
+    fn transmit(&mut self, direction: &str, now: Instant, len: usize) -> Instant {
+        if self.bytes_per_sec <= 0.0 {
+            return now;
+        }
+        let free_at = self.free_at.entry(direction.to_string()).or_insert(now);
+        let start = (*free_at).max(now);
+        *free_at = start + Duration::from_secs_f64(len as f64 / self.bytes_per_sec);
+        *free_at
+    }
+}
+
 pub async fn run(
     listen: SocketAddr,
     upstream: SocketAddr,
     log_path: &str,
-    delay_ms: f64,
-    jitter_ms: f64,
+    conditions: &Scenario,
     dist: &str,
     max_packets: u64,
     seed: Option<u64>,
-    reorder_rate: f64,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut log = LogWriter::open(log_path)?;
 
@@ -334,9 +360,22 @@ pub async fn run(
 
     eprintln!("UDP proxy listening on {}", listen);
     eprintln!("  Upstream: {}", upstream);
-    eprintln!("  Delay: {}ms ± {}ms", delay_ms, jitter_ms);
-    if reorder_rate > 0.0 {
-        eprintln!("  Target reorder rate: {:.4}%", reorder_rate * 100.0);
+    eprintln!("  Scenario: {}", conditions.name);
+    eprintln!(
+        "  Delay: {}ms ± {}ms",
+        conditions.delay_ms, conditions.jitter_ms
+    );
+    if conditions.loss_rate > 0.0 {
+        eprintln!("  Loss rate: {:.4}%", conditions.loss_rate * 100.0);
+    }
+    if conditions.rate_kbps > 0 {
+        eprintln!("  Rate: {} kbit/s", conditions.rate_kbps);
+    }
+    if conditions.reorder_rate > 0.0 {
+        eprintln!(
+            "  Target reorder rate: {:.4}%",
+            conditions.reorder_rate * 100.0
+        );
     }
 
     let dist_type = if dist == "uniform" {
@@ -344,8 +383,20 @@ pub async fn run(
     } else {
         DelayDist::Normal
     };
-    let mut delay_model = SortedDelayModel::new(delay_ms, jitter_ms, 20000, dist_type, seed);
-    let mut reorder_ctrl = ReorderController::new(reorder_rate, 0.1, 50.0);
+    let mut delay_model = SortedDelayModel::new(
+        conditions.delay_ms,
+        conditions.jitter_ms,
+        20000,
+        dist_type,
+        seed,
+    );
+    let mut reorder_ctrl = ReorderController::new(conditions.reorder_rate, 0.1, 50.0);
+    let mut rate_limiter = RateLimiter::new(conditions.rate_kbps);
+    let mut loss_rng = match seed {
+        Some(s) => StdRng::seed_from_u64(s.wrapping_add(1)),
+        None => StdRng::from_entropy(),
+    };
+    let mut dropped = 0u64;
 
     let mut last_client: Option<SocketAddr> = None;
     let mut packet_count = 0u64;
@@ -399,8 +450,18 @@ pub async fn run(
 
                 let Some(dst) = dst else { continue };
 
+                packet_count += 1;
+                if conditions.loss_rate > 0.0 && loss_rng.gen::<f64>() < conditions.loss_rate {
+                    dropped += 1;
+                    if max_packets > 0 && packet_count >= max_packets {
+                        break;
+                    }
+                    continue;
+                }
+
                 let natural_delay_ms = delay_model.sample(direction);
-                let send_at = Instant::now() + Duration::from_secs_f64(natural_delay_ms / 1000.0);
+                let sent_at = rate_limiter.transmit(direction, Instant::now(), len);
+                let send_at = sent_at + Duration::from_secs_f64(natural_delay_ms / 1000.0);
 
                 seq += 1;
                 let pkt = PendingPacket {
@@ -419,7 +480,6 @@ pub async fn run(
                     pending.push(pkt);
                 }
 
-                packet_count += 1;
                 if max_packets > 0 && packet_count >= max_packets {
                     break;
                 }
@@ -451,6 +511,9 @@ pub async fn run(
     }
 
     reorder_ctrl.print_stats();
+    if conditions.loss_rate > 0.0 {
+        eprintln!("  dropped: {}/{}", dropped, packet_count);
+    }
 
     Ok(())
 }
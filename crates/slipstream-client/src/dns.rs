@@ -1,6 +1,16 @@
 mod debug;
+mod liveness;
 mod poll;
+mod quality;
+mod query_log;
 mod resolver;
+mod size_probe;
 
+pub(crate) use debug::maybe_report_debug;
 pub(crate) use poll::expire_inflight_polls;
-pub(crate) use resolver::{normalize_dual_stack_addr, resolve_resolvers, ResolverState};
+pub(crate) use query_log::{FragmentInfo, QueryLog};
+pub(crate) use resolver::{
+    normalize_dual_stack_addr, record_type_name, reset_resolver_path, resolve_resolvers,
+    ResolverState, RECORD_TYPES,
+};
+pub(crate) use size_probe::SizeProbe;
@@ -60,6 +60,7 @@ pub(crate) fn maybe_report_debug(
     pacing_snapshot: Option<PacingBudgetSnapshot>,
 ) {
     let label = resolver.label();
+    let quality_summary = resolver.quality.summary();
     let debug = &mut resolver.debug;
     if !debug.enabled {
         return;
@@ -101,7 +102,7 @@ pub(crate) fn maybe_report_debug(
         String::new()
     };
     debug!(
-        "debug: {} dns+={} send_pkts+={} send_bytes+={} polls+={} zero_send+={} zero_send_streams+={} streams={} enqueued+={} last_enqueue_ms={} pending_polls={} inflight_polls={}{}",
+        "debug: {} dns+={} send_pkts+={} send_bytes+={} polls+={} zero_send+={} zero_send_streams+={} streams={} enqueued+={} last_enqueue_ms={} pending_polls={} inflight_polls={}{}{}",
         label,
         dns_delta,
         send_pkt_delta,
@@ -114,6 +115,7 @@ pub(crate) fn maybe_report_debug(
         enqueue_ms,
         pending_polls,
         inflight_polls,
+        quality_summary,
         pacing_summary
     );
     debug.last_report_at = now;
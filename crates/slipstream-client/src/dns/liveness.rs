@@ -0,0 +1,138 @@
+/// How long a resolver stays marked as rate limiting after a REFUSED or SERVFAIL.
+const RATE_LIMIT_HOLD_US: u64 = 2_000_000;
+/// How long polling stays reduced after the path last reported packet loss.
+const LOSS_BACKOFF_US: u64 = 1_000_000;
+/// Most times the poll budget is halved while losses keep coming.
+const MAX_LOSS_BACKOFF_STEPS: u32 = 4;
+
+/// Application-level liveness for one resolver path.
+///
+/// QUIC only notices a blackholed path once the idle timeout expires, which can
+/// take far longer than a DNS path that silently stopped answering. A path is
+/// considered dead once queries have been outstanding for `timeout_us` without a
+/// single response in between.
+///
+/// A resolver that answers with REFUSED or SERVFAIL is alive but shedding our
+/// queries, which is how most of them rate limit; it is held as rate limiting
+/// for a short while after each such answer.
+///
+/// Packet loss QUIC reports on the path halves its poll budget, again for each
+/// loss within [`LOSS_BACKOFF_US`] of the previous one, so polling eases off
+/// before throughput collapses.
+#[derive(Debug, Default)]
+pub(crate) struct Liveness {
+    unanswered_since: Option<u64>,
+    rate_limited_until: u64,
+    loss_backoff_until: u64,
+    loss_backoff_steps: u32,
+}
+
+impl Liveness {
+    pub(crate) fn on_query_sent(&mut self, now: u64) {
+        self.unanswered_since.get_or_insert(now);
+    }
+
+    pub(crate) fn on_response(&mut self) {
+        self.unanswered_since = None;
+    }
+
+    pub(crate) fn on_refused(&mut self, now: u64) {
+        self.rate_limited_until = now.saturating_add(RATE_LIMIT_HOLD_US);
+    }
+
+    pub(crate) fn is_rate_limited(&self, now: u64) -> bool {
+        now < self.rate_limited_until
+    }
+
+    pub(crate) fn on_loss(&mut self, now: u64) {
+        self.loss_backoff_steps = if now < self.loss_backoff_until {
+            (self.loss_backoff_steps + 1).min(MAX_LOSS_BACKOFF_STEPS)
+        } else {
+            1
+        };
+        self.loss_backoff_until = now.saturating_add(LOSS_BACKOFF_US);
+    }
+
+    /// Polls the path may have outstanding out of `max`, at least one.
+    pub(crate) fn poll_budget(&self, max: usize, now: u64) -> usize {
+        if now >= self.loss_backoff_until {
+            return max;
+        }
+        (max >> self.loss_backoff_steps).max(1)
+    }
+
+    /// Whether a query has been sent since the last response.
+    pub(crate) fn has_outstanding(&self) -> bool {
+        self.unanswered_since.is_some()
+    }
+
+    pub(crate) fn is_dead(&self, now: u64, timeout_us: u64) -> bool {
+        if timeout_us == 0 {
+            return false;
+        }
+        matches!(self.unanswered_since, Some(since) if now.saturating_sub(since) >= timeout_us)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.unanswered_since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Liveness;
+
+    #[test]
+    fn dead_after_timeout_without_response() {
+        let mut liveness = Liveness::default();
+        liveness.on_query_sent(1_000);
+        liveness.on_query_sent(2_000_000);
+        assert!(!liveness.is_dead(2_000_999, 2_000_000));
+        assert!(liveness.is_dead(2_001_000, 2_000_000));
+    }
+
+    #[test]
+    fn response_clears_outstanding_queries() {
+        let mut liveness = Liveness::default();
+        liveness.on_query_sent(0);
+        liveness.on_response();
+        assert!(!liveness.is_dead(10_000_000, 1_000_000));
+        assert!(!Liveness::default().is_dead(10_000_000, 1_000_000));
+    }
+
+    #[test]
+    fn refusals_mark_rate_limiting_for_a_while() {
+        let mut liveness = Liveness::default();
+        assert!(!liveness.is_rate_limited(0));
+        liveness.on_response();
+        liveness.on_refused(1_000);
+        assert!(liveness.is_rate_limited(1_000));
+        assert!(liveness.is_rate_limited(2_000_999));
+        assert!(!liveness.is_rate_limited(2_001_000));
+    }
+
+    #[test]
+    fn losses_halve_the_poll_budget_until_they_stop() {
+        let mut liveness = Liveness::default();
+        assert_eq!(liveness.poll_budget(64, 0), 64);
+        liveness.on_loss(1_000);
+        assert_eq!(liveness.poll_budget(64, 1_000), 32);
+        liveness.on_loss(500_000);
+        assert_eq!(liveness.poll_budget(64, 500_000), 16);
+        for _ in 0..10 {
+            liveness.on_loss(600_000);
+        }
+        assert_eq!(liveness.poll_budget(64, 600_000), 4);
+        assert_eq!(liveness.poll_budget(1, 600_000), 1);
+        assert_eq!(liveness.poll_budget(64, 1_600_000), 64);
+        liveness.on_loss(3_000_000);
+        assert_eq!(liveness.poll_budget(64, 3_000_000), 32);
+    }
+
+    #[test]
+    fn zero_timeout_disables_detection() {
+        let mut liveness = Liveness::default();
+        liveness.on_query_sent(0);
+        assert!(!liveness.is_dead(u64::MAX, 0));
+    }
+}
@@ -0,0 +1,165 @@
+use slipstream_quic::multipath::PathInfo;
+use std::collections::VecDeque;
+
+/// How often each path's RTT and loss counters are sampled.
+pub(crate) const QUALITY_SAMPLE_INTERVAL_US: u64 = 1_000_000;
+/// How long a path may go without any query before it is probed with a PING.
+const PROBE_IDLE_US: u64 = 5_000_000;
+/// Samples kept per path; with the interval above, a 30 s window.
+const HISTORY_LEN: usize = 30;
+/// Loss rate over the window above which a path counts as degraded.
+const DEGRADED_LOSS_RATE: f64 = 0.5;
+/// Samples that must have seen traffic before a path can count as degraded.
+const MIN_DEGRADED_SAMPLES: usize = 3;
+
+/// One sample of a path: the smoothed RTT and what was sent and lost since the
+/// previous sample.
+#[derive(Clone, Copy, Debug)]
+struct QualitySample {
+    rtt_us: u64,
+    sent_bytes: u64,
+    lost_bytes: u64,
+}
+
+/// Rolling RTT/loss history for one resolver path.
+///
+/// Paths that only carry occasional traffic would otherwise be judged on
+/// counters from whenever they were last used, so idle paths are probed with
+/// PING frames to keep the samples fresh.
+#[derive(Debug, Default)]
+pub(crate) struct QualityHistory {
+    samples: VecDeque<QualitySample>,
+    last_sample_at: u64,
+    last_sent_total: u64,
+    last_lost_total: u64,
+    last_query_at: u64,
+    probes_sent: u64,
+}
+
+impl QualityHistory {
+    pub(crate) fn on_query_sent(&mut self, now: u64) {
+        self.last_query_at = now;
+    }
+
+    pub(crate) fn on_probe_sent(&mut self, now: u64) {
+        self.probes_sent = self.probes_sent.saturating_add(1);
+        self.last_query_at = now;
+    }
+
+    /// Whether the path has gone quiet long enough to need a probe.
+    pub(crate) fn probe_due(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_query_at) >= PROBE_IDLE_US
+    }
+
+    pub(crate) fn sample_due(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_sample_at) >= QUALITY_SAMPLE_INTERVAL_US
+    }
+
+    /// Record a snapshot of the path's cumulative tquic counters.
+    pub(crate) fn record(&mut self, now: u64, info: &PathInfo) {
+        let sample = QualitySample {
+            rtt_us: info.rtt_us,
+            sent_bytes: info.sent_bytes.saturating_sub(self.last_sent_total),
+            lost_bytes: info.lost_bytes.saturating_sub(self.last_lost_total),
+        };
+        self.last_sent_total = info.sent_bytes;
+        self.last_lost_total = info.lost_bytes;
+        self.last_sample_at = now;
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Latest smoothed RTT, once the path has been sampled.
+    pub(crate) fn rtt_us(&self) -> Option<u64> {
+        self.samples.back().map(|sample| sample.rtt_us)
+    }
+
+    /// Fraction of the bytes sent over the window that were declared lost.
+    pub(crate) fn loss_rate(&self) -> f64 {
+        let (sent, lost) = self.samples.iter().fold((0u64, 0u64), |(sent, lost), s| {
+            (
+                sent.saturating_add(s.sent_bytes),
+                lost.saturating_add(s.lost_bytes),
+            )
+        });
+        if sent == 0 {
+            return 0.0;
+        }
+        (lost as f64 / sent as f64).min(1.0)
+    }
+
+    /// Whether recent samples show the path losing most of what is sent on it.
+    pub(crate) fn is_degraded(&self) -> bool {
+        let active = self.samples.iter().filter(|s| s.sent_bytes > 0).count();
+        active >= MIN_DEGRADED_SAMPLES && self.loss_rate() >= DEGRADED_LOSS_RATE
+    }
+
+    /// Summary for the periodic debug report.
+    pub(crate) fn summary(&self) -> String {
+        match self.rtt_us() {
+            Some(rtt_us) => format!(
+                " rtt_ms={:.1} loss={:.1}% probes={}",
+                rtt_us as f64 / 1_000.0,
+                self.loss_rate() * 100.0,
+                self.probes_sent
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// Forget the history, e.g. when the path is replaced.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QualityHistory, PROBE_IDLE_US};
+    use slipstream_quic::multipath::PathInfo;
+
+    fn info(sent_bytes: u64, lost_bytes: u64) -> PathInfo {
+        let addr = "127.0.0.1:53".parse().unwrap();
+        PathInfo {
+            path_id: 0,
+            local_addr: addr,
+            peer_addr: addr,
+            rtt_us: 50_000,
+            cwnd: 0,
+            pacing_rate: 0,
+            bytes_in_flight: 0,
+            sent_bytes,
+            recv_bytes: 0,
+            lost_bytes,
+            validated: true,
+            is_active: true,
+        }
+    }
+
+    #[test]
+    fn loss_rate_uses_deltas_between_samples() {
+        let mut history = QualityHistory::default();
+        history.record(1, &info(1_000, 0));
+        history.record(2, &info(2_000, 600));
+        history.record(3, &info(3_000, 1_200));
+        assert!((history.loss_rate() - 0.4).abs() < 1e-9);
+        assert!(!history.is_degraded());
+
+        history.record(4, &info(4_000, 2_200));
+        history.record(5, &info(5_000, 3_200));
+        assert!(history.is_degraded());
+        assert_eq!(history.rtt_us(), Some(50_000));
+    }
+
+    #[test]
+    fn probes_only_quiet_paths() {
+        let mut history = QualityHistory::default();
+        history.on_query_sent(1_000);
+        assert!(!history.probe_due(1_000 + PROBE_IDLE_US - 1));
+        assert!(history.probe_due(1_000 + PROBE_IDLE_US));
+        history.on_probe_sent(1_000 + PROBE_IDLE_US);
+        assert!(!history.probe_due(1_000 + PROBE_IDLE_US + 1));
+    }
+}
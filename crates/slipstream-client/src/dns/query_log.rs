@@ -0,0 +1,142 @@
+//! Optional JSONL log of emitted DNS queries and received responses.
+//!
+//! Records carry only metadata (timing, sizes, fragment positions, rcodes), never
+//! the tunneled payload, so the log can be shared for resolver-behavior analysis.
+
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Queries older than this are forgotten; a late response is logged without latency.
+const PENDING_QUERY_TTL: Duration = Duration::from_secs(30);
+const PENDING_QUERY_MAX: usize = 4096;
+
+/// Position of a query within a fragmented QUIC packet.
+pub(crate) struct FragmentInfo {
+    pub(crate) packet_id: u16,
+    pub(crate) index: usize,
+    pub(crate) count: usize,
+}
+
+pub(crate) struct QueryLog {
+    file: File,
+    pending: HashMap<(SocketAddr, u16), Instant>,
+}
+
+impl QueryLog {
+    /// Open (or create) the query log for appending.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Log an emitted query; `fragment` is `None` for polls, which carry no QUIC data.
+    pub(crate) fn record_query(
+        &mut self,
+        resolver: SocketAddr,
+        id: u16,
+        qtype: u16,
+        qname_len: usize,
+        fragment: Option<&FragmentInfo>,
+    ) {
+        let now = Instant::now();
+        if self.pending.len() >= PENDING_QUERY_MAX {
+            self.pending
+                .retain(|_, sent_at| now.duration_since(*sent_at) < PENDING_QUERY_TTL);
+        }
+        self.pending.insert((resolver, id), now);
+        self.write(json!({
+            "ts": unix_ts(),
+            "event": "query",
+            "resolver": resolver.to_string(),
+            "id": id,
+            "qtype": qtype,
+            "qname_len": qname_len,
+            "poll": fragment.is_none(),
+            "packet_id": fragment.map(|f| f.packet_id),
+            "fragment_index": fragment.map(|f| f.index),
+            "fragment_count": fragment.map(|f| f.count),
+        }));
+    }
+
+    /// Log a response packet; packets too short to carry a DNS header are ignored.
+    pub(crate) fn record_response(&mut self, resolver: SocketAddr, packet: &[u8]) {
+        if packet.len() < 12 {
+            return;
+        }
+        let id = u16::from_be_bytes([packet[0], packet[1]]);
+        let rcode = packet[3] & 0x0f;
+        let latency_ms = self
+            .pending
+            .remove(&(resolver, id))
+            .map(|sent_at| sent_at.elapsed().as_secs_f64() * 1000.0);
+        self.write(json!({
+            "ts": unix_ts(),
+            "event": "response",
+            "resolver": resolver.to_string(),
+            "id": id,
+            "rcode": rcode,
+            "size": packet.len(),
+            "latency_ms": latency_ms,
+        }));
+    }
+
+    fn write(&mut self, line: serde_json::Value) {
+        if let Err(err) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            tracing::warn!("Failed to write query log record: {}", err);
+        }
+    }
+}
+
+fn unix_ts() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FragmentInfo, QueryLog};
+    use slipstream_core::test_util::TempLog;
+
+    #[test]
+    fn pairs_responses_with_queries() {
+        let file = TempLog::new("query-log");
+        let resolver = "127.0.0.1:53".parse().expect("resolver");
+        let mut log = QueryLog::open(file.path()).expect("open query log");
+        log.record_query(
+            resolver,
+            7,
+            16,
+            120,
+            Some(&FragmentInfo {
+                packet_id: 1,
+                index: 0,
+                count: 2,
+            }),
+        );
+        let mut response = vec![0u8; 40];
+        response[0..2].copy_from_slice(&7u16.to_be_bytes());
+        response[3] = 0x83;
+        log.record_response(resolver, &response);
+        log.record_response(resolver, &response);
+
+        let lines = file.json_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["event"], "query");
+        assert_eq!(lines[0]["qname_len"], 120);
+        assert_eq!(lines[0]["fragment_count"], 2);
+        assert_eq!(lines[1]["rcode"], 3);
+        assert_eq!(lines[1]["size"], 40);
+        assert!(lines[1]["latency_ms"].is_number());
+        assert!(lines[2]["latency_ms"].is_null());
+    }
+}
@@ -2,33 +2,66 @@
 
 use crate::error::ClientError;
 use crate::pacing::{PacingBudgetSnapshot, PacingPollBudget};
-use slipstream_core::{resolve_host_port, ResolverMode, ResolverSpec};
+use slipstream_core::{resolve_host_port, QnameEncoding, ResolverMode, ResolverSpec};
+use slipstream_dns::{RR_A, RR_AAAA, RR_NULL, RR_TXT};
 use std::collections::HashMap;
 use std::net::{SocketAddr, SocketAddrV6};
 use tracing::warn;
 
 use super::debug::DebugMetrics;
+use super::liveness::Liveness;
+use super::quality::QualityHistory;
+use super::size_probe::SizeProbe;
+
+/// Record types queries can ask for, by command-line name.
+pub(crate) const RECORD_TYPES: [(&str, u16); 4] = [
+    ("txt", RR_TXT),
+    ("null", RR_NULL),
+    ("a", RR_A),
+    ("aaaa", RR_AAAA),
+];
+
+pub(crate) fn record_type_name(record_type: u16) -> &'static str {
+    RECORD_TYPES
+        .iter()
+        .find(|(_, known)| *known == record_type)
+        .map_or("unknown", |(name, _)| name)
+}
 
 pub(crate) struct ResolverState {
     pub(crate) addr: SocketAddr,
     pub(crate) mode: ResolverMode,
+    /// Backup path that only polls while every other resolver is degraded.
+    pub(crate) standby: bool,
+    /// Record type of the queries sent through this resolver.
+    pub(crate) record_type: u16,
+    /// Encoding of the payload in the names of those queries.
+    pub(crate) qname_encoding: QnameEncoding,
+    /// Learned query and response size limits, with `--probe-sizes`.
+    pub(crate) size_probe: Option<SizeProbe>,
     pub(crate) added: bool,
     /// tquic path ID for multipath support
     pub(crate) path_id_tquic: Option<u64>,
     pub(crate) probe_attempts: u32,
     pub(crate) next_probe_at: u64,
     pub(crate) pending_polls: usize,
+    /// DNS queries carrying QUIC packets sent during the current loop.
+    pub(crate) loop_data_queries: usize,
     pub(crate) inflight_poll_ids: HashMap<u16, u64>,
     pub(crate) pacing_budget: Option<PacingPollBudget>,
     pub(crate) last_pacing_snapshot: Option<PacingBudgetSnapshot>,
+    /// Latest server-reported backlog (bytes queued for us) from response hints.
+    pub(crate) server_backlog: u64,
+    pub(crate) liveness: Liveness,
+    pub(crate) quality: QualityHistory,
     pub(crate) debug: DebugMetrics,
 }
 
 impl ResolverState {
     pub(crate) fn label(&self) -> String {
         format!(
-            "path_id_tquic={:?} resolver={} mode={:?}",
-            self.path_id_tquic, self.addr, self.mode
+            "path_id_tquic={:?} resolver={} mode={:?} standby={}",
+            self.path_id_tquic, self.addr, self.mode, self.standby
         )
     }
 }
@@ -42,10 +75,10 @@ pub(crate) fn resolve_resolvers(
     let mut seen = HashMap::new();
     for (idx, resolver) in resolvers.iter().enumerate() {
         let addr = resolve_host_port(&resolver.resolver)
-            .map_err(|err| ClientError::new(err.to_string()))?;
+            .map_err(|err| ClientError::config(err.to_string()))?;
         let addr = normalize_dual_stack_addr(addr);
         if let Some(existing_mode) = seen.get(&addr) {
-            return Err(ClientError::new(format!(
+            return Err(ClientError::config(format!(
                 "Duplicate resolver address {} (modes: {:?} and {:?})",
                 addr, existing_mode, resolver.mode
             )));
@@ -55,17 +88,25 @@ pub(crate) fn resolve_resolvers(
         resolved.push(ResolverState {
             addr,
             mode: resolver.mode,
+            standby: resolver.standby,
+            record_type: resolver.record_type,
+            qname_encoding: resolver.qname_encoding,
+            size_probe: None,
             added: is_primary,
             path_id_tquic: if is_primary { Some(0) } else { None },
             probe_attempts: 0,
             next_probe_at: 0,
             pending_polls: 0,
+            loop_data_queries: 0,
             inflight_poll_ids: HashMap::new(),
             pacing_budget: match resolver.mode {
                 ResolverMode::Authoritative => Some(PacingPollBudget::new(mtu)),
                 ResolverMode::Recursive => None,
             },
             last_pacing_snapshot: None,
+            server_backlog: 0,
+            liveness: Liveness::default(),
+            quality: QualityHistory::default(),
             debug: DebugMetrics::new(debug_poll),
         });
     }
@@ -82,6 +123,12 @@ pub(crate) fn reset_resolver_path(resolver: &mut ResolverState) {
     resolver.pending_polls = 0;
     resolver.inflight_poll_ids.clear();
     resolver.last_pacing_snapshot = None;
+    resolver.server_backlog = 0;
+    resolver.liveness.reset();
+    resolver.quality.reset();
+    if let Some(size_probe) = resolver.size_probe.as_mut() {
+        size_probe.clear_inflight();
+    }
     resolver.probe_attempts = 0;
     resolver.next_probe_at = 0;
 }
@@ -98,7 +145,8 @@ pub(crate) fn normalize_dual_stack_addr(addr: SocketAddr) -> SocketAddr {
 #[cfg(test)]
 mod tests {
     use super::resolve_resolvers;
-    use slipstream_core::{AddressFamily, HostPort, ResolverMode, ResolverSpec};
+    use slipstream_core::{AddressFamily, HostPort, QnameEncoding, ResolverMode, ResolverSpec};
+    use slipstream_dns::RR_TXT;
 
     #[test]
     fn rejects_duplicate_resolver_addr() {
@@ -110,6 +158,12 @@ mod tests {
                     family: AddressFamily::V4,
                 },
                 mode: ResolverMode::Recursive,
+                standby: false,
+                record_type: RR_TXT,
+                qname_encoding: QnameEncoding::Base32,
+                doh_url: None,
+                dot_server: None,
+                doq_server: None,
             },
             ResolverSpec {
                 resolver: HostPort {
@@ -118,6 +172,12 @@ mod tests {
                     family: AddressFamily::V4,
                 },
                 mode: ResolverMode::Authoritative,
+                standby: false,
+                record_type: RR_TXT,
+                qname_encoding: QnameEncoding::Base32,
+                doh_url: None,
+                dot_server: None,
+                doq_server: None,
             },
         ];
 
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+/// Smallest EDNS0 UDP payload size a query advertises (RFC 6891).
+const EDNS_UDP_PAYLOAD_MIN: usize = 512;
+/// Answers at the current size before the probe tries a larger one.
+const RAMP_STREAK: u32 = 8;
+/// How long a query may go unanswered before it counts as lost.
+const PROBE_TIMEOUT_US: u64 = 3_000_000;
+/// How long a size that failed stays off limits before it is tried again.
+const CEILING_HOLD_US: u64 = 60_000_000;
+/// Most queries whose outcome the probe waits for; more go untracked.
+const TRACKED_QUERIES_MAX: usize = 1024;
+
+/// Searches for the largest size that gets through, between `min` and `max`.
+///
+/// Queries use `current`. After [`RAMP_STREAK`] of them are answered at that
+/// size it moves halfway to the ceiling, which is `max` unless a larger size
+/// failed within [`CEILING_HOLD_US`]. A failure above the largest size known
+/// to work drops back to that size and makes the failed one the ceiling.
+/// Failures at or below it are blamed on something else, such as rate
+/// limiting or loss. `min` is assumed to work.
+#[derive(Debug)]
+struct Ramp {
+    max: usize,
+    /// Largest size seen getting through.
+    good: usize,
+    /// Smallest size that failed, and until when it caps the ramp.
+    bad: Option<(usize, u64)>,
+    current: usize,
+    streak: u32,
+}
+
+impl Ramp {
+    fn new(min: usize, max: usize) -> Self {
+        let min = min.min(max);
+        Self {
+            max,
+            good: min,
+            bad: None,
+            current: min,
+            streak: 0,
+        }
+    }
+
+    fn ceiling(&self, now: u64) -> usize {
+        match self.bad {
+            Some((bad, until)) if now < until => bad - 1,
+            _ => self.max,
+        }
+    }
+
+    /// A query that used `size` was answered, and `seen` bytes of it are
+    /// known to have got through.
+    fn on_success(&mut self, size: usize, seen: usize, now: u64) {
+        self.good = self.good.max(seen.min(size));
+        if size < self.current {
+            return;
+        }
+        self.streak += 1;
+        let ceiling = self.ceiling(now);
+        if self.streak >= RAMP_STREAK && self.current < ceiling {
+            self.current += (ceiling - self.current).div_ceil(2);
+            self.streak = 0;
+        }
+    }
+
+    fn on_failure(&mut self, size: usize, now: u64) {
+        if size <= self.good {
+            return;
+        }
+        let bad = match self.bad {
+            Some((bad, until)) if now < until => bad.min(size),
+            _ => size,
+        };
+        self.bad = Some((bad, now.saturating_add(CEILING_HOLD_US)));
+        self.current = self.good;
+        self.streak = 0;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SentQuery {
+    payload_len: usize,
+    edns_udp_payload: usize,
+    sent_at: u64,
+}
+
+/// Learns how large the queries and responses one resolver passes are.
+///
+/// Resolvers and middleboxes cap query names below what DNS allows and
+/// responses below the EDNS0 size we advertise, answering oversized queries
+/// with SERVFAIL or FORMERR, truncating responses or dropping them. Two
+/// [`Ramp`]s search for the real limits: one for the QUIC bytes a query name
+/// carries, one for the UDP payload size a query advertises. Each query is
+/// remembered by DNS ID with the sizes it used, so its answer, or its lack
+/// of one, is charged to the right ramp.
+#[derive(Debug)]
+pub(crate) struct SizeProbe {
+    query: Ramp,
+    response: Ramp,
+    sent: HashMap<u16, SentQuery>,
+}
+
+impl SizeProbe {
+    /// Probe queries carrying up to `max_payload` bytes and advertising up to
+    /// `max_udp_payload`, starting from half the payload and 512 bytes.
+    pub(crate) fn new(max_payload: usize, max_udp_payload: u16) -> Self {
+        Self {
+            query: Ramp::new(max_payload / 2, max_payload),
+            response: Ramp::new(EDNS_UDP_PAYLOAD_MIN, max_udp_payload as usize),
+            sent: HashMap::new(),
+        }
+    }
+
+    /// QUIC bytes the next query may carry.
+    pub(crate) fn query_payload(&self) -> usize {
+        self.query.current
+    }
+
+    /// EDNS0 UDP payload size the next query advertises.
+    pub(crate) fn edns_udp_payload(&self) -> u16 {
+        self.response.current as u16
+    }
+
+    pub(crate) fn on_query_sent(&mut self, id: u16, payload_len: usize, now: u64) {
+        if self.sent.len() >= TRACKED_QUERIES_MAX {
+            return;
+        }
+        self.sent.insert(
+            id,
+            SentQuery {
+                payload_len,
+                edns_udp_payload: self.response.current,
+                sent_at: now,
+            },
+        );
+    }
+
+    /// Account for the answer to query `id`, `response_len` bytes long.
+    ///
+    /// `rejected` is set for SERVFAIL and FORMERR, which is how resolvers
+    /// turn away names they will not forward.
+    pub(crate) fn on_response(
+        &mut self,
+        id: u16,
+        response_len: usize,
+        rejected: bool,
+        truncated: bool,
+        now: u64,
+    ) {
+        let Some(sent) = self.sent.remove(&id) else {
+            return;
+        };
+        if rejected {
+            self.query.on_failure(sent.payload_len, now);
+            return;
+        }
+        self.query
+            .on_success(sent.payload_len, sent.payload_len, now);
+        if truncated {
+            self.response.on_failure(sent.edns_udp_payload, now);
+        } else {
+            // Most answers are short replies to polls, so only the bytes that
+            // actually arrived prove anything about the size limit.
+            self.response
+                .on_success(sent.edns_udp_payload, response_len, now);
+        }
+    }
+
+    /// Charge queries unanswered for [`PROBE_TIMEOUT_US`] to the larger of
+    /// their sizes that had not been proven yet.
+    pub(crate) fn expire(&mut self, now: u64) {
+        let mut expired = Vec::new();
+        self.sent.retain(|_, sent| {
+            let keep = now.saturating_sub(sent.sent_at) < PROBE_TIMEOUT_US;
+            if !keep {
+                expired.push(*sent);
+            }
+            keep
+        });
+        for sent in expired {
+            if sent.payload_len > self.query.good {
+                self.query.on_failure(sent.payload_len, now);
+            } else {
+                self.response.on_failure(sent.edns_udp_payload, now);
+            }
+        }
+    }
+
+    /// Forget queries in flight, e.g. once the path to the resolver is reset.
+    pub(crate) fn clear_inflight(&mut self) {
+        self.sent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SizeProbe, CEILING_HOLD_US, PROBE_TIMEOUT_US, RAMP_STREAK};
+
+    fn answer_streak(probe: &mut SizeProbe, id: &mut u16, response_len: usize, now: u64) {
+        for _ in 0..RAMP_STREAK {
+            let payload = probe.query_payload();
+            probe.on_query_sent(*id, payload, now);
+            probe.on_response(*id, response_len, false, false, now);
+            *id += 1;
+        }
+    }
+
+    #[test]
+    fn ramps_up_to_the_configured_limits() {
+        let mut probe = SizeProbe::new(150, 1232);
+        assert_eq!(probe.query_payload(), 75);
+        assert_eq!(probe.edns_udp_payload(), 512);
+        let mut id = 0;
+        for _ in 0..16 {
+            answer_streak(&mut probe, &mut id, 1400, 0);
+        }
+        assert_eq!(probe.query_payload(), 150);
+        assert_eq!(probe.edns_udp_payload(), 1232);
+    }
+
+    #[test]
+    fn servfail_caps_the_query_size_until_the_hold_expires() {
+        let mut probe = SizeProbe::new(150, 1232);
+        let mut id = 0;
+        answer_streak(&mut probe, &mut id, 100, 0);
+        let tried = probe.query_payload();
+        assert_eq!(tried, 113);
+        probe.on_query_sent(id, tried, 0);
+        probe.on_response(id, 100, true, false, 0);
+        id += 1;
+        assert_eq!(probe.query_payload(), 75);
+        for _ in 0..8 {
+            answer_streak(&mut probe, &mut id, 100, 0);
+        }
+        assert_eq!(probe.query_payload(), 112);
+
+        // SERVFAIL at a size already answered is not the size's fault
+        probe.on_query_sent(id, 100, 0);
+        probe.on_response(id, 100, true, false, 0);
+        id += 1;
+        assert_eq!(probe.query_payload(), 112);
+
+        answer_streak(&mut probe, &mut id, 100, CEILING_HOLD_US);
+        assert!(probe.query_payload() > 112);
+    }
+
+    #[test]
+    fn truncation_and_silence_cap_the_response_size() {
+        let mut probe = SizeProbe::new(150, 1232);
+        let mut id = 0;
+        answer_streak(&mut probe, &mut id, 600, 0);
+        let tried = probe.edns_udp_payload();
+        assert_eq!(tried, 872);
+        probe.on_query_sent(id, probe.query_payload(), 0);
+        probe.on_response(id, 512, false, true, 0);
+        id += 1;
+        assert_eq!(probe.edns_udp_payload(), 512);
+
+        answer_streak(&mut probe, &mut id, 700, 0);
+        let tried = probe.edns_udp_payload();
+        assert_eq!(tried, 692);
+        // A query at an answered payload size that never comes back
+        probe.on_query_sent(id, 75, 0);
+        probe.expire(PROBE_TIMEOUT_US - 1);
+        assert_eq!(probe.edns_udp_payload(), tried);
+        probe.expire(PROBE_TIMEOUT_US);
+        assert_eq!(probe.edns_udp_payload(), 512);
+    }
+}
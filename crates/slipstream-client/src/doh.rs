@@ -0,0 +1,445 @@
+//! DNS-over-HTTPS transport (RFC 8484).
+//!
+//! Queries to a resolver selected with `--resolver-doh` are POSTed in wire
+//! format to its URL instead of going out over UDP, for networks that block
+//! port 53 but let HTTPS through. The TLS connection goes to the resolver
+//! address at the URL's port, so no lookup of the URL host is needed; the
+//! host is still sent for SNI, certificate checks and the `Host` header.
+//!
+//! HTTP/1.1 carries one request at a time per connection, so a few
+//! connections run side by side, each on a thread of its own with blocking
+//! I/O. Answers are handed to the runtime tagged with the resolver address,
+//! as if they had arrived over UDP from it. A query that fails is dropped,
+//! like a lost datagram, and QUIC retransmits what it carried.
+
+use openssl::ssl::{SslConnector, SslMethod, SslStream};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+
+/// Connections, and so requests in flight, per DoH resolver.
+const DOH_CONNECTIONS: usize = 4;
+/// How long connecting, or one request and its response, may take.
+const DOH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest response header section accepted.
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+/// Longest response body accepted; more than any UDP DNS message.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Where an `https://` DoH URL points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DohUrl {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// Path and query, `/` at least.
+    pub(crate) path: String,
+}
+
+impl DohUrl {
+    pub(crate) fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("https://")
+            .ok_or_else(|| format!("DoH URL '{}' must start with https://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            // IPv6 literal
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, rest)) => (host, Some(rest.strip_prefix(':').unwrap_or(rest))),
+                None => ("", None),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| format!("Invalid port in DoH URL '{}'", url))?,
+            None => 443,
+        };
+        if host.is_empty() || port == 0 {
+            return Err(format!("DoH URL '{}' has no host", url));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Host and port for the `Host` header: an IPv6 literal in brackets,
+    /// and the port unless it is 443.
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match self.port {
+            443 => host,
+            port => format!("{}:{}", host, port),
+        }
+    }
+}
+
+/// Sends the queries for one resolver over DoH.
+///
+/// Dropping it stops the connection threads once their current request is
+/// done.
+pub(crate) struct DohTransport {
+    queries: mpsc::Sender<Vec<u8>>,
+}
+
+impl DohTransport {
+    /// Start the connections to `url` through `resolver`; answers go to
+    /// `responses`, tagged with `resolver`.
+    pub(crate) fn spawn(
+        url: DohUrl,
+        resolver: SocketAddr,
+        responses: UnboundedSender<(Vec<u8>, SocketAddr)>,
+    ) -> io::Result<Self> {
+        let mut builder =
+            SslConnector::builder(SslMethod::tls_client()).map_err(io::Error::other)?;
+        builder
+            .set_alpn_protos(b"\x08http/1.1")
+            .map_err(io::Error::other)?;
+        let connector = builder.build();
+        let (queries, queue) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let worker = Arc::new(Worker {
+            connect_addr: SocketAddr::new(resolver.ip().to_canonical(), url.port),
+            url,
+            resolver,
+            connector,
+        });
+        for _ in 0..DOH_CONNECTIONS {
+            let worker = Arc::clone(&worker);
+            let queue = Arc::clone(&queue);
+            let responses = responses.clone();
+            std::thread::Builder::new()
+                .name("slipstream-doh".to_string())
+                .spawn(move || worker.run(&queue, &responses))?;
+        }
+        Ok(Self { queries })
+    }
+
+    /// Queue `query` for the next free connection.
+    pub(crate) fn send(&self, query: Vec<u8>) -> io::Result<()> {
+        self.queries
+            .send(query)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "DoH threads stopped"))
+    }
+}
+
+type DohStream = BufReader<SslStream<TcpStream>>;
+
+/// What every connection thread of one resolver shares.
+struct Worker {
+    url: DohUrl,
+    /// The resolver's IP, without the IPv4-mapped form UDP uses, at the
+    /// URL's port.
+    connect_addr: SocketAddr,
+    resolver: SocketAddr,
+    connector: SslConnector,
+}
+
+impl Worker {
+    fn run(
+        &self,
+        queue: &Mutex<mpsc::Receiver<Vec<u8>>>,
+        responses: &UnboundedSender<(Vec<u8>, SocketAddr)>,
+    ) {
+        let mut stream: Option<DohStream> = None;
+        loop {
+            let query = match queue.lock() {
+                Ok(queue) => match queue.recv() {
+                    Ok(query) => query,
+                    Err(_) => return,
+                },
+                Err(_) => return,
+            };
+            // A kept-alive connection may have been closed by the server in
+            // the meantime; try once more on a fresh one.
+            for _ in 0..2 {
+                let fresh = stream.is_none();
+                if fresh {
+                    match self.connect() {
+                        Ok(connected) => stream = Some(connected),
+                        Err(e) => {
+                            debug!("DoH connection to {} failed: {}", self.url.host, e);
+                            break;
+                        }
+                    }
+                }
+                let Some(connected) = stream.as_mut() else {
+                    break;
+                };
+                match exchange(connected, &self.url, &query) {
+                    Ok((answer, keep_alive)) => {
+                        if !keep_alive {
+                            stream = None;
+                        }
+                        if responses.send((answer, self.resolver)).is_err() {
+                            return;
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        stream = None;
+                        if fresh {
+                            debug!("DoH request to {} failed: {}", self.url.host, e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn connect(&self) -> io::Result<DohStream> {
+        let tcp = TcpStream::connect_timeout(&self.connect_addr, DOH_TIMEOUT)?;
+        tcp.set_read_timeout(Some(DOH_TIMEOUT))?;
+        tcp.set_write_timeout(Some(DOH_TIMEOUT))?;
+        tcp.set_nodelay(true)?;
+        let tls = self
+            .connector
+            .connect(&self.url.host, tcp)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(BufReader::new(tls))
+    }
+}
+
+/// POST `query` to `url` over `stream` and read the answer. Returns the DNS
+/// message and whether the connection may be used again.
+fn exchange<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    url: &DohUrl,
+    query: &[u8],
+) -> io::Result<(Vec<u8>, bool)> {
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/dns-message\r\nAccept: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        url.path,
+        url.authority(),
+        query.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(query);
+    stream.get_mut().write_all(&request)?;
+    stream.get_mut().flush()?;
+
+    let status_line = read_line(stream)?;
+    let status = status_line
+        .strip_prefix("HTTP/1.1 ")
+        .or_else(|| status_line.strip_prefix("HTTP/1.0 "))
+        .and_then(|rest| rest.get(..3))
+        .ok_or_else(|| invalid(format!("bad status line '{}'", status_line)))?;
+    let mut keep_alive = status_line.starts_with("HTTP/1.1");
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut header_bytes = status_line.len();
+    loop {
+        let line = read_line(stream)?;
+        if line.is_empty() {
+            break;
+        }
+        header_bytes += line.len();
+        if header_bytes > MAX_HEADER_BYTES {
+            return Err(invalid("response headers too long".to_string()));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| invalid(format!("bad Content-Length '{}'", value)))?,
+            );
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("connection") {
+            keep_alive = !value.eq_ignore_ascii_case("close");
+        }
+    }
+
+    let body = if chunked {
+        read_chunked(stream)?
+    } else {
+        let len = content_length.ok_or_else(|| invalid("response without length".to_string()))?;
+        if len > MAX_BODY_BYTES {
+            return Err(invalid(format!("response body of {} bytes", len)));
+        }
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body)?;
+        body
+    };
+    if status != "200" {
+        return Err(invalid(format!("HTTP status {}", status)));
+    }
+    Ok((body, keep_alive))
+}
+
+fn read_chunked<R: BufRead>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(stream)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid(format!("bad chunk size '{}'", line)))?;
+        if size == 0 {
+            // Trailers, up to the empty line
+            while !read_line(stream)?.is_empty() {}
+            return Ok(body);
+        }
+        if body.len() + size > MAX_BODY_BYTES {
+            return Err(invalid("chunked response body too long".to_string()));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        stream.read_exact(&mut body[start..])?;
+        if !read_line(stream)?.is_empty() {
+            return Err(invalid("chunk not followed by CRLF".to_string()));
+        }
+    }
+}
+
+/// One header line, without its line ending.
+fn read_line<R: BufRead>(stream: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    stream
+        .by_ref()
+        .take(MAX_HEADER_BYTES as u64)
+        .read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed mid-response",
+        ));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| invalid("response header is not UTF-8".to_string()))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exchange, DohUrl};
+    use std::io::{BufReader, Cursor, Read, Write};
+
+    /// Replays a canned response and records what was written.
+    struct Canned {
+        response: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Canned {
+        fn new(response: &[u8]) -> BufReader<Self> {
+            BufReader::new(Self {
+                response: Cursor::new(response.to_vec()),
+                written: Vec::new(),
+            })
+        }
+    }
+
+    impl Read for Canned {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Canned {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parses_doh_urls() {
+        assert_eq!(
+            DohUrl::parse("https://dns.example/dns-query").unwrap(),
+            DohUrl {
+                host: "dns.example".to_string(),
+                port: 443,
+                path: "/dns-query".to_string(),
+            }
+        );
+        assert_eq!(
+            DohUrl::parse("https://[2001:db8::1]:8443").unwrap(),
+            DohUrl {
+                host: "2001:db8::1".to_string(),
+                port: 8443,
+                path: "/".to_string(),
+            }
+        );
+        assert!(DohUrl::parse("http://dns.example/dns-query").is_err());
+        assert!(DohUrl::parse("https:///dns-query").is_err());
+        assert!(DohUrl::parse("https://dns.example:x/").is_err());
+        assert!(DohUrl::parse("https://[2001:db8::1]x/").is_err());
+        assert_eq!(
+            DohUrl::parse("https://[2001:db8::1]/q").unwrap().host,
+            "2001:db8::1"
+        );
+    }
+
+    #[test]
+    fn host_headers_carry_brackets_and_other_ports() {
+        let authority = |url| DohUrl::parse(url).unwrap().authority();
+        assert_eq!(authority("https://dns.example/q"), "dns.example");
+        assert_eq!(authority("https://dns.example:443/q"), "dns.example");
+        assert_eq!(authority("https://dns.example:8443/q"), "dns.example:8443");
+        assert_eq!(authority("https://[2001:db8::1]/q"), "[2001:db8::1]");
+        assert_eq!(
+            authority("https://[2001:db8::1]:8443/q"),
+            "[2001:db8::1]:8443"
+        );
+    }
+
+    #[test]
+    fn posts_queries_and_reads_answers() {
+        let url = DohUrl::parse("https://dns.example/dns-query").unwrap();
+        let mut stream = Canned::new(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: 3\r\n\r\nabcHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n2\r\nde\r\n1;ext\r\nf\r\n0\r\n\r\n",
+        );
+        assert_eq!(
+            exchange(&mut stream, &url, b"query").unwrap(),
+            (b"abc".to_vec(), true)
+        );
+        let written = String::from_utf8(stream.get_ref().written.clone()).unwrap();
+        assert!(written.starts_with("POST /dns-query HTTP/1.1\r\nHost: dns.example\r\n"));
+        assert!(written.contains("Content-Type: application/dns-message\r\n"));
+        assert!(written.ends_with("Content-Length: 5\r\n\r\nquery"));
+        assert_eq!(
+            exchange(&mut stream, &url, b"query").unwrap(),
+            (b"def".to_vec(), false)
+        );
+    }
+
+    #[test]
+    fn rejects_errors_and_short_responses() {
+        let url = DohUrl::parse("https://dns.example/dns-query").unwrap();
+        let mut stream = Canned::new(b"HTTP/1.1 415 Unsupported\r\nContent-Length: 0\r\n\r\n");
+        assert!(exchange(&mut stream, &url, b"query").is_err());
+        let mut stream = Canned::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc");
+        assert!(exchange(&mut stream, &url, b"query").is_err());
+    }
+}
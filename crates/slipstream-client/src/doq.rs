@@ -0,0 +1,238 @@
+//! DNS-over-QUIC transport (RFC 9250).
+//!
+//! Queries to a resolver selected with `--resolver-doq` go to it over QUIC,
+//! on UDP port 853 or whichever port was given, for networks that filter
+//! UDP/53 but let DoQ out. As with DoH and DoT, the connection goes to the
+//! resolver address and the configured name is only used for SNI and to
+//! check the certificate.
+//!
+//! Each query gets a stream of its own, so they never wait on each other.
+//! RFC 9250 has queries carry DNS ID 0; the transport puts the tunnel's ID
+//! back into each answer before handing it to the runtime, tagged with the
+//! resolver address. The connection runs as a
+//! [`SharedConnection`], and the sessions it is given are kept for the life
+//! of the transport, so after the resolver closes an idle connection the next
+//! one resumes and sends its first queries as 0-RTT data.
+
+use slipstream_quic::{
+    Client, Config as QuicConfig, MemorySessionStore, SessionData, SessionStore, SharedConnection,
+};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::debug;
+
+/// Application protocol of DoQ.
+const DOQ_ALPN: &[u8] = b"doq";
+/// Queries, and so streams, open at a time per DoQ resolver.
+const DOQ_STREAMS_MAX: usize = 64;
+/// How long connecting, or one query and its answer, may take.
+const DOQ_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often an idle connection is pinged to keep it open.
+const DOQ_KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Pause after a failed connection attempt before the next one.
+const DOQ_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Sends the queries for one resolver over DoQ.
+///
+/// Dropping it closes the connection once the queries in flight are done.
+pub(crate) struct DoqTransport {
+    queries: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl DoqTransport {
+    /// Start connecting to `server_name` at `resolver`'s IP and `port`;
+    /// answers go to `responses`, tagged with `resolver`. Must be called on
+    /// the runtime the queries should run on.
+    pub(crate) fn spawn(
+        server_name: &str,
+        port: u16,
+        resolver: SocketAddr,
+        responses: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+    ) -> Self {
+        let (queries, queue) = mpsc::unbounded_channel();
+        let session = Session {
+            server_name: server_name.to_string(),
+            connect_addr: SocketAddr::new(resolver.ip().to_canonical(), port),
+            resolver,
+            sessions: SharedSessionStore::default(),
+            responses,
+        };
+        tokio::spawn(session.run(queue));
+        Self { queries }
+    }
+
+    /// Queue `query` to go out on a stream of its own.
+    pub(crate) fn send(&self, query: Vec<u8>) -> io::Result<()> {
+        self.queries
+            .send(query)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "DoQ connection stopped"))
+    }
+}
+
+/// Session tickets of one resolver, kept across its connections.
+///
+/// Each [`SharedConnection`] builds its client on a thread of its own, so the
+/// store it gets must be shared rather than owned.
+#[derive(Clone, Default)]
+struct SharedSessionStore(Arc<Mutex<MemorySessionStore>>);
+
+impl SessionStore for SharedSessionStore {
+    fn load(&mut self, server_name: &str) -> Option<SessionData> {
+        self.0.lock().ok()?.load(server_name)
+    }
+
+    fn save(&mut self, server_name: &str, session: &SessionData) {
+        if let Ok(mut store) = self.0.lock() {
+            store.save(server_name, session);
+        }
+    }
+}
+
+struct Session {
+    server_name: String,
+    /// The resolver's IP, without the IPv4-mapped form UDP uses, at the DoQ
+    /// port.
+    connect_addr: SocketAddr,
+    resolver: SocketAddr,
+    sessions: SharedSessionStore,
+    responses: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+}
+
+impl Session {
+    /// Send queued queries, connecting whenever there is no connection,
+    /// until the transport is dropped.
+    async fn run(self, mut queue: mpsc::UnboundedReceiver<Vec<u8>>) {
+        let streams = Arc::new(Semaphore::new(DOQ_STREAMS_MAX));
+        let mut conn: Option<SharedConnection> = None;
+        while let Some(query) = queue.recv().await {
+            if conn.as_ref().is_some_and(SharedConnection::is_closed) {
+                conn = None;
+            }
+            if conn.is_none() {
+                match self.connect().await {
+                    Ok(connected) => conn = Some(connected),
+                    Err(e) => {
+                        debug!("DoQ connection to {} failed: {}", self.server_name, e);
+                        // Queries queued meanwhile are stale by the time
+                        // the next attempt could carry them.
+                        tokio::time::sleep(DOQ_RECONNECT_DELAY).await;
+                        while queue.try_recv().is_ok() {}
+                        continue;
+                    }
+                }
+            }
+            let Some(connected) = conn.as_ref() else {
+                continue;
+            };
+            let Ok(permit) = Arc::clone(&streams).acquire_owned().await else {
+                return;
+            };
+            let stream = match connected.open_bi().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("DoQ stream to {} failed: {}", self.server_name, e);
+                    conn = None;
+                    continue;
+                }
+            };
+            let responses = self.responses.clone();
+            let resolver = self.resolver;
+            tokio::spawn(async move {
+                let mut stream = stream;
+                match tokio::time::timeout(DOQ_TIMEOUT, exchange(&mut stream, &query)).await {
+                    Ok(Ok(answer)) => {
+                        let _ = responses.send((answer, resolver));
+                    }
+                    Ok(Err(e)) => debug!("DoQ query to {} failed: {}", resolver, e),
+                    Err(_) => debug!("DoQ query to {} timed out", resolver),
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn connect(&self) -> Result<SharedConnection, slipstream_quic::Error> {
+        let sessions = self.sessions.clone();
+        let make_client = move || {
+            let config = QuicConfig::new()
+                .with_alpn(&[DOQ_ALPN])
+                .with_multipath(false)
+                .with_verify_cert_chain(true)
+                .with_early_data(true)
+                .with_keep_alive(DOQ_KEEP_ALIVE)
+                .with_handshake_timeout(DOQ_TIMEOUT);
+            Ok(Client::new(config)?.with_session_store(sessions))
+        };
+        let connecting =
+            SharedConnection::connect(make_client, self.connect_addr, &self.server_name);
+        tokio::time::timeout(DOQ_TIMEOUT, connecting)
+            .await
+            .map_err(|_| slipstream_quic::Error::ConnectionClosed {
+                reason: "handshake timed out".to_string(),
+            })?
+    }
+}
+
+/// Send `query` on `stream` as RFC 9250 has it, with DNS ID 0 and a length
+/// in front, and read the answer, with the query's ID put back.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    let (Some(id), Ok(len)) = (query.get(..2), u16::try_from(query.len())) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "query does not fit a DoQ message",
+        ));
+    };
+    let mut framed = Vec::with_capacity(2 + query.len());
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(&[0, 0]);
+    framed.extend_from_slice(&query[2..]);
+    stream.write_all(&framed).await?;
+    // The end of the stream tells the resolver the query is complete
+    stream.shutdown().await?;
+
+    let len = stream.read_u16().await? as usize;
+    let mut answer = vec![0u8; len];
+    stream.read_exact(&mut answer).await?;
+    if answer.len() < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "answer shorter than a DNS header",
+        ));
+    }
+    answer[..2].copy_from_slice(id);
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::exchange;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn queries_carry_id_zero_and_answers_get_theirs_back() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (mut ours, mut theirs) = tokio::io::duplex(1024);
+            let resolver = async move {
+                let mut query = Vec::new();
+                theirs.read_to_end(&mut query).await.unwrap();
+                assert_eq!(query, b"\x00\x05\x00\x00abc");
+                theirs.write_all(b"\x00\x04\x00\x00xy").await.unwrap();
+            };
+            let (answer, ()) = tokio::join!(exchange(&mut ours, b"\x12\x34abc"), resolver);
+            assert_eq!(answer.unwrap(), b"\x12\x34xy");
+
+            let (mut ours, _theirs) = tokio::io::duplex(1024);
+            assert!(exchange(&mut ours, b"\x12").await.is_err());
+        });
+    }
+}
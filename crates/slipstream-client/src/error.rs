@@ -1,16 +1,45 @@
+use slipstream_core::exit::{EXIT_AUTH, EXIT_CONFIG, EXIT_HANDSHAKE, EXIT_RESOLVER, EXIT_RUNTIME};
 use std::fmt;
 
 #[derive(Debug)]
 pub struct ClientError {
     message: String,
+    exit_code: i32,
 }
 
 impl ClientError {
+    /// A runtime failure.
     pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self::with_exit_code(EXIT_RUNTIME, message)
+    }
+
+    pub(crate) fn config(message: impl Into<String>) -> Self {
+        Self::with_exit_code(EXIT_CONFIG, message)
+    }
+
+    pub(crate) fn handshake(message: impl Into<String>) -> Self {
+        Self::with_exit_code(EXIT_HANDSHAKE, message)
+    }
+
+    pub(crate) fn resolver(message: impl Into<String>) -> Self {
+        Self::with_exit_code(EXIT_RESOLVER, message)
+    }
+
+    pub(crate) fn auth(message: impl Into<String>) -> Self {
+        Self::with_exit_code(EXIT_AUTH, message)
+    }
+
+    fn with_exit_code(exit_code: i32, message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
+            exit_code,
         }
     }
+
+    /// Process exit code for this failure, see [`slipstream_core::exit`].
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
 }
 
 impl fmt::Display for ClientError {
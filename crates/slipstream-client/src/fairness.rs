@@ -0,0 +1,126 @@
+//! Fair sharing of the send path between streams.
+//!
+//! tquic sends buffered stream data in stream order, so whatever is handed to
+//! it first goes out first. Writing each stream's backlog as its TCP data
+//! arrives would let one bulk transfer fill the congestion window ahead of
+//! interactive streams. Instead, pending data is handed over in deficit
+//! round-robin order: every backlogged stream earns a quantum of bytes per round
+//! and the rounds stop once the loop's budget is spent.
+
+use std::collections::{HashMap, VecDeque};
+
+/// Bytes each backlogged stream earns per round.
+pub(crate) const STREAM_WRITE_QUANTUM: usize = 4096;
+
+#[derive(Debug)]
+pub(crate) struct WriteScheduler {
+    quantum: usize,
+    /// Round-robin order; the front is served first on the next call.
+    order: VecDeque<u64>,
+    /// Credit a stream earned but could not use within the budget.
+    deficits: HashMap<u64, usize>,
+}
+
+impl WriteScheduler {
+    pub(crate) fn new(quantum: usize) -> Self {
+        Self {
+            quantum: quantum.max(1),
+            order: VecDeque::new(),
+            deficits: HashMap::new(),
+        }
+    }
+
+    /// Split `budget` bytes between streams with pending data.
+    ///
+    /// `backlogs` holds each stream's pending byte count. At least one quantum
+    /// is handed out, so streams keep moving while the window is reported full.
+    /// Returns how many bytes to write on each stream, in the order to write.
+    pub(crate) fn plan(&mut self, backlogs: &[(u64, usize)], budget: usize) -> Vec<(u64, usize)> {
+        let mut pending: HashMap<u64, usize> = backlogs
+            .iter()
+            .filter(|(_, len)| *len > 0)
+            .copied()
+            .collect();
+        self.order
+            .retain(|stream_id| pending.contains_key(stream_id));
+        self.deficits
+            .retain(|stream_id, _| pending.contains_key(stream_id));
+        for (stream_id, _) in backlogs {
+            if pending.contains_key(stream_id) && !self.order.contains(stream_id) {
+                self.order.push_back(*stream_id);
+            }
+        }
+
+        let mut grants: Vec<(u64, usize)> = Vec::new();
+        let mut budget = budget.max(self.quantum);
+        while budget > 0 && !pending.is_empty() {
+            let mut served = false;
+            for _ in 0..self.order.len() {
+                let Some(stream_id) = self.order.pop_front() else {
+                    break;
+                };
+                self.order.push_back(stream_id);
+                let Some(left) = pending.get_mut(&stream_id) else {
+                    continue;
+                };
+                let deficit = self.deficits.entry(stream_id).or_insert(0);
+                *deficit += self.quantum;
+                let grant = (*deficit).min(*left).min(budget);
+                if grant == 0 {
+                    continue;
+                }
+                *deficit -= grant;
+                *left -= grant;
+                budget -= grant;
+                served = true;
+                match grants.iter_mut().find(|(id, _)| *id == stream_id) {
+                    Some((_, total)) => *total += grant,
+                    None => grants.push((stream_id, grant)),
+                }
+                if *left == 0 {
+                    // An emptied queue keeps no credit (standard DRR)
+                    pending.remove(&stream_id);
+                    self.deficits.remove(&stream_id);
+                }
+                if budget == 0 {
+                    break;
+                }
+            }
+            if !served {
+                break;
+            }
+        }
+        grants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteScheduler;
+
+    #[test]
+    fn bulk_stream_does_not_starve_small_ones() {
+        let mut scheduler = WriteScheduler::new(1_000);
+        let grants = scheduler.plan(&[(0, 100_000), (4, 300), (8, 2_500)], 4_000);
+        let total = |id| {
+            grants
+                .iter()
+                .filter(|(stream_id, _)| *stream_id == id)
+                .map(|(_, len)| len)
+                .sum::<usize>()
+        };
+        assert_eq!(total(4), 300);
+        assert_eq!(total(0), 2_000);
+        assert_eq!(total(8), 1_700);
+        assert_eq!(grants.iter().map(|(_, len)| len).sum::<usize>(), 4_000);
+    }
+
+    #[test]
+    fn rotates_between_calls() {
+        let mut scheduler = WriteScheduler::new(1_000);
+        let first = scheduler.plan(&[(0, 10_000), (4, 10_000)], 1_000);
+        let second = scheduler.plan(&[(0, 10_000), (4, 10_000)], 1_000);
+        assert_eq!(first, vec![(0, 1_000)]);
+        assert_eq!(second, vec![(4, 1_000)]);
+    }
+}
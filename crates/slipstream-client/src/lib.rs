@@ -0,0 +1,749 @@
+//! Slipstream DNS tunnel client.
+//!
+//! The `slipstream-client` binary and the combined `slipstream client` subcommand
+//! both parse arguments with [`command`] and hand the matches to [`run`].
+
+mod dns;
+mod doh;
+mod doq;
+mod error;
+mod fairness;
+mod pacing;
+mod runtime;
+mod schedule;
+mod self_test;
+mod streams;
+mod tcp;
+
+use clap::builder::PossibleValuesParser;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use slipstream_core::domain::check_domain_resolvers;
+use slipstream_core::exit::EXIT_CONFIG;
+use slipstream_core::{
+    normalize_domain, parse_host_port, AddressKind, HostPort, QnameEncoding, ResolverMode,
+    ResolverSpec,
+};
+use slipstream_dns::RR_TXT;
+use slipstream_quic::{CONGESTION_CONTROL_NAMES, SCHEDULER_NAMES};
+use std::path::PathBuf;
+use tokio::runtime::Builder;
+
+use dns::RECORD_TYPES;
+use runtime::{run_client, TquicClientConfig};
+use self_test::SelfTestArgs;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slipstream-client",
+    about = "slipstream-client - A high-performance covert channel over DNS (client)",
+    group(
+        ArgGroup::new("resolvers")
+            .required(true)
+            .multiple(true)
+            .args(["resolver", "authoritative"])
+    )
+)]
+struct Args {
+    #[arg(long = "tcp-listen-port", short = 'l', default_value_t = 5201)]
+    tcp_listen_port: u16,
+    #[arg(long = "resolver", short = 'r', value_parser = parse_resolver)]
+    resolver: Vec<HostPort>,
+    #[arg(
+        long = "congestion-control",
+        short = 'c',
+        value_parser = PossibleValuesParser::new(CONGESTION_CONTROL_NAMES.iter().copied())
+    )]
+    congestion_control: Option<String>,
+    #[arg(
+        long = "multipath-scheduler",
+        value_parser = PossibleValuesParser::new(SCHEDULER_NAMES.iter().copied())
+    )]
+    multipath_scheduler: Option<String>,
+    #[arg(long = "no-multipath", conflicts_with = "multipath_scheduler")]
+    no_multipath: bool,
+    #[arg(long = "authoritative", value_parser = parse_resolver)]
+    authoritative: Vec<HostPort>,
+    #[arg(long = "standby-resolver", value_parser = parse_resolver)]
+    standby_resolver: Vec<HostPort>,
+    #[arg(
+        short = 'g',
+        long = "gso",
+        num_args = 0..=1,
+        default_value_t = false,
+        default_missing_value = "true"
+    )]
+    gso: bool,
+    #[arg(long = "domain", short = 'd', value_parser = parse_domain)]
+    domain: String,
+    #[arg(
+        long = "record-type",
+        value_name = "TYPE",
+        default_value = "txt",
+        value_parser = parse_record_type
+    )]
+    record_type: u16,
+    #[arg(
+        long = "resolver-record-type",
+        value_name = "RESOLVER=TYPE",
+        value_parser = parse_resolver_record_type
+    )]
+    resolver_record_types: Vec<(HostPort, u16)>,
+    #[arg(
+        long = "qname-encoding",
+        value_name = "ENCODING",
+        default_value = "base32",
+        value_parser = parse_qname_encoding
+    )]
+    qname_encoding: QnameEncoding,
+    #[arg(
+        long = "resolver-qname-encoding",
+        value_name = "RESOLVER=ENCODING",
+        value_parser = parse_resolver_qname_encoding
+    )]
+    resolver_qname_encodings: Vec<(HostPort, QnameEncoding)>,
+    #[arg(
+        long = "resolver-doh",
+        value_name = "RESOLVER=URL",
+        value_parser = parse_resolver_doh
+    )]
+    resolver_doh: Vec<(HostPort, String)>,
+    #[arg(
+        long = "resolver-dot",
+        value_name = "RESOLVER=NAME",
+        value_parser = parse_resolver_server
+    )]
+    resolver_dot: Vec<(HostPort, HostPort)>,
+    #[arg(
+        long = "resolver-doq",
+        value_name = "RESOLVER=NAME",
+        value_parser = parse_resolver_server
+    )]
+    resolver_doq: Vec<(HostPort, HostPort)>,
+    #[arg(
+        long = "edns-udp-size",
+        value_name = "BYTES",
+        default_value_t = slipstream_dns::EDNS_UDP_PAYLOAD,
+        value_parser = clap::value_parser!(u16).range(512..)
+    )]
+    edns_udp_size: u16,
+    #[arg(long = "probe-sizes")]
+    probe_sizes: bool,
+    #[arg(long = "nonce-label")]
+    nonce_label: bool,
+    #[arg(long = "cert", value_name = "PATH")]
+    cert: Option<String>,
+    #[arg(long = "keep-alive-interval", short = 't', default_value_t = 400)]
+    keep_alive_interval: u16,
+    #[arg(
+        long = "handshake-timeout",
+        value_name = "SECONDS",
+        default_value_t = 10
+    )]
+    handshake_timeout: u64,
+    #[arg(long = "dead-peer-timeout", default_value_t = 0)]
+    dead_peer_timeout: u64,
+    #[arg(long = "query-log", value_name = "PATH")]
+    query_log: Option<PathBuf>,
+    #[arg(long = "qlog-dir", value_name = "DIR")]
+    qlog_dir: Option<PathBuf>,
+    #[arg(long = "session-dir", value_name = "DIR")]
+    session_dir: Option<PathBuf>,
+    #[arg(long = "zero-rtt", requires = "session_dir")]
+    zero_rtt: bool,
+    #[arg(long = "debug-poll")]
+    debug_poll: bool,
+    #[arg(long = "debug-streams")]
+    debug_streams: bool,
+    #[arg(long = "fail-fast")]
+    fail_fast: bool,
+    #[arg(
+        long = "heartbeat-interval",
+        value_name = "SECONDS",
+        default_value_t = 0
+    )]
+    heartbeat_interval: u64,
+    #[arg(long = "shutdown-grace", value_name = "SECONDS", default_value_t = 5)]
+    shutdown_grace: u64,
+}
+
+/// Command-line definition for the client.
+pub fn command() -> clap::Command {
+    Args::command()
+        .subcommand(SelfTestArgs::command().name("self-test"))
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+}
+
+/// Run the client with parsed arguments and return the process exit code.
+pub fn run(matches: &ArgMatches) -> i32 {
+    if let Some(("self-test", matches)) = matches.subcommand() {
+        let args = SelfTestArgs::from_arg_matches(matches).unwrap_or_else(|err| err.exit());
+        return self_test::run(args);
+    }
+    let args = Args::from_arg_matches(matches).unwrap_or_else(|err| err.exit());
+    let resolvers = match build_resolvers(matches) {
+        Ok(resolvers) => resolvers,
+        Err(err) => {
+            tracing::error!("Resolver error: {}", err);
+            return EXIT_CONFIG;
+        }
+    };
+    if let Err(err) = check_domain_resolvers(&args.domain, &resolvers) {
+        tracing::error!("Resolver error: {}", err);
+        return EXIT_CONFIG;
+    }
+    if args.no_multipath && resolvers.len() > 1 {
+        tracing::error!("Resolver error: --no-multipath allows only one resolver");
+        return EXIT_CONFIG;
+    }
+
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+
+    let config = TquicClientConfig {
+        tcp_listen_port: args.tcp_listen_port,
+        resolvers: &resolvers,
+        domain: &args.domain,
+        edns_udp_payload: args.edns_udp_size,
+        probe_sizes: args.probe_sizes,
+        nonce_label: args.nonce_label,
+        cert: args.cert.as_deref(),
+        congestion_control: args.congestion_control.as_deref(),
+        multipath: !args.no_multipath,
+        multipath_scheduler: args.multipath_scheduler.as_deref(),
+        gso: args.gso,
+        keep_alive_interval: args.keep_alive_interval as usize,
+        dead_peer_timeout: args.dead_peer_timeout,
+        query_log: args.query_log.as_deref(),
+        qlog_dir: args.qlog_dir.as_deref(),
+        session_dir: args.session_dir.as_deref(),
+        zero_rtt: args.zero_rtt,
+        debug_poll: args.debug_poll,
+        debug_streams: args.debug_streams,
+        fail_fast: args.fail_fast,
+        heartbeat_interval: args.heartbeat_interval,
+        shutdown_grace: args.shutdown_grace,
+        handshake_timeout: args.handshake_timeout,
+    };
+    match runtime.block_on(run_client(&config)) {
+        Ok(code) => code,
+        Err(err) => {
+            tracing::error!("Client error: {}", err);
+            err.exit_code()
+        }
+    }
+}
+
+fn parse_domain(input: &str) -> Result<String, String> {
+    normalize_domain(input).map_err(|err| err.to_string())
+}
+
+fn parse_record_type(input: &str) -> Result<u16, String> {
+    RECORD_TYPES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(input))
+        .map(|(_, record_type)| *record_type)
+        .ok_or_else(|| {
+            let names: Vec<&str> = RECORD_TYPES.iter().map(|(name, _)| *name).collect();
+            format!(
+                "Unknown record type '{}' (expected one of {})",
+                input,
+                names.join(", ")
+            )
+        })
+}
+
+fn parse_resolver_record_type(input: &str) -> Result<(HostPort, u16), String> {
+    let (resolver, record_type) = input
+        .rsplit_once('=')
+        .ok_or_else(|| "Expected RESOLVER=TYPE".to_string())?;
+    Ok((parse_resolver(resolver)?, parse_record_type(record_type)?))
+}
+
+fn parse_qname_encoding(input: &str) -> Result<QnameEncoding, String> {
+    QnameEncoding::ALL
+        .into_iter()
+        .find(|encoding| encoding.name().eq_ignore_ascii_case(input))
+        .ok_or_else(|| {
+            let names: Vec<&str> = QnameEncoding::ALL.iter().map(|e| e.name()).collect();
+            format!(
+                "Unknown qname encoding '{}' (expected one of {})",
+                input,
+                names.join(", ")
+            )
+        })
+}
+
+fn parse_resolver_qname_encoding(input: &str) -> Result<(HostPort, QnameEncoding), String> {
+    let (resolver, encoding) = input
+        .rsplit_once('=')
+        .ok_or_else(|| "Expected RESOLVER=ENCODING".to_string())?;
+    Ok((parse_resolver(resolver)?, parse_qname_encoding(encoding)?))
+}
+
+fn parse_resolver_doh(input: &str) -> Result<(HostPort, String), String> {
+    let (resolver, url) = input
+        .split_once('=')
+        .ok_or_else(|| "Expected RESOLVER=URL".to_string())?;
+    doh::DohUrl::parse(url)?;
+    Ok((parse_resolver(resolver)?, url.to_string()))
+}
+
+/// `RESOLVER=NAME[:PORT]`, for the transports whose servers listen on 853.
+fn parse_resolver_server(input: &str) -> Result<(HostPort, HostPort), String> {
+    let (resolver, server) = input
+        .split_once('=')
+        .ok_or_else(|| "Expected RESOLVER=NAME[:PORT]".to_string())?;
+    let server =
+        parse_host_port(server, 853, AddressKind::Resolver).map_err(|err| err.to_string())?;
+    Ok((parse_resolver(resolver)?, server))
+}
+
+fn parse_resolver(input: &str) -> Result<HostPort, String> {
+    parse_host_port(input, 53, AddressKind::Resolver).map_err(|err| err.to_string())
+}
+
+fn build_resolvers(matches: &clap::ArgMatches) -> Result<Vec<ResolverSpec>, String> {
+    let mut ordered = Vec::new();
+    collect_resolvers(
+        matches,
+        "resolver",
+        ResolverMode::Recursive,
+        false,
+        &mut ordered,
+    )?;
+    collect_resolvers(
+        matches,
+        "authoritative",
+        ResolverMode::Authoritative,
+        false,
+        &mut ordered,
+    )?;
+    collect_resolvers(
+        matches,
+        "standby_resolver",
+        ResolverMode::Recursive,
+        true,
+        &mut ordered,
+    )?;
+    if ordered.is_empty() {
+        return Err("At least one resolver is required".to_string());
+    }
+    ordered.sort_by_key(|(idx, _)| *idx);
+    if ordered[0].1.standby {
+        return Err("The first resolver cannot be a standby resolver".to_string());
+    }
+    let mut resolvers: Vec<ResolverSpec> = ordered.into_iter().map(|(_, spec)| spec).collect();
+    apply_record_types(matches, &mut resolvers)?;
+    apply_qname_encodings(matches, &mut resolvers)?;
+    apply_doh_urls(matches, &mut resolvers)?;
+    apply_dot_servers(matches, &mut resolvers)?;
+    apply_doq_servers(matches, &mut resolvers)?;
+    Ok(resolvers)
+}
+
+/// Give each resolver the `--record-type` default unless
+/// `--resolver-record-type` names it.
+fn apply_record_types(
+    matches: &clap::ArgMatches,
+    resolvers: &mut [ResolverSpec],
+) -> Result<(), String> {
+    let default = matches
+        .get_one::<u16>("record_type")
+        .copied()
+        .unwrap_or(RR_TXT);
+    for spec in resolvers.iter_mut() {
+        spec.record_type = default;
+    }
+    let overrides = matches
+        .get_many::<(HostPort, u16)>("resolver_record_types")
+        .into_iter()
+        .flatten();
+    for (resolver, record_type) in overrides {
+        find_resolver_spec(resolvers, resolver, "Record type")?.record_type = *record_type;
+    }
+    Ok(())
+}
+
+/// Give each resolver the `--qname-encoding` default unless
+/// `--resolver-qname-encoding` names it.
+fn apply_qname_encodings(
+    matches: &clap::ArgMatches,
+    resolvers: &mut [ResolverSpec],
+) -> Result<(), String> {
+    let default = matches
+        .get_one::<QnameEncoding>("qname_encoding")
+        .copied()
+        .unwrap_or_default();
+    for spec in resolvers.iter_mut() {
+        spec.qname_encoding = default;
+    }
+    let overrides = matches
+        .get_many::<(HostPort, QnameEncoding)>("resolver_qname_encodings")
+        .into_iter()
+        .flatten();
+    for (resolver, encoding) in overrides {
+        find_resolver_spec(resolvers, resolver, "Qname encoding")?.qname_encoding = *encoding;
+    }
+    Ok(())
+}
+
+/// Send the queries of the resolvers `--resolver-doh` names over DoH.
+fn apply_doh_urls(
+    matches: &clap::ArgMatches,
+    resolvers: &mut [ResolverSpec],
+) -> Result<(), String> {
+    let overrides = matches
+        .get_many::<(HostPort, String)>("resolver_doh")
+        .into_iter()
+        .flatten();
+    for (resolver, url) in overrides {
+        find_resolver_spec(resolvers, resolver, "DoH URL")?.doh_url = Some(url.clone());
+    }
+    Ok(())
+}
+
+/// Send the queries of the resolvers `--resolver-dot` names over DoT.
+fn apply_dot_servers(
+    matches: &clap::ArgMatches,
+    resolvers: &mut [ResolverSpec],
+) -> Result<(), String> {
+    let overrides = matches
+        .get_many::<(HostPort, HostPort)>("resolver_dot")
+        .into_iter()
+        .flatten();
+    for (resolver, server) in overrides {
+        let spec = find_resolver_spec(resolvers, resolver, "DoT server")?;
+        check_single_transport(spec)?;
+        spec.dot_server = Some(server.clone());
+    }
+    Ok(())
+}
+
+/// Send the queries of the resolvers `--resolver-doq` names over DoQ.
+fn apply_doq_servers(
+    matches: &clap::ArgMatches,
+    resolvers: &mut [ResolverSpec],
+) -> Result<(), String> {
+    let overrides = matches
+        .get_many::<(HostPort, HostPort)>("resolver_doq")
+        .into_iter()
+        .flatten();
+    for (resolver, server) in overrides {
+        let spec = find_resolver_spec(resolvers, resolver, "DoQ server")?;
+        check_single_transport(spec)?;
+        spec.doq_server = Some(server.clone());
+    }
+    Ok(())
+}
+
+/// Fail if `spec` was already given a transport other than UDP.
+fn check_single_transport(spec: &ResolverSpec) -> Result<(), String> {
+    if spec.doh_url.is_some() || spec.dot_server.is_some() || spec.doq_server.is_some() {
+        return Err(format!(
+            "More than one of DoH, DoT and DoQ set for {}:{}",
+            spec.resolver.host, spec.resolver.port
+        ));
+    }
+    Ok(())
+}
+
+/// The resolver a per-resolver option names, as given on the command line.
+fn find_resolver_spec<'a>(
+    resolvers: &'a mut [ResolverSpec],
+    resolver: &HostPort,
+    setting: &str,
+) -> Result<&'a mut ResolverSpec, String> {
+    resolvers
+        .iter_mut()
+        .find(|spec| {
+            spec.resolver.host.eq_ignore_ascii_case(&resolver.host)
+                && spec.resolver.port == resolver.port
+        })
+        .ok_or_else(|| {
+            format!(
+                "{} set for {}:{}, which is not a resolver",
+                setting, resolver.host, resolver.port
+            )
+        })
+}
+
+fn collect_resolvers(
+    matches: &clap::ArgMatches,
+    name: &str,
+    mode: ResolverMode,
+    standby: bool,
+    ordered: &mut Vec<(usize, ResolverSpec)>,
+) -> Result<(), String> {
+    let indices: Vec<usize> = matches.indices_of(name).into_iter().flatten().collect();
+    let values: Vec<HostPort> = matches
+        .get_many::<HostPort>(name)
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    if indices.len() != values.len() {
+        return Err(format!("Mismatched {} arguments", name));
+    }
+    for (idx, resolver) in indices.into_iter().zip(values) {
+        ordered.push((
+            idx,
+            ResolverSpec {
+                resolver,
+                mode,
+                standby,
+                record_type: RR_TXT,
+                qname_encoding: QnameEncoding::Base32,
+                doh_url: None,
+                dot_server: None,
+                doq_server: None,
+            },
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolver_record_types_override_default() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver",
+                "3.3.3.3:5353",
+                "--record-type",
+                "null",
+                "--resolver-record-type",
+                "3.3.3.3:5353=AAAA",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        assert_eq!(resolvers[0].record_type, slipstream_dns::RR_NULL);
+        assert_eq!(resolvers[1].record_type, slipstream_dns::RR_AAAA);
+
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver-record-type",
+                "8.8.8.8=a",
+            ])
+            .expect("matches should parse");
+        assert!(build_resolvers(&matches).is_err());
+    }
+
+    #[test]
+    fn resolver_qname_encodings_override_default() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--authoritative",
+                "3.3.3.3",
+                "--qname-encoding",
+                "base64url",
+                "--resolver-qname-encoding",
+                "3.3.3.3=binary",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        assert_eq!(resolvers[0].qname_encoding, QnameEncoding::Base64Url);
+        assert_eq!(resolvers[1].qname_encoding, QnameEncoding::Binary);
+    }
+
+    #[test]
+    fn resolver_doh_urls_select_the_transport() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver",
+                "8.8.8.8",
+                "--resolver-doh",
+                "8.8.8.8=https://dns.google/dns-query?x=1",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        assert_eq!(resolvers[0].doh_url, None);
+        assert_eq!(
+            resolvers[1].doh_url.as_deref(),
+            Some("https://dns.google/dns-query?x=1")
+        );
+
+        let parsed = Args::command().try_get_matches_from([
+            "slipstream-client",
+            "--domain",
+            "example.com",
+            "--resolver",
+            "1.1.1.1",
+            "--resolver-doh",
+            "1.1.1.1=http://cloudflare-dns.com/dns-query",
+        ]);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn resolver_dot_and_doq_servers_select_the_transport() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver",
+                "9.9.9.9",
+                "--resolver-dot",
+                "1.1.1.1=cloudflare-dns.com",
+                "--resolver-dot",
+                "9.9.9.9=dns.quad9.net:8853",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        let server = resolvers[0].dot_server.as_ref().unwrap();
+        assert_eq!(
+            (server.host.as_str(), server.port),
+            ("cloudflare-dns.com", 853)
+        );
+        let server = resolvers[1].dot_server.as_ref().unwrap();
+        assert_eq!((server.host.as_str(), server.port), ("dns.quad9.net", 8853));
+
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver-doh",
+                "1.1.1.1=https://cloudflare-dns.com/dns-query",
+                "--resolver-dot",
+                "1.1.1.1=cloudflare-dns.com",
+            ])
+            .expect("matches should parse");
+        assert!(build_resolvers(&matches).is_err());
+
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver",
+                "94.140.14.14",
+                "--resolver-doq",
+                "94.140.14.14=dns.adguard-dns.com",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        assert!(resolvers[0].doq_server.is_none());
+        let server = resolvers[1].doq_server.as_ref().unwrap();
+        assert_eq!(
+            (server.host.as_str(), server.port),
+            ("dns.adguard-dns.com", 853)
+        );
+    }
+
+    #[test]
+    fn preserves_ordered_resolvers() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--authoritative",
+                "2.2.2.2",
+                "--resolver",
+                "3.3.3.3:5353",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        assert_eq!(resolvers.len(), 3);
+        assert_eq!(resolvers[0].resolver.host, "1.1.1.1");
+        assert_eq!(resolvers[0].resolver.port, 53);
+        assert_eq!(resolvers[0].mode, ResolverMode::Recursive);
+        assert_eq!(resolvers[1].resolver.host, "2.2.2.2");
+        assert_eq!(resolvers[1].mode, ResolverMode::Authoritative);
+        assert_eq!(resolvers[2].resolver.host, "3.3.3.3");
+        assert_eq!(resolvers[2].resolver.port, 5353);
+    }
+
+    #[test]
+    fn maps_authoritative_first() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--authoritative",
+                "8.8.8.8",
+                "--resolver",
+                "9.9.9.9",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches).expect("resolvers should parse");
+        assert_eq!(resolvers.len(), 2);
+        assert_eq!(resolvers[0].resolver.host, "8.8.8.8");
+        assert_eq!(resolvers[0].mode, ResolverMode::Authoritative);
+        assert_eq!(resolvers[1].resolver.host, "9.9.9.9");
+        assert_eq!(resolvers[1].mode, ResolverMode::Recursive);
+    }
+
+    #[test]
+    fn standby_resolvers_follow_a_primary() {
+        let parse = |args: &[&str]| {
+            let matches = Args::command()
+                .try_get_matches_from(
+                    ["slipstream-client", "--domain", "example.com"]
+                        .iter()
+                        .chain(args),
+                )
+                .expect("matches should parse");
+            build_resolvers(&matches)
+        };
+        let resolvers = parse(&["--resolver", "1.1.1.1", "--standby-resolver", "9.9.9.9"])
+            .expect("resolvers should parse");
+        assert!(!resolvers[0].standby);
+        assert!(resolvers[1].standby);
+        assert_eq!(resolvers[1].mode, ResolverMode::Recursive);
+
+        assert!(parse(&["--standby-resolver", "9.9.9.9", "--resolver", "1.1.1.1"]).is_err());
+    }
+
+    #[test]
+    fn no_multipath_conflicts_with_scheduler() {
+        let result = Args::command().try_get_matches_from([
+            "slipstream-client",
+            "--domain",
+            "example.com",
+            "--resolver",
+            "1.1.1.1",
+            "--no-multipath",
+            "--multipath-scheduler",
+            "minrtt",
+        ]);
+        assert!(result.is_err());
+    }
+}
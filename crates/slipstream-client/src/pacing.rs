@@ -97,6 +97,11 @@ pub(crate) fn cwnd_target_polls(cwin: u64, mtu: u32) -> usize {
     usize::try_from(target).unwrap_or(usize::MAX)
 }
 
+/// Polls needed to drain a server-reported backlog, one response packet per poll.
+pub(crate) fn backlog_target_polls(backlog_bytes: u64, mtu: u32) -> usize {
+    inflight_packet_estimate(backlog_bytes, mtu)
+}
+
 pub(crate) fn inflight_packet_estimate(bytes_in_transit: u64, mtu: u32) -> usize {
     debug_assert!(mtu > 0, "mtu must be > 0");
     let mtu = mtu as u64;
@@ -0,0 +1,88 @@
+//! Client side of the heartbeat exchange.
+//!
+//! The client drives the exchange: it writes a heartbeat every interval on a
+//! unidirectional stream of its own and reads the server's answers from the
+//! server's next control stream. See [`slipstream_core::heartbeat`].
+
+use slipstream_core::heartbeat::{HeartbeatEvent, HeartbeatMonitor, HeartbeatReader};
+use slipstream_quic::{ClientConnection, Error as QuicError};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub(crate) struct HeartbeatStream {
+    stream_id: u64,
+    monitor: HeartbeatMonitor,
+    reader: HeartbeatReader,
+    next_at: Instant,
+}
+
+impl HeartbeatStream {
+    /// Open the heartbeat stream; the first heartbeat goes out on the next tick.
+    pub(crate) fn open(
+        conn: &mut ClientConnection,
+        interval: Duration,
+        now: Instant,
+    ) -> Result<Self, QuicError> {
+        let stream_id = conn.open_uni()?;
+        info!("Sending heartbeats every {} ms", interval.as_millis());
+        Ok(Self {
+            stream_id,
+            monitor: HeartbeatMonitor::new(interval, now),
+            reader: HeartbeatReader::new(),
+            next_at: now,
+        })
+    }
+
+    /// When the next heartbeat is due.
+    pub(crate) fn next_at(&self) -> Instant {
+        self.next_at
+    }
+
+    /// Send a heartbeat if one is due and report stalls and recoveries.
+    pub(crate) fn tick(
+        &mut self,
+        conn: &mut ClientConnection,
+        tx_bytes: u64,
+        rx_bytes: u64,
+        now: Instant,
+    ) {
+        if now >= self.next_at {
+            let heartbeat = self.monitor.next(tx_bytes, rx_bytes);
+            if let Err(e) = conn.stream_write(self.stream_id, &heartbeat.encode(), false) {
+                warn!("Failed to send heartbeat: {}", e);
+            }
+            self.next_at = now + self.monitor.interval();
+        }
+        match self.monitor.check(tx_bytes, now) {
+            Some(HeartbeatEvent::Stalled(stall)) => warn!("Heartbeat: {}", stall),
+            Some(HeartbeatEvent::Recovered) => info!("Heartbeats recovered"),
+            None => {}
+        }
+    }
+
+    /// Read the server's heartbeats from its control stream.
+    pub(crate) fn on_control_stream(
+        &mut self,
+        conn: &mut ClientConnection,
+        stream_id: u64,
+        now: Instant,
+    ) {
+        let mut read_buf = [0u8; 512];
+        loop {
+            match conn.stream_read(stream_id, &mut read_buf) {
+                Ok((n, fin)) => {
+                    for heartbeat in self.reader.push(&read_buf[..n]) {
+                        self.monitor.on_heartbeat(heartbeat, now);
+                    }
+                    if fin || n == 0 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!("stream {}: heartbeat stream_read: {}", stream_id, e);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -4,38 +4,65 @@
 //! The tquic runtime is now the default (replacing the legacy picoquic FFI).
 
 // TODO(flow-control): The pending_data buffer approach works but is not optimal.
-//   - Should use stream_writable_iter() to check which streams can accept data
 //   - stream_capacity() consistently returns 0 but data still flows via tquic
-//   - Consider using on_stream_writable callback instead of polling
 //   - Need to properly acknowledge received data to open flow control window
 
+mod heartbeat;
 mod path;
+mod shutdown;
 
+use self::heartbeat::HeartbeatStream;
 use self::path::{
-    apply_path_mode_tquic, drain_path_events_tquic, fetch_path_quality_tquic,
-    find_resolver_by_addr_mut, loop_burst_total,
+    apply_path_mode_tquic, configure_path_tquic, drain_path_events_tquic, fetch_path_quality_tquic,
+    find_resolver_by_addr_mut, handle_path_event_tquic, loop_burst_total, path_poll_burst_max,
+    primary_path_available, promote_next_resolver, reprobe_resolvers_tquic,
+    update_path_quality_tquic,
 };
-use crate::dns::{expire_inflight_polls, normalize_dual_stack_addr, resolve_resolvers};
+use self::shutdown::{signal_count, spawn_signal_listener};
+use crate::dns::{
+    expire_inflight_polls, maybe_report_debug, normalize_dual_stack_addr, record_type_name,
+    reset_resolver_path, resolve_resolvers, FragmentInfo, QueryLog, ResolverState, SizeProbe,
+};
+use crate::doh::{DohTransport, DohUrl};
+use crate::doq::DoqTransport;
 use crate::error::ClientError;
-use crate::pacing::{cwnd_target_polls, inflight_packet_estimate};
+use crate::fairness::{WriteScheduler, STREAM_WRITE_QUANTUM};
+use crate::pacing::{backlog_target_polls, cwnd_target_polls, inflight_packet_estimate};
+use crate::schedule::PacketScheduler;
 use crate::streams::{spawn_acceptor, Command};
-use slipstream_core::ResolverMode;
+use crate::tcp::TcpTransport;
+use slipstream_core::capabilities::{
+    is_control_stream, Capabilities, ALPN_LEGACY, ALPN_SLIPSTREAM, MAX_CAPABILITIES_LEN,
+};
+use slipstream_core::logging::suppressed_log_messages;
+use slipstream_core::{
+    log_limited, ResolverMode, SLIPSTREAM_PATH_UNRESPONSIVE_ERROR,
+    SLIPSTREAM_PROTOCOL_VERSION_ERROR,
+};
 use slipstream_dns::{
-    build_qname, decode_response, encode_query, fragment_packet, is_fragmented,
-    max_payload_len_for_domain, FragmentBuffer, QueryParams, CLASS_IN, RR_TXT,
+    build_qname_with_codec, build_qname_with_nonce, decode_backlog_hint, decode_response,
+    encode_query, fragment_packet, is_fragmented, is_truncated, max_payload_len_with_codec,
+    max_payload_len_with_nonce, poll_payload, qname_codec, response_rcode, FragmentBuffer,
+    QnameEncoding, QueryParams, Rcode, CLASS_IN, RR_TXT,
 };
-use slipstream_quic::{Client, ClientConnection, Config as QuicConfig};
-use std::collections::HashMap;
+use slipstream_quic::{
+    parse_congestion_control, parse_scheduler, Client, ClientConnection, CloseCause,
+    Config as QuicConfig, ConnectionEvent, Error as QuicError, FileSessionStore, StreamTable,
+};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener as TokioTcpListener, UdpSocket};
 use tokio::sync::{mpsc, Notify};
 use tokio::time::sleep;
-use tracing::{debug, info, trace, warn};
+use tracing::{debug, info, warn, Level};
 
 // Protocol defaults matching picoquic runtime
 const DNS_WAKE_DELAY_MAX_US: u64 = 10_000_000;
 const DNS_POLL_SLICE_US: u64 = 50_000;
+/// Shortest poll slice, however fast the pacer would let packets go.
+const DNS_POLL_SLICE_MIN_US: u64 = 1_000;
 const MAX_PACKET_SIZE: usize = 1500;
 const PACKET_LOOP_SEND_MAX: usize = 64;
 const PACKET_LOOP_RECV_MAX: usize = 64;
@@ -46,23 +73,51 @@ pub struct TquicClientConfig<'a> {
     pub tcp_listen_port: u16,
     pub resolvers: &'a [slipstream_core::ResolverSpec],
     pub domain: &'a str,
+    /// UDP payload size advertised in the EDNS0 OPT record of each query.
+    pub edns_udp_payload: u16,
+    /// Learn how large the queries and responses each resolver passes are,
+    /// instead of assuming the configured limits hold.
+    pub probe_sizes: bool,
+    /// Start every query name with a random nonce label.
+    pub nonce_label: bool,
     pub cert: Option<&'a str>,
     pub congestion_control: Option<&'a str>,
+    pub multipath: bool,
+    pub multipath_scheduler: Option<&'a str>,
     pub gso: bool,
     pub keep_alive_interval: usize,
+    pub dead_peer_timeout: u64,
+    pub query_log: Option<&'a Path>,
+    pub qlog_dir: Option<&'a Path>,
+    pub session_dir: Option<&'a Path>,
+    pub zero_rtt: bool,
     pub debug_poll: bool,
     pub debug_streams: bool,
+    /// Exit on the first resolver or connection error instead of recovering.
+    pub fail_fast: bool,
+    /// Seconds between heartbeats on the control stream; 0 disables them.
+    pub heartbeat_interval: u64,
+    /// Seconds open streams may take to drain after SIGINT or SIGTERM.
+    pub shutdown_grace: u64,
+    /// Seconds the handshake through one resolver may take before the next
+    /// is tried; 0 disables the timeout.
+    pub handshake_timeout: u64,
 }
 
 /// Stream state for tracking QUIC stream to TCP connection mapping.
+///
+/// Kept in a [`StreamTable`] keyed by stream ID and found through the label
+/// the stream carries.
 #[allow(dead_code)]
 struct StreamState {
     write_tx: mpsc::UnboundedSender<Vec<u8>>,
     queued_bytes: usize,
     rx_bytes: u64,
     tx_bytes: u64,
-    /// Pending data that couldn't be written due to flow control.
+    /// Data read from TCP that is waiting for its turn to be written to QUIC.
     pending_data: Vec<u8>,
+    /// The TCP side closed; FIN goes out once `pending_data` is written.
+    fin_pending: bool,
 }
 
 /// Run the client.
@@ -71,8 +126,24 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
     let mtu = compute_mtu(domain_len)?;
     let mut resolvers = resolve_resolvers(config.resolvers, mtu, config.debug_poll)?;
     if resolvers.is_empty() {
-        return Err(ClientError::new("At least one resolver is required"));
+        return Err(ClientError::config("At least one resolver is required"));
+    }
+    if config.probe_sizes {
+        for resolver in resolvers.iter_mut() {
+            let max_payload = max_query_payload(config, resolver.qname_encoding)?;
+            resolver.size_probe = Some(SizeProbe::new(max_payload, config.edns_udp_payload));
+        }
     }
+    let mut query_log = match config.query_log {
+        Some(path) => Some(QueryLog::open(path).map_err(|e| {
+            ClientError::config(format!(
+                "Failed to open query log {}: {}",
+                path.display(),
+                e
+            ))
+        })?),
+        None => None,
+    };
 
     // Bind UDP socket for DNS queries (use IPv6 dual-stack for compatibility with tquic)
     let udp = UdpSocket::bind("[::]:0")
@@ -81,6 +152,44 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
     let local_addr = udp
         .local_addr()
         .map_err(|e| ClientError::new(format!("Failed to get local addr: {}", e)))?;
+    // Answers from DoH, DoT, DoQ and TCP retries arrive here, tagged with the
+    // resolver address
+    let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+    let mut doh = HashMap::new();
+    let mut dot = HashMap::new();
+    let mut doq = HashMap::new();
+    for (spec, resolver) in config.resolvers.iter().zip(&resolvers) {
+        if let Some(url) = &spec.doh_url {
+            let url = DohUrl::parse(url).map_err(ClientError::config)?;
+            let transport = DohTransport::spawn(url, resolver.addr, stream_tx.clone())
+                .map_err(|e| ClientError::new(format!("Failed to start DoH transport: {}", e)))?;
+            doh.insert(resolver.addr, transport);
+        }
+        if let Some(server) = &spec.dot_server {
+            let transport = TcpTransport::spawn_tls(
+                &server.host,
+                server.port,
+                resolver.addr,
+                stream_tx.clone(),
+            )
+            .map_err(|e| ClientError::new(format!("Failed to start DoT transport: {}", e)))?;
+            dot.insert(resolver.addr, transport);
+        }
+        if let Some(server) = &spec.doq_server {
+            let transport =
+                DoqTransport::spawn(&server.host, server.port, resolver.addr, stream_tx.clone());
+            doq.insert(resolver.addr, transport);
+        }
+    }
+    let mut transports = QueryTransports {
+        udp,
+        doh,
+        dot,
+        doq,
+        tcp: HashMap::new(),
+        sent_udp: VecDeque::new(),
+        answers: stream_tx,
+    };
 
     // Setup TCP listener for incoming connections
     let (command_tx, mut command_rx) = mpsc::unbounded_channel();
@@ -89,13 +198,17 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
     let listener = TokioTcpListener::bind(("0.0.0.0", config.tcp_listen_port))
         .await
         .map_err(|e| ClientError::new(format!("Failed to bind TCP: {}", e)))?;
-    spawn_acceptor(listener, command_tx.clone());
+    let acceptor = spawn_acceptor(listener, command_tx.clone());
     info!("Listening on TCP port {}", config.tcp_listen_port);
+    spawn_signal_listener(data_notify.clone());
 
-    // Create tquic client config with multipath and DNS-appropriate packet size
+    // Create tquic client config with DNS-appropriate packet size
     let mut quic_config = QuicConfig::new()
-        .with_multipath(true)
-        .with_send_udp_payload_size(mtu as usize);
+        .with_multipath(config.multipath)
+        .with_fixed_udp_payload_size(mtu as usize)
+        .with_fragmentation(true)
+        .with_alpn(&[ALPN_SLIPSTREAM, ALPN_LEGACY])
+        .with_handshake_timeout(Duration::from_secs(config.handshake_timeout));
     if config.keep_alive_interval > 0 {
         quic_config =
             quic_config.with_keep_alive(Duration::from_millis(config.keep_alive_interval as u64));
@@ -106,18 +219,40 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
         quic_config = quic_config.with_ca(cert);
     }
 
-    // TODO: Add congestion control override for tquic
-    if config.congestion_control.is_some() {
-        warn!("Congestion control override not yet implemented for tquic runtime");
+    if let Some(name) = config.congestion_control {
+        let algo =
+            parse_congestion_control(name).map_err(|e| ClientError::config(e.to_string()))?;
+        quic_config = quic_config.with_congestion_control(algo);
+    }
+
+    if let Some(name) = config.multipath_scheduler {
+        let scheduler = parse_scheduler(name).map_err(|e| ClientError::config(e.to_string()))?;
+        quic_config = quic_config.with_scheduler(scheduler);
+    }
+
+    if let Some(dir) = config.qlog_dir {
+        quic_config = quic_config.with_qlog_dir(dir);
+    }
+    quic_config = quic_config.with_keylog_from_env();
+
+    if config.zero_rtt {
+        quic_config = quic_config.with_early_data(true);
     }
 
     if config.gso {
         warn!("GSO is not implemented in the tquic client runtime.");
     }
+    log_effective_config(config, &resolvers, mtu, &quic_config);
 
     // Create QUIC client
-    let client = Client::new(quic_config)
-        .map_err(|e| ClientError::new(format!("Failed to create QUIC client: {}", e)))?;
+    let mut client = Client::new(quic_config)
+        .map_err(|e| ClientError::config(format!("Failed to create QUIC client: {}", e)))?;
+    if let Some(dir) = config.session_dir {
+        let store = FileSessionStore::new(dir).map_err(|e| ClientError::config(e.to_string()))?;
+        client = client
+            .with_session_store(store.clone())
+            .with_token_store(store);
+    }
 
     // Connect to first resolver using domain as SNI
     let server_addr = resolvers[0].addr;
@@ -131,66 +266,238 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
     resolvers[0].added = true;
     resolvers[0].path_id_tquic = Some(0);
 
-    let mut dns_id = 1u16;
+    let mut query_seq = QuerySequence::new(config.nonce_label);
+    // Seeded from the clock so polls from successive runs don't repeat cached QNAMEs
+    let mut poll_nonce = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
     let mut packet_id = 0u16; // For fragment tracking
+    let mut primary_was_available = true;
+    // Resolvers the handshake was tried through, the current one included
+    let mut handshake_attempts = 1;
+    let primary_candidates = resolvers.iter().filter(|r| !r.standby).count();
     let mut recv_fragment_buffer = FragmentBuffer::new(); // For reassembling fragmented responses
     let mut recv_buf = vec![0u8; 4096];
     let _send_buf = vec![0u8; MAX_PACKET_SIZE];
     let packet_loop_send_max = loop_burst_total(&resolvers, PACKET_LOOP_SEND_MAX);
     let packet_loop_recv_max = loop_burst_total(&resolvers, PACKET_LOOP_RECV_MAX);
-    let mut streams: HashMap<u64, StreamState> = HashMap::new();
+    let mut streams: StreamTable<u64, StreamState> = StreamTable::new();
+    let mut write_scheduler = WriteScheduler::new(STREAM_WRITE_QUANTUM);
+    let mut packet_scheduler = PacketScheduler::default();
     let mut zero_send_loops = 0u64;
+    let mut last_lost_bytes = 0u64;
     let mut ready = false;
+    let mut capabilities_buf = Vec::new();
+    let mut capabilities: Option<Capabilities> = None;
+    let mut heartbeat: Option<HeartbeatStream> = None;
+    // Tunnel stream bytes, reported to the server in heartbeats
+    let mut tunnel_tx_bytes = 0u64;
+    let mut tunnel_rx_bytes = 0u64;
+    // Set once a shutdown signal arrived; streams drain until then
+    let mut drain_deadline: Option<Instant> = None;
 
     // Main event loop (mirrors picoquic runtime loop)
     loop {
-        // Check connection state
-        if conn.is_ready() && !ready {
-            ready = true;
-            info!("Connection ready");
-
-            // Add additional paths for multipath
-            for resolver in resolvers.iter_mut().skip(1) {
-                if !resolver.added {
-                    match conn.probe_path(resolver.addr) {
-                        Ok(path_id) => {
-                            resolver.path_id_tquic = Some(path_id);
-                            debug!("Probing path to {}", resolver.addr);
+        let current_time_us = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+
+        // Handle what tquic reported since the last pass
+        let mut closed = None;
+        for event in conn.poll_events() {
+            match event {
+                ConnectionEvent::Established => {
+                    ready = true;
+                    info!(
+                        "Connection ready (QUIC version {})",
+                        conn.quic_version()
+                            .map_or_else(|| "unknown".to_string(), |v| format!("{:#x}", v))
+                    );
+                    if let Some(params) = conn.peer_transport_params() {
+                        debug!("Server transport parameters: {}", params);
+                        if config.multipath && !params.multipath && resolvers.len() > 1 {
+                            warn!(
+                                "Server did not negotiate multipath; only {} will carry traffic",
+                                resolvers[0].addr
+                            );
                         }
-                        Err(e) => {
-                            warn!("Failed to probe path to {}: {}", resolver.addr, e);
+                    }
+
+                    if conn.alpn() == ALPN_SLIPSTREAM {
+                        let message = Capabilities::local().encode();
+                        let sent = conn
+                            .open_uni()
+                            .and_then(|stream_id| conn.stream_write(stream_id, &message, true));
+                        if let Err(e) = sent {
+                            warn!("Failed to send capabilities: {}", e);
+                        }
+                    } else {
+                        info!("Server uses the legacy ALPN; assuming baseline capabilities");
+                        capabilities = Some(Capabilities::baseline());
+                    }
+
+                    // Add additional paths for multipath
+                    for resolver in resolvers.iter_mut().skip(1) {
+                        if !resolver.added {
+                            match conn.probe_path(resolver.addr) {
+                                Ok(path_id) => {
+                                    resolver.path_id_tquic = Some(path_id);
+                                    // The mode is set up front so the first polls already honour it
+                                    if let Err(e) =
+                                        configure_path_tquic(&mut conn, resolver, path_id)
+                                    {
+                                        warn!("Failed to set mode for {}: {}", resolver.addr, e);
+                                    }
+                                    debug!("Probing path to {}", resolver.addr);
+                                }
+                                Err(e) if config.fail_fast => {
+                                    return Err(ClientError::resolver(format!(
+                                        "Failed to probe path to {}: {}",
+                                        resolver.addr, e
+                                    )));
+                                }
+                                Err(e) => {
+                                    warn!("Failed to probe path to {}: {}", resolver.addr, e);
+                                }
+                            }
                         }
                     }
                 }
+                ConnectionEvent::Path(event) => {
+                    handle_path_event_tquic(event, &mut resolvers, current_time_us)
+                }
+                ConnectionEvent::Closed(cause) => closed = Some(cause),
+                _ => {}
             }
         }
 
-        if conn.is_closing() {
-            info!("Connection closing");
+        if let Some(cause) = closed {
+            if let Err(QuicError::HandshakeTimeout) = conn.handshake_status() {
+                let timed_out = resolvers[0].addr;
+                // Streams opened in 0-RTT data belong to this connection
+                if config.fail_fast
+                    || !streams.is_empty()
+                    || handshake_attempts >= primary_candidates
+                    || !promote_next_resolver(&mut resolvers)
+                {
+                    return Err(ClientError::resolver(format!(
+                        "Handshake through {} timed out after {}s ({} of {} resolvers tried)",
+                        timed_out, config.handshake_timeout, handshake_attempts, primary_candidates
+                    )));
+                }
+                handshake_attempts += 1;
+                warn!(
+                    "Handshake through {} timed out after {}s; trying {}",
+                    timed_out, config.handshake_timeout, resolvers[0].addr
+                );
+                conn = client
+                    .connect(local_addr, resolvers[0].addr, config.domain)
+                    .map_err(|e| ClientError::new(format!("Failed to connect: {}", e)))?;
+                info!("Connecting to {}", resolvers[0].addr);
+                continue;
+            }
+            match cause {
+                Some(cause) if cause.is_error() && !ready => {
+                    return Err(handshake_failure(&cause));
+                }
+                Some(cause) if cause.is_error() && config.fail_fast => {
+                    return Err(ClientError::new(format!("Connection {}", cause)));
+                }
+                Some(cause) if cause.is_error() => warn!("Connection closed: {}", cause),
+                Some(cause) => info!("Connection closed: {}", cause),
+                None => info!("Connection closing"),
+            }
             break;
         }
 
-        // Drain path events
-        drain_path_events_tquic(&mut conn, &mut resolvers);
+        match signal_count() {
+            0 => {}
+            1 if drain_deadline.is_none() => {
+                acceptor.abort();
+                info!(
+                    "Shutting down: draining {} streams for up to {}s",
+                    streams.len(),
+                    config.shutdown_grace
+                );
+                drain_deadline = Some(Instant::now() + Duration::from_secs(config.shutdown_grace));
+                begin_drain(&mut conn, &mut streams);
+            }
+            1 => {}
+            _ => {
+                warn!("Second shutdown signal; closing without draining");
+                break;
+            }
+        }
+        if let Some(deadline) = drain_deadline {
+            if streams.is_empty() && bytes_in_flight(&mut conn) == 0 {
+                info!("All streams drained");
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    "Shutdown grace period expired with {} streams still open",
+                    streams.len()
+                );
+                break;
+            }
+        }
 
         // Expire inflight polls for authoritative resolvers
-        let current_time_us = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_micros() as u64)
-            .unwrap_or(0);
         for resolver in resolvers.iter_mut() {
             if resolver.mode == ResolverMode::Authoritative {
                 expire_inflight_polls(&mut resolver.inflight_poll_ids, current_time_us);
             }
+            if let Some(size_probe) = resolver.size_probe.as_mut() {
+                size_probe.expire(current_time_us);
+            }
+        }
+
+        // Dead-peer detection: a secondary path that stopped answering is
+        // abandoned and reset, to be probed again later; a dead primary path
+        // ends the run.
+        let dead_peer_timeout_us = config.dead_peer_timeout.saturating_mul(1_000_000);
+        for (idx, resolver) in resolvers.iter_mut().enumerate() {
+            if !resolver
+                .liveness
+                .is_dead(current_time_us, dead_peer_timeout_us)
+            {
+                continue;
+            }
+            if idx == 0 || config.fail_fast {
+                return Err(ClientError::resolver(format!(
+                    "Resolver {} did not answer any query for {}s; peer presumed dead",
+                    resolver.addr, config.dead_peer_timeout
+                )));
+            }
+            if let Some(path_id) = resolver.path_id_tquic {
+                if let Err(e) = conn.abandon_path(path_id, SLIPSTREAM_PATH_UNRESPONSIVE_ERROR) {
+                    debug!("Failed to abandon path to {}: {}", resolver.addr, e);
+                }
+            }
+            reset_resolver_path(resolver);
+            resolver.next_probe_at = current_time_us.saturating_add(dead_peer_timeout_us);
+        }
+        if ready {
+            reprobe_resolvers_tquic(
+                &mut conn,
+                &mut resolvers,
+                current_time_us,
+                dead_peer_timeout_us,
+            );
         }
 
+        // Keep per-path RTT/loss history fresh, probing paths that went idle
+        update_path_quality_tquic(&mut conn, &mut resolvers, current_time_us);
+
         // Calculate delay and work status
         let delay_us = conn
             .timeout()
             .map(|d| d.as_micros() as u64)
             .unwrap_or(DNS_WAKE_DELAY_MAX_US);
         let streams_len = streams.len();
-        let mut has_work = streams_len > 0;
+        let mut has_work = streams_len > 0 || packet_scheduler.deferred() > 0;
 
         for resolver in resolvers.iter_mut() {
             if !resolver.added {
@@ -199,8 +506,12 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
             let pending_for_sleep = match resolver.mode {
                 ResolverMode::Authoritative => {
                     let quality = fetch_path_quality_tquic(&mut conn, resolver);
-                    let target = cwnd_target_polls(quality.cwin, mtu);
-                    let inflight_packets = inflight_packet_estimate(quality.bytes_in_transit, mtu);
+                    let poll_mtu = poll_mtu(resolver, mtu);
+                    // The server's backlog hint can ask for more polls than cwnd alone would.
+                    let target = cwnd_target_polls(quality.cwin, poll_mtu)
+                        .max(backlog_target_polls(resolver.server_backlog, poll_mtu));
+                    let inflight_packets =
+                        inflight_packet_estimate(quality.bytes_in_transit, poll_mtu);
                     target.saturating_sub(inflight_packets)
                 }
                 ResolverMode::Recursive => resolver.pending_polls,
@@ -216,18 +527,34 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
         }
 
         let timeout_us = if has_work {
-            delay_us.clamp(1, DNS_POLL_SLICE_US)
+            delay_us.clamp(1, poll_slice_us(&mut conn, mtu))
         } else {
             delay_us.max(1)
         };
+        let timeout_us = match &heartbeat {
+            Some(heartbeat) => {
+                let until_due = heartbeat
+                    .next_at()
+                    .saturating_duration_since(Instant::now());
+                timeout_us.min(until_due.as_micros() as u64).max(1)
+            }
+            None => timeout_us,
+        };
+        let timeout_us = match drain_deadline {
+            Some(deadline) => {
+                let until_deadline = deadline.saturating_duration_since(Instant::now());
+                timeout_us.min(until_deadline.as_micros() as u64).max(1)
+            }
+            None => timeout_us,
+        };
         let timeout = Duration::from_micros(timeout_us);
 
         // Main select loop
         tokio::select! {
             // Handle incoming commands (new TCP connections, stream data)
             command = command_rx.recv() => {
-                if let Some(command) = command {
-                    handle_command(&mut conn, &mut streams, command, &command_tx, &data_notify, debug_streams)?;
+                if let Some(command) = command.filter(|c| accepts(c, drain_deadline)) {
+                    handle_command(&mut conn, &mut streams, command, &command_tx, &data_notify, &mut tunnel_tx_bytes, debug_streams)?;
                 }
             }
 
@@ -235,51 +562,21 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
             _ = data_notify.notified() => {}
 
             // Handle incoming UDP packets (DNS responses)
-            recv = udp.recv_from(&mut recv_buf) => {
+            recv = transports.udp.recv_from(&mut recv_buf) => {
                 match recv {
                     Ok((size, from)) => {
-                        // Decode DNS response to extract QUIC payload
-                        if let Some(quic_payload) = decode_response(&recv_buf[..size]) {
-                            // Handle fragmented responses
-                            let complete_packet = if is_fragmented(&quic_payload) {
-                                recv_fragment_buffer.receive_fragment(&quic_payload)
-                            } else {
-                                Some(quic_payload)
-                            };
-
-                            if let Some(data) = complete_packet {
-                                if let Err(e) = conn.recv(&data, from) {
-                                    debug!("Failed to process QUIC packet from {}: {}", from, e);
-                                }
-                            }
-                        } else {
-                            // Not a valid DNS response - try as raw QUIC packet
-                            // (fallback for empty responses or direct UDP)
-                            if let Err(e) = conn.recv(&recv_buf[..size], from) {
-                                trace!("Failed to process raw packet from {}: {}", from, e);
-                            }
+                        handle_response(&mut conn, &mut resolvers, &mut query_log, &mut recv_fragment_buffer, &recv_buf[..size], from, current_time_us);
+                        if is_truncated(&recv_buf[..size]) {
+                            transports.retry_over_tcp(&recv_buf[..size], from);
                         }
 
                         // Try to receive more packets in burst
                         for _ in 1..packet_loop_recv_max {
-                            match udp.try_recv_from(&mut recv_buf) {
+                            match transports.udp.try_recv_from(&mut recv_buf) {
                                 Ok((size, from)) => {
-                                    // Decode DNS response
-                                    if let Some(quic_payload) = decode_response(&recv_buf[..size]) {
-                                        let complete_packet = if is_fragmented(&quic_payload) {
-                                            recv_fragment_buffer.receive_fragment(&quic_payload)
-                                        } else {
-                                            Some(quic_payload)
-                                        };
-
-                                        if let Some(data) = complete_packet {
-                                            if let Err(e) = conn.recv(&data, from) {
-                                                debug!("Failed to process QUIC packet: {}", e);
-                                            }
-                                        }
-                                    } else {
-                                        // Fallback to raw packet
-                                        let _ = conn.recv(&recv_buf[..size], from);
+                                    handle_response(&mut conn, &mut resolvers, &mut query_log, &mut recv_fragment_buffer, &recv_buf[..size], from, current_time_us);
+                                    if is_truncated(&recv_buf[..size]) {
+                                        transports.retry_over_tcp(&recv_buf[..size], from);
                                     }
                                 }
                                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
@@ -295,6 +592,14 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
                 }
             }
 
+            // DNS responses from DoH, DoT and DoQ resolvers and TCP retries
+            Some((packet, from)) = stream_rx.recv() => {
+                handle_response(&mut conn, &mut resolvers, &mut query_log, &mut recv_fragment_buffer, &packet, from, current_time_us);
+                while let Ok((packet, from)) = stream_rx.try_recv() {
+                    handle_response(&mut conn, &mut resolvers, &mut query_log, &mut recv_fragment_buffer, &packet, from, current_time_us);
+                }
+            }
+
             // Handle timeout
             _ = sleep(timeout) => {
                 conn.on_timeout();
@@ -304,19 +609,37 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
         // Read from QUIC streams and forward to TCP connections
         for stream_id in conn.readable_streams() {
             let mut read_buf = vec![0u8; 4096];
+            if is_control_stream(stream_id) {
+                if capabilities.is_none() {
+                    read_capabilities(
+                        &mut conn,
+                        stream_id,
+                        &mut capabilities_buf,
+                        &mut capabilities,
+                    )?;
+                    if let Some(agreed) = capabilities.as_ref() {
+                        heartbeat = start_heartbeat(&mut conn, agreed, config.heartbeat_interval);
+                    }
+                } else if let Some(heartbeat) = heartbeat.as_mut() {
+                    heartbeat.on_control_stream(&mut conn, stream_id, Instant::now());
+                }
+                continue;
+            }
             match conn.stream_read(stream_id, &mut read_buf) {
                 Ok((n, fin)) if n > 0 => {
-                    if let Some(state) = streams.get(&stream_id) {
+                    tunnel_rx_bytes += n as u64;
+                    let label = conn.stream_label(stream_id);
+                    if let Some(state) = streams.get(label, &stream_id) {
                         // Send data to TCP writer via channel
                         let _ = state.write_tx.send(read_buf[..n].to_vec());
                     }
                     if fin {
-                        streams.remove(&stream_id);
+                        streams.remove(label, &stream_id);
                     }
                 }
                 Ok((_, true)) => {
                     // Stream finished
-                    streams.remove(&stream_id);
+                    streams.remove(conn.stream_label(stream_id), &stream_id);
                 }
                 _ => {}
             }
@@ -324,62 +647,117 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
 
         // Drain pending commands
         while let Ok(command) = command_rx.try_recv() {
+            if !accepts(&command, drain_deadline) {
+                continue;
+            }
             handle_command(
                 &mut conn,
                 &mut streams,
                 command,
                 &command_tx,
                 &data_notify,
+                &mut tunnel_tx_bytes,
                 debug_streams,
             )?;
         }
 
-        // Try to write pending data for streams with available capacity
-        for (stream_id, stream) in streams.iter_mut() {
-            if !stream.pending_data.is_empty() {
-                let capacity = conn.stream_capacity(*stream_id);
-                tracing::debug!(
-                    "stream {} pending={} capacity={}",
-                    stream_id,
-                    stream.pending_data.len(),
-                    capacity
-                );
-                if capacity > 0 {
-                    let write_len = std::cmp::min(capacity, stream.pending_data.len());
-                    let data_to_write = stream.pending_data.drain(..write_len).collect::<Vec<_>>();
-                    match conn.stream_write(*stream_id, &data_to_write, false) {
-                        Ok(written) => {
-                            stream.tx_bytes = stream.tx_bytes.saturating_add(written as u64);
-                            tracing::debug!("stream {} wrote {} bytes", stream_id, written);
-                            // Put unwritten data back at front
-                            if written < data_to_write.len() {
-                                let mut remaining = data_to_write[written..].to_vec();
-                                remaining.append(&mut stream.pending_data);
-                                stream.pending_data = remaining;
-                            }
-                        }
-                        Err(e) => {
-                            tracing::debug!("stream {} write error: {}", stream_id, e);
-                            // Put data back in pending buffer
-                            let mut remaining = data_to_write;
-                            remaining.append(&mut stream.pending_data);
-                            stream.pending_data = remaining;
-                        }
+        if let Some(heartbeat) = heartbeat.as_mut() {
+            heartbeat.tick(&mut conn, tunnel_tx_bytes, tunnel_rx_bytes, Instant::now());
+        }
+
+        for stream_id in conn.drain_writable_events() {
+            log_limited!(Level::DEBUG, "stream {} writable again", stream_id);
+        }
+
+        // Hand pending data to QUIC in deficit round-robin order, bounded by what
+        // the congestion windows can take, so a bulk stream cannot crowd out others.
+        // Streams blocked by flow control wait until tquic reports capacity.
+        let backlogs: Vec<(u64, usize)> = streams
+            .iter()
+            .map(|(_, stream_id, stream)| (*stream_id, stream.pending_data.len()))
+            .filter(|(stream_id, len)| *len > 0 && conn.is_writable(*stream_id))
+            .collect();
+        if !backlogs.is_empty() {
+            let budget = free_cwnd_bytes(&mut conn);
+            let mut failed = Vec::new();
+            for (stream_id, len) in write_scheduler.plan(&backlogs, budget) {
+                let Some(stream) = streams.get_mut(conn.stream_label(stream_id), &stream_id) else {
+                    continue;
+                };
+                match conn.stream_write(stream_id, &stream.pending_data[..len], false) {
+                    Ok(written) => {
+                        tunnel_tx_bytes += written as u64;
+                        stream.tx_bytes = stream.tx_bytes.saturating_add(written as u64);
+                        stream.pending_data.drain(..written);
+                        log_limited!(
+                            Level::DEBUG,
+                            "stream {} wrote {} of {} bytes, {} pending",
+                            stream_id,
+                            written,
+                            len,
+                            stream.pending_data.len()
+                        );
+                    }
+                    // Flow control blocked: the data stays pending
+                    Err(e) if e.would_block() => {
+                        log_limited!(Level::DEBUG, "stream {} blocked", stream_id);
+                    }
+                    Err(e) => {
+                        warn!("Failed to write to stream {}: {}", stream_id, e);
+                        failed.push(stream_id);
                     }
                 }
             }
+            for stream_id in failed {
+                streams.remove(conn.stream_label(stream_id), &stream_id);
+            }
+            let drained: Vec<u64> = streams
+                .iter()
+                .filter(|(_, _, stream)| stream.fin_pending && stream.pending_data.is_empty())
+                .map(|(_, stream_id, _)| *stream_id)
+                .collect();
+            for stream_id in drained {
+                finish_stream(&mut conn, &mut streams, stream_id);
+            }
         }
 
-        // Poll for outgoing packets
+        // Poll for outgoing packets, most important first within the loop budget
         let packets = conn.poll_send();
+        let lost_bytes = conn.lost_bytes();
+        let newly_lost = lost_bytes.saturating_sub(last_lost_bytes);
+        last_lost_bytes = lost_bytes;
+        let packets = packet_scheduler.schedule(packets, newly_lost, packet_loop_send_max);
         if packets.is_empty() {
             zero_send_loops = zero_send_loops.saturating_add(1);
         }
+        if packet_scheduler.deferred() > 0 {
+            log_limited!(
+                Level::DEBUG,
+                "send budget exceeded; {} packets wait for the next loop",
+                packet_scheduler.deferred()
+            );
+        }
+        for resolver in resolvers.iter_mut() {
+            resolver.loop_data_queries = 0;
+        }
 
-        for (packet_data, dest) in packets.into_iter().take(packet_loop_send_max) {
-            // Update resolver stats
+        for (packet_data, dest) in packets {
             let dest = normalize_dual_stack_addr(dest);
+            let shape = query_shape_for(&mut resolvers, dest, config)?;
+
+            // Fragment the QUIC packet if needed
+            let fragments = fragment_packet(&packet_data, packet_id, shape.max_payload);
+            let fragment_packet_id = packet_id;
+            packet_id = packet_id.wrapping_add(1);
+
+            // Update resolver stats
             if let Some(resolver) = find_resolver_by_addr_mut(&mut resolvers, dest) {
+                resolver.liveness.on_query_sent(current_time_us);
+                resolver.quality.on_query_sent(current_time_us);
+                // A data query draws a response just like a poll does
+                resolver.pending_polls = resolver.pending_polls.saturating_sub(fragments.len());
+                resolver.loop_data_queries =
+                    resolver.loop_data_queries.saturating_add(fragments.len());
                 resolver.debug.send_packets = resolver.debug.send_packets.saturating_add(1);
                 resolver.debug.send_bytes = resolver
                     .debug
@@ -387,85 +765,345 @@ pub async fn run_client(config: &TquicClientConfig<'_>) -> Result<i32, ClientErr
                     .saturating_add(packet_data.len() as u64);
             }
 
-            // Get max payload for domain
-            let max_payload = max_payload_len_for_domain(config.domain)
-                .map_err(|e| ClientError::new(format!("Failed to get max payload: {}", e)))?;
-
-            // Fragment the QUIC packet if needed
-            let fragments = fragment_packet(&packet_data, packet_id, max_payload);
-            packet_id = packet_id.wrapping_add(1);
-
             // Send each fragment as a separate DNS query
-            for fragment in fragments {
-                let qname = build_qname(&fragment, config.domain)
-                    .map_err(|e| ClientError::new(format!("Failed to build qname: {}", e)))?;
-                let params = QueryParams {
-                    id: dns_id,
-                    qname: &qname,
-                    qtype: RR_TXT,
-                    qclass: CLASS_IN,
-                    rd: true,
-                    cd: false,
-                    qdcount: 1,
-                    is_query: true,
-                };
-                dns_id = dns_id.wrapping_add(1);
-
-                let dns_packet = encode_query(&params)
-                    .map_err(|e| ClientError::new(format!("Failed to encode DNS query: {}", e)))?;
+            let fragment_count = fragments.len();
+            for (fragment_index, fragment) in fragments.into_iter().enumerate() {
+                let id = send_query(
+                    &mut transports,
+                    dest,
+                    &fragment,
+                    config,
+                    &shape,
+                    &mut query_seq,
+                    &mut query_log,
+                    Some(&FragmentInfo {
+                        packet_id: fragment_packet_id,
+                        index: fragment_index,
+                        count: fragment_count,
+                    }),
+                )
+                .await?;
+                if let Some(size_probe) = find_resolver_by_addr_mut(&mut resolvers, dest)
+                    .and_then(|resolver| resolver.size_probe.as_mut())
+                {
+                    size_probe.on_query_sent(id, fragment.len(), current_time_us);
+                }
+            }
+            conn.recycle(packet_data);
+        }
 
-                // Send to resolver
-                udp.send_to(&dns_packet, dest)
-                    .await
-                    .map_err(|e| ClientError::new(format!("Failed to send DNS: {}", e)))?;
+        // Recursive resolvers only relay server data in answers to our queries, so
+        // keep a poll outstanding while streams are open and send the polls owed
+        // for responses that carried data. Path modes decide which paths may poll.
+        let primary_available = primary_path_available(&conn, &resolvers, current_time_us);
+        if primary_available != primary_was_available {
+            if primary_available {
+                info!("Primary resolver path usable again; standby paths back to standby");
+            } else {
+                warn!("No primary resolver path usable; polling standby paths");
+            }
+            primary_was_available = primary_available;
+        }
+        for resolver in resolvers.iter_mut() {
+            if !resolver.added || resolver.mode != ResolverMode::Recursive {
+                continue;
+            }
+            let polls_allowed = resolver
+                .path_id_tquic
+                .is_none_or(|path_id| conn.path_mode(path_id).allows_polls(primary_available));
+            if !polls_allowed {
+                resolver.pending_polls = 0;
+                continue;
+            }
+            if !streams.is_empty() && !resolver.liveness.has_outstanding() {
+                resolver.pending_polls = resolver.pending_polls.max(1);
+            }
+            // Data queries sent this loop come out of the same budget; owed polls
+            // that no longer fit are redundant, as those queries draw responses too.
+            // Fewer polls while the path is losing packets
+            let budget = resolver
+                .liveness
+                .poll_budget(path_poll_burst_max(resolver), current_time_us)
+                .saturating_sub(resolver.loop_data_queries);
+            let burst = resolver.pending_polls.min(budget);
+            let shape = query_shape(resolver, config)?;
+            for _ in 0..burst {
+                poll_nonce = poll_nonce.wrapping_add(1);
+                let payload = poll_payload(poll_nonce);
+                let id = send_query(
+                    &mut transports,
+                    resolver.addr,
+                    &payload,
+                    config,
+                    &shape,
+                    &mut query_seq,
+                    &mut query_log,
+                    None,
+                )
+                .await?;
+                if let Some(size_probe) = resolver.size_probe.as_mut() {
+                    size_probe.on_query_sent(id, payload.len(), current_time_us);
+                }
+                resolver.liveness.on_query_sent(current_time_us);
+                resolver.quality.on_query_sent(current_time_us);
+                resolver.debug.polls_sent = resolver.debug.polls_sent.saturating_add(1);
             }
+            resolver.pending_polls = if resolver.loop_data_queries > 0 {
+                0
+            } else {
+                resolver.pending_polls - burst
+            };
+        }
+
+        for resolver in resolvers.iter_mut() {
+            let pending_polls = resolver.pending_polls;
+            let inflight_polls = resolver.inflight_poll_ids.len();
+            let pacing_snapshot = resolver.last_pacing_snapshot;
+            maybe_report_debug(
+                resolver,
+                current_time_us,
+                streams.len(),
+                pending_polls,
+                inflight_polls,
+                pacing_snapshot,
+            );
         }
 
         // Path event handling and polling (for authoritative mode)
-        drain_path_events_tquic(&mut conn, &mut resolvers);
+        drain_path_events_tquic(&mut conn, &mut resolvers, current_time_us);
 
         for resolver in resolvers.iter_mut() {
-            if !resolver.added {
+            // Probed paths are promoted here once tquic reports them
+            if resolver.path_id_tquic.is_none() {
                 continue;
             }
             apply_path_mode_tquic(&mut conn, resolver)?;
         }
     }
 
-    // Close connection
+    let stats = conn.stats();
+    info!("Connection stats: {}", stats);
+    for path in &stats.paths {
+        info!("Connection {}", path);
+    }
+    for resolver in &resolvers {
+        if let Some(size_probe) = &resolver.size_probe {
+            info!(
+                "Resolver {} learned limits: query_payload={} edns_udp_size={}",
+                resolver.addr,
+                size_probe.query_payload(),
+                size_probe.edns_udp_payload()
+            );
+        }
+    }
+
+    let suppressed = suppressed_log_messages();
+    if suppressed > 0 {
+        info!(
+            "{} log messages were suppressed by rate limiting",
+            suppressed
+        );
+    }
+
+    // Close connection and send the CONNECTION_CLOSE before exiting
     conn.close(0, "client shutdown")
         .map_err(|e| ClientError::new(format!("Failed to close: {}", e)))?;
+    for (packet_data, dest) in conn.poll_send() {
+        let dest = normalize_dual_stack_addr(dest);
+        let shape = query_shape_for(&mut resolvers, dest, config)?;
+        for fragment in fragment_packet(&packet_data, packet_id, shape.max_payload) {
+            send_query(
+                &mut transports,
+                dest,
+                &fragment,
+                config,
+                &shape,
+                &mut query_seq,
+                &mut query_log,
+                None,
+            )
+            .await?;
+        }
+        packet_id = packet_id.wrapping_add(1);
+    }
 
     Ok(0)
 }
 
+/// Log the settings the client actually runs with, in one line for bug reports.
+fn log_effective_config(
+    config: &TquicClientConfig<'_>,
+    resolvers: &[crate::dns::ResolverState],
+    mtu: u32,
+    quic_config: &QuicConfig,
+) {
+    let resolvers = config
+        .resolvers
+        .iter()
+        .zip(resolvers)
+        .map(|(spec, resolver)| {
+            let mode = match resolver.mode {
+                ResolverMode::Recursive => "recursive",
+                ResolverMode::Authoritative => "authoritative",
+            };
+            let transport = if spec.doh_url.is_some() {
+                "doh"
+            } else if spec.dot_server.is_some() {
+                "dot"
+            } else if spec.doq_server.is_some() {
+                "doq"
+            } else {
+                "udp"
+            };
+            let query_payload = max_query_payload(config, resolver.qname_encoding)
+                .map_or_else(|_| "?".to_string(), |len| len.to_string());
+            format!(
+                "{}/{}/{}/{}/{}/{}",
+                resolver.addr,
+                mode,
+                record_type_name(resolver.record_type),
+                resolver.qname_encoding.name(),
+                transport,
+                query_payload
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    info!(
+        "Effective configuration: version={} runtime=tquic domain={} resolvers=[{}] mtu={} quic_packet_size={} edns_udp_size={} probe_sizes={} nonce_label={} cc={} multipath={} scheduler={} keep_alive_ms={} handshake_timeout_s={} dead_peer_timeout_s={} pinned_cert={} session_resumption={} zero_rtt={} fail_fast={} heartbeat_s={} shutdown_grace_s={}",
+        env!("CARGO_PKG_VERSION"),
+        config.domain,
+        resolvers,
+        mtu,
+        quic_config
+            .max_packet_size()
+            .map_or_else(|| "pmtud".to_string(), |size| size.to_string()),
+        config.edns_udp_payload,
+        config.probe_sizes,
+        config.nonce_label,
+        quic_config.congestion_control.name(),
+        config.multipath,
+        quic_config.scheduler.name(),
+        config.keep_alive_interval,
+        config.handshake_timeout,
+        config.dead_peer_timeout,
+        config.cert.is_some(),
+        config.session_dir.is_some(),
+        config.zero_rtt,
+        config.fail_fast,
+        config.heartbeat_interval,
+        config.shutdown_grace
+    );
+}
+
+/// Bytes the congestion windows of all active paths can still take.
+fn free_cwnd_bytes(conn: &mut ClientConnection) -> usize {
+    let free: u64 = conn
+        .active_paths()
+        .iter()
+        .map(|path| path.cwnd.saturating_sub(path.bytes_in_flight))
+        .sum();
+    usize::try_from(free).unwrap_or(usize::MAX)
+}
+
+/// How long to wait between polls while there is work: one packet's spacing
+/// at the pacing rate, or the full slice while the congestion window is full.
+fn poll_slice_us(conn: &mut ClientConnection, mtu: u32) -> u64 {
+    conn.next_send_delay(mtu as usize)
+        .map_or(DNS_POLL_SLICE_US, |delay| {
+            (delay.as_micros() as u64).clamp(DNS_POLL_SLICE_MIN_US, DNS_POLL_SLICE_US)
+        })
+}
+
+/// Bytes sent on any path and not yet acknowledged or declared lost.
+fn bytes_in_flight(conn: &mut ClientConnection) -> u64 {
+    conn.active_paths()
+        .iter()
+        .map(|path| path.bytes_in_flight)
+        .sum()
+}
+
+/// Finish every stream once its queued data is written, as if its TCP side closed.
+fn begin_drain(conn: &mut ClientConnection, streams: &mut StreamTable<u64, StreamState>) {
+    let idle: Vec<u64> = streams
+        .iter_mut()
+        .filter_map(|(_, stream_id, stream)| {
+            stream.fin_pending = true;
+            stream.pending_data.is_empty().then_some(*stream_id)
+        })
+        .collect();
+    for stream_id in idle {
+        finish_stream(conn, streams, stream_id);
+    }
+}
+
+/// Whether the main loop takes `command`; new streams are refused while draining.
+fn accepts(command: &Command, drain_deadline: Option<Instant>) -> bool {
+    drain_deadline.is_none() || !matches!(command, Command::NewStream(..))
+}
+
+/// Send FIN on a stream whose TCP side closed and stop tracking it.
+fn finish_stream(
+    conn: &mut ClientConnection,
+    streams: &mut StreamTable<u64, StreamState>,
+    stream_id: u64,
+) {
+    if let Err(e) = conn.stream_write(stream_id, &[], true) {
+        warn!("Failed to close stream {}: {}", stream_id, e);
+    }
+    streams.remove(conn.stream_label(stream_id), &stream_id);
+}
+
 /// Handle a command.
 fn handle_command(
     conn: &mut ClientConnection,
-    streams: &mut HashMap<u64, StreamState>,
+    streams: &mut StreamTable<u64, StreamState>,
     command: Command,
     command_tx: &mpsc::UnboundedSender<Command>,
     _data_notify: &Arc<Notify>,
+    tunnel_tx_bytes: &mut u64,
     debug_streams: bool,
 ) -> Result<(), ClientError> {
     match command {
-        Command::NewStream(tcp_stream) => {
+        Command::NewStream(tcp_stream, early_data) => {
             let _ = tcp_stream.set_nodelay(true);
-            match conn.open_bi() {
+            // On a resumed session the first payload can go out as 0-RTT data.
+            let opened = if !conn.is_ready() && conn.is_in_early_data() {
+                conn.open_bi_early()
+            } else {
+                conn.open_bi()
+            };
+            match opened {
                 Ok(stream_id) => {
                     let (write_tx, write_rx) = mpsc::unbounded_channel();
-                    streams.insert(
-                        stream_id,
-                        StreamState {
-                            write_tx,
-                            queued_bytes: 0,
-                            rx_bytes: 0,
-                            tx_bytes: 0,
-                            pending_data: Vec::new(),
-                        },
-                    );
+                    let mut state = StreamState {
+                        write_tx,
+                        queued_bytes: 0,
+                        rx_bytes: 0,
+                        tx_bytes: 0,
+                        pending_data: Vec::new(),
+                        fin_pending: false,
+                    };
+                    if !early_data.is_empty() {
+                        // Bundle the first payload with the stream open; whatever flow
+                        // control refuses now is retried from pending_data.
+                        let written = conn
+                            .stream_write(stream_id, &early_data, false)
+                            .unwrap_or(0);
+                        state.tx_bytes = written as u64;
+                        *tunnel_tx_bytes += written as u64;
+                        state.pending_data = early_data[written..].to_vec();
+                    }
+                    let label = streams.insert(stream_id, state);
+                    if let Err(e) = conn.set_stream_label(stream_id, label) {
+                        warn!("Failed to label stream {}: {}", stream_id, e);
+                    }
                     if debug_streams {
-                        debug!("stream {}: accepted", stream_id);
+                        debug!(
+                            "stream {}: accepted (early_data={} bytes)",
+                            stream_id,
+                            early_data.len()
+                        );
                     } else {
                         info!("Accepted TCP stream {}", stream_id);
                     }
@@ -489,60 +1127,30 @@ fn handle_command(
             }
         }
         Command::StreamData { stream_id, data } => {
-            // Get or append to pending data buffer
-            let data_to_write = if let Some(stream) = streams.get_mut(&stream_id) {
-                if !stream.pending_data.is_empty() {
-                    stream.pending_data.extend_from_slice(&data);
-                    std::mem::take(&mut stream.pending_data)
-                } else {
-                    data
-                }
-            } else {
-                return Ok(());
-            };
-
-            match conn.stream_write(stream_id, &data_to_write, false) {
-                Ok(written) => {
-                    if let Some(stream) = streams.get_mut(&stream_id) {
-                        stream.tx_bytes = stream.tx_bytes.saturating_add(written as u64);
-                        // Buffer remaining data if partial write
-                        if written < data_to_write.len() {
-                            stream.pending_data = data_to_write[written..].to_vec();
-                        }
-                    }
-                }
-                Err(e) => {
-                    // Check if this is a flow control block ("Done" error)
-                    let err_str = e.to_string();
-                    if err_str.contains("Done") {
-                        // Flow control blocked - buffer the data for later
-                        if let Some(stream) = streams.get_mut(&stream_id) {
-                            stream.pending_data = data_to_write;
-                        }
-                    } else {
-                        // Actual error - remove the stream
-                        warn!("Failed to write to stream {}: {}", stream_id, e);
-                        streams.remove(&stream_id);
-                    }
-                }
+            // Written to QUIC by the fair scheduler in the main loop
+            if let Some(stream) = streams.get_mut(conn.stream_label(stream_id), &stream_id) {
+                stream.pending_data.extend_from_slice(&data);
             }
         }
         Command::StreamClosed { stream_id } => {
-            if let Err(e) = conn.stream_write(stream_id, &[], true) {
-                warn!("Failed to close stream {}: {}", stream_id, e);
+            if let Some(stream) = streams.get_mut(conn.stream_label(stream_id), &stream_id) {
+                if !stream.pending_data.is_empty() {
+                    stream.fin_pending = true;
+                    return Ok(());
+                }
             }
-            streams.remove(&stream_id);
+            finish_stream(conn, streams, stream_id);
         }
         Command::StreamReadError { stream_id } => {
             warn!("stream {}: read error", stream_id);
-            streams.remove(&stream_id);
+            streams.remove(conn.stream_label(stream_id), &stream_id);
         }
         Command::StreamWriteError { stream_id } => {
             warn!("stream {}: write error", stream_id);
-            streams.remove(&stream_id);
+            streams.remove(conn.stream_label(stream_id), &stream_id);
         }
         Command::StreamWriteDrained { stream_id, bytes } => {
-            if let Some(stream) = streams.get_mut(&stream_id) {
+            if let Some(stream) = streams.get_mut(conn.stream_label(stream_id), &stream_id) {
                 stream.queued_bytes = stream.queued_bytes.saturating_sub(bytes);
             }
         }
@@ -550,6 +1158,409 @@ fn handle_command(
     Ok(())
 }
 
+/// Read the server's capability message and settle on the shared feature set.
+fn read_capabilities(
+    conn: &mut ClientConnection,
+    stream_id: u64,
+    message: &mut Vec<u8>,
+    capabilities: &mut Option<Capabilities>,
+) -> Result<(), ClientError> {
+    let mut read_buf = [0u8; 512];
+    let mut finished = false;
+    while let Ok((n, fin)) = conn.stream_read(stream_id, &mut read_buf) {
+        message.extend_from_slice(&read_buf[..n]);
+        if fin || message.len() > MAX_CAPABILITIES_LEN {
+            finished = true;
+            break;
+        }
+        if n == 0 {
+            break;
+        }
+    }
+    if !finished {
+        return Ok(());
+    }
+
+    let agreed = if message.len() > MAX_CAPABILITIES_LEN {
+        Err("capability message too large".to_string())
+    } else {
+        Capabilities::decode(message)
+            .and_then(|peer| Capabilities::local().negotiate(&peer))
+            .map_err(|e| e.to_string())
+    };
+    message.clear();
+    match agreed {
+        Ok(agreed) => {
+            info!("Negotiated {}", agreed);
+            *capabilities = Some(agreed);
+            Ok(())
+        }
+        Err(reason) => {
+            let _ = conn.close(SLIPSTREAM_PROTOCOL_VERSION_ERROR, &reason);
+            Err(ClientError::handshake(format!(
+                "Incompatible server capabilities: {}",
+                reason
+            )))
+        }
+    }
+}
+
+/// Open the heartbeat stream once capabilities are agreed, if heartbeats are on.
+fn start_heartbeat(
+    conn: &mut ClientConnection,
+    agreed: &Capabilities,
+    interval_secs: u64,
+) -> Option<HeartbeatStream> {
+    if interval_secs == 0 {
+        return None;
+    }
+    if !agreed.heartbeat {
+        info!("Server does not answer heartbeats; --heartbeat-interval ignored");
+        return None;
+    }
+    HeartbeatStream::open(conn, Duration::from_secs(interval_secs), Instant::now())
+        .map_err(|e| warn!("Failed to open heartbeat stream: {}", e))
+        .ok()
+}
+
+/// Classify a connection that closed before the handshake finished.
+///
+/// TLS alerts travel as transport errors 0x100 + alert; the certificate and
+/// key alerts mean the peer's identity was rejected rather than unreachable.
+fn handshake_failure(cause: &CloseCause) -> ClientError {
+    let message = format!("Handshake failed: connection {}", cause);
+    match cause {
+        CloseCause::Peer(frame) | CloseCause::Local(frame)
+            if !frame.is_app
+                && matches!(
+                    frame.error_code.checked_sub(0x100),
+                    Some(42..=49 | 51 | 116)
+                ) =>
+        {
+            ClientError::auth(message)
+        }
+        _ => ClientError::handshake(message),
+    }
+}
+
+/// A recursive resolver answered with server data; more may be queued, so owe it a poll.
+fn owe_poll(resolvers: &mut [crate::dns::ResolverState], from: std::net::SocketAddr) {
+    if let Some(resolver) = find_resolver_by_addr_mut(resolvers, from) {
+        if resolver.mode == ResolverMode::Recursive {
+            resolver.pending_polls =
+                (resolver.pending_polls + 1).min(path_poll_burst_max(resolver));
+        }
+    }
+}
+
+/// How the queries sent to one resolver are built.
+struct QueryShape {
+    qtype: u16,
+    encoding: QnameEncoding,
+    edns_udp_payload: u16,
+    /// Bytes of a QUIC packet one query carries at most.
+    max_payload: usize,
+}
+
+/// Shape of the queries sent to `resolver`, within the limits its size
+/// probe has learned.
+fn query_shape(
+    resolver: &ResolverState,
+    config: &TquicClientConfig<'_>,
+) -> Result<QueryShape, ClientError> {
+    let max_payload = max_query_payload(config, resolver.qname_encoding)?;
+    Ok(match &resolver.size_probe {
+        Some(size_probe) => QueryShape {
+            qtype: resolver.record_type,
+            encoding: resolver.qname_encoding,
+            edns_udp_payload: size_probe.edns_udp_payload(),
+            max_payload: max_payload.min(size_probe.query_payload()),
+        },
+        None => QueryShape {
+            qtype: resolver.record_type,
+            encoding: resolver.qname_encoding,
+            edns_udp_payload: config.edns_udp_payload,
+            max_payload,
+        },
+    })
+}
+
+/// Shape of the queries sent to `dest`, TXT in base32 if no resolver has
+/// that address.
+fn query_shape_for(
+    resolvers: &mut [ResolverState],
+    dest: std::net::SocketAddr,
+    config: &TquicClientConfig<'_>,
+) -> Result<QueryShape, ClientError> {
+    match find_resolver_by_addr_mut(resolvers, dest) {
+        Some(resolver) => query_shape(resolver, config),
+        None => Ok(QueryShape {
+            qtype: RR_TXT,
+            encoding: QnameEncoding::Base32,
+            edns_udp_payload: config.edns_udp_payload,
+            max_payload: max_query_payload(config, QnameEncoding::Base32)?,
+        }),
+    }
+}
+
+/// Bytes of QUIC data one poll to `resolver` brings back at most: a whole
+/// packet, or less while its size probe keeps responses smaller.
+fn poll_mtu(resolver: &ResolverState, mtu: u32) -> u32 {
+    resolver.size_probe.as_ref().map_or(mtu, |size_probe| {
+        mtu.min(u32::from(size_probe.edns_udp_payload()))
+    })
+}
+
+/// Bytes of a QUIC packet one query name holds in `encoding`, less with
+/// nonce labels.
+fn max_query_payload(
+    config: &TquicClientConfig<'_>,
+    encoding: QnameEncoding,
+) -> Result<usize, ClientError> {
+    let codec = qname_codec(encoding);
+    if config.nonce_label {
+        max_payload_len_with_nonce(codec, config.domain)
+    } else {
+        max_payload_len_with_codec(codec, config.domain)
+    }
+    .map_err(|e| ClientError::new(format!("Failed to get max payload: {}", e)))
+}
+
+/// DNS IDs and nonce labels of successive queries.
+struct QuerySequence {
+    dns_id: u16,
+    /// xorshift64 state for nonce labels; `None` without `--nonce-label`.
+    nonce_state: Option<u64>,
+}
+
+impl QuerySequence {
+    fn new(nonce_label: bool) -> Self {
+        // Seeded from the clock so successive runs draw different nonces
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+            ^ u64::from(std::process::id());
+        Self {
+            dns_id: 1,
+            nonce_state: nonce_label.then_some(seed | 1),
+        }
+    }
+
+    fn next_id(&mut self) -> u16 {
+        let id = self.dns_id;
+        self.dns_id = self.dns_id.wrapping_add(1);
+        id
+    }
+
+    fn next_nonce(&mut self) -> Option<u32> {
+        let state = self.nonce_state.as_mut()?;
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        Some((*state >> 32) as u32)
+    }
+}
+
+/// Queries sent over UDP that are kept in case their answer comes back
+/// truncated.
+const SENT_UDP_QUERIES_MAX: usize = 256;
+
+/// Where queries leave: the UDP socket, or the DoH, DoT or DoQ transport of
+/// the resolvers that use one.
+struct QueryTransports {
+    udp: UdpSocket,
+    doh: HashMap<std::net::SocketAddr, DohTransport>,
+    dot: HashMap<std::net::SocketAddr, TcpTransport>,
+    doq: HashMap<std::net::SocketAddr, DoqTransport>,
+    /// TCP connections for queries whose UDP answer was truncated, opened
+    /// per resolver on the first such answer.
+    tcp: HashMap<std::net::SocketAddr, TcpTransport>,
+    /// Recent UDP queries with their resolver and DNS ID, oldest first.
+    sent_udp: VecDeque<(std::net::SocketAddr, u16, Vec<u8>)>,
+    /// Where the TCP connections hand their answers.
+    answers: mpsc::UnboundedSender<(Vec<u8>, std::net::SocketAddr)>,
+}
+
+impl QueryTransports {
+    async fn send(&mut self, packet: Vec<u8>, dest: std::net::SocketAddr) -> std::io::Result<()> {
+        if let Some(doh) = self.doh.get(&dest) {
+            return doh.send(packet);
+        }
+        if let Some(dot) = self.dot.get(&dest) {
+            return dot.send(packet);
+        }
+        if let Some(doq) = self.doq.get(&dest) {
+            return doq.send(packet);
+        }
+        self.udp.send_to(&packet, dest).await?;
+        if let [id_hi, id_lo, ..] = packet[..] {
+            if self.sent_udp.len() == SENT_UDP_QUERIES_MAX {
+                self.sent_udp.pop_front();
+            }
+            let id = u16::from_be_bytes([id_hi, id_lo]);
+            self.sent_udp
+                .push_back((normalize_dual_stack_addr(dest), id, packet));
+        }
+        Ok(())
+    }
+
+    /// Send the query `answer` is a truncated answer to again, over TCP to
+    /// the resolver it came from. The new answer arrives tagged with `from`.
+    fn retry_over_tcp(&mut self, answer: &[u8], from: std::net::SocketAddr) {
+        let [id_hi, id_lo, ..] = answer[..] else {
+            return;
+        };
+        let id = u16::from_be_bytes([id_hi, id_lo]);
+        let resolver = normalize_dual_stack_addr(from);
+        let Some(index) = self
+            .sent_udp
+            .iter()
+            .rposition(|(dest, query_id, _)| *dest == resolver && *query_id == id)
+        else {
+            return;
+        };
+        let Some((_, _, query)) = self.sent_udp.remove(index) else {
+            return;
+        };
+        debug!("Retrying truncated answer from {} over TCP", from);
+        let answers = &self.answers;
+        let tcp = self
+            .tcp
+            .entry(resolver)
+            .or_insert_with(|| TcpTransport::spawn_plain(from, answers.clone()));
+        if let Err(e) = tcp.send(query) {
+            debug!("TCP retry to {} failed: {}", from, e);
+        }
+    }
+}
+
+/// Encode `payload` as a query under the tunnel domain, built as `shape`
+/// says, and send it to `dest`. Returns the DNS ID of the query.
+#[allow(clippy::too_many_arguments)]
+async fn send_query(
+    transports: &mut QueryTransports,
+    dest: std::net::SocketAddr,
+    payload: &[u8],
+    config: &TquicClientConfig<'_>,
+    shape: &QueryShape,
+    query_seq: &mut QuerySequence,
+    query_log: &mut Option<QueryLog>,
+    fragment: Option<&FragmentInfo>,
+) -> Result<u16, ClientError> {
+    let codec = qname_codec(shape.encoding);
+    let qname = match query_seq.next_nonce() {
+        Some(nonce) => build_qname_with_nonce(codec, payload, config.domain, nonce),
+        None => build_qname_with_codec(codec, payload, config.domain),
+    }
+    .map_err(|e| ClientError::new(format!("Failed to build qname: {}", e)))?;
+    let id = query_seq.next_id();
+    let params = QueryParams {
+        id,
+        qname: &qname,
+        qtype: shape.qtype,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns_udp_payload: shape.edns_udp_payload,
+    };
+    if let Some(query_log) = query_log.as_mut() {
+        query_log.record_query(dest, id, shape.qtype, qname.len(), fragment);
+    }
+
+    let dns_packet = encode_query(&params)
+        .map_err(|e| ClientError::new(format!("Failed to encode DNS query: {}", e)))?;
+
+    // Send to resolver
+    transports
+        .send(dns_packet, dest)
+        .await
+        .map_err(|e| ClientError::new(format!("Failed to send DNS: {}", e)))?;
+    Ok(id)
+}
+
+/// Feed a DNS response from `from` to the connection: the QUIC packet it
+/// carries, once all its fragments are in, or the datagram itself if it is
+/// not a DNS response.
+#[allow(clippy::too_many_arguments)]
+fn handle_response(
+    conn: &mut ClientConnection,
+    resolvers: &mut [ResolverState],
+    query_log: &mut Option<QueryLog>,
+    fragment_buffer: &mut FragmentBuffer,
+    packet: &[u8],
+    from: std::net::SocketAddr,
+    now: u64,
+) {
+    record_response(resolvers, query_log, packet, from, now);
+    // Decode DNS response to extract QUIC payload
+    if let Some(quic_payload) = decode_response(packet) {
+        owe_poll(resolvers, from);
+        // Handle fragmented responses
+        let complete_packet = if is_fragmented(&quic_payload) {
+            fragment_buffer.receive_fragment(&quic_payload)
+        } else {
+            Some(quic_payload)
+        };
+        if let Some(data) = complete_packet {
+            if let Err(e) = conn.recv(&data, from) {
+                log_limited!(
+                    Level::DEBUG,
+                    "Failed to process QUIC packet from {}: {}",
+                    from,
+                    e
+                );
+            }
+        }
+    } else {
+        // Not a valid DNS response - try as raw QUIC packet
+        // (fallback for empty responses or direct UDP)
+        if let Err(e) = conn.recv(packet, from) {
+            log_limited!(
+                Level::TRACE,
+                "Failed to process raw packet from {}: {}",
+                from,
+                e
+            );
+        }
+    }
+}
+
+/// Note a response on the resolver path it arrived from: it proves the path is
+/// alive and may carry a backlog hint.
+fn record_response(
+    resolvers: &mut [crate::dns::ResolverState],
+    query_log: &mut Option<QueryLog>,
+    packet: &[u8],
+    from: std::net::SocketAddr,
+    now: u64,
+) {
+    if let Some(query_log) = query_log.as_mut() {
+        query_log.record_response(normalize_dual_stack_addr(from), packet);
+    }
+    if let Some(resolver) = find_resolver_by_addr_mut(resolvers, from) {
+        resolver.liveness.on_response();
+        let rcode = response_rcode(packet);
+        if matches!(rcode, Some(Rcode::Refused | Rcode::ServerFailure)) {
+            resolver.liveness.on_refused(now);
+        }
+        if let (Some(size_probe), [id_hi, id_lo, ..]) = (resolver.size_probe.as_mut(), packet) {
+            size_probe.on_response(
+                u16::from_be_bytes([*id_hi, *id_lo]),
+                packet.len(),
+                matches!(rcode, Some(Rcode::ServerFailure | Rcode::FormatError)),
+                is_truncated(packet),
+                now,
+            );
+        }
+        if let Some(backlog) = decode_backlog_hint(packet) {
+            resolver.server_backlog = backlog as u64;
+        }
+    }
+}
+
 /// Compute MTU based on domain length (mirrors setup.rs).
 fn compute_mtu(domain_len: usize) -> Result<u32, ClientError> {
     // DNS query overhead + domain length considerations
@@ -558,10 +1569,94 @@ fn compute_mtu(domain_len: usize) -> Result<u32, ClientError> {
     const DOMAIN_OVERHEAD_PER_CHAR: u32 = 1;
     let overhead = domain_len as u32 * DOMAIN_OVERHEAD_PER_CHAR;
     if overhead >= BASE_MTU {
-        return Err(ClientError::new("Domain too long for DNS tunneling"));
+        return Err(ClientError::config("Domain too long for DNS tunneling"));
     }
     Ok(BASE_MTU - overhead)
 }
 
 // Re-export PathManager trait for multipath
 use slipstream_quic::multipath::PathManager;
+
+#[cfg(test)]
+mod tests {
+    use super::QueryTransports;
+    use std::collections::{HashMap, VecDeque};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn truncated_answers_are_retried_over_tcp() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let resolver = listener.local_addr().unwrap();
+            let (answers, mut answer_rx) = mpsc::unbounded_channel();
+            let mut transports = QueryTransports {
+                udp: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+                doh: HashMap::new(),
+                dot: HashMap::new(),
+                doq: HashMap::new(),
+                tcp: HashMap::new(),
+                sent_udp: VecDeque::new(),
+                answers,
+            };
+            let query = b"\x12\x34query".to_vec();
+            transports.send(query.clone(), resolver).await.unwrap();
+
+            // Answers to no query sent, or from another resolver, are left be
+            transports.retry_over_tcp(b"\x43\x21", resolver);
+            transports.retry_over_tcp(b"\x12\x34", "127.0.0.1:9".parse().unwrap());
+            assert!(transports.tcp.is_empty());
+
+            transports.retry_over_tcp(b"\x12\x34\x82\x00", resolver);
+            assert!(transports.sent_udp.is_empty());
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut retried = vec![0u8; 2 + query.len()];
+            stream.read_exact(&mut retried).await.unwrap();
+            assert_eq!(&retried[..2], &[0, query.len() as u8]);
+            assert_eq!(&retried[2..], &query[..]);
+
+            stream.write_all(b"\x00\x02ok").await.unwrap();
+            assert_eq!(answer_rx.recv().await, Some((b"ok".to_vec(), resolver)));
+        });
+    }
+
+    #[test]
+    fn certificate_alerts_are_auth_failures() {
+        use super::handshake_failure;
+        use slipstream_core::exit::{EXIT_AUTH, EXIT_HANDSHAKE};
+        use slipstream_quic::{CloseCause, CloseFrame};
+
+        let transport = |error_code| CloseFrame {
+            is_app: false,
+            error_code,
+            reason: String::new(),
+        };
+        // bad_certificate, certificate_unknown and certificate_required.
+        for code in [0x100 + 42, 0x100 + 46, 0x100 + 116] {
+            let cause = CloseCause::Peer(transport(code));
+            assert_eq!(handshake_failure(&cause).exit_code(), EXIT_AUTH);
+            let cause = CloseCause::Local(transport(code));
+            assert_eq!(handshake_failure(&cause).exit_code(), EXIT_AUTH);
+        }
+        // handshake_failure, a plain transport error and an application close.
+        let app = CloseFrame {
+            is_app: true,
+            error_code: 0x100 + 42,
+            reason: String::new(),
+        };
+        for cause in [
+            CloseCause::Peer(transport(0x100 + 40)),
+            CloseCause::Peer(transport(0x0a)),
+            CloseCause::Peer(app),
+            CloseCause::HandshakeTimeout,
+            CloseCause::IdleTimeout,
+        ] {
+            assert_eq!(handshake_failure(&cause).exit_code(), EXIT_HANDSHAKE);
+        }
+    }
+}
@@ -5,10 +5,11 @@
 use crate::dns::{normalize_dual_stack_addr, ResolverState};
 use crate::error::ClientError;
 use crate::pacing::PathQuality;
-use slipstream_core::ResolverMode;
-use slipstream_quic::multipath::PathManager;
+use slipstream_core::{log_limited, ResolverMode};
+use slipstream_quic::multipath::{PathEvent, PathManager, PathMode};
 use slipstream_quic::ClientConnection;
 use std::net::SocketAddr;
+use tracing::{info, warn, Level};
 
 const AUTHORITATIVE_LOOP_MULTIPLIER: usize = 4;
 
@@ -20,15 +21,63 @@ pub(crate) fn apply_path_mode_tquic(
     if !refresh_resolver_path_tquic(conn, resolver) {
         return Ok(());
     }
+    let Some(path_id) = resolver.path_id_tquic else {
+        return Ok(());
+    };
 
-    // tquic doesn't have per-path mode settings like picoquic
-    // The multipath scheduling algorithm handles this at the config level
-    // For now, we track the mode but don't apply specific per-path settings
+    if conn.path_mode(path_id) != path_mode_for_resolver(resolver) {
+        configure_path_tquic(conn, resolver, path_id).map_err(|e| {
+            ClientError::new(format!("Failed to set mode for {}: {}", resolver.addr, e))
+        })?;
+    }
 
     resolver.added = true;
     Ok(())
 }
 
+/// Set the mode of a resolver path.
+pub(crate) fn configure_path_tquic(
+    conn: &mut ClientConnection,
+    resolver: &ResolverState,
+    path_id: u64,
+) -> Result<(), slipstream_quic::Error> {
+    conn.set_path_mode(path_id, path_mode_for_resolver(resolver))
+}
+
+/// Map a resolver mode onto the path mode the scheduler honours.
+///
+/// Authoritative paths answer directly and are polled for downstream data, like
+/// picoquic's authoritative path mode; recursive paths carry both directions.
+/// Standby resolvers are backup paths whatever their mode.
+fn path_mode_for_resolver(resolver: &ResolverState) -> PathMode {
+    if resolver.standby {
+        return PathMode::Backup;
+    }
+    match resolver.mode {
+        ResolverMode::Authoritative => PathMode::RecvPrimary,
+        ResolverMode::Recursive => PathMode::Normal,
+    }
+}
+
+/// Whether any non-backup path is usable; backup paths stay quiet until none is.
+///
+/// A path losing most of what is sent on it, or whose resolver has lately been
+/// refusing queries, does not count as usable.
+pub(crate) fn primary_path_available(
+    conn: &ClientConnection,
+    resolvers: &[ResolverState],
+    now: u64,
+) -> bool {
+    resolvers.iter().any(|resolver| {
+        resolver.added
+            && !resolver.quality.is_degraded()
+            && !resolver.liveness.is_rate_limited(now)
+            && resolver
+                .path_id_tquic
+                .is_some_and(|path_id| conn.path_mode(path_id) != PathMode::Backup)
+    })
+}
+
 /// Refresh resolver path information from tquic connection.
 pub(crate) fn refresh_resolver_path_tquic(
     conn: &mut ClientConnection,
@@ -69,40 +118,150 @@ pub(crate) fn fetch_path_quality_tquic(
     }
 }
 
+/// Sample the quality of every live path and PING the ones that went quiet.
+///
+/// Failover compares paths on these samples, so an idle path must not be judged
+/// on counters from whenever it last carried traffic.
+pub(crate) fn update_path_quality_tquic(
+    conn: &mut ClientConnection,
+    resolvers: &mut [ResolverState],
+    now: u64,
+) {
+    for resolver in resolvers.iter_mut() {
+        let Some(path_id) = resolver.path_id_tquic.filter(|_| resolver.added) else {
+            continue;
+        };
+        if resolver.quality.sample_due(now) {
+            if let Some(info) = conn.path_info(path_id) {
+                resolver.quality.record(now, &info);
+            }
+        }
+        if resolver.quality.probe_due(now) {
+            match conn.ping_path(path_id) {
+                Ok(()) => resolver.quality.on_probe_sent(now),
+                Err(e) => log_limited!(
+                    Level::DEBUG,
+                    "Failed to probe path to {}: {}",
+                    resolver.addr,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// Probe resolvers whose path was abandoned again once their wait is over.
+///
+/// A failed probe doubles the wait, starting from `retry_after_us`.
+pub(crate) fn reprobe_resolvers_tquic(
+    conn: &mut ClientConnection,
+    resolvers: &mut [ResolverState],
+    now: u64,
+    retry_after_us: u64,
+) {
+    for resolver in resolvers.iter_mut().skip(1) {
+        if resolver.added
+            || resolver.path_id_tquic.is_some()
+            || resolver.next_probe_at == 0
+            || now < resolver.next_probe_at
+        {
+            continue;
+        }
+        match conn.probe_path(resolver.addr) {
+            Ok(path_id) => {
+                resolver.path_id_tquic = Some(path_id);
+                resolver.probe_attempts = 0;
+                resolver.next_probe_at = 0;
+                if let Err(e) = configure_path_tquic(conn, resolver, path_id) {
+                    warn!("Failed to set mode for {}: {}", resolver.addr, e);
+                }
+                info!("Probing path to {} again", resolver.addr);
+            }
+            Err(e) => {
+                resolver.probe_attempts = resolver.probe_attempts.saturating_add(1);
+                let backoff = retry_after_us.max(1) << resolver.probe_attempts.min(6);
+                resolver.next_probe_at = now.saturating_add(backoff);
+                log_limited!(
+                    Level::DEBUG,
+                    "Failed to probe path to {} again: {}",
+                    resolver.addr,
+                    e
+                );
+            }
+        }
+    }
+}
+
 /// Drain path events from the tquic connection and update resolver state.
 pub(crate) fn drain_path_events_tquic(
     conn: &mut ClientConnection,
     resolvers: &mut [ResolverState],
+    now: u64,
 ) {
-    let events = conn.drain_path_events();
-    if events.is_empty() {
-        return;
-    }
-
-    for event in events {
-        match event {
-            slipstream_quic::multipath::PathEvent::Available(path_id) => {
-                // Find resolver by checking which one this path might belong to
-                // In tquic, we need to query the connection for path addresses
-                // For now, mark the first unassigned resolver as having this path
-                for resolver in resolvers.iter_mut() {
-                    if resolver.path_id_tquic.is_none() && !resolver.added {
-                        resolver.path_id_tquic = Some(path_id);
-                        resolver.added = true;
-                        break;
-                    }
+    for event in conn.drain_path_events() {
+        handle_path_event_tquic(event, resolvers, now);
+    }
+}
+
+/// Update resolver state for one path event.
+pub(crate) fn handle_path_event_tquic(event: PathEvent, resolvers: &mut [ResolverState], now: u64) {
+    match event {
+        PathEvent::Available(path_id) => {
+            // Find resolver by checking which one this path might belong to
+            // In tquic, we need to query the connection for path addresses
+            // For now, mark the first unassigned resolver as having this path
+            for resolver in resolvers.iter_mut() {
+                if resolver.path_id_tquic.is_none() && !resolver.added {
+                    resolver.path_id_tquic = Some(path_id);
+                    resolver.added = true;
+                    break;
                 }
             }
-            slipstream_quic::multipath::PathEvent::Deleted(path_id) => {
-                if let Some(resolver) = find_resolver_by_path_id_mut(resolvers, path_id) {
-                    reset_resolver_path_tquic(resolver);
-                }
+        }
+        PathEvent::Deleted(path_id) => {
+            if let Some(resolver) = find_resolver_by_path_id_mut(resolvers, path_id) {
+                reset_resolver_path_tquic(resolver);
+            }
+        }
+        PathEvent::Loss {
+            path_id,
+            lost_packets,
+        } => {
+            if let Some(resolver) = find_resolver_by_path_id_mut(resolvers, path_id) {
+                resolver.liveness.on_loss(now);
+                log_limited!(
+                    Level::DEBUG,
+                    "{} packets lost via {}; polling less",
+                    lost_packets,
+                    resolver.addr
+                );
             }
-            _ => {}
         }
+        _ => {}
     }
 }
 
+/// Make the next resolver that is not a standby the primary, after the
+/// handshake through the current one timed out. Returns false if there is none.
+///
+/// The failed resolver moves to the back, so repeated calls go round all of them.
+pub(crate) fn promote_next_resolver(resolvers: &mut [ResolverState]) -> bool {
+    let Some(next) = resolvers
+        .iter()
+        .skip(1)
+        .position(|resolver| !resolver.standby)
+    else {
+        return false;
+    };
+    resolvers.rotate_left(next + 1);
+    for resolver in resolvers.iter_mut() {
+        reset_resolver_path_tquic(resolver);
+    }
+    resolvers[0].added = true;
+    resolvers[0].path_id_tquic = Some(0);
+    true
+}
+
 /// Reset resolver path state.
 pub(crate) fn reset_resolver_path_tquic(resolver: &mut ResolverState) {
     resolver.path_id_tquic = None;
@@ -117,7 +276,6 @@ pub(crate) fn loop_burst_total(resolvers: &[ResolverState], base: usize) -> usiz
 }
 
 /// Calculate max poll burst for a path.
-#[allow(dead_code)]
 pub(crate) fn path_poll_burst_max(resolver: &ResolverState) -> usize {
     64usize.saturating_mul(path_loop_multiplier(resolver.mode))
 }
@@ -147,3 +305,63 @@ fn find_resolver_by_path_id_mut(
         .iter_mut()
         .find(|resolver| resolver.path_id_tquic == Some(path_id))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::promote_next_resolver;
+    use crate::dns::resolve_resolvers;
+    use slipstream_core::{AddressFamily, HostPort, QnameEncoding, ResolverMode, ResolverSpec};
+    use slipstream_dns::RR_TXT;
+
+    fn resolvers(specs: &[(u16, bool)]) -> Vec<crate::dns::ResolverState> {
+        let specs: Vec<_> = specs
+            .iter()
+            .map(|&(port, standby)| ResolverSpec {
+                resolver: HostPort {
+                    host: "127.0.0.1".to_string(),
+                    port,
+                    family: AddressFamily::V4,
+                },
+                mode: ResolverMode::Recursive,
+                standby,
+                record_type: RR_TXT,
+                qname_encoding: QnameEncoding::Base32,
+                doh_url: None,
+                dot_server: None,
+                doq_server: None,
+            })
+            .collect();
+        resolve_resolvers(&specs, 900, false).unwrap()
+    }
+
+    fn ports(resolvers: &[crate::dns::ResolverState]) -> Vec<u16> {
+        resolvers
+            .iter()
+            .map(|resolver| resolver.addr.port())
+            .collect()
+    }
+
+    #[test]
+    fn promotion_skips_standbys_and_moves_the_failed_resolver_back() {
+        let mut resolvers = resolvers(&[(1, false), (2, true), (3, false)]);
+        assert!(promote_next_resolver(&mut resolvers));
+        assert_eq!(ports(&resolvers), vec![3, 1, 2]);
+        assert!(resolvers[0].added);
+        assert_eq!(resolvers[0].path_id_tquic, Some(0));
+        assert!(resolvers[1..]
+            .iter()
+            .all(|resolver| !resolver.added && resolver.path_id_tquic.is_none()));
+
+        // Repeated timeouts go round every resolver that is not a standby
+        assert!(promote_next_resolver(&mut resolvers));
+        assert_eq!(ports(&resolvers), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn promotion_fails_without_another_candidate() {
+        let mut resolvers = resolvers(&[(1, false), (2, true)]);
+        assert!(!promote_next_resolver(&mut resolvers));
+        assert_eq!(ports(&resolvers), vec![1, 2]);
+        assert_eq!(resolvers[0].path_id_tquic, Some(0));
+    }
+}
@@ -0,0 +1,63 @@
+//! Graceful shutdown on SIGINT and SIGTERM.
+//!
+//! The first signal stops accepting TCP connections and lets open streams
+//! drain for the grace period; a second one closes the tunnel at once.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::warn;
+
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of shutdown signals received so far.
+pub(crate) fn signal_count() -> usize {
+    SIGNALS.load(Ordering::Relaxed)
+}
+
+fn on_signal(wake: &Notify) {
+    SIGNALS.fetch_add(1, Ordering::Relaxed);
+    wake.notify_one();
+}
+
+/// Count SIGINT and SIGTERM, waking the main loop on each.
+#[cfg(unix)]
+pub(crate) fn spawn_signal_listener(wake: Arc<Notify>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    // Registered before spawning so a signal that arrives before the task first runs is kept.
+    let (mut sigint, mut sigterm) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(sigint), Ok(sigterm)) => (sigint, sigterm),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Failed to install shutdown signal handlers: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = sigint.recv() => on_signal(&wake),
+                Some(()) = sigterm.recv() => on_signal(&wake),
+                else => break,
+            }
+        }
+    });
+}
+
+/// Count Ctrl-C presses, the nearest thing to SIGTERM off Unix.
+#[cfg(not(unix))]
+pub(crate) fn spawn_signal_listener(wake: Arc<Notify>) {
+    tokio::spawn(async move {
+        loop {
+            match tokio::signal::ctrl_c().await {
+                Ok(()) => on_signal(&wake),
+                Err(e) => {
+                    warn!("Failed to install Ctrl-C handler: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
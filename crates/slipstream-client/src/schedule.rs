@@ -0,0 +1,164 @@
+//! Ordering of outgoing QUIC packets within the per-loop query budget.
+//!
+//! Each packet from `poll_send` costs at least one DNS query and the loop only
+//! sends so many per iteration. Instead of draining them first come, first
+//! served, packets the peer has to acknowledge go ahead of ACK-only ones and
+//! retransmissions ahead of everything, so a full budget cuts the packets that
+//! are cheapest to lose.
+//!
+//! Header protection hides the frame types, so packets are classified by shape:
+//! a short-header packet too small to carry more than an ACK frame counts as
+//! ACK-only. A small PING or flow-control update may land there too, so packets
+//! past the budget are never cut: they wait, ahead of newer packets of their
+//! class, for the next loop.
+
+use std::net::SocketAddr;
+
+/// Short-header packets up to this size are assumed to carry only ACK frames:
+/// header, connection ID, packet number, a few ACK ranges and the AEAD tag.
+const ACK_ONLY_MAX_LEN: usize = 64;
+
+/// Header form bit; set on long-header (handshake) packets.
+const LONG_HEADER_FORM: u8 = 0x80;
+
+/// Send priority of an outgoing packet, highest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SendClass {
+    /// Carries data resent after a loss.
+    Retransmit,
+    /// Carries frames the peer must acknowledge.
+    AckEliciting,
+    /// Carries only acknowledgements.
+    AckOnly,
+}
+
+pub(crate) fn classify_packet(packet: &[u8]) -> SendClass {
+    match packet.first() {
+        Some(first) if first & LONG_HEADER_FORM == 0 && packet.len() <= ACK_ONLY_MAX_LEN => {
+            SendClass::AckOnly
+        }
+        _ => SendClass::AckEliciting,
+    }
+}
+
+/// Holds packets that did not fit a loop's budget until the next loop.
+#[derive(Debug, Default)]
+pub(crate) struct PacketScheduler {
+    /// Packets past the last budget, in send order, with the class they had.
+    deferred: Vec<(SendClass, (Vec<u8>, SocketAddr))>,
+}
+
+impl PacketScheduler {
+    /// Order deferred and new `packets` by [`SendClass`] and return the first
+    /// `budget`, plus any retransmissions beyond it. The rest wait for the next
+    /// call.
+    ///
+    /// `lost_bytes` is what tquic declared lost since the previous call. tquic
+    /// resends lost data ahead of new data, so the leading new ACK-eliciting
+    /// packets covering that many bytes are treated as retransmissions. Holding
+    /// one back would only get it declared lost again, so they always go out.
+    /// Packets of the same class keep their order, deferred ones first.
+    pub(crate) fn schedule(
+        &mut self,
+        packets: Vec<(Vec<u8>, SocketAddr)>,
+        lost_bytes: u64,
+        budget: usize,
+    ) -> Vec<(Vec<u8>, SocketAddr)> {
+        let mut retransmit_bytes = lost_bytes;
+        let mut classified = std::mem::take(&mut self.deferred);
+        classified.extend(packets.into_iter().map(|packet| {
+            let mut class = classify_packet(&packet.0);
+            if class == SendClass::AckEliciting && retransmit_bytes > 0 {
+                class = SendClass::Retransmit;
+                retransmit_bytes = retransmit_bytes.saturating_sub(packet.0.len() as u64);
+            }
+            (class, packet)
+        }));
+        // Stable, so tquic's order survives within a class.
+        classified.sort_by_key(|(class, _)| *class);
+        let retransmits = classified
+            .iter()
+            .take_while(|(class, _)| *class == SendClass::Retransmit)
+            .count();
+        let keep = budget.max(retransmits).min(classified.len());
+        self.deferred = classified.split_off(keep);
+        classified.into_iter().map(|(_, packet)| packet).collect()
+    }
+
+    /// Packets waiting for the next loop.
+    pub(crate) fn deferred(&self) -> usize {
+        self.deferred.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify_packet, PacketScheduler, SendClass};
+    use std::net::SocketAddr;
+
+    fn packet(first: u8, len: usize, tag: u8) -> (Vec<u8>, SocketAddr) {
+        let mut data = vec![tag; len];
+        data[0] = first;
+        (data, "127.0.0.1:53".parse().unwrap())
+    }
+
+    fn tags(packets: &[(Vec<u8>, SocketAddr)]) -> Vec<u8> {
+        packets.iter().map(|(data, _)| data[1]).collect()
+    }
+
+    #[test]
+    fn classifies_by_header_form_and_size() {
+        assert_eq!(classify_packet(&packet(0x40, 40, 0).0), SendClass::AckOnly);
+        assert_eq!(
+            classify_packet(&packet(0x40, 200, 0).0),
+            SendClass::AckEliciting
+        );
+        assert_eq!(
+            classify_packet(&packet(0xc0, 40, 0).0),
+            SendClass::AckEliciting
+        );
+    }
+
+    #[test]
+    fn ack_only_packets_go_last_and_wait_first() {
+        let mut scheduler = PacketScheduler::default();
+        let packets = vec![
+            packet(0x40, 40, 1),
+            packet(0x40, 300, 2),
+            packet(0x40, 40, 3),
+            packet(0x40, 300, 4),
+        ];
+        assert_eq!(tags(&scheduler.schedule(packets, 0, 3)), vec![2, 4, 1]);
+        assert_eq!(scheduler.deferred(), 1);
+    }
+
+    #[test]
+    fn retransmissions_survive_the_budget() {
+        let mut scheduler = PacketScheduler::default();
+        let packets = vec![
+            packet(0x40, 40, 1),
+            packet(0x40, 300, 2),
+            packet(0x40, 300, 3),
+            packet(0x40, 300, 4),
+        ];
+        // 400 lost bytes cover the first two ACK-eliciting packets, which are
+        // sent even though the budget only allows one.
+        assert_eq!(tags(&scheduler.schedule(packets, 400, 1)), vec![2, 3]);
+        assert_eq!(scheduler.deferred(), 2);
+    }
+
+    #[test]
+    fn small_packets_past_the_budget_go_out_next_loop() {
+        let mut scheduler = PacketScheduler::default();
+        // A 40-byte PING or MAX_DATA looks the same as an ACK-only packet.
+        let packets = vec![packet(0x40, 300, 1), packet(0x40, 40, 2)];
+        assert_eq!(tags(&scheduler.schedule(packets, 0, 1)), vec![1]);
+        assert_eq!(scheduler.deferred(), 1);
+
+        // It goes out ahead of newer packets of its class.
+        let packets = vec![packet(0x40, 40, 3), packet(0x40, 300, 4)];
+        assert_eq!(tags(&scheduler.schedule(packets, 0, 2)), vec![4, 2]);
+        assert_eq!(tags(&scheduler.schedule(Vec::new(), 0, 2)), vec![3]);
+        assert_eq!(scheduler.deferred(), 0);
+    }
+}
@@ -0,0 +1,529 @@
+//! `slipstream-client self-test`: validate a deployment end to end.
+//!
+//! Runs each stage in order and stops at the first failure, since later stages
+//! cannot succeed without the earlier ones:
+//! 1. delegation: the resolver can resolve NS for the tunnel domain,
+//! 2. txt: TXT queries under the domain reach a server that answers them,
+//! 3. health (with `--control-secret-file`): the server answers an authenticated
+//!    health query,
+//! 4. tunnel: a QUIC handshake completes and a payload round-trips through the
+//!    server's target, which must be an echo service for this check.
+
+use crate::runtime::{run_client, TquicClientConfig};
+use clap::Parser;
+use slipstream_core::exit::{EXIT_AUTH, EXIT_HANDSHAKE};
+use slipstream_core::{resolve_host_port, HostPort, QnameEncoding, ResolverMode, ResolverSpec};
+use slipstream_dns::{
+    base32_encode, build_qname, control_payload, decode_response, encode_query, ControlCommand,
+    HealthStatus, QueryParams, Rcode, CLASS_IN, CONTROL_WINDOW_SECS, EDNS_UDP_PAYLOAD, RR_NS,
+    RR_TXT,
+};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::runtime::Builder;
+use tokio::time::{sleep, timeout};
+
+const DNS_RCODE_REFUSED: u8 = 5;
+const PROBE_ATTEMPTS: usize = 3;
+
+#[derive(Parser, Debug)]
+#[command(about = "Validate a deployment: delegation, TXT reachability, handshake and echo")]
+pub(crate) struct SelfTestArgs {
+    #[arg(long = "resolver", short = 'r', value_parser = crate::parse_resolver)]
+    resolver: HostPort,
+    #[arg(long = "domain", short = 'd', value_parser = crate::parse_domain)]
+    domain: String,
+    #[arg(long = "cert", value_name = "PATH")]
+    cert: Option<String>,
+    /// File holding the server's control secret; enables the health stage.
+    #[arg(long = "control-secret-file", value_name = "PATH")]
+    control_secret_file: Option<PathBuf>,
+    /// Seconds allowed for each stage.
+    #[arg(long = "timeout", default_value_t = 10)]
+    timeout: u64,
+    /// Bytes sent through the tunnel and expected back from the echo target.
+    #[arg(long = "echo-bytes", default_value_t = 4096)]
+    echo_bytes: usize,
+}
+
+/// Result of one self-test stage.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Pass(String),
+    Fail { problem: String, hint: String },
+}
+
+impl Outcome {
+    fn fail(problem: impl Into<String>, hint: impl Into<String>) -> Self {
+        Outcome::Fail {
+            problem: problem.into(),
+            hint: hint.into(),
+        }
+    }
+}
+
+pub(crate) fn run(args: SelfTestArgs) -> i32 {
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+    runtime.block_on(run_stages(&args))
+}
+
+async fn run_stages(args: &SelfTestArgs) -> i32 {
+    let stage_timeout = Duration::from_secs(args.timeout);
+    let resolver = match resolve_host_port(&args.resolver) {
+        Ok(addr) => addr,
+        Err(err) => {
+            report(
+                "resolver",
+                &Outcome::fail(
+                    format!("cannot resolve {}: {}", args.resolver.host, err),
+                    "pass the resolver as an IP address or a resolvable host name",
+                ),
+            );
+            return 1;
+        }
+    };
+
+    let delegation = check_delegation(resolver, &args.domain, stage_timeout).await;
+    report("delegation", &delegation);
+    if matches!(delegation, Outcome::Fail { .. }) {
+        return 1;
+    }
+
+    let txt = check_txt(resolver, &args.domain, stage_timeout).await;
+    report("txt", &txt);
+    if matches!(txt, Outcome::Fail { .. }) {
+        return 1;
+    }
+
+    if let Some(path) = &args.control_secret_file {
+        let health = check_health(resolver, &args.domain, path, stage_timeout).await;
+        report("health", &health);
+        if matches!(health, Outcome::Fail { .. }) {
+            return 1;
+        }
+    }
+
+    let tunnel = check_tunnel(args, stage_timeout).await;
+    report("tunnel", &tunnel);
+    match tunnel {
+        Outcome::Pass(_) => 0,
+        Outcome::Fail { .. } => 1,
+    }
+}
+
+fn report(stage: &str, outcome: &Outcome) {
+    match outcome {
+        Outcome::Pass(detail) => println!("PASS {:<10} {}", stage, detail),
+        Outcome::Fail { problem, hint } => {
+            println!("FAIL {:<10} {}", stage, problem);
+            println!("     {:<10} hint: {}", "", hint);
+        }
+    }
+}
+
+async fn check_delegation(resolver: SocketAddr, domain: &str, wait: Duration) -> Outcome {
+    let rcode = match query_rcode(resolver, domain, RR_NS, wait).await {
+        Ok(rcode) => rcode,
+        Err(err) => return Outcome::fail(err, "check the resolver address and local firewall"),
+    };
+    classify_delegation(resolver, domain, rcode)
+}
+
+fn classify_delegation(resolver: SocketAddr, domain: &str, rcode: Option<u8>) -> Outcome {
+    match rcode {
+        None => Outcome::fail(
+            format!("no answer from {} within the timeout", resolver),
+            "the resolver is unreachable or drops queries from this network",
+        ),
+        Some(code) if code == Rcode::Ok.to_u8() => {
+            Outcome::Pass(format!("{} resolves NS for {}", resolver, domain))
+        }
+        Some(code) if code == Rcode::NameError.to_u8() => Outcome::fail(
+            format!("{} does not exist (NXDOMAIN)", domain),
+            "add an NS record delegating the domain to the slipstream server in the parent zone",
+        ),
+        Some(code) if code == Rcode::ServerFailure.to_u8() => Outcome::fail(
+            format!("delegation for {} is broken (SERVFAIL)", domain),
+            "the delegated name server is unreachable; check the NS/glue records and that the server listens on UDP 53",
+        ),
+        Some(DNS_RCODE_REFUSED) => Outcome::fail(
+            format!("{} refused the query", resolver),
+            "the resolver does not serve this client; try another resolver",
+        ),
+        Some(code) => Outcome::fail(
+            format!("unexpected RCODE {} for NS {}", code, domain),
+            "inspect the delegation with dig +trace",
+        ),
+    }
+}
+
+async fn check_txt(resolver: SocketAddr, domain: &str, wait: Duration) -> Outcome {
+    // A random label keeps the probe out of resolver caches; the server decodes it
+    // as a (meaningless) tunnel payload and answers NOERROR.
+    let label = base32_encode(&rand_bytes::<8>());
+    let qname = format!("{}.{}", label.to_ascii_lowercase(), domain);
+    let rcode = match query_rcode(resolver, &qname, RR_TXT, wait).await {
+        Ok(rcode) => rcode,
+        Err(err) => return Outcome::fail(err, "check the resolver address and local firewall"),
+    };
+    classify_txt(rcode)
+}
+
+fn classify_txt(rcode: Option<u8>) -> Outcome {
+    match rcode {
+        Some(code) if code == Rcode::Ok.to_u8() => {
+            Outcome::Pass("TXT queries reach the server".to_string())
+        }
+        None => Outcome::fail(
+            "TXT query timed out",
+            "the resolver blocks or filters TXT queries, or the server is not running",
+        ),
+        Some(code) if code == Rcode::NameError.to_u8() => Outcome::fail(
+            "TXT query answered NXDOMAIN",
+            "the name server for the domain is not slipstream-server, or its --domain does not match",
+        ),
+        Some(DNS_RCODE_REFUSED) => Outcome::fail(
+            "TXT query refused",
+            "the resolver blocks TXT queries; try another resolver",
+        ),
+        Some(code) => Outcome::fail(
+            format!("TXT query failed with RCODE {}", code),
+            "check the server logs for decode errors",
+        ),
+    }
+}
+
+async fn check_health(
+    resolver: SocketAddr,
+    domain: &str,
+    secret_file: &Path,
+    wait: Duration,
+) -> Outcome {
+    let secret = match std::fs::read(secret_file) {
+        Ok(contents) => contents.trim_ascii().to_vec(),
+        Err(err) => {
+            return Outcome::fail(
+                format!("cannot read {}: {}", secret_file.display(), err),
+                "pass the same file the server uses for --control-secret-file",
+            )
+        }
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let nonce = u32::from_be_bytes(rand_bytes::<4>());
+    let qname = match control_payload(&secret, ControlCommand::Health, now, nonce)
+        .and_then(|payload| build_qname(&payload, domain))
+    {
+        Ok(qname) => qname,
+        Err(err) => {
+            return Outcome::fail(
+                format!("cannot build health query: {}", err),
+                "report this as a bug",
+            )
+        }
+    };
+    match query(resolver, &qname, RR_TXT, wait).await {
+        Ok(response) => classify_health(response.as_deref()),
+        Err(err) => Outcome::fail(err, "check the resolver address and local firewall"),
+    }
+}
+
+fn classify_health(response: Option<&[u8]>) -> Outcome {
+    let Some(response) = response else {
+        return Outcome::fail(
+            "health query timed out",
+            "the resolver dropped the query or the server is not running",
+        );
+    };
+    match decode_response(response).and_then(|payload| HealthStatus::decode(&payload)) {
+        Some(status) => Outcome::Pass(format!(
+            "server up {}s with {} connection(s)",
+            status.uptime_secs, status.connections
+        )),
+        None => Outcome::fail(
+            "server did not accept the health query",
+            format!(
+                "check that the server uses the same --control-secret-file and that both clocks agree within {}s",
+                CONTROL_WINDOW_SECS
+            ),
+        ),
+    }
+}
+
+async fn check_tunnel(args: &SelfTestArgs, wait: Duration) -> Outcome {
+    let port = match free_tcp_port() {
+        Ok(port) => port,
+        Err(err) => {
+            return Outcome::fail(
+                format!("cannot pick a local TCP port: {}", err),
+                "check local socket limits",
+            )
+        }
+    };
+    let resolvers = [ResolverSpec {
+        resolver: args.resolver.clone(),
+        mode: ResolverMode::Recursive,
+        standby: false,
+        record_type: RR_TXT,
+        qname_encoding: QnameEncoding::Base32,
+        doh_url: None,
+        dot_server: None,
+        doq_server: None,
+    }];
+    let config = TquicClientConfig {
+        tcp_listen_port: port,
+        resolvers: &resolvers,
+        domain: &args.domain,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
+        probe_sizes: false,
+        nonce_label: false,
+        cert: args.cert.as_deref(),
+        congestion_control: None,
+        multipath: true,
+        multipath_scheduler: None,
+        gso: false,
+        keep_alive_interval: 400,
+        dead_peer_timeout: args.timeout,
+        query_log: None,
+        qlog_dir: None,
+        session_dir: None,
+        zero_rtt: false,
+        debug_poll: false,
+        debug_streams: false,
+        fail_fast: true,
+        heartbeat_interval: 0,
+        shutdown_grace: 0,
+        handshake_timeout: args.timeout,
+    };
+    let payload = rand_payload(args.echo_bytes);
+    let started = Instant::now();
+
+    tokio::select! {
+        result = run_client(&config) => match result {
+            Ok(_) => Outcome::fail(
+                "connection closed during the handshake",
+                handshake_hint(args),
+            ),
+            Err(err) if matches!(err.exit_code(), EXIT_HANDSHAKE | EXIT_AUTH) => {
+                Outcome::fail(format!("client failed: {}", err), handshake_hint(args))
+            }
+            Err(err) => Outcome::fail(
+                format!("client failed: {}", err),
+                "the server stopped answering; check that it is running and reachable",
+            ),
+        },
+        result = timeout(wait, echo(port, &payload)) => match result {
+            Ok(Ok(())) => Outcome::Pass(format!(
+                "handshake and {}-byte echo in {} ms",
+                payload.len(),
+                started.elapsed().as_millis()
+            )),
+            Ok(Err(err)) => Outcome::fail(
+                format!("echo failed: {}", err),
+                "point the server --target-address at an echo service (slipstream-bench echo)",
+            ),
+            Err(_) => Outcome::fail(
+                "handshake or echo timed out",
+                "a certificate mismatch or a resolver rewriting responses stalls the handshake; \
+                 otherwise check that the server target echoes data",
+            ),
+        },
+    }
+}
+
+fn handshake_hint(args: &SelfTestArgs) -> &'static str {
+    if args.cert.is_some() {
+        "the server certificate does not match --cert, or the server ALPN differs"
+    } else {
+        "the server rejected the handshake; check its logs"
+    }
+}
+
+async fn echo(port: u16, payload: &[u8]) -> std::io::Result<()> {
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => break stream,
+            // The client runtime binds its listener asynchronously.
+            Err(_) => sleep(Duration::from_millis(50)).await,
+        }
+    };
+    stream.write_all(payload).await?;
+    let mut received = vec![0u8; payload.len()];
+    stream.read_exact(&mut received).await?;
+    if received != payload {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "echoed bytes differ from the payload",
+        ));
+    }
+    Ok(())
+}
+
+/// Send a query and return the response RCODE, or `None` if nothing matched in time.
+async fn query_rcode(
+    resolver: SocketAddr,
+    qname: &str,
+    qtype: u16,
+    wait: Duration,
+) -> Result<Option<u8>, String> {
+    let response = query(resolver, qname, qtype, wait).await?;
+    Ok(response.map(|packet| packet[3] & 0x0f))
+}
+
+/// Send a query and return the matching response, or `None` if nothing matched in time.
+async fn query(
+    resolver: SocketAddr,
+    qname: &str,
+    qtype: u16,
+    wait: Duration,
+) -> Result<Option<Vec<u8>>, String> {
+    let bind_addr = if resolver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let udp = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|e| format!("cannot bind UDP socket: {}", e))?;
+    let qname = format!("{}.", qname.trim_end_matches('.'));
+    let id = u16::from_be_bytes(rand_bytes::<2>());
+    let query = encode_query(&QueryParams {
+        id,
+        qname: &qname,
+        qtype,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
+    })
+    .map_err(|e| format!("cannot encode query for {}: {}", qname, e))?;
+
+    let attempt_wait = wait / PROBE_ATTEMPTS as u32;
+    let mut buf = vec![0u8; 4096];
+    for _ in 0..PROBE_ATTEMPTS {
+        udp.send_to(&query, resolver)
+            .await
+            .map_err(|e| format!("cannot send to {}: {}", resolver, e))?;
+        let deadline = Instant::now() + attempt_wait;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match timeout(remaining, udp.recv_from(&mut buf)).await {
+                Ok(Ok((size, _))) if size >= 12 && buf[..2] == id.to_be_bytes() => {
+                    return Ok(Some(buf[..size].to_vec()));
+                }
+                Ok(_) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn free_tcp_port() -> std::io::Result<u16> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Non-cryptographic filler; uniqueness per run is all the probes need.
+fn rand_bytes<const N: usize>() -> [u8; N] {
+    let mut state = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+        ^ u64::from(std::process::id());
+    let mut out = [0u8; N];
+    for byte in out.iter_mut() {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *byte = state as u8;
+    }
+    out
+}
+
+fn rand_payload(len: usize) -> Vec<u8> {
+    let seed = rand_bytes::<1>()[0];
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify_delegation, classify_health, classify_txt, Outcome, DNS_RCODE_REFUSED};
+    use slipstream_dns::{encode_response, HealthStatus, Question, ResponseParams, RR_TXT};
+
+    #[test]
+    fn delegation_failures_carry_hints() {
+        let resolver = "127.0.0.1:53".parse().expect("resolver");
+        assert!(matches!(
+            classify_delegation(resolver, "t.example.com", Some(0)),
+            Outcome::Pass(_)
+        ));
+        match classify_delegation(resolver, "t.example.com", Some(3)) {
+            Outcome::Fail { problem, .. } => assert!(problem.contains("NXDOMAIN")),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+        assert!(matches!(
+            classify_delegation(resolver, "t.example.com", None),
+            Outcome::Fail { .. }
+        ));
+    }
+
+    #[test]
+    fn txt_refusal_is_reported_as_blocked() {
+        match classify_txt(Some(DNS_RCODE_REFUSED)) {
+            Outcome::Fail { hint, .. } => assert!(hint.contains("blocks TXT")),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+        assert!(matches!(classify_txt(Some(0)), Outcome::Pass(_)));
+    }
+
+    #[test]
+    fn health_reports_status_or_rejection() {
+        let question = Question {
+            name: "abc.t.example.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: 1,
+        };
+        let respond = |payload: &[u8]| {
+            encode_response(&ResponseParams {
+                id: 1,
+                rd: true,
+                cd: false,
+                question: &question,
+                payload: Some(payload),
+                rcode: None,
+                backlog_hint: None,
+                compress_names: true,
+                txt_record_size: None,
+                padding_limit: None,
+            })
+            .expect("encode response")
+        };
+        let status = HealthStatus {
+            uptime_secs: 42,
+            connections: 3,
+        };
+        match classify_health(Some(&respond(&status.encode()))) {
+            Outcome::Pass(detail) => assert!(detail.contains("up 42s")),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+        assert!(matches!(
+            classify_health(Some(&respond(b"quic"))),
+            Outcome::Fail { .. }
+        ));
+        assert!(matches!(classify_health(None), Outcome::Fail { .. }));
+    }
+}
@@ -2,14 +2,20 @@
 #![allow(private_interfaces)]
 
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener as TokioTcpListener, TcpStream as TokioTcpStream};
 use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
 
 const STREAM_READ_CHUNK_BYTES: usize = 4096;
+// How long a freshly accepted connection may take to send its first bytes and
+// still have them bundled with the stream open.
+const EARLY_DATA_WAIT: Duration = Duration::from_millis(10);
 
 pub(crate) enum Command {
-    NewStream(TokioTcpStream),
+    NewStream(TokioTcpStream, Vec<u8>),
     StreamData { stream_id: u64, data: Vec<u8> },
     StreamClosed { stream_id: u64 },
     StreamReadError { stream_id: u64 },
@@ -17,23 +23,40 @@ pub(crate) enum Command {
     StreamWriteDrained { stream_id: u64, bytes: usize },
 }
 
+/// Accept local TCP connections until the returned task is aborted.
 pub(crate) fn spawn_acceptor(
     listener: TokioTcpListener,
     command_tx: mpsc::UnboundedSender<Command>,
-) {
+) -> JoinHandle<()> {
     tokio::spawn(async move {
         loop {
             match listener.accept().await {
                 Ok((stream, _)) => {
-                    if command_tx.send(Command::NewStream(stream)).is_err() {
+                    if command_tx.is_closed() {
                         break;
                     }
+                    let command_tx = command_tx.clone();
+                    tokio::spawn(async move {
+                        let (stream, early_data) = read_early_data(stream).await;
+                        let _ = command_tx.send(Command::NewStream(stream, early_data));
+                    });
                 }
                 Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(_) => break,
             }
         }
-    });
+    })
+}
+
+/// Wait briefly for the first TCP payload so it can ride along with the stream open
+/// instead of costing an extra tunnel round trip.
+async fn read_early_data(mut stream: TokioTcpStream) -> (TokioTcpStream, Vec<u8>) {
+    let mut buf = vec![0u8; STREAM_READ_CHUNK_BYTES];
+    match timeout(EARLY_DATA_WAIT, stream.read(&mut buf)).await {
+        Ok(Ok(n)) => buf.truncate(n),
+        _ => buf.clear(),
+    }
+    (stream, buf)
 }
 
 /// Spawn a task that reads TCP data and sends it as StreamData commands for QUIC forwarding.
@@ -0,0 +1,367 @@
+//! DNS over TCP (RFC 7766) and DNS-over-TLS (RFC 7858) transports.
+//!
+//! Queries to a resolver selected with `--resolver-dot` go over TLS to port
+//! 853, or whichever port was given, instead of over UDP, for networks that
+//! let TCP/853 out but not UDP/53. As with DoH, the connection goes to the
+//! resolver address and the configured name is only used for SNI and to
+//! check the certificate. Queries whose UDP answer came back truncated are
+//! asked again over plain TCP to the resolver's own address and port.
+//!
+//! Messages are framed with a two-byte length in front. A small pool of
+//! connections runs as tasks on the client runtime, each opened once there
+//! is a query for it; each writes queries as they come, without waiting for
+//! earlier answers, and hands answers to the runtime tagged with the
+//! resolver address as soon as they arrive, in whatever order the resolver
+//! sends them. A connection that fails takes its pending queries with it,
+//! like lost datagrams, and is opened again for the next query.
+
+use openssl::ssl::{SslConnector, SslMethod};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio_openssl::SslStream;
+use tracing::debug;
+
+/// Connections per DoT resolver.
+const DOT_CONNECTIONS: usize = 2;
+/// Queries a connection may have unanswered before it waits for answers.
+const TCP_PIPELINE_MAX: usize = 64;
+/// How long connecting may take, and how long a connection with queries
+/// pending may go without an answer before it is given up.
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause after a failed connection attempt before the next one.
+const TCP_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Sends queries for one resolver over TCP, or over TLS for DoT.
+///
+/// Dropping it closes the connections once they have written what was
+/// queued.
+pub(crate) struct TcpTransport {
+    queries: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl TcpTransport {
+    /// Start the DoT connections to `server_name` at `resolver`'s IP and
+    /// `port`; answers go to `responses`, tagged with `resolver`. Must be
+    /// called on the runtime the connections should run on.
+    pub(crate) fn spawn_tls(
+        server_name: &str,
+        port: u16,
+        resolver: SocketAddr,
+        responses: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+    ) -> io::Result<Self> {
+        let mut builder =
+            SslConnector::builder(SslMethod::tls_client()).map_err(io::Error::other)?;
+        builder
+            .set_alpn_protos(b"\x03dot")
+            .map_err(io::Error::other)?;
+        let tls = Tls {
+            server_name: server_name.to_string(),
+            connector: builder.build(),
+        };
+        Ok(Self::spawn(
+            Some(tls),
+            port,
+            resolver,
+            responses,
+            DOT_CONNECTIONS,
+        ))
+    }
+
+    /// Start a plain TCP connection to `resolver`, for queries retried after
+    /// a truncated UDP answer. Must be called on the runtime the connection
+    /// should run on.
+    pub(crate) fn spawn_plain(
+        resolver: SocketAddr,
+        responses: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+    ) -> Self {
+        Self::spawn(None, resolver.port(), resolver, responses, 1)
+    }
+
+    fn spawn(
+        tls: Option<Tls>,
+        port: u16,
+        resolver: SocketAddr,
+        responses: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+        connections: usize,
+    ) -> Self {
+        let (queries, queue) = mpsc::unbounded_channel();
+        let pool = Arc::new(Pool {
+            tls,
+            connect_addr: SocketAddr::new(resolver.ip().to_canonical(), port),
+            resolver,
+            queue: Mutex::new(queue),
+            responses,
+        });
+        for _ in 0..connections {
+            tokio::spawn(Arc::clone(&pool).run());
+        }
+        Self { queries }
+    }
+
+    /// Queue `query` for the next connection ready to write.
+    pub(crate) fn send(&self, query: Vec<u8>) -> io::Result<()> {
+        self.queries
+            .send(query)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "TCP connections stopped"))
+    }
+}
+
+/// How DoT connections are secured.
+struct Tls {
+    server_name: String,
+    connector: SslConnector,
+}
+
+/// A connection to a resolver, with or without TLS.
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Stream for S {}
+
+/// What every connection of one resolver shares.
+struct Pool {
+    /// Set for DoT; plain TCP otherwise.
+    tls: Option<Tls>,
+    /// The resolver's IP, without the IPv4-mapped form UDP uses, at the TCP
+    /// or DoT port.
+    connect_addr: SocketAddr,
+    resolver: SocketAddr,
+    /// Queries not yet written, taken by whichever connection is free.
+    queue: Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+    responses: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+}
+
+impl Pool {
+    /// Keep one connection open while there are queries for it, until the
+    /// transport is dropped.
+    async fn run(self: Arc<Self>) {
+        loop {
+            let Some(first) = self.queue.lock().await.recv().await else {
+                return;
+            };
+            let stream = match self.connect().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!(
+                        "{} connection to {} failed: {}",
+                        self.kind(),
+                        self.name(),
+                        e
+                    );
+                    tokio::time::sleep(TCP_RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            match self.serve(stream, Some(first)).await {
+                Ok(()) => return,
+                Err(e) => debug!(
+                    "{} connection to {} closed: {}",
+                    self.kind(),
+                    self.name(),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.tls.is_some() {
+            "DoT"
+        } else {
+            "TCP"
+        }
+    }
+
+    fn name(&self) -> String {
+        match &self.tls {
+            Some(tls) => tls.server_name.clone(),
+            None => self.connect_addr.to_string(),
+        }
+    }
+
+    async fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        let tcp = tokio::time::timeout(TCP_TIMEOUT, TcpStream::connect(self.connect_addr))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+        tcp.set_nodelay(true)?;
+        let Some(tls) = &self.tls else {
+            return Ok(Box::new(tcp));
+        };
+        let ssl = tls
+            .connector
+            .configure()
+            .and_then(|config| config.into_ssl(&tls.server_name))
+            .map_err(io::Error::other)?;
+        let mut stream = SslStream::new(ssl, tcp).map_err(io::Error::other)?;
+        tokio::time::timeout(TCP_TIMEOUT, Pin::new(&mut stream).connect())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))?
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(Box::new(stream))
+    }
+
+    /// Write `first` and then queued queries to `stream` and pass on its
+    /// answers until either side fails. Returns `Ok` once the transport is
+    /// dropped.
+    async fn serve<S: AsyncRead + AsyncWrite>(
+        &self,
+        stream: S,
+        first: Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        let (reader, writer) = tokio::io::split(stream);
+        let pending = Semaphore::new(TCP_PIPELINE_MAX);
+        tokio::select! {
+            result = self.write_queries(writer, first, &pending) => result,
+            result = self.read_answers(reader, &pending) => result,
+        }
+    }
+
+    async fn write_queries<W: AsyncWrite + Unpin>(
+        &self,
+        mut writer: W,
+        mut first: Option<Vec<u8>>,
+        pending: &Semaphore,
+    ) -> io::Result<()> {
+        loop {
+            let query = match first.take() {
+                Some(query) => query,
+                None => match self.queue.lock().await.recv().await {
+                    Some(query) => query,
+                    None => {
+                        let _ = writer.shutdown().await;
+                        return Ok(());
+                    }
+                },
+            };
+            let Ok(len) = u16::try_from(query.len()) else {
+                continue;
+            };
+            // The semaphore is never closed, so this only waits
+            if let Ok(permit) = pending.acquire().await {
+                permit.forget();
+            }
+            let mut framed = Vec::with_capacity(2 + query.len());
+            framed.extend_from_slice(&len.to_be_bytes());
+            framed.extend_from_slice(&query);
+            writer.write_all(&framed).await?;
+            writer.flush().await?;
+        }
+    }
+
+    async fn read_answers<R: AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+        pending: &Semaphore,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut chunk = vec![0u8; 4096];
+        loop {
+            while let Some(answer) = take_frame(&mut buf) {
+                if pending.available_permits() < TCP_PIPELINE_MAX {
+                    pending.add_permits(1);
+                }
+                if self.responses.send((answer, self.resolver)).is_err() {
+                    return Ok(());
+                }
+            }
+            // Reading is cancel safe, so a timeout loses nothing; it only
+            // matters while answers are owed.
+            let read = match tokio::time::timeout(TCP_TIMEOUT, reader.read(&mut chunk)).await {
+                Ok(read) => read?,
+                Err(_) if pending.available_permits() == TCP_PIPELINE_MAX => continue,
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "no answer to pending queries",
+                    ))
+                }
+            };
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by the resolver",
+                ));
+            }
+            buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// Remove the first complete length-prefixed message from `buf`, if there
+/// is one.
+fn take_frame(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let len = u16::from_be_bytes([*buf.first()?, *buf.get(1)?]) as usize;
+    if buf.len() < 2 + len {
+        return None;
+    }
+    let frame = buf[2..2 + len].to_vec();
+    buf.drain(..2 + len);
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{take_frame, Pool};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::{mpsc, Mutex};
+
+    #[test]
+    fn frames_split_across_reads_are_reassembled() {
+        let mut buf = vec![0, 3, b'a', b'b'];
+        assert_eq!(take_frame(&mut buf), None);
+        buf.extend_from_slice(&[b'c', 0, 0, 0]);
+        assert_eq!(take_frame(&mut buf), Some(b"abc".to_vec()));
+        assert_eq!(take_frame(&mut buf), Some(Vec::new()));
+        assert_eq!(take_frame(&mut buf), None);
+        assert_eq!(buf, vec![0]);
+        buf.extend_from_slice(&[1, b'd']);
+        assert_eq!(take_frame(&mut buf), Some(b"d".to_vec()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn queries_are_pipelined_and_answers_taken_in_any_order() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let resolver = "192.0.2.1:53".parse().unwrap();
+            let (queries, queue) = mpsc::unbounded_channel();
+            let (responses, mut answers) = mpsc::unbounded_channel();
+            let pool = Arc::new(Pool {
+                tls: None,
+                connect_addr: "192.0.2.1:53".parse().unwrap(),
+                resolver,
+                queue: Mutex::new(queue),
+                responses,
+            });
+            let (ours, mut theirs) = tokio::io::duplex(1024);
+            let serving = tokio::spawn({
+                let pool = Arc::clone(&pool);
+                async move { pool.serve(ours, Some(b"one".to_vec())).await }
+            });
+            for query in [&b"two"[..], b"three"] {
+                queries.send(query.to_vec()).unwrap();
+            }
+
+            // All three arrive before any is answered
+            let mut written = vec![0u8; 2 + 3 + 2 + 3 + 2 + 5];
+            theirs.read_exact(&mut written).await.unwrap();
+            assert_eq!(&written[..5], b"\x00\x03one");
+            assert_eq!(&written[10..], b"\x00\x05three");
+            theirs.write_all(b"\x00\x02b2\x00\x02a1").await.unwrap();
+            assert_eq!(answers.recv().await, Some((b"b2".to_vec(), resolver)));
+            assert_eq!(answers.recv().await, Some((b"a1".to_vec(), resolver)));
+
+            drop(queries);
+            assert!(serving.await.unwrap().is_ok());
+        });
+    }
+}
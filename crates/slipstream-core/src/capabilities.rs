@@ -0,0 +1,261 @@
+//! Capability exchange between client and server.
+//!
+//! When both ends negotiate the slipstream ALPN, each side sends its
+//! capabilities once on its first unidirectional stream. Peers that only speak
+//! the legacy ALPN never see these messages and run with [`Capabilities::baseline`].
+//!
+//! The message is a list of TLV records (`tag: u8, len: u8, value`); unknown
+//! tags are skipped so newer peers can add fields without breaking older ones.
+
+use std::fmt;
+
+/// ALPN offered by peers that support the capability exchange, preferred first.
+pub const ALPN_SLIPSTREAM: &[u8] = b"slipstream/1";
+/// ALPN used by the C implementation and older Rust builds.
+pub const ALPN_LEGACY: &[u8] = b"picoquic_sample";
+
+/// Protocol version spoken by this build.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Oldest protocol version this build can interoperate with.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Query payload encodings.
+pub const ENCODING_BASE32: u8 = 0;
+
+/// Largest capability message either side will buffer.
+pub const MAX_CAPABILITIES_LEN: usize = 1024;
+
+const TAG_VERSION: u8 = 1;
+const TAG_ENCODINGS: u8 = 2;
+const TAG_FRAGMENTATION: u8 = 3;
+const TAG_FEC: u8 = 4;
+const TAG_COMPRESSION: u8 = 5;
+const TAG_HEARTBEAT: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: u16,
+    /// Supported query encodings, most preferred first.
+    pub encodings: Vec<u8>,
+    pub fragmentation: bool,
+    pub fec: bool,
+    /// Supported compression schemes, most preferred first.
+    pub compression: Vec<u8>,
+    /// Answers heartbeats on a control stream, see [`crate::heartbeat`].
+    pub heartbeat: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CapabilityError {
+    message: String,
+}
+
+impl CapabilityError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CapabilityError {}
+
+impl Capabilities {
+    /// What every peer supports, including ones that predate the exchange.
+    pub fn baseline() -> Self {
+        Self {
+            version: MIN_PROTOCOL_VERSION,
+            encodings: vec![ENCODING_BASE32],
+            fragmentation: true,
+            fec: false,
+            compression: Vec::new(),
+            heartbeat: false,
+        }
+    }
+
+    /// Capabilities advertised by this build.
+    pub fn local() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            heartbeat: true,
+            ..Self::baseline()
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_record(&mut out, TAG_VERSION, &self.version.to_be_bytes());
+        push_record(&mut out, TAG_ENCODINGS, &self.encodings);
+        push_record(&mut out, TAG_FRAGMENTATION, &[self.fragmentation as u8]);
+        push_record(&mut out, TAG_FEC, &[self.fec as u8]);
+        push_record(&mut out, TAG_COMPRESSION, &self.compression);
+        push_record(&mut out, TAG_HEARTBEAT, &[self.heartbeat as u8]);
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, CapabilityError> {
+        let mut caps = Self::baseline();
+        let mut version = None;
+        let mut offset = 0;
+        while offset < data.len() {
+            if offset + 2 > data.len() {
+                return Err(CapabilityError::new("truncated capability record header"));
+            }
+            let tag = data[offset];
+            let len = data[offset + 1] as usize;
+            offset += 2;
+            let value = data
+                .get(offset..offset + len)
+                .ok_or_else(|| CapabilityError::new("truncated capability record"))?;
+            offset += len;
+            match tag {
+                TAG_VERSION => {
+                    let bytes: [u8; 2] = value
+                        .try_into()
+                        .map_err(|_| CapabilityError::new("invalid version record"))?;
+                    version = Some(u16::from_be_bytes(bytes));
+                }
+                TAG_ENCODINGS => caps.encodings = value.to_vec(),
+                TAG_FRAGMENTATION => caps.fragmentation = value.first() == Some(&1),
+                TAG_FEC => caps.fec = value.first() == Some(&1),
+                TAG_COMPRESSION => caps.compression = value.to_vec(),
+                TAG_HEARTBEAT => caps.heartbeat = value.first() == Some(&1),
+                _ => {}
+            }
+        }
+        caps.version = version.ok_or_else(|| CapabilityError::new("missing version record"))?;
+        Ok(caps)
+    }
+
+    /// Agree on the features both sides support; `self` decides preference order.
+    pub fn negotiate(&self, peer: &Capabilities) -> Result<Capabilities, CapabilityError> {
+        if peer.version < MIN_PROTOCOL_VERSION {
+            return Err(CapabilityError::new(format!(
+                "peer protocol version {} is older than the minimum {}",
+                peer.version, MIN_PROTOCOL_VERSION
+            )));
+        }
+        let encodings = intersect(&self.encodings, &peer.encodings);
+        if encodings.is_empty() {
+            return Err(CapabilityError::new("no common query encoding"));
+        }
+        Ok(Capabilities {
+            version: self.version.min(peer.version),
+            encodings,
+            fragmentation: self.fragmentation && peer.fragmentation,
+            fec: self.fec && peer.fec,
+            compression: intersect(&self.compression, &peer.compression),
+            heartbeat: self.heartbeat && peer.heartbeat,
+        })
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version={} encodings={:?} fragmentation={} fec={} compression={:?} heartbeat={}",
+            self.version,
+            self.encodings,
+            self.fragmentation,
+            self.fec,
+            self.compression,
+            self.heartbeat
+        )
+    }
+}
+
+/// Capability messages travel on unidirectional streams; tunnel data only uses
+/// client-initiated bidirectional streams.
+pub fn is_control_stream(stream_id: u64) -> bool {
+    stream_id & 0x2 != 0
+}
+
+/// Whether the stream was opened by the client (QUIC stream ID bit 0 clear).
+pub fn is_client_initiated(stream_id: u64) -> bool {
+    stream_id & 0x1 == 0
+}
+
+fn push_record(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    out.push(value.len() as u8);
+    out.extend_from_slice(value);
+}
+
+fn intersect(preferred: &[u8], other: &[u8]) -> Vec<u8> {
+    preferred
+        .iter()
+        .copied()
+        .filter(|value| other.contains(value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, ENCODING_BASE32, MIN_PROTOCOL_VERSION};
+
+    #[test]
+    fn round_trips_and_skips_unknown_tags() {
+        let caps = Capabilities {
+            version: 7,
+            encodings: vec![3, ENCODING_BASE32],
+            fragmentation: true,
+            fec: true,
+            compression: vec![1],
+            heartbeat: true,
+        };
+        let mut encoded = caps.encode();
+        encoded.extend_from_slice(&[200, 3, 1, 2, 3]);
+        assert_eq!(Capabilities::decode(&encoded).expect("decode"), caps);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let encoded = Capabilities::local().encode();
+        assert!(Capabilities::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Capabilities::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn negotiates_common_subset() {
+        let local = Capabilities {
+            version: 3,
+            encodings: vec![2, ENCODING_BASE32],
+            fragmentation: true,
+            fec: true,
+            compression: vec![1, 2],
+            heartbeat: true,
+        };
+        let peer = Capabilities {
+            version: 2,
+            encodings: vec![ENCODING_BASE32, 2],
+            fragmentation: true,
+            fec: false,
+            compression: vec![2],
+            heartbeat: false,
+        };
+        let agreed = local.negotiate(&peer).expect("negotiate");
+        assert_eq!(agreed.version, 2);
+        assert_eq!(agreed.encodings, vec![2, ENCODING_BASE32]);
+        assert!(!agreed.fec);
+        assert_eq!(agreed.compression, vec![2]);
+        assert!(!agreed.heartbeat);
+
+        let ancient = Capabilities {
+            version: MIN_PROTOCOL_VERSION - 1,
+            ..peer.clone()
+        };
+        assert!(local.negotiate(&ancient).is_err());
+        let no_encoding = Capabilities {
+            encodings: vec![9],
+            ..peer
+        };
+        assert!(local.negotiate(&no_encoding).is_err());
+    }
+}
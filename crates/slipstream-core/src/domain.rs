@@ -0,0 +1,425 @@
+//! Hostname validation and normalization.
+//!
+//! Tunnel domains end up as the suffix of every QNAME, so a bad one breaks every
+//! query. These helpers enforce the RFC 1035 limits and the LDH rule
+//! (letters, digits, hyphen) up front. Internationalized labels are converted to
+//! their `xn--` A-label form with Punycode (RFC 3492); no IDNA mapping beyond
+//! lowercasing is applied.
+
+use crate::{ConfigError, ConfigErrorKind, ResolverSpec};
+use std::net::IpAddr;
+
+/// Maximum length of a domain name in presentation form, without the trailing dot.
+pub const MAX_DOMAIN_LEN: usize = 253;
+/// Maximum length of a single label.
+pub const MAX_LABEL_LEN: usize = 63;
+
+const ACE_PREFIX: &str = "xn--";
+
+/// Validate an ASCII domain name without a trailing dot.
+pub fn validate_domain(domain: &str) -> Result<(), ConfigError> {
+    if domain.is_empty() {
+        return Err(ConfigError::new("Domain must not be empty"));
+    }
+    if domain.len() > MAX_DOMAIN_LEN {
+        return Err(ConfigError::new(format!(
+            "Domain is {} bytes long; names are limited to {}",
+            domain.len(),
+            MAX_DOMAIN_LEN
+        )));
+    }
+    for label in domain.split('.') {
+        if label.is_empty() {
+            return Err(ConfigError::new(format!(
+                "Domain '{}' contains an empty label",
+                domain
+            )));
+        }
+        validate_label(label)?;
+    }
+    Ok(())
+}
+
+/// Validate a single ASCII label, including Punycode in `xn--` labels.
+pub fn validate_label(label: &str) -> Result<(), ConfigError> {
+    if label.is_empty() {
+        return Err(ConfigError::new("Domain label must not be empty"));
+    }
+    if label.len() > MAX_LABEL_LEN {
+        return Err(ConfigError::new(format!(
+            "Domain label '{}' is {} bytes long; labels are limited to {}",
+            label,
+            label.len(),
+            MAX_LABEL_LEN
+        )));
+    }
+    if let Some(c) = label
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+    {
+        return Err(ConfigError::new(format!(
+            "Domain label '{}' contains invalid character {:?}",
+            label, c
+        )));
+    }
+    if label.starts_with('-') || label.ends_with('-') {
+        return Err(ConfigError::new(format!(
+            "Domain label '{}' must not start or end with a hyphen",
+            label
+        )));
+    }
+    if label.get(2..4) == Some("--") {
+        let Some(encoded) = strip_ace_prefix(label) else {
+            return Err(ConfigError::new(format!(
+                "Domain label '{}' uses the reserved '--' in positions 3 and 4",
+                label
+            )));
+        };
+        let decoded =
+            punycode_decode(encoded).filter(|chars| !chars.iter().any(|c| c.is_control()));
+        if decoded.is_none() {
+            return Err(ConfigError::new(format!(
+                "Domain label '{}' is not valid Punycode",
+                label
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Normalize a configured domain: trim whitespace and one trailing dot,
+/// lowercase, convert Unicode labels to A-labels and validate the result.
+pub fn normalize_domain(input: &str) -> Result<String, ConfigError> {
+    let trimmed = input.trim();
+    let without_dot = trimmed.strip_suffix('.').unwrap_or(trimmed);
+    if without_dot.is_empty() {
+        return Err(ConfigError::new("Domain must not be empty"));
+    }
+    let labels = without_dot
+        .split('.')
+        .map(to_ascii_label)
+        .collect::<Result<Vec<_>, _>>()?;
+    let domain = labels.join(".");
+    validate_domain(&domain)?;
+    Ok(domain)
+}
+
+/// Whether `name` lies strictly below `domain`, compared case-insensitively on
+/// label boundaries. Trailing dots are ignored.
+pub fn is_subdomain_of(name: &str, domain: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    let domain = domain.trim_end_matches('.');
+    if domain.is_empty() || name.len() <= domain.len() + 1 {
+        return false;
+    }
+    let split = name.len() - domain.len();
+    name.as_bytes()[split - 1] == b'.'
+        && name.as_bytes()[split..].eq_ignore_ascii_case(domain.as_bytes())
+}
+
+/// Reject a tunnel domain that collides with a resolver hostname.
+///
+/// Queries for the resolver's own name would be tunnel queries, or the
+/// resolver's zone would swallow the tunnel domain. Resolvers given as IP
+/// addresses are never in conflict.
+pub fn check_domain_resolvers(domain: &str, resolvers: &[ResolverSpec]) -> Result<(), ConfigError> {
+    let domain = domain.trim_end_matches('.');
+    for spec in resolvers {
+        let host = spec.resolver.host.trim_end_matches('.');
+        if host.parse::<IpAddr>().is_ok() {
+            continue;
+        }
+        if host.eq_ignore_ascii_case(domain) {
+            return Err(ConfigError::with_kind(
+                ConfigErrorKind::DomainIsResolver,
+                format!("Domain '{}' is also used as resolver hostname", domain),
+            ));
+        }
+        if is_subdomain_of(host, domain) {
+            return Err(ConfigError::with_kind(
+                ConfigErrorKind::ResolverUnderDomain,
+                format!(
+                    "Resolver '{}' is inside domain '{}'; resolving it would need the tunnel",
+                    host, domain
+                ),
+            ));
+        }
+        if is_subdomain_of(domain, host) {
+            return Err(ConfigError::with_kind(
+                ConfigErrorKind::DomainUnderResolver,
+                format!(
+                    "Domain '{}' is inside the zone of resolver '{}'",
+                    domain, host
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn to_ascii_label(label: &str) -> Result<String, ConfigError> {
+    if label.is_ascii() {
+        return Ok(label.to_ascii_lowercase());
+    }
+    let lowered: Vec<char> = label.chars().flat_map(char::to_lowercase).collect();
+    if let Some(c) = lowered.iter().find(|c| {
+        c.is_control()
+            || c.is_whitespace()
+            || (c.is_ascii() && !c.is_ascii_alphanumeric() && **c != '-')
+    }) {
+        return Err(ConfigError::new(format!(
+            "Domain label '{}' contains invalid character {:?}",
+            label, c
+        )));
+    }
+    let encoded = punycode_encode(&lowered).ok_or_else(|| {
+        ConfigError::new(format!("Domain label '{}' is too long to encode", label))
+    })?;
+    Ok(format!("{}{}", ACE_PREFIX, encoded))
+}
+
+fn strip_ace_prefix(label: &str) -> Option<&str> {
+    let prefix = label.get(..ACE_PREFIX.len())?;
+    prefix
+        .eq_ignore_ascii_case(ACE_PREFIX)
+        .then(|| &label[ACE_PREFIX.len()..])
+}
+
+// Punycode parameters from RFC 3492 section 5.
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        TMIN
+    } else if k >= bias + TMAX {
+        TMAX
+    } else {
+        k - bias
+    }
+}
+
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn encode_digit(digit: u32) -> char {
+    let byte = if digit < 26 {
+        b'a' + digit as u8
+    } else {
+        b'0' + (digit - 26) as u8
+    };
+    byte as char
+}
+
+fn decode_digit(byte: u8) -> Option<u32> {
+    match byte {
+        b'a'..=b'z' => Some(u32::from(byte - b'a')),
+        b'A'..=b'Z' => Some(u32::from(byte - b'A')),
+        b'0'..=b'9' => Some(u32::from(byte - b'0') + 26),
+        _ => None,
+    }
+}
+
+/// Punycode-encode a label (RFC 3492 section 6.3). `None` on overflow.
+fn punycode_encode(input: &[char]) -> Option<String> {
+    let mut output: String = input.iter().filter(|c| c.is_ascii()).collect();
+    let basic_len = output.len() as u32;
+    if basic_len > 0 {
+        output.push('-');
+    }
+    let mut n = INITIAL_N;
+    let mut delta = 0u32;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic_len;
+    while (handled as usize) < input.len() {
+        let m = input.iter().map(|&c| c as u32).filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in input {
+            let c = c as u32;
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic_len);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+/// Decode a Punycode label (RFC 3492 section 6.2). `None` if malformed.
+fn punycode_decode(input: &str) -> Option<Vec<char>> {
+    let (basic, encoded) = match input.rfind('-') {
+        Some(pos) => (&input[..pos], &input[pos + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i = 0u32;
+    let mut bias = INITIAL_BIAS;
+    let mut bytes = encoded.bytes().peekable();
+    while bytes.peek().is_some() {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(bytes.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_case_trailing_dot_and_unicode() {
+        assert_eq!(
+            normalize_domain(" Tunnel.Example.COM. ").unwrap(),
+            "tunnel.example.com"
+        );
+        assert_eq!(
+            normalize_domain("Bücher.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(
+            normalize_domain("例え.テスト").unwrap(),
+            "xn--r8jz45g.xn--zckzah"
+        );
+        assert_eq!(
+            normalize_domain("xn--bcher-kva.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+    }
+
+    #[test]
+    fn punycode_round_trips() {
+        for label in ["bücher", "münchen-ost", "例え", "ü"] {
+            let chars: Vec<char> = label.chars().collect();
+            let encoded = punycode_encode(&chars).unwrap();
+            assert_eq!(punycode_decode(&encoded).unwrap(), chars, "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_domains() {
+        assert!(normalize_domain("").is_err());
+        assert!(normalize_domain(".").is_err());
+        assert!(normalize_domain("example..com").is_err());
+        assert!(normalize_domain("example.com..").is_err());
+        assert!(normalize_domain("bad_label.example").is_err());
+        assert!(normalize_domain("-lead.example").is_err());
+        assert!(normalize_domain("trail-.example").is_err());
+        assert!(normalize_domain("ab--cd.example").is_err());
+        assert!(normalize_domain("xn--abc-.example").is_err());
+        assert!(normalize_domain("xn--a.example").is_err());
+        assert!(normalize_domain("xn--99999999999999999999.example").is_err());
+        assert!(normalize_domain(&format!("{}.com", "a".repeat(64))).is_err());
+        let long = vec!["a".repeat(63); 4].join(".");
+        assert_eq!(long.len(), 255);
+        assert!(normalize_domain(&long).is_err());
+        assert!(normalize_domain(&long[..253]).is_ok());
+    }
+
+    #[test]
+    fn errors_name_the_offending_label() {
+        let err = normalize_domain("ok.bad_label.example").unwrap_err();
+        assert!(err.to_string().contains("bad_label"), "{}", err);
+        assert!(err.to_string().contains("'_'"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_domains_colliding_with_resolvers() {
+        use crate::{AddressFamily, HostPort, QnameEncoding, ResolverMode};
+
+        let resolver = |host: &str| ResolverSpec {
+            resolver: HostPort {
+                host: host.to_string(),
+                port: 53,
+                family: AddressFamily::V4,
+            },
+            mode: ResolverMode::Recursive,
+            standby: false,
+            record_type: 16,
+            qname_encoding: QnameEncoding::Base32,
+            doh_url: None,
+            dot_server: None,
+            doq_server: None,
+        };
+        let kind = |domain: &str, host: &str| {
+            check_domain_resolvers(domain, &[resolver(host)])
+                .err()
+                .map(|err| err.kind())
+        };
+        assert_eq!(
+            kind("t.example.com", "T.example.com."),
+            Some(ConfigErrorKind::DomainIsResolver)
+        );
+        assert_eq!(
+            kind("t.example.com", "ns.t.example.com"),
+            Some(ConfigErrorKind::ResolverUnderDomain)
+        );
+        assert_eq!(
+            kind("t.ns.example.com", "ns.example.com"),
+            Some(ConfigErrorKind::DomainUnderResolver)
+        );
+        assert_eq!(kind("t.example.com", "dns.example.com"), None);
+        assert_eq!(kind("t.example.com", "1.1.1.1"), None);
+    }
+
+    #[test]
+    fn subdomain_matches_on_label_boundaries() {
+        assert!(is_subdomain_of("a.example.com", "example.com"));
+        assert!(is_subdomain_of("A.EXAMPLE.com.", "example.COM"));
+        assert!(!is_subdomain_of("example.com", "example.com"));
+        assert!(!is_subdomain_of("badexample.com", "example.com"));
+        assert!(!is_subdomain_of("a.example.com", ""));
+    }
+}
@@ -0,0 +1,17 @@
+//! Process exit codes shared by the client and server binaries.
+//!
+//! Scripts can tell failure classes apart without parsing logs. Clap exits with
+//! 2 on usage errors, which matches [`EXIT_CONFIG`].
+
+/// Clean shutdown.
+pub const EXIT_OK: i32 = 0;
+/// Runtime I/O failure: sockets, sends and receives after startup.
+pub const EXIT_RUNTIME: i32 = 1;
+/// Invalid configuration: arguments, files, certificates, domains.
+pub const EXIT_CONFIG: i32 = 2;
+/// The QUIC handshake or capability negotiation failed.
+pub const EXIT_HANDSHAKE: i32 = 3;
+/// Resolvers stopped answering.
+pub const EXIT_RESOLVER: i32 = 4;
+/// The peer's certificate or key was rejected during the TLS handshake.
+pub const EXIT_AUTH: i32 = 5;
@@ -0,0 +1,261 @@
+//! Heartbeats on a dedicated control stream.
+//!
+//! When both sides advertise [`Capabilities::heartbeat`](crate::capabilities::Capabilities),
+//! the client may open a second unidirectional stream and write a [`Heartbeat`]
+//! every interval; the server answers each one on a unidirectional stream of
+//! its own. Every record carries the sender's tunnel byte counters and the last
+//! sequence number it received, so either side can tell which direction
+//! stopped delivering rather than only noticing that the tunnel went quiet.
+//!
+//! Records are fixed-size: five big-endian integers, [`HEARTBEAT_LEN`] bytes.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Encoded size of one heartbeat record.
+pub const HEARTBEAT_LEN: usize = 36;
+/// Intervals a direction may lag before it is reported stalled.
+pub const HEARTBEAT_STALL_COUNT: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Sequence number of this record, starting at 1.
+    pub seq: u64,
+    /// Highest sequence number received from the peer, 0 before the first.
+    pub peer_seq: u64,
+    /// Tunnel stream bytes the sender has written.
+    pub tx_bytes: u64,
+    /// Tunnel stream bytes the sender has read.
+    pub rx_bytes: u64,
+    /// Time between the client's heartbeats.
+    pub interval_ms: u32,
+}
+
+impl Heartbeat {
+    pub fn encode(&self) -> [u8; HEARTBEAT_LEN] {
+        let mut out = [0u8; HEARTBEAT_LEN];
+        out[0..8].copy_from_slice(&self.seq.to_be_bytes());
+        out[8..16].copy_from_slice(&self.peer_seq.to_be_bytes());
+        out[16..24].copy_from_slice(&self.tx_bytes.to_be_bytes());
+        out[24..32].copy_from_slice(&self.rx_bytes.to_be_bytes());
+        out[32..36].copy_from_slice(&self.interval_ms.to_be_bytes());
+        out
+    }
+
+    pub fn decode(data: &[u8; HEARTBEAT_LEN]) -> Self {
+        let u64_at = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[offset..offset + 8]);
+            u64::from_be_bytes(bytes)
+        };
+        Self {
+            seq: u64_at(0),
+            peer_seq: u64_at(8),
+            tx_bytes: u64_at(16),
+            rx_bytes: u64_at(24),
+            interval_ms: u32::from_be_bytes([data[32], data[33], data[34], data[35]]),
+        }
+    }
+}
+
+/// Reassembles heartbeat records from stream reads of any size.
+#[derive(Debug, Default)]
+pub struct HeartbeatReader {
+    partial: Vec<u8>,
+}
+
+impl HeartbeatReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append stream data and return the records it completed.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Heartbeat> {
+        self.partial.extend_from_slice(data);
+        let complete = self.partial.len() / HEARTBEAT_LEN * HEARTBEAT_LEN;
+        let records = self.partial[..complete]
+            .chunks_exact(HEARTBEAT_LEN)
+            .map(|chunk| Heartbeat::decode(chunk.try_into().expect("exact chunk")))
+            .collect();
+        self.partial.drain(..complete);
+        records
+    }
+}
+
+/// A direction of the tunnel that stopped delivering heartbeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stall {
+    /// The peer is heard from, but it has not seen our recent heartbeats.
+    Outbound {
+        unseen: u64,
+        tx_bytes: u64,
+        peer_rx_bytes: u64,
+    },
+    /// Nothing has been heard from the peer for `silent`.
+    Inbound { silent: Duration },
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stall::Outbound {
+                unseen,
+                tx_bytes,
+                peer_rx_bytes,
+            } => write!(
+                f,
+                "outbound blackhole: peer has not seen our last {} heartbeats \
+                 (sent {} tunnel bytes, peer received {})",
+                unseen, tx_bytes, peer_rx_bytes
+            ),
+            Stall::Inbound { silent } => write!(
+                f,
+                "inbound blackhole: no heartbeat from peer for {} ms",
+                silent.as_millis()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatEvent {
+    Stalled(Stall),
+    Recovered,
+}
+
+/// One side of a heartbeat exchange.
+#[derive(Debug)]
+pub struct HeartbeatMonitor {
+    interval: Duration,
+    seq: u64,
+    peer: Option<Heartbeat>,
+    heard_at: Instant,
+    stalled: bool,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            seq: 0,
+            peer: None,
+            heard_at: now,
+            stalled: false,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Build the next heartbeat from this side's counters.
+    pub fn next(&mut self, tx_bytes: u64, rx_bytes: u64) -> Heartbeat {
+        self.seq += 1;
+        Heartbeat {
+            seq: self.seq,
+            peer_seq: self.peer.map_or(0, |peer| peer.seq),
+            tx_bytes,
+            rx_bytes,
+            interval_ms: u32::try_from(self.interval.as_millis()).unwrap_or(u32::MAX),
+        }
+    }
+
+    pub fn on_heartbeat(&mut self, heartbeat: Heartbeat, now: Instant) {
+        if self.peer.is_none_or(|peer| heartbeat.seq > peer.seq) {
+            self.peer = Some(heartbeat);
+        }
+        self.heard_at = now;
+    }
+
+    /// Report a stall or a recovery once, when the state changes.
+    pub fn check(&mut self, tx_bytes: u64, now: Instant) -> Option<HeartbeatEvent> {
+        let stall = self.stall(tx_bytes, now);
+        match (stall, self.stalled) {
+            (Some(stall), false) => {
+                self.stalled = true;
+                Some(HeartbeatEvent::Stalled(stall))
+            }
+            (None, true) => {
+                self.stalled = false;
+                Some(HeartbeatEvent::Recovered)
+            }
+            _ => None,
+        }
+    }
+
+    fn stall(&self, tx_bytes: u64, now: Instant) -> Option<Stall> {
+        let silent = now.saturating_duration_since(self.heard_at);
+        if silent >= self.interval * HEARTBEAT_STALL_COUNT {
+            return Some(Stall::Inbound { silent });
+        }
+        let peer = self.peer?;
+        let unseen = self.seq.saturating_sub(peer.peer_seq);
+        (unseen >= u64::from(HEARTBEAT_STALL_COUNT)).then_some(Stall::Outbound {
+            unseen,
+            tx_bytes,
+            peer_rx_bytes: peer.rx_bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heartbeat, HeartbeatEvent, HeartbeatMonitor, HeartbeatReader, Stall};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reader_reassembles_split_records() {
+        let first = Heartbeat {
+            seq: 1,
+            peer_seq: 0,
+            tx_bytes: 10,
+            rx_bytes: 20,
+            interval_ms: 1_000,
+        };
+        let second = Heartbeat { seq: 2, ..first };
+        let mut encoded = first.encode().to_vec();
+        encoded.extend_from_slice(&second.encode());
+
+        let mut reader = HeartbeatReader::new();
+        assert!(reader.push(&encoded[..20]).is_empty());
+        assert_eq!(reader.push(&encoded[20..40]), vec![first]);
+        assert_eq!(reader.push(&encoded[40..]), vec![second]);
+    }
+
+    #[test]
+    fn reports_each_direction_once() {
+        let interval = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut client = HeartbeatMonitor::new(interval, start);
+        let mut server = HeartbeatMonitor::new(interval, start);
+
+        // Server replies keep arriving but never acknowledge client heartbeats
+        for _ in 0..3 {
+            client.next(500, 0);
+            let reply = server.next(0, 0);
+            client.on_heartbeat(reply, start);
+        }
+        assert_eq!(
+            client.check(500, start),
+            Some(HeartbeatEvent::Stalled(Stall::Outbound {
+                unseen: 3,
+                tx_bytes: 500,
+                peer_rx_bytes: 0,
+            }))
+        );
+        assert_eq!(client.check(500, start), None);
+
+        let seen = client.next(500, 0);
+        server.on_heartbeat(seen, start);
+        client.on_heartbeat(server.next(0, 500), start);
+        assert_eq!(client.check(500, start), Some(HeartbeatEvent::Recovered));
+
+        let later = start + interval * 3;
+        assert_eq!(
+            client.check(500, later),
+            Some(HeartbeatEvent::Stalled(Stall::Inbound {
+                silent: interval * 3
+            }))
+        );
+    }
+}
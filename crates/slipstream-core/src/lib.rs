@@ -1,13 +1,24 @@
 use std::fmt;
 
+pub mod capabilities;
+pub mod domain;
+pub mod exit;
+pub mod heartbeat;
+pub mod logging;
 mod macros;
 pub mod stream;
 pub mod tcp;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub use domain::normalize_domain;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 
 // Error codes for stream handling
 pub const SLIPSTREAM_INTERNAL_ERROR: u64 = 0x101;
 pub const SLIPSTREAM_FILE_CANCEL_ERROR: u64 = 0x105;
+pub const SLIPSTREAM_CONNECTION_LIMIT_ERROR: u64 = 0x106;
+pub const SLIPSTREAM_PROTOCOL_VERSION_ERROR: u64 = 0x107;
+pub const SLIPSTREAM_PATH_UNRESPONSIVE_ERROR: u64 = 0x108;
 
 /// Resolver operating mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,11 +28,56 @@ pub enum ResolverMode {
     Authoritative = 2,
 }
 
+/// How payload bytes are spelled in the labels of a query name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QnameEncoding {
+    /// Case-insensitive, so it survives resolvers that change letter case.
+    #[default]
+    Base32,
+    /// About 20% more payload per query, but letter case must survive.
+    Base64Url,
+    /// Raw bytes in the labels: the most payload, and the fewest resolvers
+    /// that pass it unchanged. Experimental.
+    Binary,
+}
+
+impl QnameEncoding {
+    pub const ALL: [QnameEncoding; 3] = [
+        QnameEncoding::Base32,
+        QnameEncoding::Base64Url,
+        QnameEncoding::Binary,
+    ];
+
+    /// Name on the command line and in logs.
+    pub fn name(self) -> &'static str {
+        match self {
+            QnameEncoding::Base32 => "base32",
+            QnameEncoding::Base64Url => "base64url",
+            QnameEncoding::Binary => "binary",
+        }
+    }
+}
+
 /// Resolver specification with address and mode.
 #[derive(Debug, Clone)]
 pub struct ResolverSpec {
     pub resolver: HostPort,
     pub mode: ResolverMode,
+    /// Kept warm as a backup path and polled only while no other resolver is usable.
+    pub standby: bool,
+    /// Record type (QTYPE) of the queries sent through this resolver.
+    pub record_type: u16,
+    /// Encoding of the payload in the names of queries sent through this resolver.
+    pub qname_encoding: QnameEncoding,
+    /// DNS-over-HTTPS URL the queries are POSTed to, through the resolver
+    /// address; `None` sends them over UDP.
+    pub doh_url: Option<String>,
+    /// DNS-over-TLS server name and port the queries go to, through the
+    /// resolver address; `None` sends them over UDP.
+    pub dot_server: Option<HostPort>,
+    /// DNS-over-QUIC server name and port the queries go to, through the
+    /// resolver address; `None` sends them over UDP.
+    pub doq_server: Option<HostPort>,
 }
 
 /// Client configuration.
@@ -53,15 +109,40 @@ pub struct HostPort {
 
 #[derive(Debug, Clone)]
 pub struct ConfigError {
+    kind: ConfigErrorKind,
     message: String,
 }
 
+/// What a [`ConfigError`] is about, for callers that react to specific problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigErrorKind {
+    /// Malformed or out-of-range value.
+    Invalid,
+    /// The tunnel domain is the hostname of a configured resolver.
+    DomainIsResolver,
+    /// The tunnel domain lies inside a resolver hostname's zone.
+    DomainUnderResolver,
+    /// A resolver hostname lies inside the tunnel domain, so resolving it would
+    /// need the tunnel.
+    ResolverUnderDomain,
+}
+
 impl ConfigError {
     pub fn new(message: impl Into<String>) -> Self {
+        Self::with_kind(ConfigErrorKind::Invalid, message)
+    }
+
+    pub fn with_kind(kind: ConfigErrorKind, message: impl Into<String>) -> Self {
         Self {
+            kind,
             message: message.into(),
         }
     }
+
+    pub fn kind(&self) -> ConfigErrorKind {
+        self.kind
+    }
 }
 
 impl fmt::Display for ConfigError {
@@ -87,18 +168,6 @@ impl AddressKind {
     }
 }
 
-pub fn normalize_domain(input: &str) -> Result<String, ConfigError> {
-    let trimmed = input.trim();
-    if trimmed.is_empty() {
-        return Err(ConfigError::new("Domain must not be empty"));
-    }
-    let without_dot = trimmed.trim_end_matches('.');
-    if without_dot.is_empty() {
-        return Err(ConfigError::new("Domain must not be empty"));
-    }
-    Ok(without_dot.to_string())
-}
-
 pub fn parse_resolver_addresses(addrs: &[String]) -> Result<Vec<HostPort>, ConfigError> {
     let mut parsed = Vec::with_capacity(addrs.len());
 
@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing_subscriber::EnvFilter;
+
+/// Length of a rate-limiting window for [`LogLimiter`].
+const LOG_WINDOW_MS: u64 = 1_000;
+
+/// Messages a single log site may emit per window.
+const LOG_BURST: u32 = 10;
+
+/// Messages dropped by every [`LogLimiter`] since startup.
+static SUPPRESSED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// Install the shared stderr log subscriber (RUST_LOG, default `info`).
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .without_time()
+        .try_init();
+}
+
+/// Total number of log messages suppressed by rate limiting so far.
+pub fn suppressed_log_messages() -> u64 {
+    SUPPRESSED_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Rate limiter for one high-frequency log site.
+///
+/// Lets a small burst of messages through per second and counts the rest; the
+/// count is handed to the next message that gets through. Counting is
+/// approximate when several threads share a site, which is fine for logs.
+/// Use it through [`crate::log_limited`], which keeps one limiter per call site.
+pub struct LogLimiter {
+    window: AtomicU64,
+    emitted: AtomicU32,
+    suppressed: AtomicU64,
+}
+
+impl LogLimiter {
+    pub const fn new() -> Self {
+        Self {
+            window: AtomicU64::new(0),
+            emitted: AtomicU32::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Check whether a message may be logged now.
+    ///
+    /// Returns the number of messages suppressed since the last one let
+    /// through, or `None` if this one should be dropped.
+    pub fn check(&self) -> Option<u64> {
+        static START: OnceLock<Instant> = OnceLock::new();
+        let elapsed_ms = START.get_or_init(Instant::now).elapsed().as_millis() as u64;
+        self.check_at(elapsed_ms)
+    }
+
+    fn check_at(&self, now_ms: u64) -> Option<u64> {
+        // Window 0 means "never used", so number real windows from 1.
+        let window = now_ms / LOG_WINDOW_MS + 1;
+        if self.window.swap(window, Ordering::Relaxed) != window {
+            self.emitted.store(0, Ordering::Relaxed);
+        }
+        if self.emitted.fetch_add(1, Ordering::Relaxed) < LOG_BURST {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            SUPPRESSED_TOTAL.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+impl Default for LogLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_burst_and_reports_suppressed_count() {
+        let limiter = LogLimiter::new();
+        for _ in 0..LOG_BURST {
+            assert_eq!(limiter.check_at(5), Some(0));
+        }
+        assert_eq!(limiter.check_at(10), None);
+        assert_eq!(limiter.check_at(999), None);
+        assert_eq!(limiter.check_at(LOG_WINDOW_MS), Some(2));
+        assert_eq!(limiter.check_at(LOG_WINDOW_MS + 1), Some(0));
+        assert!(suppressed_log_messages() >= 2);
+    }
+}
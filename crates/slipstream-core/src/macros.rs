@@ -19,3 +19,21 @@ macro_rules! drain_stream_data {
         }
     }};
 }
+
+/// Log a high-frequency event at most a few times per second per call site.
+///
+/// Takes a `tracing::Level` and the usual format arguments. When messages were
+/// dropped since the last one, the next carries a `suppressed` field with the count.
+#[macro_export]
+macro_rules! log_limited {
+    ($level:expr, $($arg:tt)+) => {{
+        static LIMITER: $crate::logging::LogLimiter = $crate::logging::LogLimiter::new();
+        if ::tracing::enabled!($level) {
+            match LIMITER.check() {
+                Some(0) => ::tracing::event!($level, $($arg)+),
+                Some(suppressed) => ::tracing::event!($level, suppressed, $($arg)+),
+                None => {}
+            }
+        }
+    }};
+}
@@ -0,0 +1,41 @@
+//! Test fixtures shared across the workspace.
+
+use std::path::{Path, PathBuf};
+
+/// A JSON Lines file in the temp directory, removed when dropped.
+///
+/// The name carries the process ID, so test binaries running side by side
+/// do not write to each other's file.
+pub struct TempLog {
+    path: PathBuf,
+}
+
+impl TempLog {
+    /// Start from an empty `slipstream-<name>-<pid>.jsonl`.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("slipstream-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Parse every line written so far, panicking on one that is not JSON;
+    /// empty while the file does not exist.
+    pub fn json_lines(&self) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("valid json"))
+            .collect()
+    }
+}
+
+impl Drop for TempLog {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -1,6 +1,7 @@
 use slipstream_dns::{
     build_qname, decode_query, decode_response, encode_query, encode_response,
-    max_payload_len_for_domain, QueryParams, Question, ResponseParams, CLASS_IN, RR_TXT,
+    max_payload_len_for_domain, QueryParams, Question, ResponseParams, CLASS_IN, EDNS_UDP_PAYLOAD,
+    RR_TXT,
 };
 use std::env;
 use std::time::Instant;
@@ -62,6 +63,7 @@ fn main() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
     };
     let query = encode_query(&query_params).expect("encode query");
 
@@ -77,6 +79,10 @@ fn main() {
         question: &question,
         payload: Some(&payload),
         rcode: None,
+        backlog_hint: None,
+        compress_names: true,
+        txt_record_size: None,
+        padding_limit: None,
     };
     let response = encode_response(&response_params).expect("encode response");
 
@@ -1,14 +1,13 @@
-use crate::base32;
-use crate::dots;
-
-use crate::name::{encode_name, extract_subdomain_multi, parse_name};
+use crate::name::{encode_name, extract_subdomain_multi, parse_name, unescape_labels};
+use crate::qname;
 use crate::types::{
-    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Rcode, ResponseParams, EDNS_UDP_PAYLOAD,
-    RR_OPT, RR_TXT,
+    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Question, Rcode, ResponseParams,
+    CLASSIC_UDP_PAYLOAD, EDNS_OPT_BACKLOG_HINT, EDNS_OPT_PADDING, EDNS_UDP_PAYLOAD,
+    RESPONSE_PADDING_SIZES, RR_A, RR_AAAA, RR_NULL, RR_OPT, RR_TXT,
 };
 use crate::wire::{
     parse_header, parse_question, parse_question_for_reply, read_u16, read_u32, write_u16,
-    write_u32,
+    write_u32, Header,
 };
 
 pub fn decode_query(packet: &[u8], domain: &str) -> Result<DecodedQuery, DecodeQueryError> {
@@ -18,6 +17,18 @@ pub fn decode_query(packet: &[u8], domain: &str) -> Result<DecodedQuery, DecodeQ
 pub fn decode_query_with_domains(
     packet: &[u8],
     domains: &[&str],
+) -> Result<DecodedQuery, DecodeQueryError> {
+    decode_query_with_record_types(packet, domains, &[RR_TXT, RR_NULL])
+}
+
+/// Decode a query that asks for one of `qtypes`; other types get NAME_ERROR.
+///
+/// [`decode_query_with_domains`] takes TXT and NULL queries. A server that
+/// answers with address records adds A and AAAA.
+pub fn decode_query_with_record_types(
+    packet: &[u8],
+    domains: &[&str],
+    qtypes: &[u16],
 ) -> Result<DecodedQuery, DecodeQueryError> {
     let header = match parse_header(packet) {
         Some(header) => header,
@@ -49,12 +60,12 @@ pub fn decode_query_with_domains(
         });
     }
 
-    let question = match parse_question(packet, header.offset) {
-        Ok((question, _)) => question,
+    let (question, question_end) = match parse_question(packet, header.offset) {
+        Ok(parsed) => parsed,
         Err(_) => return Err(DecodeQueryError::Drop),
     };
 
-    if question.qtype != RR_TXT {
+    if !qtypes.contains(&question.qtype) {
         return Err(DecodeQueryError::Reply {
             id: header.id,
             rd,
@@ -77,8 +88,14 @@ pub fn decode_query_with_domains(
         }
     };
 
-    let undotted = dots::undotify(&subdomain_raw);
-    if undotted.is_empty() {
+    let data = match unescape_labels(&subdomain_raw) {
+        Ok(mut labels) => {
+            qname::strip_nonce_label(&mut labels);
+            labels.concat()
+        }
+        Err(_) => return Err(DecodeQueryError::Drop),
+    };
+    if data.is_empty() {
         return Err(DecodeQueryError::Reply {
             id: header.id,
             rd,
@@ -88,9 +105,9 @@ pub fn decode_query_with_domains(
         });
     }
 
-    let payload = match base32::decode(&undotted) {
-        Ok(payload) => payload,
-        Err(_) => {
+    let payload = match qname::decode_data(&data) {
+        Some(payload) => payload,
+        None => {
             return Err(DecodeQueryError::Reply {
                 id: header.id,
                 rd,
@@ -107,9 +124,58 @@ pub fn decode_query_with_domains(
         cd,
         question,
         payload,
+        edns_udp_payload: query_udp_payload(packet, &header, question_end),
     })
 }
 
+/// UDP payload size from the OPT record among a query's additional records.
+fn query_udp_payload(packet: &[u8], header: &Header, mut offset: usize) -> Option<u16> {
+    for _ in 0..(header.ancount as usize + header.nscount as usize) {
+        let (_, rdata) = parse_record(packet, offset)?;
+        offset = rdata.end;
+    }
+    for _ in 0..header.arcount {
+        let (rtype, rdata) = parse_record(packet, offset)?;
+        offset = rdata.end;
+        if rtype == RR_OPT {
+            // The class field, ahead of TTL and RDATA length, holds the size
+            return read_u16(packet, rdata.start - 8);
+        }
+    }
+    None
+}
+
+/// Response size a query allows: what its OPT record advertises, at least
+/// the 512 bytes every resolver takes and at most [`EDNS_UDP_PAYLOAD`].
+pub fn negotiated_udp_payload(advertised: Option<u16>) -> usize {
+    advertised
+        .unwrap_or(CLASSIC_UDP_PAYLOAD)
+        .clamp(CLASSIC_UDP_PAYLOAD, EDNS_UDP_PAYLOAD) as usize
+}
+
+/// Largest payload a response to `question` carries within `udp_payload`
+/// bytes, backlog hint included.
+pub fn response_payload_capacity(
+    question: &Question,
+    compress_names: bool,
+    txt_record_size: Option<usize>,
+    udp_payload: usize,
+) -> usize {
+    let mut name = Vec::new();
+    if encode_name(&question.name, &mut name).is_err() {
+        return 0;
+    }
+    let answer_name_len = if compress_names { 2 } else { name.len() };
+    // Header, question, OPT record with backlog hint
+    let overhead = 12 + name.len() + 4 + 11 + 8;
+    max_answers_payload_len(
+        question.qtype,
+        udp_payload.saturating_sub(overhead),
+        answer_name_len,
+        txt_record_size,
+    )
+}
+
 pub fn encode_query(params: &QueryParams<'_>) -> Result<Vec<u8>, DnsError> {
     let mut out = Vec::with_capacity(256);
     let mut flags = 0u16;
@@ -136,7 +202,7 @@ pub fn encode_query(params: &QueryParams<'_>) -> Result<Vec<u8>, DnsError> {
         write_u16(&mut out, params.qclass);
     }
 
-    encode_opt_record(&mut out)?;
+    encode_opt_record(&mut out, params.edns_udp_payload, None, None)?;
 
     Ok(out)
 }
@@ -150,12 +216,17 @@ pub fn encode_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError>
         Rcode::NameError
     });
 
-    let mut ancount = 0u16;
+    let mut rdatas = Vec::new();
     if payload_len > 0 && rcode == Rcode::Ok {
-        ancount = 1;
+        rdatas = answer_rdatas(
+            params.question.qtype,
+            params.payload.unwrap_or_default(),
+            params.txt_record_size,
+        )?;
     } else if params.rcode.is_some() {
         rcode = params.rcode.unwrap_or(Rcode::Ok);
     }
+    let ancount = rdatas.len() as u16;
 
     let mut out = Vec::with_capacity(256);
     let mut flags = 0x8000 | 0x0400;
@@ -178,33 +249,210 @@ pub fn encode_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError>
     write_u16(&mut out, params.question.qtype);
     write_u16(&mut out, params.question.qclass);
 
-    if ancount == 1 {
-        out.extend_from_slice(&[0xC0, 0x0C]);
+    for rdata in &rdatas {
+        if params.compress_names {
+            // The question name always starts right after the 12-byte header.
+            out.extend_from_slice(&[0xC0, 0x0C]);
+        } else {
+            encode_name(&params.question.name, &mut out)?;
+        }
         write_u16(&mut out, params.question.qtype);
         write_u16(&mut out, params.question.qclass);
         write_u32(&mut out, 60);
-        let chunk_count = payload_len.div_ceil(255);
-        let rdata_len = payload_len + chunk_count;
-        if rdata_len > u16::MAX as usize {
-            return Err(DnsError::new("payload too long"));
-        }
-        write_u16(&mut out, rdata_len as u16);
-        if let Some(payload) = params.payload {
-            let mut remaining = payload_len;
-            let mut cursor = 0;
-            while remaining > 0 {
-                let chunk_len = remaining.min(255);
-                out.push(chunk_len as u8);
-                out.extend_from_slice(&payload[cursor..cursor + chunk_len]);
-                cursor += chunk_len;
-                remaining -= chunk_len;
+        write_u16(&mut out, rdata.len() as u16);
+        out.extend_from_slice(rdata);
+    }
+
+    let padding = params.padding_limit.and_then(|limit| {
+        padding_len(out.len() + opt_record_len(params.backlog_hint, None), limit)
+    });
+    encode_opt_record(&mut out, EDNS_UDP_PAYLOAD, params.backlog_hint, padding)?;
+
+    Ok(out)
+}
+
+/// Response to the query `params` answers, with the TC bit set and no
+/// answers, telling the resolver to ask again over TCP (RFC 7766).
+pub fn encode_truncated_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError> {
+    let mut out = encode_response(&ResponseParams {
+        payload: None,
+        rcode: Some(Rcode::Ok),
+        backlog_hint: None,
+        ..params.clone()
+    })?;
+    out[2] |= 0x02;
+    Ok(out)
+}
+
+/// Bytes of padding that bring a response of `unpadded_len` bytes, OPT
+/// record included, to the smallest of [`RESPONSE_PADDING_SIZES`] or `limit`
+/// that holds it and the padding option header; `None` if none does.
+fn padding_len(unpadded_len: usize, limit: usize) -> Option<usize> {
+    let min_len = unpadded_len + 4;
+    RESPONSE_PADDING_SIZES
+        .iter()
+        .copied()
+        .chain(std::iter::once(limit))
+        .filter(|&size| size <= limit)
+        .find(|&size| size >= min_len)
+        .map(|size| size - min_len)
+}
+
+/// Answers a response may spread a payload over; sequence numbers are one byte.
+const ADDRESS_RECORDS_MAX: usize = 256;
+
+/// TXT RDATA holding `data_len` bytes in 255-byte character-strings.
+fn txt_rdata_len(data_len: usize) -> usize {
+    data_len + data_len.div_ceil(255)
+}
+
+/// Payload bytes each TXT answer carries, after its sequence number, when
+/// `payload_len` bytes do not fit one answer of `record_size` RDATA bytes;
+/// `None` if they do.
+fn txt_chunk_len(payload_len: usize, record_size: Option<usize>) -> Option<usize> {
+    let record_size = record_size?;
+    if txt_rdata_len(payload_len) <= record_size {
+        return None;
+    }
+    let mut chunk_len = record_size.saturating_sub(1);
+    while chunk_len > 0 && txt_rdata_len(chunk_len + 1) > record_size {
+        chunk_len -= 1;
+    }
+    Some(chunk_len)
+}
+
+/// TXT RDATA carrying `data` as 255-byte character-strings.
+fn txt_rdata(data: &[u8]) -> Vec<u8> {
+    let mut rdata = Vec::with_capacity(txt_rdata_len(data.len()));
+    for chunk in data.chunks(255) {
+        rdata.push(chunk.len() as u8);
+        rdata.extend_from_slice(chunk);
+    }
+    rdata
+}
+
+/// Bytes an A or AAAA record carries after its sequence number.
+fn address_chunk_len(qtype: u16) -> Option<usize> {
+    match qtype {
+        RR_A => Some(3),
+        RR_AAAA => Some(15),
+        _ => None,
+    }
+}
+
+/// RDATA of the answers carrying `payload` in records of type `qtype`.
+///
+/// TXT splits the payload into 255-byte strings behind a length byte each
+/// and NULL carries it as is, both in one record. A TXT payload larger than
+/// `txt_record_size` is cut into one chunk per record instead, each led by
+/// its sequence number inside the strings. A and AAAA records are too small
+/// for one record: the payload, behind its length as a big-endian u16, is
+/// cut into one chunk per record, each led by its sequence number so the
+/// client can undo the reordering resolvers apply to address records. The
+/// last chunk is padded with zeros.
+fn answer_rdatas(
+    qtype: u16,
+    payload: &[u8],
+    txt_record_size: Option<usize>,
+) -> Result<Vec<Vec<u8>>, DnsError> {
+    let Some(chunk_len) = address_chunk_len(qtype) else {
+        if qtype != RR_NULL {
+            if let Some(chunk_len) = txt_chunk_len(payload.len(), txt_record_size) {
+                if chunk_len == 0 || payload.len().div_ceil(chunk_len) > ADDRESS_RECORDS_MAX {
+                    return Err(DnsError::new("payload too long"));
+                }
+                return Ok(payload
+                    .chunks(chunk_len)
+                    .enumerate()
+                    .map(|(seq, chunk)| {
+                        let mut data = Vec::with_capacity(chunk.len() + 1);
+                        data.push(seq as u8);
+                        data.extend_from_slice(chunk);
+                        txt_rdata(&data)
+                    })
+                    .collect());
             }
         }
+        let rdata = if qtype == RR_NULL {
+            payload.to_vec()
+        } else {
+            txt_rdata(payload)
+        };
+        if rdata.len() > u16::MAX as usize {
+            return Err(DnsError::new("payload too long"));
+        }
+        return Ok(vec![rdata]);
+    };
+    if (payload.len() + 2).div_ceil(chunk_len) > ADDRESS_RECORDS_MAX {
+        return Err(DnsError::new("payload too long"));
     }
+    let mut stream = Vec::with_capacity(payload.len() + 2);
+    write_u16(&mut stream, payload.len() as u16);
+    stream.extend_from_slice(payload);
+    Ok(stream
+        .chunks(chunk_len)
+        .enumerate()
+        .map(|(seq, chunk)| {
+            let mut rdata = Vec::with_capacity(chunk_len + 1);
+            rdata.push(seq as u8);
+            rdata.extend_from_slice(chunk);
+            rdata.resize(chunk_len + 1, 0);
+            rdata
+        })
+        .collect())
+}
 
-    encode_opt_record(&mut out)?;
+/// Bytes of the answer section carrying `payload_len` bytes in records of
+/// type `qtype`, with owner names of `owner_len` bytes and TXT answers of at
+/// most `txt_record_size` RDATA bytes.
+pub fn answers_len(
+    qtype: u16,
+    payload_len: usize,
+    owner_len: usize,
+    txt_record_size: Option<usize>,
+) -> usize {
+    // Owner name, then type, class, TTL and RDATA length
+    let record_len = |rdata_len: usize| owner_len + 10 + rdata_len;
+    match address_chunk_len(qtype) {
+        Some(chunk_len) => (payload_len + 2).div_ceil(chunk_len) * record_len(chunk_len + 1),
+        None if qtype == RR_NULL => record_len(payload_len),
+        None => match txt_chunk_len(payload_len, txt_record_size) {
+            Some(chunk_len)
+                if chunk_len == 0 || payload_len.div_ceil(chunk_len) > ADDRESS_RECORDS_MAX =>
+            {
+                usize::MAX
+            }
+            Some(chunk_len) => {
+                let full = payload_len / chunk_len;
+                let rest = payload_len % chunk_len;
+                let mut len = full * record_len(txt_rdata_len(chunk_len + 1));
+                if rest > 0 {
+                    len += record_len(txt_rdata_len(rest + 1));
+                }
+                len
+            }
+            None => record_len(txt_rdata_len(payload_len)),
+        },
+    }
+}
 
-    Ok(out)
+/// Largest payload answers of type `qtype` carry in `room` bytes, with owner
+/// names of `owner_len` bytes and TXT answers of at most `txt_record_size`
+/// RDATA bytes.
+pub fn max_answers_payload_len(
+    qtype: u16,
+    room: usize,
+    owner_len: usize,
+    txt_record_size: Option<usize>,
+) -> usize {
+    let mut payload = room;
+    if let Some(chunk_len) = address_chunk_len(qtype) {
+        payload = payload.min(ADDRESS_RECORDS_MAX * chunk_len - 2);
+    }
+    while payload > 0 && answers_len(qtype, payload, owner_len, txt_record_size) > room {
+        payload -= 1;
+    }
+    payload
 }
 
 pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
@@ -216,7 +464,7 @@ pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
     if rcode != Rcode::Ok {
         return None;
     }
-    if header.ancount != 1 {
+    if header.ancount == 0 {
         return None;
     }
 
@@ -230,12 +478,22 @@ pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
         offset += 4;
     }
 
+    let answers = offset;
     let (_, new_offset) = parse_name(packet, offset).ok()?;
     offset = new_offset;
     if offset + 10 > packet.len() {
         return None;
     }
     let qtype = read_u16(packet, offset)?;
+    if let Some(chunk_len) = address_chunk_len(qtype) {
+        return decode_address_records(packet, answers, header.ancount, qtype, chunk_len);
+    }
+    if qtype == RR_TXT && header.ancount > 1 {
+        return decode_txt_records(packet, answers, header.ancount);
+    }
+    if header.ancount != 1 {
+        return None;
+    }
     offset += 2;
     let _qclass = read_u16(packet, offset)?;
     offset += 2;
@@ -246,49 +504,210 @@ pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
     if offset + rdlen > packet.len() || rdlen < 1 {
         return None;
     }
+    if qtype == RR_NULL {
+        return Some(packet[offset..offset + rdlen].to_vec());
+    }
     if qtype != RR_TXT {
         return None;
     }
 
-    let mut remaining = rdlen;
-    let mut cursor = offset;
-    let mut out = Vec::with_capacity(rdlen);
-    while remaining > 0 {
-        let txt_len = packet[cursor] as usize;
+    let out = txt_strings(&packet[offset..offset + rdlen])?;
+    if out.is_empty() {
+        return None;
+    }
+    Some(out)
+}
+
+/// Concatenated character-strings of a TXT RDATA.
+fn txt_strings(rdata: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(rdata.len());
+    let mut cursor = 0usize;
+    while cursor < rdata.len() {
+        let txt_len = rdata[cursor] as usize;
         cursor += 1;
-        remaining -= 1;
-        if txt_len > remaining {
+        out.extend_from_slice(rdata.get(cursor..cursor + txt_len)?);
+        cursor += txt_len;
+    }
+    Some(out)
+}
+
+/// Reassemble a payload from `count` TXT answers starting at `offset`, each
+/// led by its sequence number, in whatever order the resolver put them.
+fn decode_txt_records(packet: &[u8], mut offset: usize, count: u16) -> Option<Vec<u8>> {
+    let mut chunks: Vec<Option<Vec<u8>>> = vec![None; count as usize];
+    for _ in 0..count {
+        let (rtype, rdata) = parse_record(packet, offset)?;
+        offset = rdata.end;
+        if rtype != RR_TXT {
             return None;
         }
-        out.extend_from_slice(&packet[cursor..cursor + txt_len]);
-        cursor += txt_len;
-        remaining -= txt_len;
+        let mut data = txt_strings(&packet[rdata])?;
+        if data.len() < 2 {
+            return None;
+        }
+        let slot = chunks.get_mut(data[0] as usize)?;
+        if slot.is_some() {
+            return None;
+        }
+        data.remove(0);
+        *slot = Some(data);
     }
-    if out.is_empty() {
-        return None;
+    let mut out = Vec::new();
+    for chunk in chunks {
+        out.extend_from_slice(&chunk?);
     }
     Some(out)
 }
 
+/// Reassemble a payload from `count` A or AAAA answers starting at `offset`,
+/// in whatever order the resolver put them.
+fn decode_address_records(
+    packet: &[u8],
+    mut offset: usize,
+    count: u16,
+    qtype: u16,
+    chunk_len: usize,
+) -> Option<Vec<u8>> {
+    let mut chunks: Vec<Option<&[u8]>> = vec![None; count as usize];
+    for _ in 0..count {
+        let (rtype, rdata) = parse_record(packet, offset)?;
+        offset = rdata.end;
+        if rtype != qtype || rdata.len() != chunk_len + 1 {
+            return None;
+        }
+        let slot = chunks.get_mut(packet[rdata.start] as usize)?;
+        if slot.is_some() {
+            return None;
+        }
+        *slot = Some(&packet[rdata.start + 1..rdata.end]);
+    }
+    let mut stream = Vec::with_capacity(count as usize * chunk_len);
+    for chunk in chunks {
+        stream.extend_from_slice(chunk?);
+    }
+    let len = read_u16(&stream, 0)? as usize;
+    if len == 0 || len > stream.len() - 2 {
+        return None;
+    }
+    stream.truncate(len + 2);
+    stream.drain(..2);
+    Some(stream)
+}
+
+/// Extract the server backlog hint from a response's EDNS0 OPT record, if present.
+pub fn decode_backlog_hint(packet: &[u8]) -> Option<u32> {
+    let header = parse_header(packet)?;
+    if !header.is_response {
+        return None;
+    }
+
+    let mut offset = header.offset;
+    for _ in 0..header.qdcount {
+        let (_, new_offset) = parse_name(packet, offset).ok()?;
+        offset = new_offset.checked_add(4)?;
+    }
+    for _ in 0..(header.ancount as usize + header.nscount as usize) {
+        let (_, rdata) = parse_record(packet, offset)?;
+        offset = rdata.end;
+    }
+    for _ in 0..header.arcount {
+        let (rtype, rdata) = parse_record(packet, offset)?;
+        offset = rdata.end;
+        if rtype != RR_OPT {
+            continue;
+        }
+        let mut cursor = rdata.start;
+        while cursor + 4 <= rdata.end {
+            let code = read_u16(packet, cursor)?;
+            let len = read_u16(packet, cursor + 2)? as usize;
+            cursor += 4;
+            if cursor + len > rdata.end {
+                return None;
+            }
+            if code == EDNS_OPT_BACKLOG_HINT && len == 4 {
+                return read_u32(packet, cursor);
+            }
+            cursor += len;
+        }
+    }
+    None
+}
+
+/// Parse a resource record header at `offset`, returning its type and RDATA range.
+fn parse_record(packet: &[u8], offset: usize) -> Option<(u16, std::ops::Range<usize>)> {
+    let (_, mut offset) = parse_name(packet, offset).ok()?;
+    let rtype = read_u16(packet, offset)?;
+    offset += 8;
+    let rdlen = read_u16(packet, offset)? as usize;
+    offset += 2;
+    if offset + rdlen > packet.len() {
+        return None;
+    }
+    Some((rtype, offset..offset + rdlen))
+}
+
+/// Response code of a response, if it is one this crate knows.
+pub fn response_rcode(packet: &[u8]) -> Option<Rcode> {
+    parse_header(packet)
+        .filter(|header| header.is_response)
+        .and_then(|header| header.rcode)
+}
+
+/// Whether `packet` is a response with the TC bit set.
+pub fn is_truncated(packet: &[u8]) -> bool {
+    parse_header(packet)
+        .map(|header| header.is_response && header.truncated)
+        .unwrap_or(false)
+}
+
 pub fn is_response(packet: &[u8]) -> bool {
     parse_header(packet)
         .map(|header| header.is_response)
         .unwrap_or(false)
 }
 
-fn encode_opt_record(out: &mut Vec<u8>) -> Result<(), DnsError> {
+/// Length of an OPT record with the given options.
+fn opt_record_len(backlog_hint: Option<u32>, padding: Option<usize>) -> usize {
+    11 + backlog_hint.map_or(0, |_| 8) + padding.map_or(0, |len| 4 + len)
+}
+
+fn encode_opt_record(
+    out: &mut Vec<u8>,
+    udp_payload: u16,
+    backlog_hint: Option<u32>,
+    padding: Option<usize>,
+) -> Result<(), DnsError> {
     out.push(0);
     write_u16(out, RR_OPT);
-    write_u16(out, EDNS_UDP_PAYLOAD);
+    write_u16(out, udp_payload);
     write_u32(out, 0);
-    write_u16(out, 0);
+    let rdata_len = opt_record_len(backlog_hint, padding) - 11;
+    let rdata_len = u16::try_from(rdata_len).map_err(|_| DnsError::new("OPT record too long"))?;
+    write_u16(out, rdata_len);
+    if let Some(backlog) = backlog_hint {
+        write_u16(out, EDNS_OPT_BACKLOG_HINT);
+        write_u16(out, 4);
+        write_u32(out, backlog);
+    }
+    if let Some(len) = padding {
+        write_u16(out, EDNS_OPT_PADDING);
+        write_u16(out, len as u16);
+        out.resize(out.len() + len, 0);
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::encode_response;
-    use crate::types::{Question, ResponseParams, CLASS_IN, RR_TXT};
+    use super::{
+        answers_len, decode_backlog_hint, decode_query, decode_response, encode_query,
+        encode_response, encode_truncated_response, is_truncated, negotiated_udp_payload,
+        response_payload_capacity, response_rcode,
+    };
+    use crate::types::{
+        QueryParams, Question, Rcode, ResponseParams, CLASS_IN, RR_A, RR_AAAA, RR_NULL, RR_TXT,
+    };
+    use crate::wire::read_u16;
 
     #[test]
     fn encode_response_rejects_large_payload() {
@@ -305,7 +724,346 @@ mod tests {
             question: &question,
             payload: Some(&payload),
             rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
         };
         assert!(encode_response(&params).is_err());
     }
+
+    #[test]
+    fn truncated_responses_are_recognized() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let payload = vec![7u8; 16];
+        let params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        let mut response = encode_response(&params).expect("encode response");
+        assert!(!is_truncated(&response));
+        let truncated = encode_truncated_response(&params).expect("encode response");
+        assert!(is_truncated(&truncated));
+        assert!(truncated.len() < response.len());
+        assert_eq!(decode_response(&truncated), None);
+        assert_eq!(response_rcode(&truncated), Some(Rcode::Ok));
+        response[2] |= 0x02;
+        assert!(is_truncated(&response));
+        // A query never counts, whatever its flags say
+        response[2] &= 0x7f;
+        assert!(!is_truncated(&response));
+    }
+
+    #[test]
+    fn backlog_hint_round_trips() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let payload = vec![7u8; 300];
+        let mut params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: Some(48_000),
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        let response = encode_response(&params).expect("encode response");
+        assert_eq!(decode_backlog_hint(&response), Some(48_000));
+        assert_eq!(decode_response(&response), Some(payload.clone()));
+
+        params.backlog_hint = None;
+        let response = encode_response(&params).expect("encode response");
+        assert_eq!(decode_backlog_hint(&response), None);
+    }
+
+    #[test]
+    fn padded_responses_land_on_fixed_sizes() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        for (payload_len, limit, padded_len) in [
+            (0, 1232, 128),
+            (40, 1232, 128),
+            (150, 1232, 256),
+            (300, 1232, 512),
+            (600, 1232, 1232),
+            (600, 900, 900),
+        ] {
+            let payload = vec![7u8; payload_len];
+            let mut params = ResponseParams {
+                id: 0x1234,
+                rd: true,
+                cd: false,
+                question: &question,
+                payload: (payload_len > 0).then_some(payload.as_slice()),
+                rcode: None,
+                backlog_hint: Some(1_000),
+                compress_names: true,
+                txt_record_size: None,
+                padding_limit: Some(limit),
+            };
+            let response = encode_response(&params).expect("encode response");
+            assert_eq!(response.len(), padded_len, "payload {}", payload_len);
+            assert_eq!(decode_backlog_hint(&response), Some(1_000));
+            let decoded = decode_response(&response);
+            assert_eq!(decoded, (payload_len > 0).then_some(payload.clone()));
+
+            params.padding_limit = None;
+            let unpadded = encode_response(&params).expect("encode response");
+            assert!(unpadded.len() < padded_len);
+            assert_eq!(decode_response(&unpadded), decoded);
+        }
+    }
+
+    #[test]
+    fn uncompressed_answer_repeats_question_name() {
+        let question = Question {
+            name: "abcdefgh.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let payload = vec![3u8; 100];
+        let mut params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        let compressed = encode_response(&params).expect("encode response");
+        params.compress_names = false;
+        let uncompressed = encode_response(&params).expect("encode response");
+
+        // The wire name is 19 bytes; the pointer takes 2.
+        assert_eq!(uncompressed.len() - compressed.len(), 17);
+        assert_eq!(decode_response(&compressed), Some(payload.clone()));
+        assert_eq!(decode_response(&uncompressed), Some(payload));
+    }
+
+    #[test]
+    fn null_answer_carries_raw_payload() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_NULL,
+            qclass: CLASS_IN,
+        };
+        let payload = vec![5u8; 600];
+        let mut params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        let null = encode_response(&params).expect("encode response");
+        assert_eq!(decode_response(&null), Some(payload.clone()));
+
+        let txt_question = Question {
+            qtype: RR_TXT,
+            ..question.clone()
+        };
+        params.question = &txt_question;
+        let txt = encode_response(&params).expect("encode response");
+        // Three TXT strings, each behind a length byte.
+        assert_eq!(txt.len() - null.len(), 3);
+    }
+
+    #[test]
+    fn address_answers_survive_reordering() {
+        for (qtype, record_len) in [(RR_A, 16), (RR_AAAA, 28)] {
+            let question = Question {
+                name: "a.test.com.".to_string(),
+                qtype,
+                qclass: CLASS_IN,
+            };
+            let payload: Vec<u8> = (0..100).collect();
+            let params = ResponseParams {
+                id: 0x1234,
+                rd: true,
+                cd: false,
+                question: &question,
+                payload: Some(&payload),
+                rcode: None,
+                backlog_hint: None,
+                compress_names: true,
+                txt_record_size: None,
+                padding_limit: None,
+            };
+            let mut response = encode_response(&params).expect("encode response");
+            assert_eq!(decode_response(&response), Some(payload.clone()));
+
+            // Swap the first two answers, as a round-robin resolver might.
+            let answers = 12 + 12 + 4;
+            let (first, second) =
+                response[answers..answers + 2 * record_len].split_at_mut(record_len);
+            first.swap_with_slice(second);
+            assert_eq!(decode_response(&response), Some(payload));
+        }
+    }
+
+    #[test]
+    fn txt_answers_split_and_survive_reordering() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let payload: Vec<u8> = (0..=255).collect();
+        let mut params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload[..100]),
+            rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: Some(200),
+            padding_limit: None,
+        };
+        // A payload that fits one answer keeps the single-record format.
+        let response = encode_response(&params).expect("encode response");
+        assert_eq!(read_u16(&response, 6), Some(1));
+        assert_eq!(decode_response(&response), Some(payload[..100].to_vec()));
+
+        params.payload = Some(&payload);
+        let mut response = encode_response(&params).expect("encode response");
+        assert_eq!(read_u16(&response, 6), Some(2));
+        assert_eq!(decode_response(&response), Some(payload.clone()));
+        assert_eq!(
+            response.len() - 12 - 16 - 11,
+            answers_len(RR_TXT, payload.len(), 2, Some(200))
+        );
+
+        // Move the second answer in front of the first.
+        let answers = 12 + 12 + 4;
+        let first_len = 12 + read_u16(&response, answers + 10).unwrap() as usize;
+        let second_end = response.len() - 11;
+        response[answers..second_end].rotate_left(first_len);
+        assert_eq!(decode_response(&response), Some(payload));
+    }
+
+    #[test]
+    fn address_answers_reject_missing_records() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_AAAA,
+            qclass: CLASS_IN,
+        };
+        let payload = vec![9u8; 40];
+        let params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        let mut response = encode_response(&params).expect("encode response");
+        // Rewrite the last record's sequence number so one goes missing.
+        let answers = 12 + 12 + 4;
+        response[answers + 2 * 28 + 12] = 1;
+        assert_eq!(decode_response(&response), None);
+    }
+
+    #[test]
+    fn query_advertises_udp_payload() {
+        let mut params = QueryParams {
+            id: 7,
+            qname: "nbswy3dp.test.com.",
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns_udp_payload: 900,
+        };
+        let query = encode_query(&params).expect("encode query");
+        let decoded = decode_query(&query, "test.com").expect("decode query");
+        assert_eq!(decoded.edns_udp_payload, Some(900));
+        assert_eq!(negotiated_udp_payload(decoded.edns_udp_payload), 900);
+
+        params.edns_udp_payload = 4096;
+        let query = encode_query(&params).expect("encode query");
+        let decoded = decode_query(&query, "test.com").expect("decode query");
+        assert_eq!(negotiated_udp_payload(decoded.edns_udp_payload), 1232);
+
+        // Strip the OPT record: ARCOUNT 0, and drop its 11 bytes.
+        let mut query = query[..query.len() - 11].to_vec();
+        query[11] = 0;
+        let decoded = decode_query(&query, "test.com").expect("decode query");
+        assert_eq!(decoded.edns_udp_payload, None);
+        assert_eq!(negotiated_udp_payload(decoded.edns_udp_payload), 512);
+    }
+
+    #[test]
+    fn response_capacity_fits_negotiated_size() {
+        for qtype in [RR_TXT, RR_NULL, RR_AAAA] {
+            let question = Question {
+                name: "nbswy3dp.test.com.".to_string(),
+                qtype,
+                qclass: CLASS_IN,
+            };
+            for (compress_names, txt_record_size) in
+                [(true, None), (false, None), (true, Some(100))]
+            {
+                let capacity =
+                    response_payload_capacity(&question, compress_names, txt_record_size, 512);
+                let encode = |len: usize| {
+                    encode_response(&ResponseParams {
+                        id: 1,
+                        rd: true,
+                        cd: false,
+                        question: &question,
+                        payload: Some(&vec![0u8; len]),
+                        rcode: None,
+                        backlog_hint: Some(0),
+                        compress_names,
+                        txt_record_size,
+                        padding_limit: None,
+                    })
+                    .unwrap()
+                    .len()
+                };
+                assert!(encode(capacity) <= 512);
+                assert!(encode(capacity + 1) > 512);
+            }
+        }
+    }
 }
@@ -0,0 +1,184 @@
+//! Authenticated control queries.
+//!
+//! Health checks and other control-plane requests travel as ordinary tunnel
+//! payloads tagged with a magic byte and an HMAC-SHA256 keyed by a secret shared
+//! by client and server. The MAC covers a timestamp that must fall within
+//! [`CONTROL_WINDOW_SECS`] of the server clock. A query that fails verification
+//! is treated as junk tunnel data, so knowing the domain alone reveals nothing
+//! about the server.
+
+use crate::types::DnsError;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// Magic byte to identify control payloads (ASCII 'C' for Control)
+const CONTROL_MAGIC: u8 = 0x43;
+
+/// Signed header: magic (1) + command (1) + timestamp (4) + nonce (4)
+const SIGNED_LEN: usize = 10;
+
+/// Truncated HMAC-SHA256 length.
+const MAC_LEN: usize = 16;
+
+/// Control payload size: signed header + MAC
+pub const CONTROL_PAYLOAD_SIZE: usize = SIGNED_LEN + MAC_LEN;
+
+/// Health status size: magic (1) + command (1) + uptime (4) + connections (4)
+const HEALTH_STATUS_LEN: usize = 10;
+
+/// Largest accepted clock difference between client and server.
+pub const CONTROL_WINDOW_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    Health,
+}
+
+impl ControlCommand {
+    fn to_u8(self) -> u8 {
+        match self {
+            ControlCommand::Health => 1,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(ControlCommand::Health),
+            _ => None,
+        }
+    }
+}
+
+/// Server status returned for [`ControlCommand::Health`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthStatus {
+    pub uptime_secs: u32,
+    pub connections: u32,
+}
+
+impl HealthStatus {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEALTH_STATUS_LEN);
+        out.push(CONTROL_MAGIC);
+        out.push(ControlCommand::Health.to_u8());
+        out.extend_from_slice(&self.uptime_secs.to_be_bytes());
+        out.extend_from_slice(&self.connections.to_be_bytes());
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != HEALTH_STATUS_LEN
+            || data[0] != CONTROL_MAGIC
+            || data[1] != ControlCommand::Health.to_u8()
+        {
+            return None;
+        }
+        Some(Self {
+            uptime_secs: u32::from_be_bytes([data[2], data[3], data[4], data[5]]),
+            connections: u32::from_be_bytes([data[6], data[7], data[8], data[9]]),
+        })
+    }
+}
+
+/// Build a signed control payload.
+///
+/// `unix_secs` is the current time; `nonce` should differ between queries so
+/// resolvers never answer from cache.
+pub fn control_payload(
+    secret: &[u8],
+    command: ControlCommand,
+    unix_secs: u64,
+    nonce: u32,
+) -> Result<Vec<u8>, DnsError> {
+    let mut out = Vec::with_capacity(CONTROL_PAYLOAD_SIZE);
+    out.push(CONTROL_MAGIC);
+    out.push(command.to_u8());
+    out.extend_from_slice(&(unix_secs as u32).to_be_bytes());
+    out.extend_from_slice(&nonce.to_be_bytes());
+    let mac = mac(secret, &out)?;
+    out.extend_from_slice(&mac);
+    Ok(out)
+}
+
+/// Verify a control payload against `secret` and the current time.
+///
+/// Returns `None` for anything that is not a valid, fresh control payload.
+pub fn verify_control(secret: &[u8], data: &[u8], unix_secs: u64) -> Option<ControlCommand> {
+    if data.len() != CONTROL_PAYLOAD_SIZE || data[0] != CONTROL_MAGIC {
+        return None;
+    }
+    let (signed, tag) = data.split_at(SIGNED_LEN);
+    let expected = mac(secret, signed).ok()?;
+    if !openssl::memcmp::eq(&expected, tag) {
+        return None;
+    }
+    // Timestamps are 32-bit on the wire; compare in that space so wraparound is harmless.
+    let sent = u32::from_be_bytes([signed[2], signed[3], signed[4], signed[5]]);
+    let skew = (unix_secs as u32).wrapping_sub(sent) as i32;
+    if u64::from(skew.unsigned_abs()) > CONTROL_WINDOW_SECS {
+        return None;
+    }
+    ControlCommand::from_u8(signed[1])
+}
+
+fn mac(secret: &[u8], data: &[u8]) -> Result<[u8; MAC_LEN], DnsError> {
+    let map_err = |e: openssl::error::ErrorStack| DnsError::new(format!("HMAC failed: {}", e));
+    let key = PKey::hmac(secret).map_err(map_err)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(map_err)?;
+    signer.update(data).map_err(map_err)?;
+    let full = signer.sign_to_vec().map_err(map_err)?;
+    let mut out = [0u8; MAC_LEN];
+    out.copy_from_slice(&full[..MAC_LEN]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fragment::is_fragmented;
+    use crate::poll::is_poll;
+
+    const SECRET: &[u8] = b"correct horse battery staple";
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn accepts_fresh_signed_payload() {
+        let payload = control_payload(SECRET, ControlCommand::Health, NOW, 7).expect("sign");
+        assert_eq!(payload.len(), CONTROL_PAYLOAD_SIZE);
+        assert!(!is_poll(&payload));
+        assert!(!is_fragmented(&payload));
+        assert_eq!(
+            verify_control(SECRET, &payload, NOW + CONTROL_WINDOW_SECS),
+            Some(ControlCommand::Health)
+        );
+        assert_eq!(
+            verify_control(SECRET, &payload, NOW - CONTROL_WINDOW_SECS),
+            Some(ControlCommand::Health)
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_key_stale_or_tampered_payload() {
+        let payload = control_payload(SECRET, ControlCommand::Health, NOW, 7).expect("sign");
+        assert_eq!(verify_control(b"other secret", &payload, NOW), None);
+        assert_eq!(
+            verify_control(SECRET, &payload, NOW + CONTROL_WINDOW_SECS + 1),
+            None
+        );
+        let mut tampered = payload.clone();
+        tampered[6] ^= 1;
+        assert_eq!(verify_control(SECRET, &tampered, NOW), None);
+        assert_eq!(verify_control(SECRET, &payload[..SIGNED_LEN], NOW), None);
+    }
+
+    #[test]
+    fn health_status_round_trips() {
+        let status = HealthStatus {
+            uptime_secs: 3600,
+            connections: 12,
+        };
+        assert_eq!(HealthStatus::decode(&status.encode()), Some(status));
+        assert_eq!(HealthStatus::decode(&[CONTROL_MAGIC]), None);
+    }
+}
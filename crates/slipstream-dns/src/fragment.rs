@@ -17,6 +17,12 @@ pub const FRAGMENT_HEADER_SIZE: usize = 5;
 /// Default timeout for incomplete fragment reassembly (5 seconds)
 const FRAGMENT_TIMEOUT_SECS: u64 = 5;
 
+/// Default cap on incomplete reassemblies held at once.
+pub const FRAGMENT_BUFFER_MAX_ENTRIES: usize = 1024;
+
+/// Default cap on the fragment bytes those reassemblies hold.
+pub const FRAGMENT_BUFFER_MAX_BYTES: usize = 4 * 1024 * 1024;
+
 /// Fragment a QUIC packet into multiple chunks for DNS encoding.
 ///
 /// Each fragment contains:
@@ -101,12 +107,37 @@ pub fn is_fragmented(data: &[u8]) -> bool {
     data[0] == FRAGMENT_MAGIC
 }
 
+/// What a [`FragmentBuffer`] did with the reassemblies it started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FragmentStats {
+    /// Packets put back together.
+    pub completed: u64,
+    /// Incomplete reassemblies given up after the timeout, or because one
+    /// packet alone would exceed the byte cap.
+    pub dropped: u64,
+    /// Incomplete reassemblies pushed out, least recently used first, to
+    /// stay within the entry or byte cap.
+    pub evicted: u64,
+}
+
 /// Buffer for reassembling fragmented QUIC packets.
+///
+/// A peer that starts packets without finishing them cannot make it grow
+/// past [`FRAGMENT_BUFFER_MAX_ENTRIES`] reassemblies or
+/// [`FRAGMENT_BUFFER_MAX_BYTES`] bytes (or the caps given to
+/// [`Self::with_limits`]); the least recently used reassembly makes room.
 pub struct FragmentBuffer {
     /// Fragments indexed by packet_id
     fragments: HashMap<u16, FragmentEntry>,
     /// Maximum age for incomplete reassembly
     timeout_secs: u64,
+    max_entries: usize,
+    max_bytes: usize,
+    /// Fragment bytes held across all entries
+    buffered_bytes: usize,
+    /// Bumped on every fragment; entries remember it to order evictions.
+    clock: u64,
+    stats: FragmentStats,
 }
 
 struct FragmentEntry {
@@ -118,6 +149,10 @@ struct FragmentEntry {
     created: Instant,
     /// Count of received fragments
     received: u8,
+    /// Fragment bytes stored so far
+    bytes: usize,
+    /// `FragmentBuffer::clock` when the last fragment arrived
+    last_used: u64,
 }
 
 impl Default for FragmentBuffer {
@@ -129,10 +164,7 @@ impl Default for FragmentBuffer {
 impl FragmentBuffer {
     /// Create a new fragment buffer with default timeout.
     pub fn new() -> Self {
-        Self {
-            fragments: HashMap::new(),
-            timeout_secs: FRAGMENT_TIMEOUT_SECS,
-        }
+        Self::with_timeout(FRAGMENT_TIMEOUT_SECS)
     }
 
     /// Create a new fragment buffer with custom timeout.
@@ -140,9 +172,22 @@ impl FragmentBuffer {
         Self {
             fragments: HashMap::new(),
             timeout_secs,
+            max_entries: FRAGMENT_BUFFER_MAX_ENTRIES,
+            max_bytes: FRAGMENT_BUFFER_MAX_BYTES,
+            buffered_bytes: 0,
+            clock: 0,
+            stats: FragmentStats::default(),
         }
     }
 
+    /// Hold at most `max_entries` incomplete reassemblies and `max_bytes`
+    /// bytes of fragments. Both are at least 1.
+    pub fn with_limits(mut self, max_entries: usize, max_bytes: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self.max_bytes = max_bytes.max(1);
+        self
+    }
+
     /// Receive a fragment and return the reassembled packet if complete.
     ///
     /// # Arguments
@@ -158,6 +203,13 @@ impl FragmentBuffer {
             return None;
         }
 
+        if !self.fragments.contains_key(&packet_id) && self.fragments.len() >= self.max_entries {
+            self.cleanup_stale();
+            while self.fragments.len() >= self.max_entries {
+                self.evict_oldest(packet_id);
+            }
+        }
+        self.clock += 1;
         let entry = self
             .fragments
             .entry(packet_id)
@@ -166,18 +218,23 @@ impl FragmentBuffer {
                 total,
                 created: Instant::now(),
                 received: 0,
+                bytes: 0,
+                last_used: 0,
             });
 
         // Verify consistent total
         if entry.total != total {
             return None;
         }
+        entry.last_used = self.clock;
 
         // Store fragment if not already received
         let idx = frag_num as usize;
         if idx < entry.data.len() && entry.data[idx].is_none() {
             entry.data[idx] = Some(payload.to_vec());
             entry.received += 1;
+            entry.bytes += payload.len();
+            self.buffered_bytes += payload.len();
         }
 
         // Check if all fragments received
@@ -188,24 +245,72 @@ impl FragmentBuffer {
                 .iter()
                 .flat_map(|f| f.as_ref().unwrap().iter().cloned())
                 .collect();
-            self.fragments.remove(&packet_id);
+            self.remove(packet_id);
+            self.stats.completed += 1;
             return Some(packet);
         }
 
+        if entry.bytes > self.max_bytes {
+            self.remove(packet_id);
+            self.stats.dropped += 1;
+            return None;
+        }
+        while self.buffered_bytes > self.max_bytes {
+            self.evict_oldest(packet_id);
+        }
+
         None
     }
 
     /// Clean up stale incomplete reassemblies.
     pub fn cleanup_stale(&mut self) {
         let timeout = std::time::Duration::from_secs(self.timeout_secs);
-        self.fragments
-            .retain(|_, entry| entry.created.elapsed() < timeout);
+        let stale: Vec<u16> = self
+            .fragments
+            .iter()
+            .filter(|(_, entry)| entry.created.elapsed() >= timeout)
+            .map(|(packet_id, _)| *packet_id)
+            .collect();
+        for packet_id in stale {
+            self.remove(packet_id);
+            self.stats.dropped += 1;
+        }
     }
 
     /// Number of pending incomplete reassemblies.
     pub fn pending_count(&self) -> usize {
         self.fragments.len()
     }
+
+    /// Fragment bytes held by pending reassemblies.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Reassemblies completed, dropped and evicted so far.
+    pub fn stats(&self) -> FragmentStats {
+        self.stats
+    }
+
+    /// Evict the least recently used reassembly other than `keep`.
+    fn evict_oldest(&mut self, keep: u16) {
+        let oldest = self
+            .fragments
+            .iter()
+            .filter(|(packet_id, _)| **packet_id != keep)
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(packet_id, _)| *packet_id);
+        if let Some(packet_id) = oldest {
+            self.remove(packet_id);
+            self.stats.evicted += 1;
+        }
+    }
+
+    fn remove(&mut self, packet_id: u16) {
+        if let Some(entry) = self.fragments.remove(&packet_id) {
+            self.buffered_bytes -= entry.bytes;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -304,4 +409,45 @@ mod tests {
 
         assert_eq!(buffer.pending_count(), 0);
     }
+
+    #[test]
+    fn evicts_least_recently_used_at_entry_cap() {
+        let frags: Vec<_> = (0..3u16)
+            .map(|id| fragment_packet(&[id as u8; 40], id, 20))
+            .collect();
+        let mut buffer = FragmentBuffer::new().with_limits(2, 1024);
+        buffer.receive_fragment(&frags[0][0]);
+        buffer.receive_fragment(&frags[1][0]);
+        // Packet 0 is now the most recently used
+        buffer.receive_fragment(&frags[0][1]);
+        buffer.receive_fragment(&frags[2][0]);
+        assert_eq!(buffer.pending_count(), 2);
+        assert_eq!(buffer.stats().evicted, 1);
+
+        assert_eq!(buffer.receive_fragment(&frags[0][2]), Some(vec![0u8; 40]));
+        for frag in &frags[1][1..] {
+            assert_eq!(buffer.receive_fragment(frag), None);
+        }
+        assert_eq!(buffer.stats().completed, 1);
+    }
+
+    #[test]
+    fn byte_cap_bounds_buffered_fragments() {
+        let mut buffer = FragmentBuffer::new().with_limits(64, 50);
+        for id in 0..10u16 {
+            for frag in fragment_packet(&[1u8; 60], id, 20).iter().take(2) {
+                buffer.receive_fragment(frag);
+                assert!(buffer.buffered_bytes() <= 50);
+            }
+        }
+        assert!(buffer.stats().evicted > 0);
+
+        // A packet larger than the cap by itself is given up
+        let frags = fragment_packet(&[2u8; 100], 99, 20);
+        for frag in &frags[..frags.len() - 1] {
+            assert_eq!(buffer.receive_fragment(frag), None);
+        }
+        assert!(buffer.stats().dropped > 0);
+        assert!(buffer.buffered_bytes() <= 50);
+    }
 }
@@ -1,78 +1,182 @@
 mod base32;
 mod codec;
+#[cfg(feature = "control")]
+pub mod control;
 mod dots;
 pub mod fragment;
 mod name;
+mod poll;
+mod qname;
 mod types;
 mod wire;
 
 pub use base32::{decode as base32_decode, encode as base32_encode, Base32Error};
 pub use codec::{
-    decode_query, decode_query_with_domains, decode_response, encode_query, encode_response,
-    is_response,
+    answers_len, decode_backlog_hint, decode_query, decode_query_with_domains,
+    decode_query_with_record_types, decode_response, encode_query, encode_response,
+    encode_truncated_response, is_response, is_truncated, max_answers_payload_len,
+    negotiated_udp_payload, response_payload_capacity, response_rcode,
+};
+#[cfg(feature = "control")]
+pub use control::{
+    control_payload, verify_control, ControlCommand, HealthStatus, CONTROL_PAYLOAD_SIZE,
+    CONTROL_WINDOW_SECS,
 };
 pub use dots::{dotify, undotify};
 pub use fragment::{
-    fragment_packet, is_fragmented, parse_fragment, FragmentBuffer, FRAGMENT_HEADER_SIZE,
+    fragment_packet, is_fragmented, parse_fragment, FragmentBuffer, FragmentStats,
+    FRAGMENT_BUFFER_MAX_BYTES, FRAGMENT_BUFFER_MAX_ENTRIES, FRAGMENT_HEADER_SIZE,
 };
+pub use poll::{is_poll, poll_payload, POLL_PAYLOAD_SIZE};
+pub use qname::{qname_codec, Base32, Base64Url, BinaryLabels, QnameCodec, NONCE_LABEL_LEN};
+pub use slipstream_core::QnameEncoding;
 pub use types::{
     DecodeQueryError, DecodedQuery, DnsError, QueryParams, Question, Rcode, ResponseParams,
-    CLASS_IN, EDNS_UDP_PAYLOAD, RR_A, RR_OPT, RR_TXT,
+    CLASSIC_UDP_PAYLOAD, CLASS_IN, EDNS_OPT_BACKLOG_HINT, EDNS_OPT_PADDING, EDNS_UDP_PAYLOAD,
+    RESPONSE_PADDING_SIZES, RR_A, RR_AAAA, RR_NS, RR_NULL, RR_OPT, RR_TXT,
 };
 
+use slipstream_core::domain::validate_domain;
+
 pub fn build_qname(payload: &[u8], domain: &str) -> Result<String, DnsError> {
+    build_qname_with_codec(&Base32, payload, domain)
+}
+
+/// Query name carrying `payload` under `domain`, spelled by `codec`.
+///
+/// Labels hold at most 57 bytes, split from the right as [`dotify`] does.
+pub fn build_qname_with_codec(
+    codec: &dyn QnameCodec,
+    payload: &[u8],
+    domain: &str,
+) -> Result<String, DnsError> {
+    build_qname_inner(codec, payload, domain, None)
+}
+
+/// Like [`build_qname_with_codec`], but starting with a nonce label for
+/// `nonce`, so no two names repeat and resolvers cannot answer from cache.
+pub fn build_qname_with_nonce(
+    codec: &dyn QnameCodec,
+    payload: &[u8],
+    domain: &str,
+    nonce: u32,
+) -> Result<String, DnsError> {
+    build_qname_inner(codec, payload, domain, Some(nonce))
+}
+
+fn build_qname_inner(
+    codec: &dyn QnameCodec,
+    payload: &[u8],
+    domain: &str,
+    nonce: Option<u32>,
+) -> Result<String, DnsError> {
     let domain = domain.trim_end_matches('.');
-    if domain.is_empty() {
-        return Err(DnsError::new("domain must not be empty"));
-    }
-    let max_payload = max_payload_len_for_domain(domain)?;
+    let max_payload = max_payload_len_inner(codec, domain, nonce.is_some())?;
     if payload.len() > max_payload {
         return Err(DnsError::new("payload too large for domain"));
     }
-    let base32 = base32_encode(payload);
-    let dotted = dotify(&base32);
-    Ok(format!("{}.{}.", dotted, domain))
+    let mut data: Vec<u8> = codec.marker().into_iter().collect();
+    data.extend_from_slice(&codec.encode(payload));
+    let mut qname = String::with_capacity(data.len() * 2 + domain.len() + NONCE_LABEL_LEN + 3);
+    if let Some(nonce) = nonce {
+        name::escape_label(&qname::nonce_label(nonce), &mut qname);
+        qname.push('.');
+    }
+    let first_label = match data.len() % 57 {
+        0 => 57,
+        rem => rem,
+    };
+    let (first, rest) = data.split_at(first_label.min(data.len()));
+    name::escape_label(first, &mut qname);
+    for label in rest.chunks(57) {
+        qname.push('.');
+        name::escape_label(label, &mut qname);
+    }
+    qname.push('.');
+    qname.push_str(domain);
+    qname.push('.');
+    Ok(qname)
 }
 
 pub fn max_payload_len_for_domain(domain: &str) -> Result<usize, DnsError> {
+    max_payload_len_with_codec(&Base32, domain)
+}
+
+/// Largest payload a query name under `domain` holds when spelled by `codec`.
+pub fn max_payload_len_with_codec(codec: &dyn QnameCodec, domain: &str) -> Result<usize, DnsError> {
+    max_payload_len_inner(codec, domain, false)
+}
+
+/// Largest payload [`build_qname_with_nonce`] fits in a name under `domain`.
+pub fn max_payload_len_with_nonce(codec: &dyn QnameCodec, domain: &str) -> Result<usize, DnsError> {
+    max_payload_len_inner(codec, domain, true)
+}
+
+fn max_payload_len_inner(
+    codec: &dyn QnameCodec,
+    domain: &str,
+    nonce: bool,
+) -> Result<usize, DnsError> {
     let domain = domain.trim_end_matches('.');
-    if domain.is_empty() {
-        return Err(DnsError::new("domain must not be empty"));
-    }
-    if domain.len() > name::MAX_DNS_NAME_LEN {
-        return Err(DnsError::new("domain too long"));
-    }
+    validate_domain(domain).map_err(|err| DnsError::new(err.to_string()))?;
     let max_name_len = name::MAX_DNS_NAME_LEN;
-    let max_dotted_len = max_name_len.saturating_sub(domain.len() + 1);
+    let nonce_len = if nonce { NONCE_LABEL_LEN + 1 } else { 0 };
+    let max_dotted_len = max_name_len.saturating_sub(domain.len() + 1 + nonce_len);
     if max_dotted_len == 0 {
         return Ok(0);
     }
-    let mut max_base32_len = 0usize;
+    let mut max_data_len = 0usize;
     for len in 1..=max_dotted_len {
         let dots = (len - 1) / 57;
         if len + dots > max_dotted_len {
             break;
         }
-        max_base32_len = len;
+        max_data_len = len;
     }
+    let max_encoded_len = max_data_len.saturating_sub(codec.marker().map_or(0, |_| 1));
 
-    let mut max_payload = (max_base32_len * 5) / 8;
-    while max_payload > 0 && base32_len(max_payload) > max_base32_len {
+    let mut max_payload = max_encoded_len;
+    while max_payload > 0 && codec.encoded_len(max_payload) > max_encoded_len {
         max_payload -= 1;
     }
     Ok(max_payload)
 }
 
-fn base32_len(payload_len: usize) -> usize {
-    if payload_len == 0 {
-        return 0;
-    }
-    (payload_len * 8).div_ceil(5)
+/// Largest payload answers of type `qtype` to any query for `domain` can
+/// carry within [`EDNS_UDP_PAYLOAD`] bytes, backlog hint included.
+///
+/// The question is echoed in full, so this assumes the longest query name a
+/// client builds for the domain; without name compression the answer repeats
+/// it.
+pub fn max_response_payload_len(
+    domain: &str,
+    qtype: u16,
+    compress_names: bool,
+    txt_record_size: Option<usize>,
+) -> Result<usize, DnsError> {
+    let max_query_payload = max_payload_len_for_domain(domain)?;
+    let question = Question {
+        name: build_qname(&vec![0u8; max_query_payload], domain)?,
+        qtype,
+        qclass: CLASS_IN,
+    };
+    Ok(response_payload_capacity(
+        &question,
+        compress_names,
+        txt_record_size,
+        EDNS_UDP_PAYLOAD as usize,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{build_qname, max_payload_len_for_domain};
+    use super::{
+        build_qname, build_qname_with_codec, build_qname_with_nonce, decode_query, encode_query,
+        encode_response, max_payload_len_for_domain, max_payload_len_with_codec,
+        max_payload_len_with_nonce, max_response_payload_len, qname_codec, QnameEncoding,
+        QueryParams, Question, ResponseParams, CLASS_IN, EDNS_UDP_PAYLOAD, RR_A, RR_AAAA, RR_NULL,
+        RR_TXT,
+    };
 
     #[test]
     fn build_qname_rejects_payload_overflow() {
@@ -88,4 +192,109 @@ mod tests {
         let payload = vec![0u8; 1];
         assert!(build_qname(&payload, &domain).is_err());
     }
+
+    #[test]
+    fn response_payload_fits_edns_limit() {
+        let domain = "test.com";
+        let name = build_qname(
+            &vec![0u8; max_payload_len_for_domain(domain).unwrap()],
+            domain,
+        )
+        .unwrap();
+        for qtype in [RR_TXT, RR_NULL, RR_A, RR_AAAA] {
+            let question = Question {
+                name: name.clone(),
+                qtype,
+                qclass: CLASS_IN,
+            };
+            for compress_names in [true, false] {
+                let max = max_response_payload_len(domain, qtype, compress_names, None).unwrap();
+                let encode = |len: usize| {
+                    encode_response(&ResponseParams {
+                        id: 1,
+                        rd: true,
+                        cd: false,
+                        question: &question,
+                        payload: Some(&vec![0u8; len]),
+                        rcode: None,
+                        backlog_hint: Some(0),
+                        compress_names,
+                        txt_record_size: None,
+                        padding_limit: None,
+                    })
+                    .unwrap()
+                    .len()
+                };
+                assert!(encode(max) <= EDNS_UDP_PAYLOAD as usize);
+                assert!(encode(max + 1) > EDNS_UDP_PAYLOAD as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn null_answers_carry_more_than_txt() {
+        let txt = max_response_payload_len("test.com", RR_TXT, true, None).unwrap();
+        let null = max_response_payload_len("test.com", RR_NULL, true, None).unwrap();
+        assert!(null > txt);
+    }
+
+    #[test]
+    fn every_codec_survives_a_query() {
+        let domain = "test.com";
+        let mut capacities = Vec::new();
+        for encoding in QnameEncoding::ALL {
+            let codec = qname_codec(encoding);
+            let max = max_payload_len_with_codec(codec, domain).unwrap();
+            capacities.push(max);
+            let payload: Vec<u8> = (0..max).map(|i| (i * 131 + 3) as u8).collect();
+            let qname = build_qname_with_codec(codec, &payload, domain).unwrap();
+            assert!(build_qname_with_codec(codec, &vec![0u8; max + 1], domain).is_err());
+            let query = encode_query(&QueryParams {
+                id: 7,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns_udp_payload: EDNS_UDP_PAYLOAD,
+            })
+            .unwrap();
+            let decoded = decode_query(&query, domain).unwrap();
+            assert_eq!(decoded.payload, payload, "{}", encoding.name());
+        }
+        assert!(capacities.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn nonce_labels_keep_names_unique_and_decode() {
+        let domain = "test.com";
+        for encoding in QnameEncoding::ALL {
+            let codec = qname_codec(encoding);
+            let max = max_payload_len_with_nonce(codec, domain).unwrap();
+            assert!(max < max_payload_len_with_codec(codec, domain).unwrap());
+            assert!(build_qname_with_nonce(codec, &vec![0u8; max + 1], domain, 1).is_err());
+            let payload: Vec<u8> = (0..max).map(|i| (i * 97 + 5) as u8).collect();
+            let qname = build_qname_with_nonce(codec, &payload, domain, 1).unwrap();
+            assert_ne!(
+                qname,
+                build_qname_with_nonce(codec, &payload, domain, 2).unwrap()
+            );
+            let query = encode_query(&QueryParams {
+                id: 7,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns_udp_payload: EDNS_UDP_PAYLOAD,
+            })
+            .unwrap();
+            let decoded = decode_query(&query, domain).unwrap();
+            assert_eq!(decoded.payload, payload, "{}", encoding.name());
+        }
+    }
 }
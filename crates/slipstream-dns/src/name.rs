@@ -1,4 +1,5 @@
 use crate::types::{DnsError, Rcode};
+use slipstream_core::domain::is_subdomain_of;
 
 pub(crate) const MAX_DNS_NAME_LEN: usize = 253;
 
@@ -47,10 +48,7 @@ pub(crate) fn extract_subdomain_multi(qname: &str, domains: &[&str]) -> Result<S
         let domain_lower = domain_trimmed.to_ascii_lowercase();
 
         let is_exact = qname_lower == domain_lower;
-        let is_suffix = !is_exact
-            && qname_lower.len() > domain_lower.len()
-            && qname_lower.ends_with(&domain_lower)
-            && qname_lower.as_bytes()[qname_lower.len() - domain_lower.len() - 1] == b'.';
+        let is_suffix = !is_exact && is_subdomain_of(&qname_lower, &domain_lower);
 
         if !is_exact && !is_suffix {
             continue;
@@ -133,9 +131,9 @@ pub(crate) fn parse_name(packet: &[u8], start: usize) -> Result<(String, usize),
         if name_len > MAX_DNS_NAME_LEN {
             return Err(DnsError::new("name too long"));
         }
-        let label = std::str::from_utf8(&packet[offset..end])
-            .map_err(|_| DnsError::new("label not utf-8"))?;
-        labels.push(label.to_string());
+        let mut label = String::with_capacity(len as usize);
+        escape_label(&packet[offset..end], &mut label);
+        labels.push(label);
         offset = end;
         if !jumped {
             end_offset = offset;
@@ -159,10 +157,9 @@ pub(crate) fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<(), DnsError>
         return Ok(());
     }
 
-    let trimmed = name.trim_end_matches('.');
     let mut name_len = 0usize;
     let mut first = true;
-    for label in trimmed.split('.') {
+    for label in unescape_labels(name)? {
         if label.is_empty() {
             return Err(DnsError::new("empty label"));
         }
@@ -177,17 +174,76 @@ pub(crate) fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<(), DnsError>
             return Err(DnsError::new("name too long"));
         }
         out.push(label.len() as u8);
-        out.extend_from_slice(label.as_bytes());
+        out.extend_from_slice(&label);
         first = false;
     }
     out.push(0);
     Ok(())
 }
 
+/// Append `label` in presentation form: the dot, the backslash and bytes
+/// outside printable ASCII become `\DDD` escapes, as in zone files.
+pub(crate) fn escape_label(label: &[u8], out: &mut String) {
+    for &byte in label {
+        if byte == b'.' || byte == b'\\' || !(0x21..=0x7e).contains(&byte) {
+            out.push('\\');
+            out.push(char::from(b'0' + byte / 100));
+            out.push(char::from(b'0' + byte / 10 % 10));
+            out.push(char::from(b'0' + byte % 10));
+        } else {
+            out.push(byte as char);
+        }
+    }
+}
+
+/// Labels of a presentation-form name, escapes undone. A trailing dot ends
+/// the name; any other empty label is kept for the caller to reject.
+pub(crate) fn unescape_labels(name: &str) -> Result<Vec<Vec<u8>>, DnsError> {
+    let bytes = name.as_bytes();
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut index = 0usize;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'.' => {
+                labels.push(std::mem::take(&mut label));
+                index += 1;
+                if index == bytes.len() {
+                    return Ok(labels);
+                }
+            }
+            b'\\' => {
+                let digits = bytes.get(index + 1..index + 4);
+                if let Some(digits) = digits.filter(|d| d.iter().all(u8::is_ascii_digit)) {
+                    let value = digits
+                        .iter()
+                        .fold(0u16, |acc, d| acc * 10 + u16::from(d - b'0'));
+                    let value =
+                        u8::try_from(value).map_err(|_| DnsError::new("bad escape in name"))?;
+                    label.push(value);
+                    index += 4;
+                } else {
+                    let escaped = bytes
+                        .get(index + 1)
+                        .ok_or_else(|| DnsError::new("bad escape in name"))?;
+                    label.push(*escaped);
+                    index += 2;
+                }
+            }
+            byte => {
+                label.push(byte);
+                index += 1;
+            }
+        }
+    }
+    labels.push(label);
+    Ok(labels)
+}
+
 #[cfg(test)]
 mod tests {
     use super::MAX_DNS_NAME_LEN;
-    use super::{encode_name, parse_name};
+    use super::{encode_name, escape_label, parse_name, unescape_labels};
 
     fn build_name(last_label_len: usize) -> String {
         format!(
@@ -231,4 +287,27 @@ mod tests {
         packet.push(0);
         assert!(parse_name(&packet, 0).is_err());
     }
+
+    #[test]
+    fn escapes_use_three_decimal_digits() {
+        let mut out = String::from("x.");
+        escape_label(b"a.\\\x00\x09 \xff", &mut out);
+        assert_eq!(out, "x.a\\046\\092\\000\\009\\032\\255");
+    }
+
+    #[test]
+    fn binary_labels_round_trip() {
+        let label: Vec<u8> = (0u8..=62).map(|i| i.wrapping_mul(37)).collect();
+        let mut packet = vec![label.len() as u8];
+        packet.extend_from_slice(&label);
+        packet.extend_from_slice(b"\x04test\x03com\x00");
+        let (name, end) = parse_name(&packet, 0).unwrap();
+        assert_eq!(end, packet.len());
+        assert!(name.is_ascii());
+        assert_eq!(unescape_labels(&name).unwrap()[0], label);
+
+        let mut out = Vec::new();
+        encode_name(&name, &mut out).unwrap();
+        assert_eq!(out, packet);
+    }
 }
@@ -0,0 +1,46 @@
+//! Empty poll queries.
+//!
+//! A recursive resolver only relays server data in answers to client queries, so
+//! an idle client keeps queries flowing with polls: payloads that carry no QUIC
+//! packet, only a marker and a nonce that keeps resolvers from answering out of
+//! cache.
+
+/// Magic byte to identify poll payloads (ASCII 'P' for Poll)
+const POLL_MAGIC: u8 = 0x50;
+
+/// Poll payload size: magic (1) + nonce (4)
+pub const POLL_PAYLOAD_SIZE: usize = 5;
+
+/// Build a poll payload; `nonce` should differ between polls.
+pub fn poll_payload(nonce: u32) -> [u8; POLL_PAYLOAD_SIZE] {
+    let mut payload = [0u8; POLL_PAYLOAD_SIZE];
+    payload[0] = POLL_MAGIC;
+    payload[1..].copy_from_slice(&nonce.to_be_bytes());
+    payload
+}
+
+/// Check if a query payload is a poll rather than QUIC data.
+pub fn is_poll(data: &[u8]) -> bool {
+    data.len() == POLL_PAYLOAD_SIZE && data[0] == POLL_MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fragment::{fragment_packet, is_fragmented};
+
+    #[test]
+    fn poll_payload_is_recognized() {
+        let payload = poll_payload(0xdead_beef);
+        assert!(is_poll(&payload));
+        assert!(!is_fragmented(&payload));
+        assert_ne!(poll_payload(1), poll_payload(2));
+    }
+
+    #[test]
+    fn fragments_are_not_polls() {
+        let fragments = fragment_packet(&[], 7, 100);
+        assert!(fragments.iter().all(|fragment| !is_poll(fragment)));
+        assert!(!is_poll(&[POLL_MAGIC]));
+    }
+}
@@ -0,0 +1,209 @@
+//! Codecs that spell payload bytes in the labels of a query name.
+//!
+//! Base32 names carry no marker, as they did before the other codecs
+//! existed. The others start with a character base32 never produces, so the
+//! server tells them apart without configuration.
+//!
+//! A query name may also start with a nonce label, which keeps recursive
+//! resolvers from answering it out of cache. It starts with `_`, which no
+//! codec puts first, and the server drops it before decoding.
+
+use crate::base32;
+use slipstream_core::QnameEncoding;
+
+const BASE64URL_TABLE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// First byte of a nonce label.
+const NONCE_LABEL_MARKER: u8 = b'_';
+
+/// Length of a nonce label: the marker and a 32-bit nonce in base32.
+pub const NONCE_LABEL_LEN: usize = 8;
+
+/// Turns a payload into the data labels of a query name and back.
+pub trait QnameCodec: Sync {
+    /// First byte of the data, telling the server which codec built it;
+    /// `None` for base32.
+    fn marker(&self) -> Option<u8>;
+
+    /// Label bytes for `payload`, before the marker and the dots.
+    fn encode(&self, payload: &[u8]) -> Vec<u8>;
+
+    /// Inverse of [`Self::encode`]; `None` if `data` is not valid.
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>>;
+
+    /// Length of [`Self::encode`] for a payload of `payload_len` bytes.
+    fn encoded_len(&self, payload_len: usize) -> usize;
+}
+
+/// RFC 4648 base32 without padding, case-insensitive on decode.
+pub struct Base32;
+
+/// RFC 4648 base64url without padding.
+pub struct Base64Url;
+
+/// The payload itself, escaped in the name's presentation form.
+pub struct BinaryLabels;
+
+impl QnameCodec for Base32 {
+    fn marker(&self) -> Option<u8> {
+        None
+    }
+
+    fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        base32::encode(payload).into_bytes()
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        base32::decode(std::str::from_utf8(data).ok()?).ok()
+    }
+
+    fn encoded_len(&self, payload_len: usize) -> usize {
+        (payload_len * 8).div_ceil(5)
+    }
+}
+
+impl QnameCodec for Base64Url {
+    fn marker(&self) -> Option<u8> {
+        Some(b'0')
+    }
+
+    fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len(payload.len()));
+        for chunk in payload.chunks(3) {
+            let mut buffer = 0u32;
+            for (i, &byte) in chunk.iter().enumerate() {
+                buffer |= (byte as u32) << (16 - 8 * i);
+            }
+            for i in 0..=chunk.len() {
+                out.push(BASE64URL_TABLE[((buffer >> (18 - 6 * i)) & 0x3f) as usize]);
+            }
+        }
+        out
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() % 4 == 1 {
+            return None;
+        }
+        let mut out = Vec::with_capacity(data.len() * 3 / 4);
+        for chunk in data.chunks(4) {
+            let mut buffer = 0u32;
+            for (i, &c) in chunk.iter().enumerate() {
+                let value = BASE64URL_TABLE.iter().position(|&known| known == c)?;
+                buffer |= (value as u32) << (18 - 6 * i);
+            }
+            for i in 0..chunk.len() - 1 {
+                out.push((buffer >> (16 - 8 * i)) as u8);
+            }
+        }
+        Some(out)
+    }
+
+    fn encoded_len(&self, payload_len: usize) -> usize {
+        (payload_len * 4).div_ceil(3)
+    }
+}
+
+impl QnameCodec for BinaryLabels {
+    fn marker(&self) -> Option<u8> {
+        Some(b'1')
+    }
+
+    fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        payload.to_vec()
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        Some(data.to_vec())
+    }
+
+    fn encoded_len(&self, payload_len: usize) -> usize {
+        payload_len
+    }
+}
+
+/// Codec for `encoding`.
+pub fn qname_codec(encoding: QnameEncoding) -> &'static dyn QnameCodec {
+    match encoding {
+        QnameEncoding::Base32 => &Base32,
+        QnameEncoding::Base64Url => &Base64Url,
+        QnameEncoding::Binary => &BinaryLabels,
+    }
+}
+
+/// Nonce label for `nonce`.
+pub(crate) fn nonce_label(nonce: u32) -> Vec<u8> {
+    let mut label = vec![NONCE_LABEL_MARKER];
+    label.extend_from_slice(base32::encode(&nonce.to_be_bytes()).as_bytes());
+    label
+}
+
+/// Drop the nonce label from the labels of a query name, if it has one.
+pub(crate) fn strip_nonce_label(labels: &mut Vec<Vec<u8>>) {
+    if labels
+        .first()
+        .is_some_and(|label| label.first() == Some(&NONCE_LABEL_MARKER))
+    {
+        labels.remove(0);
+    }
+}
+
+/// Decode the data labels of a query name, concatenated, with whichever
+/// codec their marker names.
+pub(crate) fn decode_data(data: &[u8]) -> Option<Vec<u8>> {
+    for codec in [&Base64Url as &dyn QnameCodec, &BinaryLabels] {
+        if data.first().copied() == codec.marker() {
+            return codec.decode(&data[1..]);
+        }
+    }
+    Base32.decode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_data, nonce_label, qname_codec, strip_nonce_label, QnameCodec};
+    use slipstream_core::QnameEncoding;
+
+    #[test]
+    fn codecs_round_trip_through_markers() {
+        for encoding in QnameEncoding::ALL {
+            let codec = qname_codec(encoding);
+            for len in 0..8 {
+                let payload: Vec<u8> = (0..len).map(|i| (i * 91 + 7) as u8).collect();
+                let encoded = codec.encode(&payload);
+                assert_eq!(encoded.len(), codec.encoded_len(len));
+                let mut data: Vec<u8> = codec.marker().into_iter().collect();
+                data.extend_from_slice(&encoded);
+                assert_eq!(decode_data(&data), Some(payload), "{}", encoding.name());
+            }
+        }
+    }
+
+    #[test]
+    fn nonce_labels_are_stripped_before_any_codec() {
+        let nonce = nonce_label(0xdead_beef);
+        assert_eq!(nonce.len(), super::NONCE_LABEL_LEN);
+        for encoding in QnameEncoding::ALL {
+            let codec = qname_codec(encoding);
+            let mut data: Vec<u8> = codec.marker().into_iter().collect();
+            data.extend_from_slice(&codec.encode(b"payload"));
+            let mut labels = vec![nonce.clone(), data.clone()];
+            strip_nonce_label(&mut labels);
+            assert_eq!(labels, vec![data.clone()], "{}", encoding.name());
+            strip_nonce_label(&mut labels);
+            assert_eq!(labels, vec![data], "{}", encoding.name());
+        }
+    }
+
+    #[test]
+    fn base64url_matches_rfc_4648() {
+        let codec = super::Base64Url;
+        assert_eq!(codec.encode(b"foobar"), b"Zm9vYmFy");
+        assert_eq!(codec.encode(b"fo"), b"Zm8");
+        assert_eq!(codec.encode(&[0xfb, 0xff]), b"-_8");
+        assert_eq!(codec.decode(b"Zm9vYg"), Some(b"foob".to_vec()));
+        assert_eq!(codec.decode(b"Zm9vY"), None);
+        assert_eq!(codec.decode(b"Zm9v+A"), None);
+    }
+}
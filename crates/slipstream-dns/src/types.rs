@@ -1,10 +1,23 @@
 use std::fmt;
 
 pub const RR_A: u16 = 1;
+pub const RR_NS: u16 = 2;
+/// NULL records carry the payload as raw RDATA, without TXT string framing.
+pub const RR_NULL: u16 = 10;
 pub const RR_TXT: u16 = 16;
+pub const RR_AAAA: u16 = 28;
 pub const RR_OPT: u16 = 41;
 pub const CLASS_IN: u16 = 1;
 pub const EDNS_UDP_PAYLOAD: u16 = 1232;
+/// Largest response to a query without EDNS0 (RFC 1035 section 4.2.1).
+pub const CLASSIC_UDP_PAYLOAD: u16 = 512;
+/// EDNS0 option code (local/experimental range) carrying the server backlog hint.
+pub const EDNS_OPT_BACKLOG_HINT: u16 = 65_001;
+/// EDNS0 Padding option code (RFC 7830).
+pub const EDNS_OPT_PADDING: u16 = 12;
+/// Sizes padded responses are rounded up to, below the response size limit;
+/// larger responses are padded to the limit itself.
+pub const RESPONSE_PADDING_SIZES: [usize; 3] = [128, 256, 512];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Rcode {
@@ -12,6 +25,7 @@ pub enum Rcode {
     FormatError,
     ServerFailure,
     NameError,
+    Refused,
 }
 
 impl Rcode {
@@ -21,6 +35,7 @@ impl Rcode {
             Rcode::FormatError => 1,
             Rcode::ServerFailure => 2,
             Rcode::NameError => 3,
+            Rcode::Refused => 5,
         }
     }
 
@@ -30,6 +45,7 @@ impl Rcode {
             1 => Some(Rcode::FormatError),
             2 => Some(Rcode::ServerFailure),
             3 => Some(Rcode::NameError),
+            5 => Some(Rcode::Refused),
             _ => None,
         }
     }
@@ -49,6 +65,8 @@ pub struct DecodedQuery {
     pub cd: bool,
     pub question: Question,
     pub payload: Vec<u8>,
+    /// UDP payload size advertised in the query's EDNS0 OPT record, if any.
+    pub edns_udp_payload: Option<u16>,
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +91,8 @@ pub struct QueryParams<'a> {
     pub cd: bool,
     pub qdcount: u16,
     pub is_query: bool,
+    /// UDP payload size advertised in the EDNS0 OPT record.
+    pub edns_udp_payload: u16,
 }
 
 #[derive(Debug, Clone)]
@@ -83,6 +103,18 @@ pub struct ResponseParams<'a> {
     pub question: &'a Question,
     pub payload: Option<&'a [u8]>,
     pub rcode: Option<Rcode>,
+    /// Server-observed bytes still queued for this client, sent as an EDNS0 option.
+    pub backlog_hint: Option<u32>,
+    /// Write the answer owner name as a pointer to the question name
+    /// (RFC 1035 section 4.1.4) instead of repeating it.
+    pub compress_names: bool,
+    /// RDATA bytes one TXT answer may hold; larger payloads are spread over
+    /// several answers. `None` keeps the payload in one answer.
+    pub txt_record_size: Option<usize>,
+    /// Pad the response with an EDNS0 Padding option to the smallest of
+    /// [`RESPONSE_PADDING_SIZES`] that holds it, or else to this many bytes.
+    /// `None` leaves it unpadded.
+    pub padding_limit: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
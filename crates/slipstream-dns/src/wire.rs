@@ -5,10 +5,14 @@ use crate::types::{DecodeQueryError, DnsError, Question, Rcode};
 pub(crate) struct Header {
     pub(crate) id: u16,
     pub(crate) is_response: bool,
+    /// TC bit: the answer did not fit and was cut short.
+    pub(crate) truncated: bool,
     pub(crate) rd: bool,
     pub(crate) cd: bool,
     pub(crate) qdcount: u16,
     pub(crate) ancount: u16,
+    pub(crate) nscount: u16,
+    pub(crate) arcount: u16,
     pub(crate) rcode: Option<Rcode>,
     pub(crate) offset: usize,
 }
@@ -21,10 +25,11 @@ pub(crate) fn parse_header(packet: &[u8]) -> Option<Header> {
     let flags = read_u16(packet, 2)?;
     let qdcount = read_u16(packet, 4)?;
     let ancount = read_u16(packet, 6)?;
-    let _nscount = read_u16(packet, 8)?;
-    let _arcount = read_u16(packet, 10)?;
+    let nscount = read_u16(packet, 8)?;
+    let arcount = read_u16(packet, 10)?;
 
     let is_response = flags & 0x8000 != 0;
+    let truncated = flags & 0x0200 != 0;
     let rd = flags & 0x0100 != 0;
     let cd = flags & 0x0010 != 0;
     let rcode = Rcode::from_u8((flags & 0x000f) as u8);
@@ -32,10 +37,13 @@ pub(crate) fn parse_header(packet: &[u8]) -> Option<Header> {
     Some(Header {
         id,
         is_response,
+        truncated,
         rd,
         cd,
         qdcount,
         ancount,
+        nscount,
+        arcount,
         rcode,
         offset: 12,
     })
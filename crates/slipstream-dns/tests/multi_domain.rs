@@ -1,6 +1,6 @@
 use slipstream_dns::{
     build_qname, decode_query_with_domains, encode_query, DecodeQueryError, QueryParams, Rcode,
-    CLASS_IN, RR_TXT,
+    CLASS_IN, EDNS_UDP_PAYLOAD, RR_TXT,
 };
 
 #[test]
@@ -16,6 +16,7 @@ fn decode_query_with_domains_accepts_any_match() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
     })
     .expect("encode query");
 
@@ -37,6 +38,7 @@ fn decode_query_with_domains_prefers_longest_suffix() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
     })
     .expect("encode query");
 
@@ -58,6 +60,7 @@ fn decode_query_with_domains_rejects_unknown_domain() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
     })
     .expect("encode query");
 
@@ -79,6 +82,7 @@ fn decode_query_with_domains_rejects_empty_subdomain_on_overlap() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns_udp_payload: EDNS_UDP_PAYLOAD,
     })
     .expect("encode query");
 
@@ -4,7 +4,7 @@ use std::path::Path;
 use serde::Deserialize;
 use slipstream_dns::{
     decode_query, decode_response, encode_query, encode_response, DecodeQueryError, QueryParams,
-    Question, Rcode, ResponseParams, CLASS_IN, RR_A, RR_TXT,
+    Question, Rcode, ResponseParams, CLASS_IN, EDNS_UDP_PAYLOAD, RR_A, RR_TXT,
 };
 
 #[derive(Debug, Deserialize)]
@@ -78,6 +78,7 @@ fn vectors_match_codec() {
             cd: false,
             qdcount,
             is_query,
+            edns_udp_payload: EDNS_UDP_PAYLOAD,
         })
         .expect("encode query");
         assert_eq!(
@@ -123,6 +124,10 @@ fn vectors_match_codec() {
                 question: &question,
                 payload: Some(&payload),
                 rcode: None,
+                backlog_hint: None,
+                compress_names: true,
+                txt_record_size: None,
+                padding_limit: None,
             })
             .expect("encode response_ok");
             let expected = decode_hex(&resp.packet_hex);
@@ -140,6 +145,10 @@ fn vectors_match_codec() {
                 question: &question,
                 payload: None,
                 rcode: None,
+                backlog_hint: None,
+                compress_names: true,
+                txt_record_size: None,
+                padding_limit: None,
             })
             .expect("encode response_no_data");
             let expected = decode_hex(&resp.packet_hex);
@@ -165,6 +174,10 @@ fn vectors_match_codec() {
                 question: &question,
                 payload: None,
                 rcode: Some(rcode),
+                backlog_hint: None,
+                compress_names: true,
+                txt_record_size: None,
+                padding_limit: None,
             })
             .expect("encode response_error");
             let expected = decode_hex(&resp.packet_hex);
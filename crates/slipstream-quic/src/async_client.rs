@@ -0,0 +1,209 @@
+//! Client connections that drive themselves.
+//!
+//! [`ClientConnection`] leaves the event loop to the caller: feed it datagrams,
+//! send what `poll_send` returns and call `on_timeout` when the timer fires.
+//! [`Client::connect_async`] runs that loop on a task of its own over a UDP
+//! socket it owns, so callers only deal with streams.
+
+use crate::client::{Client, ClientConnection};
+use crate::error::{CloseCause, Error};
+use crate::stream::BiStream;
+use std::cell::RefCell;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// How long to wait for a datagram when tquic has no timer armed.
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+impl Client {
+    /// Connect to `server_addr` over a new UDP socket and wait until streams
+    /// can be opened: after the handshake, or as soon as 0-RTT data is allowed.
+    ///
+    /// The connection's event loop runs on a task spawned with
+    /// [`tokio::task::spawn_local`], so this must be called within a
+    /// `tokio::task::LocalSet`. The task ends once the connection is closed.
+    pub async fn connect_async(
+        &self,
+        server_addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<AsyncConnection, Error> {
+        let bind_addr: SocketAddr = if server_addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        let local_addr = socket.local_addr()?;
+        let conn = self.connect(local_addr, server_addr, server_name)?;
+        let mut driver = Driver::new(conn, socket);
+
+        while !driver.can_open_streams()? {
+            driver.turn().await?;
+        }
+
+        let conn = driver.conn.clone();
+        let send_notify = driver.send_notify.clone();
+        let task = tokio::task::spawn_local(driver.run());
+        Ok(AsyncConnection {
+            conn,
+            send_notify,
+            task: Some(task),
+        })
+    }
+}
+
+/// A client connection whose event loop runs on a background task.
+///
+/// Dropping it closes the connection with error code 0.
+pub struct AsyncConnection {
+    conn: Rc<RefCell<ClientConnection>>,
+    send_notify: Rc<Notify>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl AsyncConnection {
+    /// Open a bidirectional stream. The returned handle implements
+    /// `AsyncRead` and `AsyncWrite`.
+    pub fn open_bi(&self) -> Result<BiStream, Error> {
+        self.conn.borrow_mut().open_bi_stream()
+    }
+
+    /// Check if the handshake has completed.
+    pub fn is_ready(&self) -> bool {
+        self.conn.borrow().is_ready()
+    }
+
+    /// Check if the handshake resumed a saved session (false until ready).
+    pub fn is_resumed(&self) -> bool {
+        self.conn.borrow().is_resumed()
+    }
+
+    /// ALPN protocol negotiated during the handshake (empty until ready).
+    pub fn alpn(&self) -> Vec<u8> {
+        self.conn.borrow().alpn()
+    }
+
+    /// Check if the connection has closed.
+    pub fn is_closed(&self) -> bool {
+        self.conn.borrow().is_closing()
+    }
+
+    /// Why the connection closed, once it has.
+    pub fn close_cause(&self) -> Option<CloseCause> {
+        self.conn.borrow().close_cause()
+    }
+
+    /// Access the underlying connection, e.g. for path management.
+    ///
+    /// Do not hold the borrow across an `.await`: the event loop needs it.
+    pub fn with_connection<T>(&self, f: impl FnOnce(&mut ClientConnection) -> T) -> T {
+        let result = f(&mut self.conn.borrow_mut());
+        self.send_notify.notify_one();
+        result
+    }
+
+    /// Start closing the connection. Use [`Self::closed`] to wait until it is gone.
+    pub fn close(&self, error_code: u64, reason: &str) -> Result<(), Error> {
+        self.conn.borrow_mut().close(error_code, reason)?;
+        self.send_notify.notify_one();
+        Ok(())
+    }
+
+    /// Take the event loop task; dropping the connection no longer closes it.
+    pub(crate) fn take_event_loop(&mut self) -> Option<JoinHandle<()>> {
+        self.task.take()
+    }
+
+    /// Wait until the connection has closed and its event loop has stopped.
+    pub async fn closed(&mut self) {
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for AsyncConnection {
+    fn drop(&mut self) {
+        if self.task.is_some() && !self.is_closed() {
+            let _ = self.close(0, "");
+        }
+    }
+}
+
+/// Event loop of one connection.
+struct Driver {
+    conn: Rc<RefCell<ClientConnection>>,
+    socket: UdpSocket,
+    send_notify: Rc<Notify>,
+    recv_buf: Vec<u8>,
+}
+
+impl Driver {
+    fn new(conn: ClientConnection, socket: UdpSocket) -> Self {
+        Self {
+            send_notify: conn.send_notify(),
+            conn: Rc::new(RefCell::new(conn)),
+            socket,
+            recv_buf: vec![0u8; MAX_DATAGRAM_SIZE],
+        }
+    }
+
+    /// Whether streams can be opened yet; fails if the handshake did not finish.
+    fn can_open_streams(&self) -> Result<bool, Error> {
+        let mut conn = self.conn.borrow_mut();
+        if conn.is_in_early_data() {
+            return Ok(true);
+        }
+        conn.handshake_status()
+    }
+
+    async fn run(mut self) {
+        loop {
+            if let Err(e) = self.turn().await {
+                tracing::warn!("Connection event loop stopped: {}", e);
+                return;
+            }
+            if self.conn.borrow().is_closing() {
+                if let Err(e) = self.flush().await {
+                    tracing::debug!("Failed to send final packets: {}", e);
+                }
+                return;
+            }
+        }
+    }
+
+    /// Send queued packets, then wait for a datagram, the timer or a stream
+    /// handle that queued data, and process it.
+    async fn turn(&mut self) -> Result<(), Error> {
+        self.flush().await?;
+        let timeout = self.conn.borrow().timeout().unwrap_or(IDLE_WAIT);
+        tokio::select! {
+            recv = self.socket.recv_from(&mut self.recv_buf) => {
+                let (len, from) = recv?;
+                if let Err(e) = self.conn.borrow_mut().recv(&self.recv_buf[..len], from) {
+                    tracing::debug!("Dropped datagram from {}: {}", from, e);
+                }
+            }
+            _ = tokio::time::sleep(timeout) => {
+                self.conn.borrow_mut().on_timeout();
+            }
+            _ = self.send_notify.notified() => {}
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        let packets = self.conn.borrow_mut().poll_send();
+        for (data, dest) in packets {
+            self.socket.send_to(&data, dest).await?;
+            self.conn.borrow().recycle(data);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Batched packet output for sendmmsg and UDP GSO.
+//!
+//! A GSO send hands the kernel one buffer and a segment size; every segment
+//! but the last must be exactly that size and all go to one destination.
+//! [`batch_packets`] groups consecutive packets that fit those rules.
+
+use std::net::SocketAddr;
+
+/// Most segments Linux accepts in one UDP GSO send.
+pub const MAX_GSO_SEGMENTS: usize = 64;
+
+/// Consecutive packets to one destination, concatenated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketBatch {
+    /// Destination of every packet in the batch.
+    pub dst: SocketAddr,
+    /// Size of each packet; the last one may be shorter.
+    pub segment_size: usize,
+    /// The packets back to back.
+    pub data: Vec<u8>,
+}
+
+impl PacketBatch {
+    fn new(data: Vec<u8>, dst: SocketAddr) -> Self {
+        Self {
+            dst,
+            segment_size: data.len(),
+            data,
+        }
+    }
+
+    /// Number of packets in the batch.
+    pub fn segment_count(&self) -> usize {
+        if self.segment_size == 0 {
+            return 1;
+        }
+        self.data.len().div_ceil(self.segment_size)
+    }
+
+    /// The individual packets, for senders without GSO.
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks(self.segment_size.max(1))
+    }
+
+    /// Whether `packet` can be appended without breaking the GSO layout.
+    fn accepts(&self, packet: &[u8], dst: SocketAddr, max_segments: usize) -> bool {
+        dst == self.dst
+            && self.segment_size > 0
+            && self.data.len().is_multiple_of(self.segment_size)
+            && !packet.is_empty()
+            && packet.len() <= self.segment_size
+            && self.segment_count() < max_segments
+    }
+}
+
+/// Group packets, in order, into batches of at most `max_segments` packets.
+///
+/// A batch ends at a change of destination, after a packet shorter than the
+/// first, or at a packet longer than the first. Order across batches is kept.
+pub fn batch_packets(packets: Vec<(Vec<u8>, SocketAddr)>, max_segments: usize) -> Vec<PacketBatch> {
+    let max_segments = max_segments.max(1);
+    let mut batches: Vec<PacketBatch> = Vec::new();
+    for (packet, dst) in packets {
+        match batches.last_mut() {
+            Some(batch) if batch.accepts(&packet, dst, max_segments) => {
+                batch.data.extend_from_slice(&packet);
+            }
+            _ => batches.push(PacketBatch::new(packet, dst)),
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn equal_packets_to_one_destination_share_a_batch() {
+        let packets = vec![(vec![1; 100], addr(1)); 3];
+        let batches = batch_packets(packets, MAX_GSO_SEGMENTS);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].segment_size, 100);
+        assert_eq!(batches[0].segment_count(), 3);
+        assert_eq!(batches[0].segments().count(), 3);
+    }
+
+    #[test]
+    fn a_short_packet_ends_its_batch() {
+        let packets = vec![
+            (vec![1; 100], addr(1)),
+            (vec![2; 60], addr(1)),
+            (vec![3; 100], addr(1)),
+        ];
+        let batches = batch_packets(packets, MAX_GSO_SEGMENTS);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].segment_count(), 2);
+        let segments: Vec<_> = batches[0].segments().collect();
+        assert_eq!(segments[1], &[2; 60][..]);
+        assert_eq!(batches[1].data, vec![3; 100]);
+    }
+
+    #[test]
+    fn longer_packets_and_new_destinations_start_batches() {
+        let packets = vec![
+            (vec![1; 60], addr(1)),
+            (vec![2; 100], addr(1)),
+            (vec![3; 100], addr(2)),
+            (vec![4; 100], addr(1)),
+        ];
+        let batches = batch_packets(packets, MAX_GSO_SEGMENTS);
+        let dsts: Vec<_> = batches.iter().map(|batch| batch.dst).collect();
+        assert_eq!(dsts, vec![addr(1), addr(1), addr(2), addr(1)]);
+        assert!(batches.iter().all(|batch| batch.segment_count() == 1));
+    }
+
+    #[test]
+    fn batches_stop_at_max_segments() {
+        let packets = vec![(vec![0; 10], addr(1)); 5];
+        let batches = batch_packets(packets.clone(), 2);
+        let counts: Vec<_> = batches.iter().map(PacketBatch::segment_count).collect();
+        assert_eq!(counts, vec![2, 2, 1]);
+        // Zero is taken as one
+        assert_eq!(batch_packets(packets, 0).len(), 5);
+    }
+
+    #[test]
+    fn empty_packets_are_never_merged() {
+        let packets = vec![(Vec::new(), addr(1)), (Vec::new(), addr(1))];
+        let batches = batch_packets(packets, MAX_GSO_SEGMENTS);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].segment_count(), 1);
+    }
+}
@@ -1,56 +1,184 @@
 //! QUIC client implementation using tquic.
 
 use crate::config::Config;
-use crate::error::Error;
-use crate::multipath::{PathEvent, PathId, PathInfo, PathManager, PathMode};
+use crate::error::{CloseCause, Error, ErrorKind};
+use crate::event::{ConnectionEvent, EventQueue};
+use crate::keylog::Keylog;
+use crate::multipath::{MultipathScheduler, PathEvent, PathId, PathInfo, PathManager, PathMode};
+use crate::observer::{long_header_version, PacketDirection, PacketObserver};
+use crate::qlog::start_qlog;
+use crate::sender::PacketSender;
+use crate::session::{SessionData, SessionStore};
+use crate::stream::{check_urgency, BiStream, SendLedger, DEFAULT_URGENCY};
+use crate::tls::TlsContext;
+use crate::token::{MemoryTokenStore, TokenStore};
 use bytes::Bytes;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::rc::Rc;
-use tquic::{Connection, Endpoint, PacketInfo, PacketSendHandler, TransportHandler};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tquic::{Connection, Endpoint, PacketInfo, TransportHandler};
 
 /// QUIC client for connecting to a server.
 pub struct Client {
     config: Config,
+    session_store: Option<Rc<RefCell<dyn SessionStore>>>,
+    token_store: Rc<RefCell<dyn TokenStore>>,
+    packet_observer: Option<Rc<RefCell<dyn PacketObserver>>>,
 }
 
 impl Client {
     /// Create a new QUIC client with the given configuration.
     pub fn new(config: Config) -> Result<Self, Error> {
-        Ok(Self { config })
+        Ok(Self {
+            config,
+            session_store: None,
+            token_store: Rc::new(RefCell::new(MemoryTokenStore::new())),
+            packet_observer: None,
+        })
+    }
+
+    /// Resume sessions saved in `store` and save new ones there.
+    ///
+    /// Connections to a server with a saved session skip the full handshake, and
+    /// with early data enabled can send stream data before it completes.
+    pub fn with_session_store(mut self, store: impl SessionStore + 'static) -> Self {
+        self.session_store = Some(Rc::new(RefCell::new(store)));
+        self
+    }
+
+    /// Present address validation tokens saved in `store` and save new ones there.
+    ///
+    /// Replaces the in-memory store every client starts with; pass a
+    /// [`FileSessionStore`](crate::FileSessionStore) to keep tokens across runs.
+    pub fn with_token_store(mut self, store: impl TokenStore + 'static) -> Self {
+        self.token_store = Rc::new(RefCell::new(store));
+        self
     }
 
-    /// Connect to a server at the given address.
+    /// Report every datagram of this client's endpoints to `observer`.
+    ///
+    /// All endpoints share the one observer.
+    pub fn with_packet_observer(mut self, observer: impl PacketObserver + 'static) -> Self {
+        self.packet_observer = Some(Rc::new(RefCell::new(observer)));
+        self
+    }
+
+    /// Connect to a server at the given address, on an endpoint of its own.
     pub fn connect(
         &self,
         local_addr: SocketAddr,
         server_addr: SocketAddr,
         server_name: &str,
     ) -> Result<ClientConnection, Error> {
-        let tquic_config = self.config.to_tquic_client_config()?;
+        self.endpoint(local_addr)?.connect(server_addr, server_name)
+    }
 
-        // Create the connection state
-        let state = Rc::new(RefCell::new(ConnectionState::new()));
+    /// Create an endpoint that can hold several connections on one socket.
+    pub fn endpoint(&self, local_addr: SocketAddr) -> Result<ClientEndpoint, Error> {
+        let (tquic_config, tls_context) = self.config.to_tquic_client_config()?;
+        self.config.prepare_qlog_dir()?;
+        let keylog = self.config.open_keylog()?;
 
-        // Create handler and sender
+        let pool: ConnectionPool = Rc::new(RefCell::new(HashMap::new()));
         let handler = Box::new(ClientHandler {
-            state: state.clone(),
+            pool: pool.clone(),
+            qlog_dir: self.config.qlog_dir.clone(),
+            keylog,
+            _tls_context: tls_context,
         });
-        let sender = Rc::new(PacketSender::new());
-
-        // Create tquic endpoint
-        let mut endpoint = Endpoint::new(
+        let sender = Rc::new(PacketSender::new(self.config.max_packet_size()));
+        if let Some(observer) = &self.packet_observer {
+            sender.set_observer(observer.clone());
+        }
+        let endpoint = Endpoint::new(
             Box::new(tquic_config),
             false, // is_server = false for client
             handler,
             sender.clone(),
         );
 
+        Ok(ClientEndpoint {
+            endpoint: Rc::new(RefCell::new(endpoint)),
+            sender,
+            pool,
+            local_addr,
+            config: self.config.clone(),
+            session_store: self.session_store.clone(),
+            token_store: self.token_store.clone(),
+        })
+    }
+}
+
+/// Connections of one endpoint, by tquic connection index.
+type ConnectionPool = Rc<RefCell<HashMap<u64, Rc<RefCell<ConnectionState>>>>>;
+
+/// A client endpoint: one UDP socket's worth of QUIC connections.
+///
+/// Every [`ClientConnection`] it hands out shares the endpoint, so driving any
+/// of them drives them all: `recv` takes packets of any connection,
+/// `poll_send` returns packets of all of them, and `timeout` is the earliest
+/// of their timers. Send everything through the one socket at `local_addr`.
+pub struct ClientEndpoint {
+    endpoint: Rc<RefCell<Endpoint>>,
+    sender: Rc<PacketSender>,
+    pool: ConnectionPool,
+    local_addr: SocketAddr,
+    config: Config,
+    session_store: Option<Rc<RefCell<dyn SessionStore>>>,
+    token_store: Rc<RefCell<dyn TokenStore>>,
+}
+
+impl ClientEndpoint {
+    /// Open another connection to a server at the given address.
+    pub fn connect(
+        &mut self,
+        server_addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<ClientConnection, Error> {
+        // A resumed handshake shows no certificate to check the pin against
+        let session = self
+            .session_store
+            .as_ref()
+            .filter(|_| self.config.pinned_spki.is_none())
+            .map(|store| SessionRecorder::new(store.clone(), server_name));
+        let tokens = TokenRecorder::new(self.token_store.clone(), server_name, server_addr);
+        let token = tokens.token.clone();
+        let state = Rc::new(RefCell::new(ConnectionState::new(session, tokens)));
+
         // Initiate connection (6 args: local, remote, server_name, session, token, config)
-        let conn_id = endpoint
-            .connect(local_addr, server_addr, Some(server_name), None, None, None)
-            .map_err(|e| Error::Quic(e.to_string()))?;
+        let saved = state
+            .borrow()
+            .session
+            .as_ref()
+            .map(|session| session.data.clone())
+            .unwrap_or_default();
+        let ticket = (!saved.ticket.is_empty()).then_some(saved.ticket.as_slice());
+        let conn_id = self
+            .endpoint
+            .borrow_mut()
+            .connect(
+                self.local_addr,
+                server_addr,
+                Some(server_name),
+                ticket,
+                token.as_deref(),
+                None,
+            )
+            .map_err(Error::quic)?;
+        if ticket.is_some() {
+            tracing::debug!("Resuming saved session for {}", server_name);
+        }
+        if token.is_some() {
+            tracing::debug!("Presenting saved address token for {}", server_addr);
+        }
+        // Inside `connect` tquic only raises `on_conn_created`, which needs no
+        // state, so registering the connection afterwards misses nothing.
+        self.pool.borrow_mut().insert(conn_id, state.clone());
 
         tracing::info!(
             "Connecting to {} ({}), conn_id={}",
@@ -60,137 +188,390 @@ impl Client {
         );
 
         Ok(ClientConnection {
-            endpoint,
+            endpoint: self.endpoint.clone(),
             conn_id,
             state,
-            sender,
-            local_addr,
+            pool: self.pool.clone(),
+            sender: self.sender.clone(),
+            recv_buf: Vec::new(),
+            local_addr: self.local_addr,
             server_addr,
+            path_peers: HashMap::from([(0, server_addr)]),
+            path_modes: HashMap::new(),
+            abandoned_paths: HashMap::new(),
+            next_path_id: 1,
+            path_losses: HashMap::new(),
+            scheduler: self.config.scheduler.build(),
+            keep_alive: (!self.config.keep_alive_interval.is_zero())
+                .then_some(self.config.keep_alive_interval),
         })
     }
+
+    /// IDs of the connections that have not closed yet, in ascending order.
+    pub fn connection_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.pool.borrow().keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Get the local address the endpoint sends from.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
 }
 
 /// Internal state shared between the handler and the connection.
 struct ConnectionState {
     ready: bool,
     closing: bool,
+    close_cause: Option<CloseCause>,
+    alpn: Vec<u8>,
+    /// QUIC version of the server's long-header packets, once one arrived.
+    version: Option<u32>,
     streams: HashMap<u64, StreamState>,
-    path_events: Vec<PathEvent>,
+    events: EventQueue<ConnectionEvent>,
+    /// Streams that regained send capacity after a write was refused.
+    writable_events: Vec<u64>,
+    resumed: bool,
+    session: Option<SessionRecorder>,
+    tokens: TokenRecorder,
+    /// Signalled when a stream handle queued frames, so whoever drives the
+    /// connection knows to call `poll_send`.
+    send_notify: Rc<Notify>,
+    /// When the connection last sent a packet, for the keep-alive.
+    last_sent: Instant,
+    /// tquic's count of packets sent when `last_sent` was last updated.
+    sent_count: u64,
 }
 
 impl ConnectionState {
-    fn new() -> Self {
+    fn new(session: Option<SessionRecorder>, tokens: TokenRecorder) -> Self {
         Self {
             ready: false,
             closing: false,
+            close_cause: None,
+            alpn: Vec::new(),
+            version: None,
             streams: HashMap::new(),
-            path_events: Vec::new(),
+            events: EventQueue::new(),
+            writable_events: Vec::new(),
+            resumed: false,
+            session,
+            tokens,
+            send_notify: Rc::new(Notify::new()),
+            last_sent: Instant::now(),
+            sent_count: 0,
+        }
+    }
+}
+
+/// Saves resumption state for one server to the store as the connection learns it.
+struct SessionRecorder {
+    store: Rc<RefCell<dyn SessionStore>>,
+    server_name: String,
+    data: SessionData,
+}
+
+impl SessionRecorder {
+    fn new(store: Rc<RefCell<dyn SessionStore>>, server_name: &str) -> Self {
+        let data = store.borrow_mut().load(server_name).unwrap_or_default();
+        Self {
+            store,
+            server_name: server_name.to_string(),
+            data,
+        }
+    }
+
+    /// Save the connection's TLS session if the server issued a new ticket.
+    fn record_ticket(&mut self, conn: &Connection) {
+        let Some(ticket) = conn.session() else {
+            return;
+        };
+        if ticket != self.data.ticket.as_slice() {
+            self.data.ticket = ticket.to_vec();
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        self.store.borrow_mut().save(&self.server_name, &self.data);
+    }
+}
+
+/// Saves the address validation tokens one server issues to the store.
+struct TokenRecorder {
+    store: Rc<RefCell<dyn TokenStore>>,
+    server_name: String,
+    server_addr: SocketAddr,
+    /// The token presented in the Initial, if any.
+    token: Option<Vec<u8>>,
+}
+
+impl TokenRecorder {
+    fn new(store: Rc<RefCell<dyn TokenStore>>, server_name: &str, server_addr: SocketAddr) -> Self {
+        let token = store.borrow_mut().load(server_name, server_addr);
+        Self {
+            store,
+            server_name: server_name.to_string(),
+            server_addr,
+            token,
+        }
+    }
+
+    fn record(&mut self, token: Vec<u8>) {
+        self.store
+            .borrow_mut()
+            .save(&self.server_name, self.server_addr, &token);
+        self.token = Some(token);
+    }
+
+    /// Forget the presented token after a handshake that never completed.
+    ///
+    /// The server may have rejected it, and another attempt with it would
+    /// draw another Retry.
+    fn forget(&mut self) {
+        if self.token.take().is_some() {
+            tracing::debug!("Dropping address token for {}", self.server_addr);
+            self.store
+                .borrow_mut()
+                .remove(&self.server_name, self.server_addr);
         }
     }
 }
 
 struct StreamState {
     readable: bool,
+    /// Cleared when [`ClientConnection::stream_write`] runs out of credit.
     writable: bool,
     finished: bool,
+    /// Writes the peer has yet to acknowledge.
+    unacked: SendLedger,
+    /// Caller's label, reported with the stream's events.
+    label: Option<u64>,
+    /// Tasks waiting in [`StreamHandle`] for data or send capacity.
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl StreamState {
+    fn new() -> Self {
+        Self {
+            readable: false,
+            writable: true,
+            finished: false,
+            unacked: SendLedger::default(),
+            label: None,
+            read_waker: None,
+            write_waker: None,
+        }
+    }
+
+    fn wake_all(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
 }
 
 /// Handler for tquic transport events.
 struct ClientHandler {
-    state: Rc<RefCell<ConnectionState>>,
+    pool: ConnectionPool,
+    qlog_dir: Option<PathBuf>,
+    keylog: Option<Keylog>,
+    /// Owns the TLS context the endpoint's config borrows, if it has one.
+    _tls_context: Option<TlsContext>,
+}
+
+impl ClientHandler {
+    /// State of the connection an event is for.
+    fn state(&self, conn: &Connection) -> Option<Rc<RefCell<ConnectionState>>> {
+        self.pool.borrow().get(&conn.index()?).cloned()
+    }
 }
 
 impl TransportHandler for ClientHandler {
-    fn on_conn_created(&mut self, _conn: &mut Connection) {
+    fn on_conn_created(&mut self, conn: &mut Connection) {
         tracing::debug!("Connection created");
+        if let Some(dir) = &self.qlog_dir {
+            start_qlog(conn, dir, "client");
+        }
+        if let Some(keylog) = &self.keylog {
+            keylog.start(conn);
+        }
     }
 
-    fn on_conn_established(&mut self, _conn: &mut Connection) {
+    fn on_conn_established(&mut self, conn: &mut Connection) {
         tracing::info!("Connection established");
-        self.state.borrow_mut().ready = true;
+        let Some(state) = self.state(conn) else {
+            return;
+        };
+        let mut state = state.borrow_mut();
+        state.ready = true;
+        state.resumed = conn.is_resumed();
+        state.alpn = conn.application_proto().to_vec();
+        if state.resumed {
+            tracing::info!("Resumed previous session");
+        }
+        if let Some(session) = &mut state.session {
+            session.record_ticket(conn);
+        }
+        state.events.push(ConnectionEvent::Established);
     }
 
-    fn on_conn_closed(&mut self, _conn: &mut Connection) {
-        tracing::info!("Connection closed");
-        self.state.borrow_mut().closing = true;
+    fn on_conn_closed(&mut self, conn: &mut Connection) {
+        let cause = CloseCause::from_connection(conn);
+        match &cause {
+            Some(cause) => tracing::info!("Connection closed: {}", cause),
+            None => tracing::info!("Connection closed"),
+        }
+        // tquic frees the connection after this; its index is never handed out
+        // again, so the entry would only go stale
+        let Some(state) = conn
+            .index()
+            .and_then(|index| self.pool.borrow_mut().remove(&index))
+        else {
+            return;
+        };
+        let mut state = state.borrow_mut();
+        state.closing = true;
+        state.close_cause = cause.clone();
+        if let Some(session) = &mut state.session {
+            session.record_ticket(conn);
+        }
+        if !state.ready {
+            state.tokens.forget();
+        }
+        for stream in state.streams.values_mut() {
+            stream.wake_all();
+        }
+        state.events.push(ConnectionEvent::Closed(cause));
     }
 
-    fn on_stream_created(&mut self, _conn: &mut Connection, stream_id: u64) {
+    fn on_stream_created(&mut self, conn: &mut Connection, stream_id: u64) {
         tracing::debug!("Stream {} created", stream_id);
-        self.state.borrow_mut().streams.insert(
-            stream_id,
-            StreamState {
-                readable: false,
-                writable: true,
-                finished: false,
-            },
-        );
+        let Some(state) = self.state(conn) else {
+            return;
+        };
+        let mut state = state.borrow_mut();
+        // A local stream may have seen writes, or a waiting task, already
+        let label = state
+            .streams
+            .entry(stream_id)
+            .or_insert_with(StreamState::new)
+            .label;
+        state
+            .events
+            .push(ConnectionEvent::StreamOpened { stream_id, label });
     }
 
-    fn on_stream_readable(&mut self, _conn: &mut Connection, stream_id: u64) {
+    fn on_stream_readable(&mut self, conn: &mut Connection, stream_id: u64) {
         tracing::trace!("Stream {} readable", stream_id);
-        if let Some(stream) = self.state.borrow_mut().streams.get_mut(&stream_id) {
-            stream.readable = true;
+        let Some(state) = self.state(conn) else {
+            return;
+        };
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        if let Some(stream) = state.streams.get_mut(&stream_id) {
+            // Like writability, reported after every event while data is buffered
+            if !stream.readable {
+                stream.readable = true;
+                state.events.push(ConnectionEvent::StreamReadable {
+                    stream_id,
+                    label: stream.label,
+                });
+            }
+            if let Some(waker) = stream.read_waker.take() {
+                waker.wake();
+            }
         }
     }
 
-    fn on_stream_writable(&mut self, _conn: &mut Connection, stream_id: u64) {
+    fn on_stream_writable(&mut self, conn: &mut Connection, stream_id: u64) {
         tracing::trace!("Stream {} writable", stream_id);
-        if let Some(stream) = self.state.borrow_mut().streams.get_mut(&stream_id) {
-            stream.writable = true;
+        let Some(state) = self.state(conn) else {
+            return;
+        };
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        if let Some(stream) = state.streams.get_mut(&stream_id) {
+            // tquic reports every writable stream after each event, not just changes
+            if !stream.writable {
+                stream.writable = true;
+                state.writable_events.push(stream_id);
+            }
+            if let Some(waker) = stream.write_waker.take() {
+                waker.wake();
+            }
         }
     }
 
-    fn on_stream_closed(&mut self, _conn: &mut Connection, stream_id: u64) {
+    fn on_stream_closed(&mut self, conn: &mut Connection, stream_id: u64) {
         tracing::debug!("Stream {} closed", stream_id);
-        if let Some(stream) = self.state.borrow_mut().streams.get_mut(&stream_id) {
+        let Some(state) = self.state(conn) else {
+            return;
+        };
+        let mut state = state.borrow_mut();
+        let mut label = None;
+        if let Some(stream) = state.streams.get_mut(&stream_id) {
             stream.finished = true;
+            // tquic dropped the stream's buffers with it
+            stream.unacked = SendLedger::default();
+            stream.wake_all();
+            label = stream.label;
         }
+        state
+            .events
+            .push(ConnectionEvent::StreamFinished { stream_id, label });
     }
 
-    fn on_new_token(&mut self, _conn: &mut Connection, _token: Vec<u8>) {
-        // Token management for 0-RTT
-    }
-}
-
-/// Packet sender for tquic.
-struct PacketSender {
-    pending_packets: RefCell<Vec<(Vec<u8>, PacketInfo)>>,
-}
-
-impl PacketSender {
-    fn new() -> Self {
-        Self {
-            pending_packets: RefCell::new(Vec::new()),
-        }
-    }
-
-    fn take_packets(&self) -> Vec<(Vec<u8>, PacketInfo)> {
-        std::mem::take(&mut *self.pending_packets.borrow_mut())
-    }
-}
-
-impl PacketSendHandler for PacketSender {
-    fn on_packets_send(&self, pkts: &[(Vec<u8>, PacketInfo)]) -> tquic::Result<usize> {
-        let mut pending = self.pending_packets.borrow_mut();
-        for (data, info) in pkts {
-            pending.push((data.clone(), *info));
-        }
-        Ok(pkts.len())
+    fn on_new_token(&mut self, conn: &mut Connection, token: Vec<u8>) {
+        let Some(state) = self.state(conn) else {
+            return;
+        };
+        state.borrow_mut().tokens.record(token);
     }
 }
 
 /// An established QUIC client connection.
 pub struct ClientConnection {
-    endpoint: Endpoint,
+    endpoint: Rc<RefCell<Endpoint>>,
     conn_id: u64,
     state: Rc<RefCell<ConnectionState>>,
+    /// Every connection of the endpoint, this one included.
+    pool: ConnectionPool,
     sender: Rc<PacketSender>,
+    /// Scratch copy of the packet being received; tquic decrypts in place.
+    recv_buf: Vec<u8>,
     local_addr: SocketAddr,
     server_addr: SocketAddr,
+    /// Peer address of each path we opened, keyed by path ID (0 is the initial path).
+    path_peers: HashMap<PathId, SocketAddr>,
+    /// Modes set through [`PathManager::set_path_mode`]; missing paths are `Normal`.
+    path_modes: HashMap<PathId, PathMode>,
+    /// Paths given up through [`PathManager::abandon_path`], by peer address.
+    /// tquic keeps them, so probing the peer again revives the same path.
+    abandoned_paths: HashMap<SocketAddr, PathId>,
+    /// ID for the next probed path. Not tquic's own path index, which a
+    /// rebind hands out again for the same peer.
+    next_path_id: PathId,
+    /// tquic's count of packets lost on each path when last checked.
+    path_losses: HashMap<PathId, u64>,
+    scheduler: Box<dyn MultipathScheduler>,
+    /// PING every path after this long without sending anything; tquic has no
+    /// keep-alive of its own.
+    keep_alive: Option<Duration>,
 }
 
 impl ClientConnection {
+    /// ID of the connection within its endpoint, as in
+    /// [`ClientEndpoint::connection_ids`].
+    pub fn conn_id(&self) -> u64 {
+        self.conn_id
+    }
+
     /// Check if the connection is ready (handshake complete).
     pub fn is_ready(&self) -> bool {
         self.state.borrow().ready
@@ -201,58 +582,328 @@ impl ClientConnection {
         self.state.borrow().closing
     }
 
+    /// Why the connection closed, once it has.
+    ///
+    /// Tells an orderly shutdown by the peer apart from a protocol violation,
+    /// timeout or stateless reset.
+    pub fn close_cause(&self) -> Option<CloseCause> {
+        self.state.borrow().close_cause.clone()
+    }
+
+    /// Whether the handshake completed, is still running (`Ok(false)`), or
+    /// failed.
+    ///
+    /// A handshake that ran out of [`Config::handshake_timeout`] fails with
+    /// [`Error::HandshakeTimeout`], so the caller can try another peer; any
+    /// other close fails with [`Error::ConnectionClosed`].
+    pub fn handshake_status(&self) -> Result<bool, Error> {
+        let state = self.state.borrow();
+        if state.ready {
+            return Ok(true);
+        }
+        if !state.closing {
+            return Ok(false);
+        }
+        match &state.close_cause {
+            Some(CloseCause::HandshakeTimeout) => Err(Error::HandshakeTimeout),
+            Some(cause) => Err(Error::ConnectionClosed {
+                reason: format!("handshake failed: {}", cause),
+            }),
+            None => Err(Error::ConnectionClosed {
+                reason: "handshake failed".to_string(),
+            }),
+        }
+    }
+
+    /// Check if the handshake resumed a saved session (false until ready).
+    pub fn is_resumed(&self) -> bool {
+        self.state.borrow().resumed
+    }
+
+    /// Check if the connection can send 0-RTT data, i.e. it is resuming a
+    /// session with early data enabled and the handshake is still running.
+    pub fn is_in_early_data(&mut self) -> bool {
+        self.endpoint
+            .borrow_mut()
+            .conn_get_mut(self.conn_id)
+            .is_some_and(|conn| conn.is_in_early_data())
+    }
+
     /// Process incoming packet data.
+    ///
+    /// The packet may belong to any connection of the endpoint.
     pub fn recv(&mut self, data: &[u8], from: SocketAddr) -> Result<(), Error> {
         let info = PacketInfo {
             src: from,
             dst: self.local_addr,
             time: std::time::Instant::now(),
         };
+        self.sender
+            .observe(PacketDirection::Received, data, self.local_addr, from);
         // tquic recv takes &mut [u8], so we need to copy
-        let mut buf = data.to_vec();
+        self.recv_buf.clear();
+        self.recv_buf.extend_from_slice(data);
         self.endpoint
-            .recv(&mut buf, &info)
-            .map_err(|e| Error::Quic(e.to_string()))?;
-        let _ = self.endpoint.process_connections();
+            .borrow_mut()
+            .recv(&mut self.recv_buf, &info)
+            .map_err(Error::quic)?;
+        // tquic keeps the version to itself; the server's long headers carry
+        // it, and version 0 marks Version Negotiation.
+        if let Some(version) = long_header_version(data).filter(|v| *v != 0) {
+            self.state.borrow_mut().version.get_or_insert(version);
+        }
+        let _ = self.endpoint.borrow_mut().process_connections();
+        // Session tickets arrive after the handshake, so look for one after every packet.
+        self.record_session();
+        self.check_path_losses();
         Ok(())
     }
 
     /// Get packets to send.
     pub fn poll_send(&mut self) -> Vec<(Vec<u8>, SocketAddr)> {
-        let _ = self.endpoint.process_connections();
-        self.sender
-            .take_packets()
-            .into_iter()
-            .map(|(data, info)| (data, info.dst))
-            .collect()
+        let mut packets = Vec::new();
+        self.poll_send_into(&mut packets);
+        packets
+    }
+
+    /// Append packets to send to `out`, reusing its capacity.
+    ///
+    /// These are the packets of every connection of the endpoint.
+    pub fn poll_send_into(&mut self, out: &mut Vec<(Vec<u8>, SocketAddr)>) {
+        self.send_keep_alives_if_due();
+        let _ = self.endpoint.borrow_mut().process_connections();
+        self.sender.drain_packets_into(out);
+        self.note_sent(Instant::now());
+    }
+
+    /// Hand back the buffer of a packet that has been sent.
+    ///
+    /// Later packets are copied into recycled buffers instead of fresh ones.
+    pub fn recycle(&self, packet: Vec<u8>) {
+        self.sender.recycle(packet);
     }
 
-    /// Get the next timeout.
+    /// Packets of the endpoint that came out larger than
+    /// [`Config::max_packet_size`](crate::Config::max_packet_size).
+    ///
+    /// Always zero while path MTU probing is on, as there is no maximum.
+    pub fn oversized_packets(&self) -> u64 {
+        self.sender.oversized_packets()
+    }
+
+    /// Get packets to send, grouped for sendmmsg or UDP GSO.
+    ///
+    /// See [`batch_packets`](crate::batch_packets) for how packets are grouped.
+    pub fn poll_send_batched(&mut self, max_segments: usize) -> Vec<crate::PacketBatch> {
+        crate::batch_packets(self.poll_send(), max_segments)
+    }
+
+    /// Get the next timeout of any connection of the endpoint, including the
+    /// next keep-alive.
     pub fn timeout(&self) -> Option<std::time::Duration> {
-        self.endpoint.timeout()
+        let quic = self.endpoint.borrow().timeout();
+        let keep_alive = self
+            .keep_alive_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (quic, keep_alive) {
+            (Some(quic), Some(keep_alive)) => Some(quic.min(keep_alive)),
+            (quic, keep_alive) => quic.or(keep_alive),
+        }
     }
 
     /// Handle timeout.
     pub fn on_timeout(&mut self) {
-        self.endpoint.on_timeout(std::time::Instant::now());
-        let _ = self.endpoint.process_connections();
+        self.endpoint
+            .borrow_mut()
+            .on_timeout(std::time::Instant::now());
+        self.send_keep_alives_if_due();
+        let _ = self.endpoint.borrow_mut().process_connections();
+        // Loss detection runs on ACKs and on the loss timer
+        self.check_path_losses();
+    }
+
+    /// Queue a [`PathEvent::Loss`] for each path tquic declared packets lost on
+    /// since the last check.
+    fn check_path_losses(&mut self) {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return;
+        };
+        let mut state = self.state.borrow_mut();
+        for (&path_id, &peer_addr) in &self.path_peers {
+            let Ok(stats) = conn.get_path_stats(self.local_addr, peer_addr) else {
+                continue;
+            };
+            let seen = self.path_losses.entry(path_id).or_insert(0);
+            if stats.lost_count > *seen {
+                state.events.push(ConnectionEvent::Path(PathEvent::Loss {
+                    path_id,
+                    lost_packets: stats.lost_count - *seen,
+                }));
+                *seen = stats.lost_count;
+            }
+        }
     }
 
-    /// Open a new bidirectional stream.
+    /// When the first keep-alive PING of the endpoint's established
+    /// connections is due.
+    fn keep_alive_deadline(&self) -> Option<Instant> {
+        let interval = self.keep_alive?;
+        self.pool
+            .borrow()
+            .values()
+            .filter_map(|state| {
+                let state = state.borrow();
+                (state.ready && !state.closing).then(|| state.last_sent + interval)
+            })
+            .min()
+    }
+
+    /// Move the send time of each connection that sent packets since the last
+    /// look to `now`; tquic counts them per connection.
+    fn note_sent(&self, now: Instant) {
+        let mut endpoint = self.endpoint.borrow_mut();
+        for (conn_id, state) in self.pool.borrow().iter() {
+            let Some(conn) = endpoint.conn_get_mut(*conn_id) else {
+                continue;
+            };
+            let sent_count = conn.stats().sent_count;
+            let mut state = state.borrow_mut();
+            if sent_count != state.sent_count {
+                state.sent_count = sent_count;
+                state.last_sent = now;
+            }
+        }
+    }
+
+    /// Queue a PING on every path of each established connection that has
+    /// sent nothing for the keep-alive interval, so resolver and NAT state
+    /// survive quiet periods.
+    fn send_keep_alives_if_due(&mut self) {
+        let Some(interval) = self.keep_alive else {
+            return;
+        };
+        let now = Instant::now();
+        self.note_sent(now);
+        let mut endpoint = self.endpoint.borrow_mut();
+        for (conn_id, state) in self.pool.borrow().iter() {
+            let mut state = state.borrow_mut();
+            if !state.ready || state.closing || now < state.last_sent + interval {
+                continue;
+            }
+            if let Some(conn) = endpoint.conn_get_mut(*conn_id) {
+                if let Err(e) = conn.ping(None) {
+                    tracing::debug!("Failed to queue keep-alive PING: {}", e);
+                }
+            }
+            // Counted as sent even if the PING is refused, so a failure is not retried in a loop
+            state.last_sent = now;
+        }
+    }
+
+    /// Open a new bidirectional stream with the default priority.
     pub fn open_bi(&mut self) -> Result<u64, Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(self.conn_id) {
-            // stream_bidi_new(priority, urgency)
+        self.open_bi_with_priority(DEFAULT_URGENCY, false)
+    }
+
+    /// Open a new bidirectional stream with an RFC 9218 priority.
+    ///
+    /// Lower `urgency` (0-7) is sent first. Streams of equal urgency share the
+    /// connection round-robin when `incremental` is set, and go one after another
+    /// otherwise.
+    pub fn open_bi_with_priority(&mut self, urgency: u8, incremental: bool) -> Result<u64, Error> {
+        check_urgency(urgency)?;
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
+            // stream_bidi_new(urgency, incremental)
             let stream_id = conn
-                .stream_bidi_new(0, false)
-                .map_err(|e| Error::Stream(e.to_string()))?;
-            self.state.borrow_mut().streams.insert(
-                stream_id,
-                StreamState {
-                    readable: false,
-                    writable: true,
-                    finished: false,
-                },
-            );
+                .stream_bidi_new(urgency, incremental)
+                .map_err(Error::stream)?;
+            self.state
+                .borrow_mut()
+                .streams
+                .insert(stream_id, StreamState::new());
+            Ok(stream_id)
+        } else {
+            Err(Error::ConnectionClosed {
+                reason: "connection not found".to_string(),
+            })
+        }
+    }
+
+    /// Open a bidirectional stream with a label, reported with its events.
+    ///
+    /// The label is opaque, e.g. an index into the caller's own table.
+    pub fn open_bi_labeled(&mut self, label: u64) -> Result<u64, Error> {
+        let stream_id = self.open_bi()?;
+        self.set_stream_label(stream_id, label)?;
+        Ok(stream_id)
+    }
+
+    /// Label a stream, e.g. one the peer opened; later events carry the label.
+    pub fn set_stream_label(&mut self, stream_id: u64, label: u64) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let stream = state
+            .streams
+            .get_mut(&stream_id)
+            .ok_or_else(|| Error::Stream {
+                kind: ErrorKind::InvalidState,
+                message: format!("unknown stream {}", stream_id),
+            })?;
+        stream.label = Some(label);
+        Ok(())
+    }
+
+    /// Label of a stream, if it has one.
+    pub fn stream_label(&self, stream_id: u64) -> Option<u64> {
+        self.state.borrow().streams.get(&stream_id)?.label
+    }
+
+    /// Open a new bidirectional stream, before the handshake completes if 0-RTT
+    /// is available.
+    ///
+    /// Data written to the stream while [`Self::is_in_early_data`] holds goes out
+    /// as 0-RTT data, which an attacker can replay to the server. Fails when the
+    /// connection is neither ready nor able to send early data.
+    pub fn open_bi_early(&mut self) -> Result<u64, Error> {
+        if !self.is_ready() && !self.is_in_early_data() {
+            return Err(Error::Stream {
+                kind: ErrorKind::InvalidState,
+                message: "0-RTT is not available before the handshake completes".to_string(),
+            });
+        }
+        self.open_bi()
+    }
+
+    /// Open a new bidirectional stream as a [`BiStream`] handle with async reads
+    /// and writes.
+    ///
+    /// The handle shares this connection, so it is not `Send`: poll it on the
+    /// thread that drives the connection, e.g. in a `tokio::task::LocalSet`.
+    /// Data written through it goes out on the next [`Self::poll_send`].
+    pub fn open_bi_stream(&mut self) -> Result<BiStream, Error> {
+        let stream_id = self.open_bi()?;
+        Ok(BiStream::new(StreamHandle {
+            endpoint: self.endpoint.clone(),
+            state: self.state.clone(),
+            conn_id: self.conn_id,
+            stream_id,
+        }))
+    }
+
+    /// Notified whenever a stream handle queued data to send.
+    pub(crate) fn send_notify(&self) -> Rc<Notify> {
+        self.state.borrow().send_notify.clone()
+    }
+
+    /// Open a new unidirectional stream.
+    pub fn open_uni(&mut self) -> Result<u64, Error> {
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
+            // stream_uni_new(urgency, incremental)
+            let stream_id = conn.stream_uni_new(0, false).map_err(Error::stream)?;
+            self.state
+                .borrow_mut()
+                .streams
+                .insert(stream_id, StreamState::new());
             Ok(stream_id)
         } else {
             Err(Error::ConnectionClosed {
@@ -261,30 +912,120 @@ impl ClientConnection {
         }
     }
 
+    /// Change the priority of an open stream; see [`Self::open_bi_with_priority`].
+    pub fn set_stream_priority(
+        &mut self,
+        stream_id: u64,
+        urgency: u8,
+        incremental: bool,
+    ) -> Result<(), Error> {
+        check_urgency(urgency)?;
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
+            conn.stream_set_priority(stream_id, urgency, incremental)
+                .map_err(Error::stream)
+        } else {
+            Err(Error::ConnectionClosed {
+                reason: "connection not found".to_string(),
+            })
+        }
+    }
+
+    /// Transport parameters the server sent, once the handshake is done.
+    ///
+    /// See [`PeerTransportParams`](crate::PeerTransportParams) for which
+    /// fields tquic reports.
+    pub fn peer_transport_params(&self) -> Option<crate::PeerTransportParams> {
+        if !self.state.borrow().ready {
+            return None;
+        }
+        self.endpoint
+            .borrow_mut()
+            .conn_get_mut(self.conn_id)
+            .map(|conn| crate::PeerTransportParams::from_tquic(conn))
+    }
+
+    /// QUIC version the server's packets carry, or `None` until its first
+    /// Initial arrived.
+    pub fn quic_version(&self) -> Option<u32> {
+        self.state.borrow().version
+    }
+
+    /// ALPN protocol negotiated during the handshake (empty until ready).
+    pub fn alpn(&self) -> Vec<u8> {
+        self.state.borrow().alpn.clone()
+    }
+
     /// Write data to a stream.
+    ///
+    /// A write that is refused or cut short by flow control takes the stream
+    /// out of [`writable_streams`](Self::writable_streams) until tquic reports
+    /// capacity again, which [`drain_writable_events`](Self::drain_writable_events)
+    /// then announces.
     pub fn stream_write(&mut self, stream_id: u64, data: &[u8], fin: bool) -> Result<usize, Error> {
         // Process connections first to update flow control state
-        let _ = self.endpoint.process_connections();
-        if let Some(conn) = self.endpoint.conn_get_mut(self.conn_id) {
-            conn.stream_write(stream_id, Bytes::copy_from_slice(data), fin)
-                .map_err(|e| Error::Stream(e.to_string()))
+        let _ = self.endpoint.borrow_mut().process_connections();
+        let data = Bytes::copy_from_slice(data);
+        let result = if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
+            conn.stream_write(stream_id, data.clone(), fin)
         } else {
-            Err(Error::ConnectionClosed {
+            return Err(Error::ConnectionClosed {
                 reason: "connection not found".to_string(),
-            })
+            });
+        };
+        let mut state = self.state.borrow_mut();
+        match &result {
+            Ok(written) => {
+                let stream = state
+                    .streams
+                    .entry(stream_id)
+                    .or_insert_with(StreamState::new);
+                if *written < data.len() {
+                    stream.writable = false;
+                }
+                stream.unacked.record(data, *written);
+            }
+            Err(tquic::Error::Done) => {
+                if let Some(stream) = state.streams.get_mut(&stream_id) {
+                    stream.writable = false;
+                }
+            }
+            Err(_) => {}
         }
+        result.map_err(Error::stream)
+    }
+
+    /// Bytes written to a stream that the peer has not acknowledged yet.
+    ///
+    /// Acknowledgements are counted per `stream_write` call, so a write is
+    /// counted in full until all of it is acknowledged. Zero for unknown or
+    /// closed streams. Use it to hold back a source whose stream is falling
+    /// behind while others sharing the connection keep up.
+    pub fn stream_queued_bytes(&self, stream_id: u64) -> usize {
+        self.state
+            .borrow_mut()
+            .streams
+            .get_mut(&stream_id)
+            .map_or(0, |stream| stream.unacked.queued_bytes())
     }
 
     /// Read data from a stream.
+    ///
+    /// A read that finds no data clears the stream's readable state, so the
+    /// next data raises [`ConnectionEvent::StreamReadable`] again.
     pub fn stream_read(&mut self, stream_id: u64, buf: &mut [u8]) -> Result<(usize, bool), Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(self.conn_id) {
+        let result = if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
             conn.stream_read(stream_id, buf)
-                .map_err(|e| Error::Stream(e.to_string()))
         } else {
-            Err(Error::ConnectionClosed {
+            return Err(Error::ConnectionClosed {
                 reason: "connection not found".to_string(),
-            })
+            });
+        };
+        if let Err(tquic::Error::Done) = result {
+            if let Some(stream) = self.state.borrow_mut().streams.get_mut(&stream_id) {
+                stream.readable = false;
+            }
         }
+        result.map_err(Error::stream)
     }
 
     /// Get stream IDs that have readable data.
@@ -298,48 +1039,422 @@ impl ClientConnection {
             .collect()
     }
 
+    /// Get stream IDs that can take more data.
+    ///
+    /// Streams leave the list when a write runs out of flow control credit and
+    /// return once tquic reports capacity again.
+    pub fn writable_streams(&self) -> Vec<u64> {
+        self.state
+            .borrow()
+            .streams
+            .iter()
+            .filter(|(_, s)| s.writable)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Whether a stream can take more data; unknown streams cannot.
+    pub fn is_writable(&self, stream_id: u64) -> bool {
+        self.state
+            .borrow()
+            .streams
+            .get(&stream_id)
+            .is_some_and(|s| s.writable)
+    }
+
+    /// Drain the streams that regained capacity since the last call, in the
+    /// order they did.
+    pub fn drain_writable_events(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.state.borrow_mut().writable_events)
+    }
+
     /// Get stream write capacity (available flow control credits).
     pub fn stream_capacity(&mut self, stream_id: u64) -> usize {
-        if let Some(conn) = self.endpoint.conn_get_mut(self.conn_id) {
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
             conn.stream_capacity(stream_id).unwrap_or(0)
         } else {
             0
         }
     }
 
-    /// Drain path events.
+    /// Mode of a path; paths never given one are `Normal`.
+    pub fn path_mode(&self, path_id: PathId) -> PathMode {
+        self.path_modes.get(&path_id).copied().unwrap_or_default()
+    }
+
+    /// Ask the configured scheduler which paths should carry the next send.
+    ///
+    /// tquic schedules its packets with the matching algorithm, so this is
+    /// where its next packet goes, for callers that place sends of their own.
+    pub fn schedule(&mut self) -> Vec<PathId> {
+        let paths = self.active_paths();
+        self.scheduler.select(&paths)
+    }
+
+    /// Queue a PING frame on a path, e.g. to measure an otherwise idle path.
+    pub fn ping_path(&mut self, path_id: PathId) -> Result<(), Error> {
+        let remote = *self
+            .path_peers
+            .get(&path_id)
+            .ok_or_else(|| Error::Path(format!("unknown path {}", path_id)))?;
+        let local = self.local_addr;
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint
+            .conn_get_mut(self.conn_id)
+            .ok_or_else(connection_gone)?;
+        conn.ping(Some(tquic::FourTuple { local, remote }))
+            .map_err(|e| Error::Path(e.to_string()))
+    }
+
+    /// Move the connection to a new local address, e.g. after the host
+    /// switched networks.
+    ///
+    /// Every open path is reopened from `local_addr` and validated with a
+    /// PATH_CHALLENGE. Path IDs, stats and events move to the new paths. Feed
+    /// the connection datagrams received on the new socket and send its
+    /// packets from there; the endpoint's other connections keep their
+    /// address.
+    ///
+    /// The paths from the old address are abandoned, but tquic 1.6 only marks
+    /// them: its scheduler may still put packets on them. Those leave through
+    /// the new socket like the rest, and any the peer does not take are
+    /// declared lost and sent again.
+    ///
+    /// tquic cannot migrate a connection's only path, so this needs multipath
+    /// to have been negotiated. Through a recursive resolver the server sees
+    /// the resolver's address either way; a rebind matters for the paths that
+    /// reach the server directly.
+    pub fn rebind(&mut self, local_addr: SocketAddr) -> Result<(), Error> {
+        if local_addr == self.local_addr {
+            return Ok(());
+        }
+        let old_local = self.local_addr;
+        let mut peers: Vec<(PathId, SocketAddr)> = self
+            .path_peers
+            .iter()
+            .map(|(id, peer)| (*id, *peer))
+            .collect();
+        peers.sort_unstable();
+        {
+            let mut endpoint = self.endpoint.borrow_mut();
+            let conn = endpoint
+                .conn_get_mut(self.conn_id)
+                .ok_or_else(connection_gone)?;
+            if !conn.is_multipath() {
+                return Err(Error::Path(
+                    "rebinding needs multipath; tquic cannot migrate a single path".to_string(),
+                ));
+            }
+            for (_, peer) in &peers {
+                match conn.add_path(local_addr, *peer) {
+                    Ok(_) | Err(tquic::Error::Done) => {}
+                    Err(e) => return Err(Error::Path(e.to_string())),
+                }
+            }
+            for (path_id, peer) in &peers {
+                if let Err(e) = conn.abandon_path(old_local, *peer) {
+                    tracing::debug!("Path {} from {} not abandoned: {}", path_id, old_local, e);
+                }
+            }
+        }
+        self.local_addr = local_addr;
+        // Abandoned paths belong to the old address; probing their peers opens new ones.
+        self.abandoned_paths.clear();
+        // Loss counts restart with the new paths
+        self.path_losses.clear();
+        tracing::info!(
+            "Rebound {} path(s) from {} to {}",
+            peers.len(),
+            old_local,
+            local_addr
+        );
+        Ok(())
+    }
+
+    /// Take the events raised since the last call, oldest first.
+    pub fn poll_events(&mut self) -> Vec<ConnectionEvent> {
+        self.state.borrow_mut().events.drain()
+    }
+
+    /// Take only the path events, leaving the others for [`poll_events`](Self::poll_events).
     pub fn drain_path_events(&mut self) -> Vec<PathEvent> {
-        std::mem::take(&mut self.state.borrow_mut().path_events)
+        self.state.borrow_mut().events.extract(|event| match event {
+            ConnectionEvent::Path(path_event) => Some(path_event.clone()),
+            _ => None,
+        })
     }
 
     /// Close the connection.
     pub fn close(&mut self, error_code: u64, reason: &str) -> Result<(), Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(self.conn_id) {
+        self.record_session();
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
             conn.close(true, error_code, reason.as_bytes())
-                .map_err(|e| Error::Quic(e.to_string()))?;
+                .map_err(Error::quic)?;
         }
         Ok(())
     }
 
-    /// Get the current RTT estimate in microseconds.
+    /// Get the current smoothed RTT estimate in microseconds.
+    ///
+    /// Returns 0 until the handshake has produced an RTT sample.
     pub fn rtt(&mut self) -> u64 {
-        // TODO: Implement proper stats access for tquic
-        // ConnectionStats fields differ from expected
-        0
+        self.primary_path_stats(|stats| stats.srtt).unwrap_or(0)
     }
 
-    /// Get the current congestion window.
+    /// Get the current congestion window in bytes.
     pub fn cwnd(&mut self) -> u64 {
-        // TODO: Implement proper stats access for tquic
-        0
+        self.primary_path_stats(|stats| stats.final_cwnd)
+            .unwrap_or(0)
+    }
+
+    /// Get the total bytes declared lost, summed over all paths.
+    pub fn lost_bytes(&mut self) -> u64 {
+        self.path_stats(|stats| stats.lost_bytes).iter().sum()
+    }
+
+    /// Get traffic and loss counters, in total and for each path we opened.
+    pub fn stats(&mut self) -> crate::ConnectionStats {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return crate::ConnectionStats::default();
+        };
+        let tuples: Vec<_> = conn.paths_iter().collect();
+        let paths = tuples
+            .into_iter()
+            .filter(|tuple| tuple.local == self.local_addr)
+            .filter_map(|tuple| {
+                let path_id = self
+                    .path_peers
+                    .iter()
+                    .find(|(_, peer)| **peer == tuple.remote)
+                    .map(|(path_id, _)| *path_id)?;
+                let stats = conn.get_path_stats(tuple.local, tuple.remote).ok()?;
+                Some(crate::PathStats::from_tquic(path_id, tuple.remote, stats))
+            })
+            .collect();
+        crate::ConnectionStats::from_tquic(conn.stats(), paths)
+    }
+
+    /// Get the pacing rate in bytes per second, summed over all paths.
+    ///
+    /// Returns 0 until the congestion controller has a bandwidth estimate.
+    pub fn pacing_rate(&mut self) -> u64 {
+        self.path_stats(|stats| stats.pacing_rate).iter().sum()
+    }
+
+    /// How long until a packet of `packet_bytes` may be sent.
+    ///
+    /// Returns `None` while every congestion window is full: nothing goes out
+    /// until an ACK arrives, however long the caller waits. Otherwise the delay
+    /// is the pacer's spacing between packets of that size, or zero before a
+    /// pacing rate is known.
+    pub fn next_send_delay(&mut self, packet_bytes: usize) -> Option<std::time::Duration> {
+        // Same in-flight estimate as PathInfo: tquic only reports cumulative counters
+        let room = self.path_stats(|stats| {
+            let in_flight = stats
+                .sent_bytes
+                .saturating_sub(stats.acked_bytes)
+                .saturating_sub(stats.lost_bytes);
+            stats.final_cwnd.saturating_sub(in_flight)
+        });
+        if !room.is_empty() && room.iter().all(|room| *room < packet_bytes as u64) {
+            return None;
+        }
+        let rate = self.pacing_rate();
+        if rate == 0 {
+            return Some(std::time::Duration::ZERO);
+        }
+        Some(std::time::Duration::from_nanos(
+            (packet_bytes as u64).saturating_mul(1_000_000_000) / rate,
+        ))
+    }
+
+    /// Save session tickets newly issued to any connection of the endpoint.
+    fn record_session(&mut self) {
+        let mut endpoint = self.endpoint.borrow_mut();
+        for (conn_id, state) in self.pool.borrow().iter() {
+            let mut state = state.borrow_mut();
+            let Some(session) = &mut state.session else {
+                continue;
+            };
+            if let Some(conn) = endpoint.conn_get_mut(*conn_id) {
+                session.record_ticket(conn);
+            }
+        }
+    }
+
+    /// Read one statistic of every path tquic reports.
+    fn path_stats(&mut self, read: impl Fn(&tquic::PathStats) -> u64) -> Vec<u64> {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return Vec::new();
+        };
+        let tuples: Vec<_> = conn.paths_iter().collect();
+        tuples
+            .into_iter()
+            .filter_map(|tuple| {
+                conn.get_path_stats(tuple.local, tuple.remote)
+                    .ok()
+                    .map(&read)
+            })
+            .collect()
+    }
+
+    /// Read statistics of the path to `server_addr`, falling back to the first
+    /// path tquic reports when the original path is gone.
+    fn primary_path_stats<T>(&mut self, read: impl FnOnce(&tquic::PathStats) -> T) -> Option<T> {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint.conn_get_mut(self.conn_id)?;
+        if let Ok(stats) = conn.get_path_stats(self.local_addr, self.server_addr) {
+            return Some(read(stats));
+        }
+        let path = conn.paths_iter().next()?;
+        conn.get_path_stats(path.local, path.remote).ok().map(read)
+    }
+}
+
+/// Shared access to one stream of a client connection, backing [`BiStream`].
+#[derive(Clone)]
+pub(crate) struct StreamHandle {
+    endpoint: Rc<RefCell<Endpoint>>,
+    state: Rc<RefCell<ConnectionState>>,
+    conn_id: u64,
+    stream_id: u64,
+}
+
+impl StreamHandle {
+    pub(crate) fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Write as much of `data` as flow control allows, waiting for credit when
+    /// none is available. A `fin` write with no data only needs the stream open.
+    pub(crate) fn poll_write(
+        &self,
+        cx: &mut Context<'_>,
+        data: &[u8],
+        fin: bool,
+    ) -> Poll<Result<usize, Error>> {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return Poll::Ready(Err(connection_gone()));
+        };
+        let data = Bytes::copy_from_slice(data);
+        match conn.stream_write(self.stream_id, data.clone(), fin) {
+            Ok(written) if written > 0 || data.is_empty() => {
+                self.state
+                    .borrow_mut()
+                    .streams
+                    .entry(self.stream_id)
+                    .or_insert_with(StreamState::new)
+                    .unacked
+                    .record(data, written);
+                self.notify_send();
+                Poll::Ready(Ok(written))
+            }
+            Ok(_) | Err(tquic::Error::Done) => {
+                self.register(cx, |stream| &mut stream.write_waker);
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(Error::stream(e))),
+        }
+    }
+
+    /// Read into `buf`, waiting for data. Returns the byte count and whether the
+    /// peer finished the stream.
+    pub(crate) fn poll_read(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<(usize, bool), Error>> {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return Poll::Ready(Err(connection_gone()));
+        };
+        match conn.stream_read(self.stream_id, buf) {
+            // Nothing new and no FIN is not the end of the stream; wait as for Done
+            Ok((0, false)) if !buf.is_empty() => {
+                if let Some(stream) = self.state.borrow_mut().streams.get_mut(&self.stream_id) {
+                    stream.readable = false;
+                }
+                self.register(cx, |stream| &mut stream.read_waker);
+                Poll::Pending
+            }
+            Ok(read) => {
+                // Reading may open the flow control window for the peer.
+                self.notify_send();
+                Poll::Ready(Ok(read))
+            }
+            Err(tquic::Error::Done) => {
+                if let Some(stream) = self.state.borrow_mut().streams.get_mut(&self.stream_id) {
+                    stream.readable = false;
+                }
+                self.register(cx, |stream| &mut stream.read_waker);
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(Error::stream(e))),
+        }
+    }
+
+    /// Abort one direction of the stream with an application error code.
+    pub(crate) fn shutdown(
+        &self,
+        direction: tquic::Shutdown,
+        error_code: u64,
+    ) -> Result<(), Error> {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint
+            .conn_get_mut(self.conn_id)
+            .ok_or_else(connection_gone)?;
+        conn.stream_shutdown(self.stream_id, direction, error_code)
+            .map_err(Error::stream)?;
+        self.notify_send();
+        Ok(())
+    }
+
+    fn notify_send(&self) {
+        self.state.borrow().send_notify.notify_one();
+    }
+
+    fn register(
+        &self,
+        cx: &mut Context<'_>,
+        slot: impl FnOnce(&mut StreamState) -> &mut Option<Waker>,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let stream = state
+            .streams
+            .entry(self.stream_id)
+            .or_insert_with(StreamState::new);
+        *slot(stream) = Some(cx.waker().clone());
+    }
+}
+
+fn connection_gone() -> Error {
+    Error::ConnectionClosed {
+        reason: "connection not found".to_string(),
     }
 }
 
 impl PathManager for ClientConnection {
     fn probe_path(&mut self, peer_addr: SocketAddr) -> Result<PathId, Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(self.conn_id) {
+        if let Some(&path_id) = self.abandoned_paths.get(&peer_addr) {
+            // tquic refuses a second path on the same addresses; revalidate the old one
+            self.path_peers.insert(path_id, peer_addr);
+            if let Err(e) = self.ping_path(path_id) {
+                self.path_peers.remove(&path_id);
+                return Err(e);
+            }
+            self.abandoned_paths.remove(&peer_addr);
+            return Ok(path_id);
+        }
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
             conn.add_path(self.local_addr, peer_addr)
-                .map_err(|e| Error::Path(e.to_string()))
+                .map_err(|e| Error::Path(e.to_string()))?;
+            let path_id = self.next_path_id;
+            self.next_path_id += 1;
+            self.path_peers.insert(path_id, peer_addr);
+            Ok(path_id)
         } else {
             Err(Error::ConnectionClosed {
                 reason: "connection not found".to_string(),
@@ -348,23 +1463,81 @@ impl PathManager for ClientConnection {
     }
 
     fn path_info(&mut self, path_id: PathId) -> Option<PathInfo> {
-        Some(PathInfo {
-            path_id,
-            local_addr: self.local_addr,
-            peer_addr: self.server_addr,
-            rtt_us: self.rtt(),
-            cwnd: self.cwnd(),
-            pacing_rate: 0,
-            bytes_in_flight: 0,
-            is_active: true,
-        })
+        let peer_addr = *self.path_peers.get(&path_id)?;
+        let local_addr = self.local_addr;
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint.conn_get_mut(self.conn_id)?;
+        let stats = conn.get_path_stats(local_addr, peer_addr).ok()?;
+        Some(PathInfo::from_stats(path_id, local_addr, peer_addr, stats))
     }
 
     fn active_paths(&mut self) -> Vec<PathInfo> {
-        vec![]
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return Vec::new();
+        };
+        let tuples: Vec<_> = conn.paths_iter().collect();
+        tuples
+            .into_iter()
+            .filter(|tuple| tuple.local == self.local_addr)
+            .filter_map(|tuple| {
+                let path_id = self
+                    .path_peers
+                    .iter()
+                    .find(|(_, peer)| **peer == tuple.remote)
+                    .map(|(path_id, _)| *path_id)?;
+                let stats = conn.get_path_stats(tuple.local, tuple.remote).ok()?;
+                Some(PathInfo::from_stats(
+                    path_id,
+                    tuple.local,
+                    tuple.remote,
+                    stats,
+                ))
+            })
+            .collect()
     }
 
-    fn set_path_mode(&mut self, _path_id: PathId, _mode: PathMode) -> Result<(), Error> {
+    fn set_path_mode(&mut self, path_id: PathId, mode: PathMode) -> Result<(), Error> {
+        if !self.path_peers.contains_key(&path_id) {
+            return Err(Error::Path(format!("unknown path {}", path_id)));
+        }
+        self.path_modes.insert(path_id, mode);
+        Ok(())
+    }
+
+    /// tquic 1.6 only marks an abandoned path: it sends no PATH_ABANDON frame,
+    /// so `error_code` is only logged, and its scheduler may still put packets
+    /// on the path. What stops is everything driven from this side, such as
+    /// the polls the tunnel sends through the path's resolver.
+    fn abandon_path(&mut self, path_id: PathId, error_code: u64) -> Result<(), Error> {
+        if path_id == 0 {
+            return Err(Error::Path(
+                "the initial path cannot be abandoned".to_string(),
+            ));
+        }
+        let peer_addr = *self
+            .path_peers
+            .get(&path_id)
+            .ok_or_else(|| Error::Path(format!("unknown path {}", path_id)))?;
+        self.endpoint
+            .borrow_mut()
+            .conn_get_mut(self.conn_id)
+            .ok_or_else(connection_gone)?
+            .abandon_path(self.local_addr, peer_addr)
+            .map_err(|e| Error::Path(e.to_string()))?;
+        self.path_peers.remove(&path_id);
+        self.path_modes.remove(&path_id);
+        self.abandoned_paths.insert(peer_addr, path_id);
+        self.state
+            .borrow_mut()
+            .events
+            .push(ConnectionEvent::Path(PathEvent::Deleted(path_id)));
+        tracing::debug!(
+            "Abandoned path {} to {} (error code {:#x})",
+            path_id,
+            peer_addr,
+            error_code
+        );
         Ok(())
     }
 
@@ -372,3 +1545,30 @@ impl PathManager for ClientConnection {
         ClientConnection::drain_path_events(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_abandon_keeps_the_path() {
+        let client = Client::new(Config::new()).unwrap();
+        let mut conn = client
+            .connect(
+                "127.0.0.1:5300".parse().unwrap(),
+                "127.0.0.1:53".parse().unwrap(),
+                "localhost",
+            )
+            .unwrap();
+        let peer: SocketAddr = "127.0.0.2:53".parse().unwrap();
+        // A path tquic never opened, so it refuses to abandon it
+        conn.path_peers.insert(1, peer);
+        conn.set_path_mode(1, PathMode::Backup).unwrap();
+
+        assert!(conn.abandon_path(1, 0).is_err());
+        assert_eq!(conn.path_peers.get(&1), Some(&peer));
+        assert_eq!(conn.path_modes.get(&1), Some(&PathMode::Backup));
+        assert!(conn.abandoned_paths.is_empty());
+        assert!(conn.drain_path_events().is_empty());
+    }
+}
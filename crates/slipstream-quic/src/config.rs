@@ -1,8 +1,125 @@
 //! Configuration for QUIC connections using tquic.
 
+use crate::keylog::Keylog;
+use crate::multipath::SchedulerKind;
+use crate::tls::TlsContext;
+use std::path::PathBuf;
+use std::sync::Once;
 use std::time::Duration;
 use tquic::CongestionControlAlgorithm;
 
+/// Default connection ID length, matching tquic.
+const DEFAULT_CID_LEN: usize = 8;
+/// Largest connection ID length permitted by QUIC v1.
+const MAX_CID_LEN: usize = 20;
+/// Longest ALPN protocol ID the TLS extension can carry (RFC 7301).
+const MAX_ALPN_LEN: usize = 255;
+/// Largest stream count a peer may be granted (RFC 9000 section 4.6).
+const MAX_STREAMS_LIMIT: u64 = 1 << 60;
+/// Anti-amplification factor required by RFC 9000 section 8; tquic's default.
+const MIN_ANTI_AMPLIFICATION_FACTOR: usize = 3;
+/// Largest max_ack_delay a peer may advertise, in milliseconds (RFC 9000 section 18.2).
+const MAX_ACK_DELAY_LIMIT_MS: u64 = 1 << 14;
+/// Smallest UDP payload every QUIC path must carry (RFC 9000 section 14).
+pub const MIN_UDP_PAYLOAD_SIZE: usize = 1200;
+/// Largest `max_udp_payload_size` a peer may advertise (RFC 9000 section 18.2).
+const MAX_UDP_PAYLOAD_SIZE: usize = 65527;
+/// Session lifetime tquic's server config sets: TLS 1.3 allows 0-RTT for
+/// seven days after the original connection.
+const SESSION_TIMEOUT_SECS: u32 = 7 * 24 * 60 * 60;
+
+/// Congestion control names accepted by [`parse_congestion_control`].
+pub const CONGESTION_CONTROL_NAMES: &[&str] =
+    &["bbr", "bbr3", "cubic", "dcubic", "copa", "slipstream"];
+
+/// QUIC versions the tquic transport speaks, in preference order.
+pub const QUIC_VERSIONS: &[u32] = &[tquic::QUIC_VERSION_V1];
+
+/// Initial congestion window of the slipstream algorithm, in packets.
+const SLIPSTREAM_INITIAL_CWND_PACKETS: u64 = 256;
+/// Floor of the slipstream algorithm's congestion window, in packets.
+const SLIPSTREAM_MIN_CWND_PACKETS: u64 = 64;
+
+/// Congestion controller used by a connection.
+///
+/// tquic keeps its controller trait private, so algorithms of our own are
+/// expressed as one of tquic's algorithms plus the window knobs it exposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionControl {
+    /// tquic BBR.
+    #[default]
+    Bbr,
+    /// tquic BBRv3.
+    Bbr3,
+    /// tquic CUBIC.
+    Cubic,
+    /// tquic COPA.
+    Copa,
+    /// tquic BBR tuned for DNS paths: a large initial window, a floor the
+    /// window never shrinks below, and a gentler ProbeRTT.
+    ///
+    /// Not a port of the picoquic slipstream controllers, whose logic needs
+    /// a controller of its own; it only shares their aim.
+    ///
+    /// Over DNS, throughput is bounded by how many queries the resolvers accept,
+    /// and the tunnel already paces polls against that. Packet-level loss mostly
+    /// reflects dropped queries, not a congested bottleneck, so collapsing the
+    /// window on it only starves the poll loop.
+    Slipstream,
+}
+
+impl CongestionControl {
+    /// Name accepted by [`parse_congestion_control`].
+    pub fn name(self) -> &'static str {
+        match self {
+            CongestionControl::Bbr => "bbr",
+            CongestionControl::Bbr3 => "bbr3",
+            CongestionControl::Cubic => "cubic",
+            CongestionControl::Copa => "copa",
+            CongestionControl::Slipstream => "slipstream",
+        }
+    }
+
+    /// Configure tquic to run this controller.
+    pub(crate) fn apply(self, config: &mut tquic::Config) {
+        let algorithm = match self {
+            CongestionControl::Bbr | CongestionControl::Slipstream => {
+                CongestionControlAlgorithm::Bbr
+            }
+            CongestionControl::Bbr3 => CongestionControlAlgorithm::Bbr3,
+            CongestionControl::Cubic => CongestionControlAlgorithm::Cubic,
+            CongestionControl::Copa => CongestionControlAlgorithm::Copa,
+        };
+        config.set_congestion_control_algorithm(algorithm);
+        if self == CongestionControl::Slipstream {
+            config.set_initial_congestion_window(SLIPSTREAM_INITIAL_CWND_PACKETS);
+            config.set_min_congestion_window(SLIPSTREAM_MIN_CWND_PACKETS);
+            // DNS RTTs swing with resolver load; keep ProbeRTT from draining the window
+            config.enable_bbr_probe_rtt_based_on_bdp(true);
+            config.set_bbr_probe_rtt_cwnd_gain(1.0);
+        }
+    }
+}
+
+/// Map a CLI congestion control name to a [`CongestionControl`].
+///
+/// `dcubic` is picoquic's delay-based CUBIC variant; tquic has no equivalent, so it
+/// maps to plain CUBIC to keep existing command lines working.
+pub fn parse_congestion_control(name: &str) -> Result<CongestionControl, crate::Error> {
+    match name.to_ascii_lowercase().as_str() {
+        "bbr" => Ok(CongestionControl::Bbr),
+        "bbr3" => Ok(CongestionControl::Bbr3),
+        "cubic" | "dcubic" => Ok(CongestionControl::Cubic),
+        "copa" => Ok(CongestionControl::Copa),
+        "slipstream" => Ok(CongestionControl::Slipstream),
+        _ => Err(crate::Error::Config(format!(
+            "unsupported congestion control '{}' (expected one of: {})",
+            name,
+            CONGESTION_CONTROL_NAMES.join(", ")
+        ))),
+    }
+}
+
 /// Configuration for QUIC endpoints.
 #[derive(Clone)]
 pub struct Config {
@@ -12,15 +129,27 @@ pub struct Config {
     /// Enable multipath QUIC.
     pub enable_multipath: bool,
 
+    /// Multipath scheduler.
+    pub scheduler: SchedulerKind,
+
     /// Congestion control algorithm.
-    pub congestion_control: CongestionControlAlgorithm,
+    pub congestion_control: CongestionControl,
 
-    /// Keep-alive interval.
+    /// Keep-alive interval. Zero disables it.
+    ///
+    /// Client connections PING every path after this long without sending.
     pub keep_alive_interval: Duration,
 
-    /// Maximum idle timeout.
+    /// Maximum idle timeout. Zero disables it.
     pub idle_timeout: Duration,
 
+    /// Time the handshake may take before the connection gives up. Zero
+    /// disables it.
+    ///
+    /// A client whose first peer never answers fails with
+    /// [`Error::HandshakeTimeout`](crate::Error::HandshakeTimeout) after this.
+    pub handshake_timeout: Duration,
+
     /// Initial RTT estimate in milliseconds.
     pub initial_rtt_ms: u64,
 
@@ -33,17 +162,109 @@ pub struct Config {
     /// TLS root CA path (for client certificate verification).
     pub ca_path: Option<String>,
 
+    /// SHA-256 of the server certificate's SubjectPublicKeyInfo (client).
+    pub pinned_spki: Option<[u8; 32]>,
+
+    /// Certificate and key paths presented to the server (client).
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+
+    /// CA that client certificates must chain to (server).
+    pub client_auth_ca_path: Option<String>,
+
     /// ALPN protocols.
     pub alpn: Vec<Vec<u8>>,
 
+    /// QUIC versions offered (client, the first is used) and accepted
+    /// (server), in preference order. Defaults to [`QUIC_VERSIONS`].
+    pub quic_versions: Vec<u32>,
+
     /// Maximum UDP payload size for outgoing packets.
     /// For DNS tunneling, this should be set to the MTU calculated from domain length.
     pub send_udp_payload_size: Option<usize>,
 
+    /// Largest UDP payload we accept, advertised as `max_udp_payload_size`.
+    pub recv_udp_payload_size: Option<usize>,
+
+    /// The caller splits datagrams larger than the path carries, so a send
+    /// payload size below the QUIC minimum is acceptable.
+    pub fragmentation: bool,
+
+    /// Probe for a larger path MTU (DPLPMTUD, RFC 8899).
+    /// On by default. Probes are sent larger than `send_udp_payload_size`,
+    /// so a path with a fixed payload budget, like a DNS query, should turn
+    /// this off.
+    pub pmtud: bool,
+
     /// Enable strict certificate chain verification.
     /// When false (default), accepts self-signed certs without chain validation.
     /// When true, validates the certificate chain against the pinned CA.
     pub verify_cert_chain: bool,
+
+    /// Set the latency spin bit on short-header packets (RFC 9000 section 17.4).
+    /// Off by default: the spin bit lets on-path observers measure RTT and is a
+    /// distinctive marker on a DNS-tunnelled flow. tquic never spins the bit,
+    /// so turning it on only logs a warning.
+    pub spin_bit: bool,
+
+    /// Length in bytes of locally issued connection IDs (0-20).
+    pub cid_len: usize,
+
+    /// Issue stateless reset tokens so a peer that lost state can be reset.
+    pub stateless_reset: bool,
+
+    /// Directory for per-connection qlog traces; none are written when unset.
+    pub qlog_dir: Option<PathBuf>,
+
+    /// File that TLS secrets are appended to in `SSLKEYLOGFILE` format, so
+    /// captures can be decrypted. Debugging only.
+    pub keylog_path: Option<PathBuf>,
+
+    /// Allow 0-RTT data on resumed connections.
+    /// Off by default: early data can be replayed by anyone who captured it.
+    pub enable_early_data: bool,
+
+    /// Answer new connections with a stateless Retry (server).
+    /// The client must echo the Retry token, proving it receives packets at its
+    /// address, before the server keeps any connection state.
+    pub retry: bool,
+
+    /// How many times the bytes received from an unvalidated client address the
+    /// server may send back (server). At least 3.
+    pub anti_amplification_factor: usize,
+
+    /// Key for encrypting session tickets (server). 48 or 80 bytes.
+    /// When unset, tickets use a random per-process key and stop working on restart.
+    pub session_ticket_key: Option<Vec<u8>>,
+
+    /// Bytes the peer may send on the whole connection before we grant more.
+    pub initial_max_data: u64,
+
+    /// Bytes the peer may send on each stream before we grant more.
+    pub initial_max_stream_data: u64,
+
+    /// Largest the connection window may grow to. tquic doubles a window
+    /// whenever the peer used it up within two smoothed RTTs, so it tracks
+    /// the bandwidth-delay product; equal to `initial_max_data`, the window
+    /// stays fixed.
+    pub max_data_window: u64,
+
+    /// Largest a stream window may grow to, like `max_data_window`.
+    pub max_stream_data_window: u64,
+
+    /// Bidirectional streams the peer may have open at once.
+    pub max_streams_bidi: u64,
+
+    /// Unidirectional streams the peer may have open at once.
+    pub max_streams_uni: u64,
+
+    /// Longest we may hold back an ACK, advertised as `max_ack_delay`.
+    /// Kept short: each DNS response can carry only one packet, so an ACK held
+    /// back stalls the sender for a whole poll round trip.
+    pub max_ack_delay: Duration,
+
+    /// Ack-eliciting packets received before an ACK is sent immediately.
+    pub ack_eliciting_threshold: u64,
 }
 
 impl Default for Config {
@@ -51,16 +272,43 @@ impl Default for Config {
         Self {
             max_connections: 256,
             enable_multipath: true,
-            congestion_control: CongestionControlAlgorithm::Bbr,
+            scheduler: SchedulerKind::MinRtt,
+            congestion_control: CongestionControl::Bbr,
             keep_alive_interval: Duration::from_millis(400),
             idle_timeout: Duration::from_secs(30),
+            handshake_timeout: Duration::from_secs(30),
             initial_rtt_ms: 100,
             cert_path: None,
             key_path: None,
             ca_path: None,
+            pinned_spki: None,
+            client_cert_path: None,
+            client_key_path: None,
+            client_auth_ca_path: None,
             alpn: vec![b"picoquic_sample".to_vec()],
+            quic_versions: QUIC_VERSIONS.to_vec(),
             send_udp_payload_size: None,
+            recv_udp_payload_size: None,
+            fragmentation: false,
+            pmtud: true,
             verify_cert_chain: false,
+            spin_bit: false,
+            cid_len: DEFAULT_CID_LEN,
+            stateless_reset: true,
+            qlog_dir: None,
+            keylog_path: None,
+            enable_early_data: false,
+            retry: false,
+            anti_amplification_factor: MIN_ANTI_AMPLIFICATION_FACTOR,
+            session_ticket_key: None,
+            initial_max_data: 10 * 1024 * 1024,
+            initial_max_stream_data: 5 * 1024 * 1024,
+            max_data_window: 15 * 1024 * 1024,
+            max_stream_data_window: 6 * 1024 * 1024,
+            max_streams_bidi: 200,
+            max_streams_uni: 100,
+            max_ack_delay: Duration::from_millis(5),
+            ack_eliciting_threshold: 1,
         }
     }
 }
@@ -72,7 +320,7 @@ impl Config {
     }
 
     /// Set the congestion control algorithm.
-    pub fn with_congestion_control(mut self, algo: CongestionControlAlgorithm) -> Self {
+    pub fn with_congestion_control(mut self, algo: CongestionControl) -> Self {
         self.congestion_control = algo;
         self
     }
@@ -83,6 +331,12 @@ impl Config {
         self
     }
 
+    /// Set the multipath scheduler.
+    pub fn with_scheduler(mut self, scheduler: SchedulerKind) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
     /// Set the keep-alive interval.
     pub fn with_keep_alive(mut self, interval: Duration) -> Self {
         self.keep_alive_interval = interval;
@@ -96,18 +350,125 @@ impl Config {
         self
     }
 
+    /// Set the ALPN protocols to offer, most preferred first.
+    ///
+    /// The server picks the first of its own protocols the client also offers;
+    /// read the result with `alpn()` on the established connection to tell
+    /// protocol versions apart.
+    pub fn with_alpn(mut self, protocols: &[&[u8]]) -> Self {
+        self.alpn = protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    /// Set the QUIC versions to offer and accept, most preferred first.
+    ///
+    /// Middleboxes that drop unknown versions can make a version worth
+    /// choosing per deployment. The tquic transport speaks only
+    /// [`QUIC_VERSIONS`] and rejects lists with any other version.
+    pub fn with_quic_versions(mut self, versions: &[u32]) -> Self {
+        self.quic_versions = versions.to_vec();
+        self
+    }
+
     /// Set the root CA path (for client verification).
     pub fn with_ca(mut self, ca: &str) -> Self {
         self.ca_path = Some(ca.to_string());
         self
     }
 
+    /// Pin the server's public key by the SHA-256 of its SubjectPublicKeyInfo.
+    ///
+    /// A 32-byte hash is easier to paste into a client config than a PEM file.
+    /// The pin replaces chain verification, so it cannot be combined with
+    /// [`Self::with_ca`] or [`Self::with_verify_cert_chain`]. Pinned clients
+    /// neither save nor resume sessions, since a resumed handshake shows no
+    /// certificate to check, and write no TLS key log.
+    pub fn with_pinned_spki(mut self, sha256: [u8; 32]) -> Self {
+        self.pinned_spki = Some(sha256);
+        self
+    }
+
+    /// Present a certificate to servers that request one (client).
+    pub fn with_client_cert(mut self, cert: &str, key: &str) -> Self {
+        self.client_cert_path = Some(cert.to_string());
+        self.client_key_path = Some(key.to_string());
+        self
+    }
+
+    /// Only accept clients whose certificate chains to `ca` (server).
+    ///
+    /// Clients that send no certificate fail the handshake too. The server
+    /// then runs on a TLS context of its own and writes no TLS key log.
+    pub fn with_require_client_auth(mut self, ca: &str) -> Self {
+        self.client_auth_ca_path = Some(ca.to_string());
+        self
+    }
+
+    /// Set the idle timeout; zero disables it.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Set the handshake timeout; zero disables it.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Set the RTT assumed before the first sample.
+    pub fn with_initial_rtt(mut self, rtt: Duration) -> Self {
+        self.initial_rtt_ms = rtt.as_millis() as u64;
+        self
+    }
+
     /// Set the maximum UDP payload size for outgoing packets (for DNS tunneling).
     pub fn with_send_udp_payload_size(mut self, size: usize) -> Self {
         self.send_udp_payload_size = Some(size);
         self
     }
 
+    /// Set the largest UDP payload accepted from the peer.
+    pub fn with_recv_udp_payload_size(mut self, size: usize) -> Self {
+        self.recv_udp_payload_size = Some(size);
+        self
+    }
+
+    /// Declare that datagrams larger than the path carries are fragmented by
+    /// the caller, allowing send payload sizes below 1200 bytes.
+    pub fn with_fragmentation(mut self, enable: bool) -> Self {
+        self.fragmentation = enable;
+        self
+    }
+
+    /// Enable or disable path MTU probing.
+    pub fn with_pmtud(mut self, enable: bool) -> Self {
+        self.pmtud = enable;
+        self
+    }
+
+    /// Pin outgoing packets to `size` bytes: sets the send payload size and
+    /// turns path MTU probing off.
+    pub fn with_fixed_udp_payload_size(self, size: usize) -> Self {
+        self.with_send_udp_payload_size(size).with_pmtud(false)
+    }
+
+    /// Largest UDP payload tquic can emit with these settings, or `None`
+    /// while path MTU probing may grow it.
+    ///
+    /// tquic never builds packets below the QUIC minimum, so a smaller send
+    /// payload size still yields packets of that minimum.
+    pub fn max_packet_size(&self) -> Option<usize> {
+        if self.pmtud {
+            return None;
+        }
+        Some(
+            self.send_udp_payload_size
+                .unwrap_or(MIN_UDP_PAYLOAD_SIZE)
+                .max(MIN_UDP_PAYLOAD_SIZE),
+        )
+    }
+
     /// Enable strict certificate chain verification.
     /// When disabled (default), accepts self-signed certs without chain validation.
     pub fn with_verify_cert_chain(mut self, verify: bool) -> Self {
@@ -115,13 +476,389 @@ impl Config {
         self
     }
 
+    /// Enable or disable the latency spin bit.
+    ///
+    /// A no-op on the tquic transport, which always sends the bit as zero;
+    /// enabling it logs a warning once per process.
+    pub fn with_spin_bit(mut self, enable: bool) -> Self {
+        self.spin_bit = enable;
+        self
+    }
+
+    /// Set the length of locally issued connection IDs.
+    pub fn with_cid_len(mut self, len: usize) -> Self {
+        self.cid_len = len;
+        self
+    }
+
+    /// Enable or disable stateless reset.
+    pub fn with_stateless_reset(mut self, enable: bool) -> Self {
+        self.stateless_reset = enable;
+        self
+    }
+
+    /// Write a qlog trace for every connection into `dir`.
+    pub fn with_qlog_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.qlog_dir = Some(dir.into());
+        self
+    }
+
+    /// Append the TLS secrets of every connection to `path`.
+    pub fn with_keylog(mut self, path: impl Into<PathBuf>) -> Self {
+        self.keylog_path = Some(path.into());
+        self
+    }
+
+    /// Append TLS secrets to the file named by `SSLKEYLOGFILE`, if it is set.
+    pub fn with_keylog_from_env(self) -> Self {
+        match std::env::var_os("SSLKEYLOGFILE") {
+            Some(path) if !path.is_empty() => self.with_keylog(path),
+            _ => self,
+        }
+    }
+
+    /// Enable or disable 0-RTT data on resumed connections.
+    pub fn with_early_data(mut self, enable: bool) -> Self {
+        self.enable_early_data = enable;
+        self
+    }
+
+    /// Set the session ticket encryption key (for server).
+    pub fn with_session_ticket_key(mut self, key: &[u8]) -> Self {
+        self.session_ticket_key = Some(key.to_vec());
+        self
+    }
+
+    /// Require address validation through a stateless Retry (server only).
+    ///
+    /// Spoofed Initials then cost the server a Retry packet instead of a
+    /// connection slot and handshake state, at one extra round trip per
+    /// connection. Tokens are bound to the address the Initial came from, so a
+    /// resolver that retries from a different egress address fails validation.
+    pub fn with_retry(mut self, enable: bool) -> Self {
+        self.retry = enable;
+        self
+    }
+
+    /// Set the anti-amplification factor (server only).
+    ///
+    /// Until the handshake validates the client's address, the server may only
+    /// send this many times what it received. Over DNS, polls are not fed to
+    /// QUIC and tquic has no way to credit them, so a server certificate chain
+    /// larger than three times the client's Initial stalls the handshake until
+    /// the client retransmits. Each response is still bound to a query the
+    /// resolver made, so raising the factor mostly trades RFC 9000 strictness
+    /// for fewer handshake round trips.
+    pub fn with_anti_amplification_factor(mut self, factor: usize) -> Self {
+        self.anti_amplification_factor = factor;
+        self
+    }
+
+    /// Set the connection-level flow control window.
+    ///
+    /// Over DNS, the window only needs to cover a bandwidth-delay product of a
+    /// few hundred queries per RTT; a large window mostly lets one side queue
+    /// more than the resolvers can carry.
+    pub fn with_initial_max_data(mut self, bytes: u64) -> Self {
+        self.initial_max_data = bytes;
+        self
+    }
+
+    /// Set the per-stream flow control window.
+    pub fn with_initial_max_stream_data(mut self, bytes: u64) -> Self {
+        self.initial_max_stream_data = bytes;
+        self
+    }
+
+    /// Let the flow control windows grow from their initial sizes up to these
+    /// limits as the measured RTT and delivery rate call for.
+    ///
+    /// Start the windows small with [`with_initial_max_data`](Self::with_initial_max_data)
+    /// and [`with_initial_max_stream_data`](Self::with_initial_max_stream_data):
+    /// a server with many idle connections then holds little per connection,
+    /// while a busy connection over a high-RTT resolver path still opens up.
+    pub fn with_window_autotune(mut self, max_data: u64, max_stream_data: u64) -> Self {
+        self.max_data_window = max_data;
+        self.max_stream_data_window = max_stream_data;
+        self
+    }
+
+    /// Keep the flow control windows at their initial sizes.
+    pub fn with_fixed_windows(mut self) -> Self {
+        self.max_data_window = self.initial_max_data;
+        self.max_stream_data_window = self.initial_max_stream_data;
+        self
+    }
+
+    /// Set how many bidirectional streams the peer may have open at once.
+    pub fn with_max_streams_bidi(mut self, count: u64) -> Self {
+        self.max_streams_bidi = count;
+        self
+    }
+
+    /// Set how many unidirectional streams the peer may have open at once.
+    pub fn with_max_streams_uni(mut self, count: u64) -> Self {
+        self.max_streams_uni = count;
+        self
+    }
+
+    /// Set the longest an ACK may be delayed.
+    pub fn with_max_ack_delay(mut self, delay: Duration) -> Self {
+        self.max_ack_delay = delay;
+        self
+    }
+
+    /// Set how many ack-eliciting packets are received before an ACK is sent
+    /// without waiting for the ACK delay; 1 acknowledges every such packet.
+    pub fn with_ack_eliciting_threshold(mut self, packets: u64) -> Self {
+        self.ack_eliciting_threshold = packets;
+        self
+    }
+
+    /// Open the key log file if one is configured.
+    pub(crate) fn open_keylog(&self) -> Result<Option<Keylog>, crate::Error> {
+        self.keylog_path.as_deref().map(Keylog::open).transpose()
+    }
+
+    /// Create the qlog directory if one is configured.
+    pub(crate) fn prepare_qlog_dir(&self) -> Result<(), crate::Error> {
+        if let Some(dir) = &self.qlog_dir {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                crate::Error::Config(format!(
+                    "Failed to create qlog directory {}: {}",
+                    dir.display(),
+                    e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check timing and payload size settings that only make sense together.
+    ///
+    /// Called by both `to_tquic_*_config` conversions; exposed so callers can
+    /// report a bad combination before binding sockets.
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.initial_rtt_ms == 0 {
+            return Err(crate::Error::Config(
+                "initial RTT must be at least 1ms".to_string(),
+            ));
+        }
+        if !self.idle_timeout.is_zero()
+            && !self.keep_alive_interval.is_zero()
+            && self.keep_alive_interval >= self.idle_timeout
+        {
+            return Err(crate::Error::Config(format!(
+                "keep-alive interval {}ms must be shorter than the idle timeout {}ms",
+                self.keep_alive_interval.as_millis(),
+                self.idle_timeout.as_millis()
+            )));
+        }
+        if let Some(size) = self.send_udp_payload_size {
+            if size == 0 || size > MAX_UDP_PAYLOAD_SIZE {
+                return Err(crate::Error::Config(format!(
+                    "send UDP payload size must be 1-{} bytes, got {}",
+                    MAX_UDP_PAYLOAD_SIZE, size
+                )));
+            }
+            if size < MIN_UDP_PAYLOAD_SIZE && !self.fragmentation {
+                // tquic never builds packets below the QUIC minimum
+                return Err(crate::Error::Config(format!(
+                    "send UDP payload size {} is below {} and requires fragmentation",
+                    size, MIN_UDP_PAYLOAD_SIZE
+                )));
+            }
+        }
+        if let Some(size) = self.recv_udp_payload_size {
+            if !(MIN_UDP_PAYLOAD_SIZE..=MAX_UDP_PAYLOAD_SIZE).contains(&size) {
+                return Err(crate::Error::Config(format!(
+                    "receive UDP payload size must be {}-{} bytes, got {}",
+                    MIN_UDP_PAYLOAD_SIZE, MAX_UDP_PAYLOAD_SIZE, size
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the idle and handshake timeouts, initial RTT, UDP payload sizes
+    /// and path MTU probing.
+    fn apply_timing_and_sizes(&self, config: &mut tquic::Config) {
+        config.set_max_idle_timeout(self.idle_timeout.as_millis() as u64);
+        config.set_max_handshake_timeout(self.handshake_timeout.as_millis() as u64);
+        config.set_initial_rtt(self.initial_rtt_ms);
+        config.enable_dplpmtud(self.pmtud);
+        if let Some(size) = self.send_udp_payload_size {
+            config.set_send_udp_payload_size(size);
+        }
+        if let Some(size) = self.recv_udp_payload_size {
+            config.set_recv_udp_payload_size(size as u16);
+        }
+    }
+
+    /// Apply the observable transport feature switches shared by client and server.
+    fn apply_transport_features(&self, config: &mut tquic::Config) -> Result<(), crate::Error> {
+        if self.spin_bit {
+            static SPIN_BIT_WARNING: Once = Once::new();
+            SPIN_BIT_WARNING.call_once(|| {
+                tracing::warn!("tquic does not implement the latency spin bit; it stays zero");
+            });
+        }
+        if self.cid_len > MAX_CID_LEN {
+            return Err(crate::Error::Config(format!(
+                "connection ID length {} exceeds {}",
+                self.cid_len, MAX_CID_LEN
+            )));
+        }
+        config.set_cid_len(self.cid_len);
+        config.enable_stateless_reset(self.stateless_reset);
+        Ok(())
+    }
+
+    /// Reject version lists the transport cannot honour.
+    fn check_quic_versions(&self) -> Result<(), crate::Error> {
+        if self.quic_versions.is_empty() {
+            return Err(crate::Error::Config(
+                "at least one QUIC version is required".to_string(),
+            ));
+        }
+        for version in &self.quic_versions {
+            if !QUIC_VERSIONS.contains(version) {
+                // tquic hardcodes v1 on the wire; anything else would be ignored
+                return Err(crate::Error::Config(format!(
+                    "QUIC version {:#010x} is not supported by the tquic transport",
+                    version
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject ALPN lists the TLS extension cannot encode.
+    fn check_alpn(&self) -> Result<(), crate::Error> {
+        if self.alpn.is_empty() {
+            return Err(crate::Error::Config(
+                "at least one ALPN protocol is required".to_string(),
+            ));
+        }
+        for protocol in &self.alpn {
+            if protocol.is_empty() || protocol.len() > MAX_ALPN_LEN {
+                return Err(crate::Error::Config(format!(
+                    "ALPN protocol must be 1-{} bytes, got {}",
+                    MAX_ALPN_LEN,
+                    protocol.len()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the flow control limits advertised to the peer during the handshake.
+    fn apply_flow_control(&self, config: &mut tquic::Config) -> Result<(), crate::Error> {
+        for (name, count) in [
+            ("bidirectional", self.max_streams_bidi),
+            ("unidirectional", self.max_streams_uni),
+        ] {
+            if count > MAX_STREAMS_LIMIT {
+                return Err(crate::Error::Config(format!(
+                    "maximum {} streams {} exceeds {}",
+                    name, count, MAX_STREAMS_LIMIT
+                )));
+            }
+        }
+        // tquic would silently cut the initial windows down to the limits
+        for (name, initial, max) in [
+            ("connection", self.initial_max_data, self.max_data_window),
+            (
+                "stream",
+                self.initial_max_stream_data,
+                self.max_stream_data_window,
+            ),
+        ] {
+            if max < initial {
+                return Err(crate::Error::Config(format!(
+                    "maximum {} window {} is below the initial window {}",
+                    name, max, initial
+                )));
+            }
+        }
+        if self.max_stream_data_window > self.max_data_window {
+            return Err(crate::Error::Config(format!(
+                "maximum stream window {} exceeds the maximum connection window {}",
+                self.max_stream_data_window, self.max_data_window
+            )));
+        }
+        config.set_max_connection_window(self.max_data_window);
+        config.set_max_stream_window(self.max_stream_data_window);
+        config.set_initial_max_streams_bidi(self.max_streams_bidi);
+        config.set_initial_max_streams_uni(self.max_streams_uni);
+        config.set_initial_max_data(self.initial_max_data);
+        // The remote limit is what grants credit to streams the peer opens.
+        config.set_initial_max_stream_data_bidi_local(self.initial_max_stream_data);
+        config.set_initial_max_stream_data_bidi_remote(self.initial_max_stream_data);
+        config.set_initial_max_stream_data_uni(self.initial_max_stream_data);
+        Ok(())
+    }
+
+    /// Apply the ACK timing shared by client and server.
+    fn apply_ack_frequency(&self, config: &mut tquic::Config) -> Result<(), crate::Error> {
+        let delay_ms = self.max_ack_delay.as_millis() as u64;
+        if delay_ms >= MAX_ACK_DELAY_LIMIT_MS {
+            return Err(crate::Error::Config(format!(
+                "max ACK delay {}ms must be below {}ms",
+                delay_ms, MAX_ACK_DELAY_LIMIT_MS
+            )));
+        }
+        if self.ack_eliciting_threshold == 0 {
+            return Err(crate::Error::Config(
+                "ACK-eliciting threshold must be at least 1".to_string(),
+            ));
+        }
+        config.set_max_ack_delay(delay_ms);
+        config.set_ack_eliciting_threshold(self.ack_eliciting_threshold);
+        Ok(())
+    }
+
     /// Convert to tquic Config for client.
-    pub fn to_tquic_client_config(&self) -> Result<tquic::Config, crate::Error> {
+    ///
+    /// Also returns the TLS context the config borrows when tquic's own
+    /// cannot do the checks asked for; it must outlive the endpoint.
+    pub(crate) fn to_tquic_client_config(
+        &self,
+    ) -> Result<(tquic::Config, Option<TlsContext>), crate::Error> {
+        self.validate()?;
         let mut config = tquic::Config::new().map_err(|e| crate::Error::Config(e.to_string()))?;
 
         // Create client TLS config with ALPN protocols
-        let mut tls_config = tquic::TlsConfig::new_client_config(self.alpn.clone(), true)
-            .map_err(|e| crate::Error::Config(format!("Failed to create TLS config: {}", e)))?;
+        self.check_alpn()?;
+        self.check_quic_versions()?;
+        let tls_context = match self.pinned_spki {
+            Some(pin) => {
+                if self.ca_path.is_some() || self.verify_cert_chain {
+                    return Err(crate::Error::Config(
+                        "SPKI pinning replaces certificate chain verification; do not combine it with a CA"
+                            .to_string(),
+                    ));
+                }
+                if self.keylog_path.is_some() {
+                    tracing::warn!("TLS key logging is not available with SPKI pinning");
+                }
+                Some(TlsContext::pinned_spki(pin)?)
+            }
+            None => None,
+        };
+        let mut tls_config = match &tls_context {
+            Some(context) => {
+                let mut tls_config = context.tls_config();
+                tls_config
+                    .set_application_protos(self.alpn.clone())
+                    .map_err(|e| {
+                        crate::Error::Config(format!("Failed to create TLS config: {}", e))
+                    })?;
+                tls_config
+            }
+            None => tquic::TlsConfig::new_client_config(self.alpn.clone(), true)
+                .map_err(|e| crate::Error::Config(format!("Failed to create TLS config: {}", e)))?,
+        };
 
         // Certificate pinning: if ca_path is set, use it as trusted CA and enable verification
         // With self-signed certs, the cert IS the CA, so verification validates the pinned cert
@@ -136,46 +873,94 @@ impl Config {
             tls_config.set_verify(true);
         }
 
+        if let (Some(cert), Some(key)) = (&self.client_cert_path, &self.client_key_path) {
+            tls_config.set_certificate_file(cert).map_err(|e| {
+                crate::Error::Config(format!("Failed to load client certificate: {}", e))
+            })?;
+            tls_config
+                .set_private_key_file(key)
+                .map_err(|e| crate::Error::Config(format!("Failed to load client key: {}", e)))?;
+        }
+
+        tls_config.set_early_data_enabled(self.enable_early_data);
         config.set_tls_config(tls_config);
 
         // Enable multipath
         config.enable_multipath(self.enable_multipath);
+        config.set_multipath_algorithm(self.scheduler.tquic_algorithm());
 
         // Set congestion control
-        config.set_congestion_control_algorithm(self.congestion_control);
+        self.congestion_control.apply(&mut config);
 
-        // Set timeouts
-        config.set_max_idle_timeout(self.idle_timeout.as_millis() as u64);
+        // Timeouts, initial RTT and UDP payload sizes for DNS tunneling
+        self.apply_timing_and_sizes(&mut config);
 
-        // Set initial RTT
-        config.set_initial_rtt(self.initial_rtt_ms);
+        // Observable transport features
+        self.apply_transport_features(&mut config)?;
 
-        // Set maximum UDP payload size for DNS tunneling
-        if let Some(size) = self.send_udp_payload_size {
-            config.set_send_udp_payload_size(size);
-        }
-
-        // Set flow control limits for streams
-        // These are advertised to the peer during handshake
-        config.set_initial_max_streams_bidi(200);
-        config.set_initial_max_streams_uni(100);
-        config.set_initial_max_data(10 * 1024 * 1024); // 10MB connection limit
-        config.set_initial_max_stream_data_bidi_local(5 * 1024 * 1024); // 5MB per local stream
-        config.set_initial_max_stream_data_bidi_remote(5 * 1024 * 1024); // 5MB per remote stream
+        self.apply_flow_control(&mut config)?;
+        self.apply_ack_frequency(&mut config)?;
 
-        Ok(config)
+        Ok((config, tls_context))
     }
 
     /// Convert to tquic Config for server.
-    pub fn to_tquic_server_config(&self) -> Result<tquic::Config, crate::Error> {
+    ///
+    /// Like [`Self::to_tquic_client_config`], also returns the TLS context
+    /// the config borrows, if it needs one.
+    pub(crate) fn to_tquic_server_config(
+        &self,
+    ) -> Result<(tquic::Config, Option<TlsContext>), crate::Error> {
+        self.validate()?;
         let mut config = tquic::Config::new().map_err(|e| crate::Error::Config(e.to_string()))?;
 
         // Create server TLS config with certificate and key
+        self.check_alpn()?;
+        self.check_quic_versions()?;
+        // tquic's verify switch lets clients without a certificate through
+        let tls_context = match &self.client_auth_ca_path {
+            Some(_) => {
+                if self.keylog_path.is_some() {
+                    tracing::warn!(
+                        "TLS key logging is not available when client certificates are required"
+                    );
+                }
+                Some(TlsContext::client_auth()?)
+            }
+            None => None,
+        };
         if let (Some(cert), Some(key)) = (&self.cert_path, &self.key_path) {
-            let tls_config =
-                tquic::TlsConfig::new_server_config(cert, key, self.alpn.clone(), true).map_err(
-                    |e| crate::Error::Config(format!("Failed to create server TLS config: {}", e)),
-                )?;
+            let mut tls_config = match (&tls_context, &self.client_auth_ca_path) {
+                (Some(context), Some(ca)) => {
+                    let mut tls_config = context.tls_config();
+                    // The settings tquic's server config starts from
+                    tls_config
+                        .set_certificate_file(cert)
+                        .and_then(|_| tls_config.set_private_key_file(key))
+                        .and_then(|_| tls_config.set_application_protos(self.alpn.clone()))
+                        .map_err(|e| {
+                            crate::Error::Config(format!(
+                                "Failed to create server TLS config: {}",
+                                e
+                            ))
+                        })?;
+                    tls_config.set_session_timeout(SESSION_TIMEOUT_SECS);
+                    tls_config.set_ca_certs(ca).map_err(|e| {
+                        crate::Error::Config(format!("Failed to load client CA: {}", e))
+                    })?;
+                    tls_config
+                }
+                _ => tquic::TlsConfig::new_server_config(cert, key, self.alpn.clone(), true)
+                    .map_err(|e| {
+                        crate::Error::Config(format!("Failed to create server TLS config: {}", e))
+                    })?,
+            };
+            tls_config.set_early_data_enabled(self.enable_early_data);
+            if let Some(ticket_key) = &self.session_ticket_key {
+                tls_config.set_ticket_key(ticket_key).map_err(|e| {
+                    crate::Error::Config(format!("Invalid session ticket key: {}", e))
+                })?;
+            }
             config.set_tls_config(tls_config);
         } else {
             return Err(crate::Error::Config(
@@ -185,25 +970,104 @@ impl Config {
 
         // Enable multipath
         config.enable_multipath(self.enable_multipath);
+        config.set_multipath_algorithm(self.scheduler.tquic_algorithm());
 
         // Set congestion control
-        config.set_congestion_control_algorithm(self.congestion_control);
+        self.congestion_control.apply(&mut config);
 
-        // Set timeouts
-        config.set_max_idle_timeout(self.idle_timeout.as_millis() as u64);
+        // Timeouts, initial RTT and UDP payload sizes
+        self.apply_timing_and_sizes(&mut config);
 
-        // Set initial RTT
-        config.set_initial_rtt(self.initial_rtt_ms);
+        // Observable transport features
+        self.apply_transport_features(&mut config)?;
+
+        // Address validation; tquic checks the echoed tokens itself
+        config.enable_retry(self.retry);
+        if self.anti_amplification_factor < MIN_ANTI_AMPLIFICATION_FACTOR {
+            return Err(crate::Error::Config(format!(
+                "anti-amplification factor {} is below {}",
+                self.anti_amplification_factor, MIN_ANTI_AMPLIFICATION_FACTOR
+            )));
+        }
+        config.set_anti_amplification_factor(self.anti_amplification_factor);
+
+        self.apply_flow_control(&mut config)?;
+        self.apply_ack_frequency(&mut config)?;
 
-        // Set flow control limits for streams
-        // These are advertised to the peer during handshake
-        // CRITICAL: initial_max_stream_data_bidi_remote grants credits to client-initiated streams
-        config.set_initial_max_streams_bidi(200);
-        config.set_initial_max_streams_uni(100);
-        config.set_initial_max_data(10 * 1024 * 1024); // 10MB connection limit
-        config.set_initial_max_stream_data_bidi_local(5 * 1024 * 1024); // 5MB per local stream
-        config.set_initial_max_stream_data_bidi_remote(5 * 1024 * 1024); // 5MB per remote (client) stream
+        Ok((config, tls_context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use std::time::Duration;
+
+    #[test]
+    fn defaults_validate() {
+        assert!(Config::new().validate().is_ok());
+        assert!(Config::new()
+            .with_idle_timeout(Duration::ZERO)
+            .with_keep_alive(Duration::from_secs(60))
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_timings_that_cannot_work_together() {
+        assert!(Config::new()
+            .with_initial_rtt(Duration::from_micros(500))
+            .validate()
+            .is_err());
+        let config = Config::new()
+            .with_idle_timeout(Duration::from_secs(30))
+            .with_keep_alive(Duration::from_secs(30));
+        assert!(config.validate().is_err());
+        // The conversions check too
+        assert!(config.to_tquic_client_config().is_err());
+    }
+
+    #[test]
+    fn small_send_payloads_need_fragmentation() {
+        assert!(Config::new()
+            .with_send_udp_payload_size(600)
+            .validate()
+            .is_err());
+        assert!(Config::new()
+            .with_send_udp_payload_size(600)
+            .with_fragmentation(true)
+            .validate()
+            .is_ok());
+        assert!(Config::new()
+            .with_send_udp_payload_size(0)
+            .with_fragmentation(true)
+            .validate()
+            .is_err());
+        assert!(Config::new()
+            .with_send_udp_payload_size(65_528)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn receive_payloads_stay_within_quic_limits() {
+        assert!(Config::new()
+            .with_recv_udp_payload_size(1_200)
+            .validate()
+            .is_ok());
+        assert!(Config::new()
+            .with_recv_udp_payload_size(1_199)
+            .validate()
+            .is_err());
+        assert!(Config::new()
+            .with_recv_udp_payload_size(65_528)
+            .validate()
+            .is_err());
+    }
 
-        Ok(config)
+    #[test]
+    fn spin_bit_is_accepted_but_inert() {
+        let config = Config::new().with_spin_bit(true);
+        assert!(config.to_tquic_client_config().is_ok());
     }
 }
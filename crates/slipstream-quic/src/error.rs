@@ -4,10 +4,11 @@ use thiserror::Error;
 
 /// Errors that can occur in slipstream-quic operations.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     /// QUIC transport error.
-    #[error("QUIC error: {0}")]
-    Quic(String),
+    #[error("QUIC error: {message}")]
+    Quic { kind: ErrorKind, message: String },
 
     /// TLS/crypto error.
     #[error("TLS error: {0}")]
@@ -18,8 +19,8 @@ pub enum Error {
     ConnectionClosed { reason: String },
 
     /// Stream error.
-    #[error("stream error: {0}")]
-    Stream(String),
+    #[error("stream error: {message}")]
+    Stream { kind: ErrorKind, message: String },
 
     /// IO error.
     #[error("IO error: {0}")]
@@ -32,10 +33,322 @@ pub enum Error {
     /// Path/multipath error.
     #[error("path error: {0}")]
     Path(String),
+
+    /// The handshake did not complete within the configured timeout, e.g.
+    /// because the peer is unreachable.
+    #[error("handshake timed out")]
+    HandshakeTimeout,
+}
+
+impl Error {
+    /// A transport error reported by tquic.
+    pub(crate) fn quic(err: tquic::Error) -> Self {
+        Error::Quic {
+            kind: ErrorKind::from_tquic(&err),
+            message: err.to_string(),
+        }
+    }
+
+    /// A stream operation tquic refused.
+    pub(crate) fn stream(err: tquic::Error) -> Self {
+        Error::Stream {
+            kind: ErrorKind::from_tquic(&err),
+            message: err.to_string(),
+        }
+    }
+
+    /// Kind of a transport or stream error; `None` for the other variants.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Error::Quic { kind, .. } | Error::Stream { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation only has to wait, e.g. a stream without data to
+    /// read or without flow control credit to write. Retry once the
+    /// connection has made progress.
+    pub fn would_block(&self) -> bool {
+        self.kind() == Some(ErrorKind::Done)
+    }
+
+    /// Whether the connection cannot be used after this error. Anything else
+    /// concerns one stream, datagram or call, and the connection carries on.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Error::ConnectionClosed { .. } | Error::HandshakeTimeout | Error::Tls(_) => true,
+            Error::Quic { kind, .. } | Error::Stream { kind, .. } => kind.is_fatal(),
+            Error::Io(_) | Error::Config(_) | Error::Path(_) => false,
+        }
+    }
 }
 
 impl From<tquic::Error> for Error {
     fn from(err: tquic::Error) -> Self {
-        Error::Quic(err.to_string())
+        Error::quic(err)
+    }
+}
+
+/// What went wrong in tquic, without tquic's own error type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Nothing to do right now: no data to read, no credit to write, no
+    /// packet to send.
+    Done,
+    /// A flow control limit was exceeded.
+    FlowControl,
+    /// The peer's stream limit does not allow another stream.
+    StreamLimit,
+    /// The stream is in the wrong state, e.g. reset or already finished.
+    StreamState,
+    /// A stream's final size changed or was exceeded.
+    FinalSize,
+    /// The buffer was too short.
+    BufferTooShort,
+    /// A datagram could not be parsed as a QUIC packet.
+    InvalidPacket,
+    /// A datagram was for a QUIC version tquic does not speak.
+    UnknownVersion,
+    /// The call is not allowed in the connection's current state.
+    InvalidState,
+    /// The call is not allowed at all, e.g. a client-only path operation on
+    /// the server.
+    InvalidOperation,
+    /// tquic rejected its configuration.
+    InvalidConfig,
+    /// An address validation token was invalid or expired.
+    InvalidToken,
+    /// The peer broke the protocol, e.g. with a malformed frame or transport
+    /// parameter.
+    Protocol,
+    /// A TLS or packet protection failure.
+    Crypto,
+    /// The peer asked us to stop sending on the stream, with this
+    /// application error code.
+    StreamStopped(u64),
+    /// The peer reset the stream, with this application error code.
+    StreamReset(u64),
+    /// No path can carry packets right now, e.g. every path was abandoned.
+    NoViablePath,
+    /// Sending or receiving a datagram failed.
+    Io,
+    /// The server refused the connection.
+    ConnectionRefused,
+    /// Anything else, such as an internal error in tquic.
+    Internal,
+}
+
+impl ErrorKind {
+    fn from_tquic(err: &tquic::Error) -> Self {
+        use tquic::Error as E;
+        match err {
+            E::Done => ErrorKind::Done,
+            E::FlowControlError => ErrorKind::FlowControl,
+            E::StreamLimitError => ErrorKind::StreamLimit,
+            E::StreamStateError => ErrorKind::StreamState,
+            E::FinalSizeError => ErrorKind::FinalSize,
+            E::BufferTooShort => ErrorKind::BufferTooShort,
+            E::InvalidPacket => ErrorKind::InvalidPacket,
+            E::UnknownVersion => ErrorKind::UnknownVersion,
+            E::InvalidState(_) => ErrorKind::InvalidState,
+            E::InvalidOperation(_) => ErrorKind::InvalidOperation,
+            E::InvalidConfig(_) => ErrorKind::InvalidConfig,
+            E::InvalidToken | E::ExpiredToken => ErrorKind::InvalidToken,
+            E::FrameEncodingError
+            | E::TransportParameterError
+            | E::ConnectionIdLimitError
+            | E::ProtocolViolation
+            | E::MultipathProtocolViolation => ErrorKind::Protocol,
+            E::CryptoError(_)
+            | E::CryptoFail
+            | E::TlsFail(_)
+            | E::CryptoBufferExceeded
+            | E::KeyUpdateError
+            | E::AeadLimitReached => ErrorKind::Crypto,
+            E::StreamStopped(code) => ErrorKind::StreamStopped(*code),
+            E::StreamReset(code) => ErrorKind::StreamReset(*code),
+            E::NoViablePath => ErrorKind::NoViablePath,
+            E::IoError(_) => ErrorKind::Io,
+            E::ConnectionRefused => ErrorKind::ConnectionRefused,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    /// Whether tquic closes the connection over an error of this kind.
+    pub fn is_fatal(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::FlowControl
+                | ErrorKind::FinalSize
+                | ErrorKind::Protocol
+                | ErrorKind::Crypto
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::Internal
+        )
+    }
+}
+
+/// Contents of a CONNECTION_CLOSE frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// Application close (frame type 0x1d) rather than a transport close (0x1c).
+    pub is_app: bool,
+    /// Application or transport error code.
+    pub error_code: u64,
+    /// Reason phrase sent with the frame, lossily decoded as UTF-8.
+    pub reason: String,
+}
+
+impl CloseFrame {
+    fn from_tquic(err: &tquic::error::ConnectionError) -> Self {
+        Self {
+            is_app: err.is_app,
+            error_code: err.error_code,
+            reason: String::from_utf8_lossy(&err.reason).into_owned(),
+        }
+    }
+
+    /// Whether the connection was closed without an error (code 0).
+    pub fn is_clean(&self) -> bool {
+        self.error_code == 0
+    }
+}
+
+impl std::fmt::Display for CloseFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_app {
+            write!(f, "application error {:#x}", self.error_code)?;
+        } else {
+            write!(f, "transport error {:#x}", self.error_code)?;
+            if let Some(name) = transport_error_name(self.error_code) {
+                write!(f, " ({})", name)?;
+            }
+        }
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a connection ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseCause {
+    /// The peer sent CONNECTION_CLOSE.
+    Peer(CloseFrame),
+    /// We sent CONNECTION_CLOSE, either on request or after a protocol error.
+    Local(CloseFrame),
+    /// Nothing was heard from the peer for the idle timeout.
+    IdleTimeout,
+    /// The handshake did not finish in time.
+    HandshakeTimeout,
+    /// The peer sent a stateless reset.
+    StatelessReset,
+}
+
+impl CloseCause {
+    /// Read the cause from a connection that is closing; `None` if it is not.
+    pub(crate) fn from_connection(conn: &tquic::Connection) -> Option<Self> {
+        if let Some(err) = conn.peer_error() {
+            return Some(CloseCause::Peer(CloseFrame::from_tquic(err)));
+        }
+        if conn.is_reset() {
+            return Some(CloseCause::StatelessReset);
+        }
+        if conn.is_handshake_timeout() {
+            return Some(CloseCause::HandshakeTimeout);
+        }
+        if conn.is_idle_timeout() {
+            return Some(CloseCause::IdleTimeout);
+        }
+        conn.local_error()
+            .map(|err| CloseCause::Local(CloseFrame::from_tquic(err)))
+    }
+
+    /// Whether the connection ended because of a failure rather than an
+    /// orderly close by either side.
+    pub fn is_error(&self) -> bool {
+        match self {
+            CloseCause::Peer(frame) | CloseCause::Local(frame) => !frame.is_clean(),
+            _ => true,
+        }
+    }
+}
+
+impl std::fmt::Display for CloseCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloseCause::Peer(frame) => write!(f, "closed by peer with {}", frame),
+            CloseCause::Local(frame) => write!(f, "closed locally with {}", frame),
+            CloseCause::IdleTimeout => f.write_str("idle timeout"),
+            CloseCause::HandshakeTimeout => f.write_str("handshake timeout"),
+            CloseCause::StatelessReset => f.write_str("stateless reset by peer"),
+        }
+    }
+}
+
+/// Names of the transport error codes from RFC 9000 section 20.1.
+fn transport_error_name(code: u64) -> Option<&'static str> {
+    Some(match code {
+        0x0 => "NO_ERROR",
+        0x1 => "INTERNAL_ERROR",
+        0x2 => "CONNECTION_REFUSED",
+        0x3 => "FLOW_CONTROL_ERROR",
+        0x4 => "STREAM_LIMIT_ERROR",
+        0x5 => "STREAM_STATE_ERROR",
+        0x6 => "FINAL_SIZE_ERROR",
+        0x7 => "FRAME_ENCODING_ERROR",
+        0x8 => "TRANSPORT_PARAMETER_ERROR",
+        0x9 => "CONNECTION_ID_LIMIT_ERROR",
+        0xa => "PROTOCOL_VIOLATION",
+        0xb => "INVALID_TOKEN",
+        0xc => "APPLICATION_ERROR",
+        0xd => "CRYPTO_BUFFER_EXCEEDED",
+        0xe => "KEY_UPDATE_ERROR",
+        0xf => "AEAD_LIMIT_REACHED",
+        0x10 => "NO_VIABLE_PATH",
+        0x100..=0x1ff => "CRYPTO_ERROR",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, ErrorKind};
+
+    #[test]
+    fn stream_and_path_errors_leave_the_connection_usable() {
+        for (err, kind) in [
+            (tquic::Error::StreamStopped(7), ErrorKind::StreamStopped(7)),
+            (tquic::Error::StreamReset(9), ErrorKind::StreamReset(9)),
+            (tquic::Error::NoViablePath, ErrorKind::NoViablePath),
+            (tquic::Error::IoError("refused".into()), ErrorKind::Io),
+        ] {
+            let err = Error::stream(err);
+            assert_eq!(err.kind(), Some(kind));
+            assert!(!err.is_fatal(), "{:?}", kind);
+            assert!(!err.would_block());
+        }
+    }
+
+    #[test]
+    fn connection_errors_are_fatal() {
+        for (err, kind) in [
+            (
+                tquic::Error::ConnectionRefused,
+                ErrorKind::ConnectionRefused,
+            ),
+            (tquic::Error::FlowControlError, ErrorKind::FlowControl),
+            (tquic::Error::ProtocolViolation, ErrorKind::Protocol),
+            (tquic::Error::CryptoFail, ErrorKind::Crypto),
+            (tquic::Error::InternalError, ErrorKind::Internal),
+        ] {
+            let err = Error::quic(err);
+            assert_eq!(err.kind(), Some(kind));
+            assert!(err.is_fatal(), "{:?}", kind);
+        }
+        assert!(Error::quic(tquic::Error::Done).would_block());
+        assert!(!Error::quic(tquic::Error::Done).is_fatal());
     }
 }
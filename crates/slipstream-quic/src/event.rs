@@ -0,0 +1,116 @@
+//! Connection events, queued as tquic reports them.
+//!
+//! Drivers used to compare `is_ready`, `readable_streams` and the path events
+//! against what they saw last time; a state that came and went between two
+//! checks was never seen. Events are queued instead, in the order tquic
+//! raised them, and taken with `poll_events`.
+
+use crate::error::CloseCause;
+use crate::multipath::PathEvent;
+use std::collections::VecDeque;
+
+/// Events kept for a driver that stopped polling; the oldest go first.
+const MAX_QUEUED_EVENTS: usize = 4096;
+
+/// Something that happened to a connection.
+///
+/// Stream events carry the label the stream was given with `open_bi_labeled`
+/// or `set_stream_label`, so a driver can find its own state for the stream
+/// without a map keyed by stream ID.
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    /// The handshake completed.
+    Established,
+    /// A stream was created, by either side.
+    ///
+    /// Streams the peer opens have no label yet.
+    StreamOpened { stream_id: u64, label: Option<u64> },
+    /// A stream has data to read where it had none.
+    ///
+    /// Raised again only after a read found the stream empty, so a reader
+    /// must read until then or keep the stream on its own list.
+    StreamReadable { stream_id: u64, label: Option<u64> },
+    /// A stream closed in both directions; its ID is not used again.
+    StreamFinished { stream_id: u64, label: Option<u64> },
+    /// A path was added or removed.
+    Path(PathEvent),
+    /// The connection closed; no further events follow.
+    Closed(Option<CloseCause>),
+}
+
+/// Bounded FIFO of events.
+#[derive(Debug)]
+pub(crate) struct EventQueue<T> {
+    events: VecDeque<T>,
+}
+
+impl<T> EventQueue<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, event: T) {
+        if self.events.len() == MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Take every queued event, oldest first.
+    pub(crate) fn drain(&mut self) -> Vec<T> {
+        self.events.drain(..).collect()
+    }
+
+    /// Take the events `take` picks, leaving the others queued in order.
+    pub(crate) fn extract<U>(&mut self, mut take: impl FnMut(&T) -> Option<U>) -> Vec<U> {
+        let mut taken = Vec::new();
+        self.events.retain(|event| match take(event) {
+            Some(picked) => {
+                taken.push(picked);
+                false
+            }
+            None => true,
+        });
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_drain_in_order() {
+        let mut queue = EventQueue::new();
+        for event in 0..3 {
+            queue.push(event);
+        }
+        assert_eq!(queue.drain(), vec![0, 1, 2]);
+        assert!(queue.drain().is_empty());
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_event() {
+        let mut queue = EventQueue::new();
+        for event in 0..MAX_QUEUED_EVENTS + 2 {
+            queue.push(event);
+        }
+        let events = queue.drain();
+        assert_eq!(events.len(), MAX_QUEUED_EVENTS);
+        assert_eq!(events[0], 2);
+        assert_eq!(events.last(), Some(&(MAX_QUEUED_EVENTS + 1)));
+    }
+
+    #[test]
+    fn extract_leaves_the_other_events_in_order() {
+        let mut queue = EventQueue::new();
+        for (conn_id, event) in [(1, 'a'), (2, 'b'), (1, 'c'), (3, 'd')] {
+            queue.push((conn_id, event));
+        }
+        let taken = queue.extract(|&(conn_id, event)| (conn_id == 1).then_some(event));
+        assert_eq!(taken, vec!['a', 'c']);
+        assert_eq!(queue.drain(), vec![(2, 'b'), (3, 'd')]);
+    }
+}
@@ -0,0 +1,54 @@
+//! TLS key log output in the NSS `SSLKEYLOGFILE` format.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tquic::Connection;
+
+/// Key log file shared by every connection of an endpoint.
+///
+/// The file is opened once, in append mode, so connections created after a
+/// sandbox has been applied still have somewhere to write.
+#[derive(Clone)]
+pub(crate) struct Keylog {
+    file: Arc<File>,
+}
+
+impl Keylog {
+    pub(crate) fn open(path: &Path) -> Result<Self, crate::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                crate::Error::Config(format!(
+                    "Failed to open key log file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        tracing::warn!(
+            "Writing TLS secrets to {}; anyone with this file can decrypt captured traffic",
+            path.display()
+        );
+        Ok(Self {
+            file: Arc::new(file),
+        })
+    }
+
+    /// Log the secrets of `conn` to the shared file.
+    pub(crate) fn start(&self, conn: &mut Connection) {
+        conn.set_keylog(Box::new(self.clone()));
+    }
+}
+
+impl Write for Keylog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.file).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.file).flush()
+    }
+}
@@ -3,18 +3,46 @@
 //! This crate wraps tquic to provide QUIC transport with multipath support
 //! for the Slipstream DNS tunnel.
 
+pub mod async_client;
+pub mod batch;
 pub mod client;
 pub mod config;
 pub mod error;
+pub mod event;
+mod keylog;
 pub mod multipath;
+pub mod observer;
+pub mod params;
+mod qlog;
+mod sender;
 pub mod server;
+pub mod session;
+pub mod shared;
+pub mod stats;
 pub mod stream;
+#[cfg(test)]
+mod testing;
+mod tls;
+pub mod token;
 
-pub use client::{Client, ClientConnection};
-pub use config::Config;
-pub use error::Error;
-pub use server::Server;
-pub use stream::{RecvStream, SendStream};
+pub use async_client::AsyncConnection;
+pub use batch::{batch_packets, PacketBatch, MAX_GSO_SEGMENTS};
+pub use client::{Client, ClientConnection, ClientEndpoint};
+pub use config::{
+    parse_congestion_control, Config, CongestionControl, CONGESTION_CONTROL_NAMES,
+    MIN_UDP_PAYLOAD_SIZE, QUIC_VERSIONS,
+};
+pub use error::{CloseCause, CloseFrame, Error, ErrorKind};
+pub use event::ConnectionEvent;
+pub use multipath::{parse_scheduler, MultipathScheduler, SchedulerKind, SCHEDULER_NAMES};
+pub use observer::{ObservedPacket, PacketDirection, PacketObserver, PacketType};
+pub use params::PeerTransportParams;
+pub use server::{EndpointStats, Server, ServerConnection};
+pub use session::{FileSessionStore, MemorySessionStore, SessionData, SessionStore};
+pub use shared::{SharedConnection, SharedServer};
+pub use stats::{ConnectionStats, PathStats};
+pub use stream::{BiStream, RecvStream, SendStream, StreamTable, DEFAULT_URGENCY, MAX_URGENCY};
+pub use token::{MemoryTokenStore, TokenStore};
 
 /// Result type for slipstream-quic operations.
 pub type Result<T> = std::result::Result<T, Error>;
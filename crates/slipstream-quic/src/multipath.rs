@@ -32,10 +32,53 @@ pub struct PathInfo {
     /// Bytes in flight on this path.
     pub bytes_in_flight: u64,
 
+    /// Total bytes sent on this path.
+    pub sent_bytes: u64,
+
+    /// Total bytes received on this path.
+    pub recv_bytes: u64,
+
+    /// Total bytes declared lost on this path.
+    pub lost_bytes: u64,
+
+    /// Whether the peer has acknowledged data sent on this path.
+    /// A path that is still being probed reports `false`.
+    pub validated: bool,
+
     /// Whether this path is currently active.
     pub is_active: bool,
 }
 
+impl PathInfo {
+    /// Build a snapshot from tquic path statistics.
+    pub(crate) fn from_stats(
+        path_id: PathId,
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        stats: &tquic::PathStats,
+    ) -> Self {
+        Self {
+            path_id,
+            local_addr,
+            peer_addr,
+            rtt_us: stats.srtt,
+            cwnd: stats.final_cwnd,
+            pacing_rate: stats.pacing_rate,
+            // tquic reports cumulative counters only; whatever was sent and neither
+            // acknowledged nor declared lost is still in flight.
+            bytes_in_flight: stats
+                .sent_bytes
+                .saturating_sub(stats.acked_bytes)
+                .saturating_sub(stats.lost_bytes),
+            sent_bytes: stats.sent_bytes,
+            recv_bytes: stats.recv_bytes,
+            lost_bytes: stats.lost_bytes,
+            validated: stats.acked_bytes > 0,
+            is_active: true,
+        }
+    }
+}
+
 /// Events related to path changes.
 #[derive(Debug, Clone)]
 pub enum PathEvent {
@@ -50,6 +93,16 @@ pub enum PathEvent {
 
     /// Path quality changed significantly.
     QualityChanged(PathId),
+
+    /// Packets sent on a path were declared lost since the last check.
+    ///
+    /// tquic keeps its PTO count to itself, so probe timeouts show up only
+    /// through the losses they end in.
+    Loss {
+        path_id: PathId,
+        /// Packets newly declared lost.
+        lost_packets: u64,
+    },
 }
 
 /// Path management interface.
@@ -66,22 +119,273 @@ pub trait PathManager {
     /// Set the mode/priority for a path.
     fn set_path_mode(&mut self, path_id: PathId, mode: PathMode) -> Result<(), crate::Error>;
 
+    /// Stop using a path, e.g. one whose resolver keeps failing.
+    ///
+    /// The path leaves [`path_info`](Self::path_info) and
+    /// [`active_paths`](Self::active_paths) and a [`PathEvent::Deleted`] is
+    /// queued. Probing the same peer again brings it back. Nothing changes if
+    /// it fails.
+    ///
+    /// The default refuses, for managers that cannot give up a path.
+    fn abandon_path(&mut self, path_id: PathId, error_code: u64) -> Result<(), crate::Error> {
+        let _ = error_code;
+        Err(crate::Error::Path(format!(
+            "path {} cannot be abandoned",
+            path_id
+        )))
+    }
+
     /// Drain pending path events.
     fn drain_path_events(&mut self) -> Vec<PathEvent>;
 }
 
-/// Mode for a path (affects scheduling and congestion control).
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Mode for a path, deciding whether the tunnel polls through it.
+///
+/// tquic has no per-path scheduler knobs, so modes are enforced where the tunnel
+/// decides what to send on a path: the polls that pull server data back. QUIC
+/// packets go wherever tquic's scheduler puts them, whatever the mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum PathMode {
     /// Normal bidirectional path.
+    #[default]
     Normal,
 
-    /// Path primarily for sending.
-    SendPrimary,
-
-    /// Path primarily for receiving.
+    /// Path primarily for receiving; polled for server data.
     RecvPrimary,
 
-    /// Backup path (only used when primary fails).
+    /// Backup path, polled only when no other path is usable.
+    ///
+    /// This is local state: tquic 1.6 neither sends nor honours MP-QUIC
+    /// PATH_STATUS frames, so the peer still treats the path as available.
     Backup,
 }
+
+impl PathMode {
+    /// Whether polls may be sent on a path in this mode.
+    ///
+    /// `primary_available` tells whether any non-backup path is still usable.
+    pub fn allows_polls(self, primary_available: bool) -> bool {
+        match self {
+            PathMode::Normal | PathMode::RecvPrimary => true,
+            PathMode::Backup => !primary_available,
+        }
+    }
+}
+
+/// Chooses the path(s) that carry the next send.
+///
+/// Schedulers see a snapshot of the connection's paths on every call and keep
+/// whatever state they need between calls.
+pub trait MultipathScheduler {
+    /// Pick the paths for the next send; empty when no path is usable.
+    fn select(&mut self, paths: &[PathInfo]) -> Vec<PathId>;
+}
+
+/// Built-in schedulers selectable through [`crate::Config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedulerKind {
+    /// Lowest smoothed RTT first.
+    #[default]
+    MinRtt,
+
+    /// Cycle through paths in path ID order.
+    RoundRobin,
+
+    /// Send on every path.
+    Redundant,
+}
+
+/// Scheduler names accepted by [`parse_scheduler`].
+pub const SCHEDULER_NAMES: &[&str] = &["minrtt", "roundrobin", "redundant"];
+
+/// Map a CLI scheduler name to a [`SchedulerKind`].
+pub fn parse_scheduler(name: &str) -> Result<SchedulerKind, crate::Error> {
+    match name.to_ascii_lowercase().as_str() {
+        "minrtt" | "min-rtt" => Ok(SchedulerKind::MinRtt),
+        "roundrobin" | "round-robin" => Ok(SchedulerKind::RoundRobin),
+        "redundant" => Ok(SchedulerKind::Redundant),
+        _ => Err(crate::Error::Config(format!(
+            "unsupported multipath scheduler '{}' (expected one of: {})",
+            name,
+            SCHEDULER_NAMES.join(", ")
+        ))),
+    }
+}
+
+impl SchedulerKind {
+    /// Name accepted by [`parse_scheduler`].
+    pub fn name(self) -> &'static str {
+        match self {
+            SchedulerKind::MinRtt => "minrtt",
+            SchedulerKind::RoundRobin => "roundrobin",
+            SchedulerKind::Redundant => "redundant",
+        }
+    }
+
+    /// Create a fresh scheduler of this kind.
+    pub fn build(self) -> Box<dyn MultipathScheduler> {
+        match self {
+            SchedulerKind::MinRtt => Box::new(MinRttScheduler),
+            SchedulerKind::RoundRobin => Box::new(RoundRobinScheduler::default()),
+            SchedulerKind::Redundant => Box::new(RedundantScheduler),
+        }
+    }
+
+    /// The tquic algorithm that schedules packets the same way.
+    pub(crate) fn tquic_algorithm(self) -> tquic::MultipathAlgorithm {
+        match self {
+            SchedulerKind::MinRtt => tquic::MultipathAlgorithm::MinRtt,
+            SchedulerKind::RoundRobin => tquic::MultipathAlgorithm::RoundRobin,
+            SchedulerKind::Redundant => tquic::MultipathAlgorithm::Redundant,
+        }
+    }
+}
+
+/// Send on the active path with the lowest smoothed RTT.
+#[derive(Debug, Default)]
+pub struct MinRttScheduler;
+
+impl MultipathScheduler for MinRttScheduler {
+    fn select(&mut self, paths: &[PathInfo]) -> Vec<PathId> {
+        paths
+            .iter()
+            .filter(|path| path.is_active)
+            .min_by_key(|path| (path.rtt_us, path.path_id))
+            .map(|path| vec![path.path_id])
+            .unwrap_or_default()
+    }
+}
+
+/// Take turns across active paths in path ID order.
+#[derive(Debug, Default)]
+pub struct RoundRobinScheduler {
+    last: Option<PathId>,
+}
+
+impl MultipathScheduler for RoundRobinScheduler {
+    fn select(&mut self, paths: &[PathInfo]) -> Vec<PathId> {
+        let mut ids: Vec<PathId> = paths
+            .iter()
+            .filter(|path| path.is_active)
+            .map(|path| path.path_id)
+            .collect();
+        ids.sort_unstable();
+        let next = match self.last {
+            Some(last) => ids.iter().copied().find(|id| *id > last),
+            None => None,
+        }
+        .or_else(|| ids.first().copied());
+        self.last = next;
+        next.into_iter().collect()
+    }
+}
+
+/// Duplicate every send across all active paths.
+#[derive(Debug, Default)]
+pub struct RedundantScheduler;
+
+impl MultipathScheduler for RedundantScheduler {
+    fn select(&mut self, paths: &[PathInfo]) -> Vec<PathId> {
+        paths
+            .iter()
+            .filter(|path| path.is_active)
+            .map(|path| path.path_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path_id: PathId, rtt_us: u64, is_active: bool) -> PathInfo {
+        let addr: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        PathInfo {
+            path_id,
+            local_addr: addr,
+            peer_addr: addr,
+            rtt_us,
+            cwnd: 0,
+            pacing_rate: 0,
+            bytes_in_flight: 0,
+            sent_bytes: 0,
+            recv_bytes: 0,
+            lost_bytes: 0,
+            validated: true,
+            is_active,
+        }
+    }
+
+    #[test]
+    fn scheduler_names_round_trip() {
+        for name in SCHEDULER_NAMES {
+            assert_eq!(parse_scheduler(name).unwrap().name(), *name);
+        }
+        assert_eq!(parse_scheduler("Min-RTT").unwrap(), SchedulerKind::MinRtt);
+        assert!(parse_scheduler("weighted").is_err());
+    }
+
+    #[test]
+    fn min_rtt_picks_the_fastest_active_path() {
+        let paths = [
+            path(0, 30_000, true),
+            path(1, 10_000, false),
+            path(2, 20_000, true),
+        ];
+        assert_eq!(MinRttScheduler.select(&paths), vec![2]);
+        assert!(MinRttScheduler.select(&[]).is_empty());
+    }
+
+    #[test]
+    fn round_robin_cycles_in_path_order() {
+        let paths = [path(2, 0, true), path(0, 0, true), path(1, 0, false)];
+        let mut scheduler = RoundRobinScheduler::default();
+        let picks: Vec<_> = (0..3).flat_map(|_| scheduler.select(&paths)).collect();
+        assert_eq!(picks, vec![0, 2, 0]);
+    }
+
+    #[test]
+    fn backup_paths_are_polled_only_without_a_primary() {
+        for mode in [PathMode::Normal, PathMode::RecvPrimary] {
+            assert!(mode.allows_polls(true));
+            assert!(mode.allows_polls(false));
+        }
+        assert!(!PathMode::Backup.allows_polls(true));
+        assert!(PathMode::Backup.allows_polls(false));
+    }
+
+    #[test]
+    fn redundant_uses_every_active_path() {
+        let paths = [path(0, 0, true), path(1, 0, false), path(2, 0, true)];
+        assert_eq!(RedundantScheduler.select(&paths), vec![0, 2]);
+    }
+
+    struct FixedPaths;
+
+    impl PathManager for FixedPaths {
+        fn probe_path(&mut self, _peer_addr: SocketAddr) -> Result<PathId, crate::Error> {
+            Ok(1)
+        }
+
+        fn path_info(&mut self, _path_id: PathId) -> Option<PathInfo> {
+            None
+        }
+
+        fn active_paths(&mut self) -> Vec<PathInfo> {
+            Vec::new()
+        }
+
+        fn set_path_mode(&mut self, _path_id: PathId, _mode: PathMode) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn drain_path_events(&mut self) -> Vec<PathEvent> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn managers_without_abandon_refuse_it() {
+        assert!(FixedPaths.abandon_path(1, 0).is_err());
+    }
+}
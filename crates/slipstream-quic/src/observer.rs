@@ -0,0 +1,147 @@
+//! Hooks that see every datagram an endpoint sends or receives.
+//!
+//! The DNS layer uses them to account for the encapsulation around each
+//! packet, and tests to check the sizes tquic emits. A datagram may carry
+//! several coalesced QUIC packets; it is reported once, with the type of the
+//! first.
+
+use std::net::SocketAddr;
+
+/// Which way a datagram went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    /// Handed out by `poll_send`.
+    Sent,
+    /// Passed to `recv`, whether or not tquic accepted it.
+    Received,
+}
+
+/// QUIC packet type, read from the packet header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    /// Long header packet that opens the handshake.
+    Initial,
+    /// 0-RTT early data.
+    ZeroRtt,
+    /// Long header packet carrying the rest of the handshake.
+    Handshake,
+    /// The server's request that the client prove its address.
+    Retry,
+    /// Long header with version 0, listing the versions the sender speaks.
+    VersionNegotiation,
+    /// Short header packet.
+    OneRtt,
+    /// Too short to tell, or a version tquic does not speak.
+    Unknown,
+}
+
+impl PacketType {
+    /// Type of the first packet in `datagram`.
+    pub fn of(datagram: &[u8]) -> Self {
+        let Some(&first) = datagram.first() else {
+            return PacketType::Unknown;
+        };
+        if first & 0x80 == 0 {
+            return PacketType::OneRtt;
+        }
+        let Some(version) = long_header_version(datagram) else {
+            return PacketType::Unknown;
+        };
+        if version == 0 {
+            return PacketType::VersionNegotiation;
+        }
+        if !crate::QUIC_VERSIONS.contains(&version) {
+            return PacketType::Unknown;
+        }
+        match (first >> 4) & 0x03 {
+            0 => PacketType::Initial,
+            1 => PacketType::ZeroRtt,
+            2 => PacketType::Handshake,
+            _ => PacketType::Retry,
+        }
+    }
+}
+
+/// Version field of the first packet in `datagram`, if it has a long header.
+pub(crate) fn long_header_version(datagram: &[u8]) -> Option<u32> {
+    if datagram.first()? & 0x80 == 0 {
+        return None;
+    }
+    let version = datagram.get(1..5)?;
+    Some(u32::from_be_bytes([
+        version[0], version[1], version[2], version[3],
+    ]))
+}
+
+/// One datagram as an observer sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObservedPacket {
+    /// Whether the datagram was sent or received.
+    pub direction: PacketDirection,
+    /// Type of the first packet in the datagram.
+    pub packet_type: PacketType,
+    /// Datagram length in bytes.
+    pub size: usize,
+    /// Our end of the path.
+    pub local: SocketAddr,
+    /// The peer's end of the path. On the server,
+    /// [`ServerConnection::path_for_peer`](crate::ServerConnection::path_for_peer) maps it to a
+    /// path ID; on the client, [`PathInfo`](crate::multipath::PathInfo) has it.
+    pub peer: SocketAddr,
+}
+
+/// Called for every datagram of an endpoint.
+///
+/// Sent datagrams are reported while tquic builds them, so the observer must
+/// not call back into the client or server; record what it needs instead.
+pub trait PacketObserver {
+    /// A datagram was sent or received.
+    fn on_packet(&mut self, packet: &ObservedPacket);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PacketType;
+
+    /// Long header with type bits `kind` and `version`, plus a few bytes.
+    fn long_header(kind: u8, version: u32) -> Vec<u8> {
+        let mut packet = vec![0xc0 | (kind << 4)];
+        packet.extend_from_slice(&version.to_be_bytes());
+        packet.extend_from_slice(&[8, 0, 0]);
+        packet
+    }
+
+    #[test]
+    fn long_header_types_follow_the_type_bits() {
+        let v1 = tquic::QUIC_VERSION_V1;
+        assert_eq!(PacketType::of(&long_header(0, v1)), PacketType::Initial);
+        assert_eq!(PacketType::of(&long_header(1, v1)), PacketType::ZeroRtt);
+        assert_eq!(PacketType::of(&long_header(2, v1)), PacketType::Handshake);
+        assert_eq!(PacketType::of(&long_header(3, v1)), PacketType::Retry);
+    }
+
+    #[test]
+    fn version_zero_is_version_negotiation() {
+        // Whatever the type bits say
+        assert_eq!(
+            PacketType::of(&long_header(2, 0)),
+            PacketType::VersionNegotiation
+        );
+    }
+
+    #[test]
+    fn short_headers_are_one_rtt() {
+        assert_eq!(PacketType::of(&[0x40, 1, 2, 3]), PacketType::OneRtt);
+        assert_eq!(PacketType::of(&[0x00]), PacketType::OneRtt);
+    }
+
+    #[test]
+    fn unreadable_headers_are_unknown() {
+        assert_eq!(PacketType::of(&[]), PacketType::Unknown);
+        assert_eq!(PacketType::of(&[0xc0, 0, 0]), PacketType::Unknown);
+        assert_eq!(
+            PacketType::of(&long_header(0, 0xff00_001d)),
+            PacketType::Unknown
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Transport parameters the peer sent in the handshake.
+//!
+//! tquic 1.6 applies the peer's transport parameters but keeps them private;
+//! the one it reports is whether multipath was negotiated. Fields are added
+//! here as tquic exposes more of them.
+
+use std::fmt;
+
+/// The peer's transport parameters, as far as the transport reports them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeerTransportParams {
+    /// Both sides sent `enable_multipath`, so extra paths can carry data.
+    pub multipath: bool,
+}
+
+impl PeerTransportParams {
+    pub(crate) fn from_tquic(conn: &tquic::Connection) -> Self {
+        Self {
+            multipath: conn.is_multipath(),
+        }
+    }
+}
+
+impl fmt::Display for PeerTransportParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "multipath={}", self.multipath)
+    }
+}
@@ -0,0 +1,25 @@
+//! qlog trace output.
+
+use std::fs::File;
+use std::path::Path;
+use tquic::Connection;
+
+/// Start writing a qlog trace for `conn` into `dir`, one file per connection.
+///
+/// Failing to create the file only costs the trace, so it is logged and the
+/// connection carries on.
+pub(crate) fn start_qlog(conn: &mut Connection, dir: &Path, role: &str) {
+    let trace_id = conn.trace_id().to_string();
+    let path = dir.join(format!("{}-{}.sqlog", role, trace_id));
+    match File::create(&path) {
+        Ok(file) => {
+            conn.set_qlog(
+                Box::new(file),
+                format!("slipstream {} qlog", role),
+                format!("id={}", trace_id),
+            );
+            tracing::debug!("Writing qlog to {}", path.display());
+        }
+        Err(e) => tracing::warn!("Failed to create qlog file {}: {}", path.display(), e),
+    }
+}
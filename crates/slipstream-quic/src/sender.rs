@@ -0,0 +1,125 @@
+//! Outgoing packet queue shared by the client and server endpoints.
+
+use crate::observer::{ObservedPacket, PacketDirection, PacketObserver, PacketType};
+use std::cell::{Cell, RefCell};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use tquic::{PacketInfo, PacketSendHandler};
+
+/// Spare packet buffers kept for reuse; more than a loop's worth is waste.
+const MAX_POOLED_BUFFERS: usize = 256;
+
+/// Packet sender for tquic.
+///
+/// tquic lends each packet only for the duration of the callback, so it has to
+/// be copied. Copies go into buffers handed back through
+/// [`recycle`](Self::recycle), so a caller that returns what it sent keeps the
+/// send path free of allocations.
+///
+/// Packets longer than `max_packet_size` are still queued, since dropping
+/// them would only cost a retransmission, but counted and reported once: each
+/// one means a DNS tunnel splits the packet over more queries than planned.
+///
+/// The endpoint's [`PacketObserver`] is kept here too, as this is where sent
+/// packets pass.
+pub(crate) struct PacketSender {
+    pending_packets: RefCell<Vec<(Vec<u8>, SocketAddr)>>,
+    pool: RefCell<Vec<Vec<u8>>>,
+    max_packet_size: Option<usize>,
+    oversized: Cell<u64>,
+    observer: RefCell<Option<Rc<RefCell<dyn PacketObserver>>>>,
+    #[cfg(test)]
+    pub(crate) fail_sends: Cell<bool>,
+}
+
+impl PacketSender {
+    pub(crate) fn new(max_packet_size: Option<usize>) -> Self {
+        Self {
+            pending_packets: RefCell::new(Vec::new()),
+            pool: RefCell::new(Vec::new()),
+            max_packet_size,
+            oversized: Cell::new(0),
+            observer: RefCell::new(None),
+            #[cfg(test)]
+            fail_sends: Cell::new(false),
+        }
+    }
+
+    /// Packets so far that exceeded the configured maximum size.
+    pub(crate) fn oversized_packets(&self) -> u64 {
+        self.oversized.get()
+    }
+
+    pub(crate) fn set_observer(&self, observer: Rc<RefCell<dyn PacketObserver>>) {
+        *self.observer.borrow_mut() = Some(observer);
+    }
+
+    /// Report a datagram to the observer, if there is one.
+    pub(crate) fn observe(
+        &self,
+        direction: PacketDirection,
+        data: &[u8],
+        local: SocketAddr,
+        peer: SocketAddr,
+    ) {
+        if let Some(observer) = self.observer.borrow().as_ref() {
+            observer.borrow_mut().on_packet(&ObservedPacket {
+                direction,
+                packet_type: PacketType::of(data),
+                size: data.len(),
+                local,
+                peer,
+            });
+        }
+    }
+
+    fn check_size(&self, len: usize) {
+        let Some(max) = self.max_packet_size else {
+            return;
+        };
+        if len <= max {
+            return;
+        }
+        if self.oversized.get() == 0 {
+            tracing::warn!(
+                "tquic emitted a {}-byte packet, over the {}-byte maximum",
+                len,
+                max
+            );
+        }
+        self.oversized.set(self.oversized.get() + 1);
+    }
+
+    /// Move queued packets to the end of `out`, keeping the queue's capacity.
+    pub(crate) fn drain_packets_into(&self, out: &mut Vec<(Vec<u8>, SocketAddr)>) {
+        out.append(&mut self.pending_packets.borrow_mut());
+    }
+
+    /// Return a sent packet's buffer for reuse.
+    pub(crate) fn recycle(&self, mut buf: Vec<u8>) {
+        let mut pool = self.pool.borrow_mut();
+        if pool.len() < MAX_POOLED_BUFFERS {
+            buf.clear();
+            pool.push(buf);
+        }
+    }
+}
+
+impl PacketSendHandler for PacketSender {
+    fn on_packets_send(&self, pkts: &[(Vec<u8>, PacketInfo)]) -> tquic::Result<usize> {
+        #[cfg(test)]
+        if self.fail_sends.get() {
+            return Err(tquic::Error::InternalError);
+        }
+        let mut pending = self.pending_packets.borrow_mut();
+        let mut pool = self.pool.borrow_mut();
+        for (data, info) in pkts {
+            self.check_size(data.len());
+            self.observe(PacketDirection::Sent, data, info.src, info.dst);
+            let mut buf = pool.pop().unwrap_or_default();
+            buf.extend_from_slice(data);
+            pending.push((buf, info.dst));
+        }
+        Ok(pkts.len())
+    }
+}
@@ -1,36 +1,174 @@
 //! QUIC server implementation using tquic.
 
 use crate::config::Config;
-use crate::error::Error;
+use crate::error::{CloseCause, Error, ErrorKind};
+use crate::event::{ConnectionEvent, EventQueue};
+use crate::keylog::Keylog;
+use crate::multipath::{PathEvent, PathId, PathInfo};
+use crate::observer::{long_header_version, PacketDirection, PacketObserver};
+use crate::qlog::start_qlog;
+use crate::sender::PacketSender;
+use crate::stream::{check_urgency, SendLedger};
+use crate::tls::TlsContext;
 use bytes::Bytes;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::rc::Rc;
-use tquic::{Connection, Endpoint, PacketInfo, PacketSendHandler, TransportHandler};
+use tquic::{Connection, Endpoint, PacketInfo, TransportHandler};
 
 /// QUIC server for accepting connections.
 pub struct Server {
-    endpoint: Endpoint,
+    endpoint: Rc<RefCell<Endpoint>>,
     sender: Rc<PacketSender>,
+    /// Scratch copy of the packet being received; tquic decrypts in place.
+    recv_buf: Vec<u8>,
     local_addr: SocketAddr,
     state: Rc<RefCell<ServerState>>,
 }
 
 struct ServerState {
     connections: HashMap<u64, ConnectionInfo>,
+    /// Connections that became ready and were not yet taken by `accept`.
+    accept_queue: VecDeque<u64>,
+    /// Events of every connection, tagged with the connection ID.
+    events: EventQueue<(u64, ConnectionEvent)>,
+    stats: EndpointStats,
+}
+
+/// Totals across every connection the server has handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EndpointStats {
+    /// UDP datagrams handed to [`Server::recv`].
+    pub packets_in: u64,
+    /// Bytes handed to [`Server::recv`].
+    pub bytes_in: u64,
+    /// Datagrams returned by [`Server::poll_send`].
+    pub packets_out: u64,
+    /// Bytes returned by [`Server::poll_send`].
+    pub bytes_out: u64,
+    /// Datagrams tquic rejected.
+    pub recv_errors: u64,
+    /// Connections created by a client Initial.
+    pub handshakes_started: u64,
+    /// Handshakes that completed.
+    pub handshakes_completed: u64,
+    /// Of the completed handshakes, those that resumed an earlier session.
+    pub handshakes_resumed: u64,
+    /// Connections closed before their handshake completed.
+    pub handshakes_failed: u64,
+    /// Long-header datagrams for a QUIC version other than v1, which call for
+    /// a Version Negotiation packet.
+    pub version_negotiations: u64,
+    /// Connections currently open.
+    pub active_connections: u64,
+    /// Datagrams larger than [`Config::max_packet_size`](crate::Config::max_packet_size).
+    pub oversized_packets: u64,
 }
 
 #[allow(dead_code)]
 struct ConnectionInfo {
     peer_addr: SocketAddr,
     ready: bool,
+    resumed: bool,
+    alpn: Vec<u8>,
+    /// QUIC version of the client's long-header packets, once one matched.
+    version: Option<u32>,
     streams: HashMap<u64, StreamState>,
+    /// Paths tquic reported at the last sync, in the order they appeared.
+    paths: Vec<TrackedPath>,
+    next_path_id: PathId,
+}
+
+impl ConnectionInfo {
+    fn new(peer_addr: SocketAddr, ready: bool, resumed: bool, alpn: Vec<u8>) -> Self {
+        Self {
+            peer_addr,
+            ready,
+            resumed,
+            alpn,
+            version: None,
+            streams: HashMap::new(),
+            paths: Vec::new(),
+            next_path_id: 0,
+        }
+    }
+
+    /// Reconcile tracked paths with the 4-tuples tquic currently reports.
+    ///
+    /// Paths keep their ID for as long as tquic reports them; new ones get the
+    /// next unused ID, so the handshake path is always 0. Returns the changes.
+    ///
+    /// `current` also carries each path's count of packets declared lost; a
+    /// rise on a tracked path is reported as [`PathEvent::Loss`].
+    fn sync_paths(&mut self, current: &[(SocketAddr, SocketAddr, u64)]) -> Vec<PathEvent> {
+        let mut events = Vec::new();
+        self.paths.retain(|path| {
+            let alive = current
+                .iter()
+                .any(|&(local, remote, _)| (local, remote) == (path.local, path.remote));
+            if !alive {
+                events.push(PathEvent::Deleted(path.path_id));
+            }
+            alive
+        });
+        for &(local, remote, lost_count) in current {
+            if let Some(path) = self
+                .paths
+                .iter_mut()
+                .find(|path| path.local == local && path.remote == remote)
+            {
+                if lost_count > path.lost_count {
+                    events.push(PathEvent::Loss {
+                        path_id: path.path_id,
+                        lost_packets: lost_count - path.lost_count,
+                    });
+                    path.lost_count = lost_count;
+                }
+                continue;
+            }
+            let path_id = self.next_path_id;
+            self.next_path_id += 1;
+            self.paths.push(TrackedPath {
+                path_id,
+                local,
+                remote,
+                lost_count,
+            });
+            events.push(PathEvent::Available(path_id));
+        }
+        events
+    }
+}
+
+/// A path of a server connection, identified by its 4-tuple.
+struct TrackedPath {
+    path_id: PathId,
+    local: SocketAddr,
+    remote: SocketAddr,
+    /// tquic's count of packets lost on the path at the last sync.
+    lost_count: u64,
 }
 
 struct StreamState {
     readable: bool,
     writable: bool,
+    /// Writes the peer has yet to acknowledge.
+    unacked: SendLedger,
+    /// Caller's label, reported with the stream's events.
+    label: Option<u64>,
+}
+
+impl StreamState {
+    fn new() -> Self {
+        Self {
+            readable: false,
+            writable: true,
+            unacked: SendLedger::default(),
+            label: None,
+        }
+    }
 }
 
 impl Server {
@@ -42,15 +180,23 @@ impl Server {
             ));
         }
 
-        let tquic_config = config.to_tquic_server_config()?;
+        let (tquic_config, tls_context) = config.to_tquic_server_config()?;
+        config.prepare_qlog_dir()?;
+        let keylog = config.open_keylog()?;
         let state = Rc::new(RefCell::new(ServerState {
             connections: HashMap::new(),
+            accept_queue: VecDeque::new(),
+            events: EventQueue::new(),
+            stats: EndpointStats::default(),
         }));
 
         let handler = Box::new(ServerHandler {
             state: state.clone(),
+            qlog_dir: config.qlog_dir.clone(),
+            keylog,
+            _tls_context: tls_context,
         });
-        let sender = Rc::new(PacketSender::new());
+        let sender = Rc::new(PacketSender::new(config.max_packet_size()));
 
         let endpoint = Endpoint::new(
             Box::new(tquic_config),
@@ -62,13 +208,20 @@ impl Server {
         tracing::info!("Server created for {}", addr);
 
         Ok(Self {
-            endpoint,
+            endpoint: Rc::new(RefCell::new(endpoint)),
             sender,
+            recv_buf: Vec::new(),
             local_addr: addr,
             state,
         })
     }
 
+    /// Report every datagram the server sends or receives to `observer`.
+    pub fn with_packet_observer(self, observer: impl PacketObserver + 'static) -> Self {
+        self.sender.set_observer(Rc::new(RefCell::new(observer)));
+        self
+    }
+
     /// Get the local address the server is bound to.
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
@@ -81,33 +234,125 @@ impl Server {
             dst: self.local_addr,
             time: std::time::Instant::now(),
         };
-        let mut buf = data.to_vec();
-        self.endpoint
-            .recv(&mut buf, &info)
-            .map_err(|e| Error::Quic(e.to_string()))?;
-        let _ = self.endpoint.process_connections();
-        Ok(())
+        self.sender
+            .observe(PacketDirection::Received, data, self.local_addr, from);
+        {
+            let stats = &mut self.state.borrow_mut().stats;
+            stats.packets_in += 1;
+            stats.bytes_in += data.len() as u64;
+            if needs_version_negotiation(data) {
+                stats.version_negotiations += 1;
+            }
+        }
+        self.recv_buf.clear();
+        self.recv_buf.extend_from_slice(data);
+        if let Err(e) = self.endpoint.borrow_mut().recv(&mut self.recv_buf, &info) {
+            self.state.borrow_mut().stats.recv_errors += 1;
+            return Err(Error::quic(e));
+        }
+        let processed = self.endpoint.borrow_mut().process_connections();
+        self.sync_paths();
+        self.record_version(data);
+        processed.map_err(Error::quic)
+    }
+
+    /// Note the version of a long-header packet on the connection it belongs to.
+    ///
+    /// tquic keeps each connection's version to itself; the client's
+    /// Handshake packets carry it and a connection ID the server issued.
+    fn record_version(&self, data: &[u8]) {
+        let Some(version) = long_header_version(data).filter(|v| *v != 0) else {
+            return;
+        };
+        let Some(conn_id) = self.connection_for_packet(data, PacketDirection::Received) else {
+            return;
+        };
+        if let Some(info) = self.state.borrow_mut().connections.get_mut(&conn_id) {
+            info.version.get_or_insert(version);
+        }
     }
 
     /// Get packets to send.
     pub fn poll_send(&mut self) -> Vec<(Vec<u8>, SocketAddr)> {
-        let _ = self.endpoint.process_connections();
-        self.sender
-            .take_packets()
-            .into_iter()
-            .map(|(data, info)| (data, info.dst))
-            .collect()
+        let mut packets = Vec::new();
+        self.poll_send_into(&mut packets);
+        packets
+    }
+
+    /// Append packets to send to `out`, reusing its capacity.
+    pub fn poll_send_into(&mut self, out: &mut Vec<(Vec<u8>, SocketAddr)>) {
+        let _ = self.endpoint.borrow_mut().process_connections();
+        let start = out.len();
+        self.sender.drain_packets_into(out);
+        let stats = &mut self.state.borrow_mut().stats;
+        stats.packets_out += (out.len() - start) as u64;
+        stats.bytes_out += out[start..]
+            .iter()
+            .map(|(data, _)| data.len() as u64)
+            .sum::<u64>();
+    }
+
+    /// Hand back the buffer of a packet that has been sent.
+    ///
+    /// Later packets are copied into recycled buffers instead of fresh ones.
+    pub fn recycle(&self, packet: Vec<u8>) {
+        self.sender.recycle(packet);
+    }
+
+    /// Get packets to send, grouped for sendmmsg or UDP GSO.
+    ///
+    /// See [`batch_packets`](crate::batch_packets) for how packets are grouped.
+    pub fn poll_send_batched(&mut self, max_segments: usize) -> Vec<crate::PacketBatch> {
+        crate::batch_packets(self.poll_send(), max_segments)
+    }
+
+    /// Totals across all connections since the server was created.
+    pub fn endpoint_stats(&self) -> EndpointStats {
+        let state = self.state.borrow();
+        EndpointStats {
+            active_connections: state.connections.len() as u64,
+            oversized_packets: self.sender.oversized_packets(),
+            ..state.stats
+        }
     }
 
     /// Get the next timeout.
     pub fn timeout(&self) -> Option<std::time::Duration> {
-        self.endpoint.timeout()
+        self.endpoint.borrow().timeout()
     }
 
     /// Handle timeout.
     pub fn on_timeout(&mut self) {
-        self.endpoint.on_timeout(std::time::Instant::now());
-        let _ = self.endpoint.process_connections();
+        self.endpoint
+            .borrow_mut()
+            .on_timeout(std::time::Instant::now());
+        let _ = self.endpoint.borrow_mut().process_connections();
+        self.sync_paths();
+    }
+
+    /// Refresh the tracked paths of every connection from tquic.
+    fn sync_paths(&self) {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        for (conn_id, info) in state.connections.iter_mut() {
+            let Some(conn) = endpoint.conn_get_mut(*conn_id) else {
+                continue;
+            };
+            let tuples: Vec<_> = conn.paths_iter().collect();
+            let current: Vec<_> = tuples
+                .into_iter()
+                .map(|t| {
+                    let lost = conn
+                        .get_path_stats(t.local, t.remote)
+                        .map_or(0, |stats| stats.lost_count);
+                    (t.local, t.remote, lost)
+                })
+                .collect();
+            for event in info.sync_paths(&current) {
+                state.events.push((*conn_id, ConnectionEvent::Path(event)));
+            }
+        }
     }
 
     /// Get ready connections.
@@ -121,30 +366,85 @@ impl Server {
             .collect()
     }
 
-    /// Get all stream IDs for a connection.
-    pub fn streams(&self, conn_id: u64) -> Vec<u64> {
+    /// Take the next connection that became ready since the last call.
+    ///
+    /// Connections are ready once the handshake completes, or earlier when the
+    /// client opens a stream in 0-RTT data.
+    pub fn accept(&mut self) -> Option<ServerConnection> {
+        let mut state = self.state.borrow_mut();
+        while let Some(conn_id) = state.accept_queue.pop_front() {
+            if state.connections.contains_key(&conn_id) {
+                drop(state);
+                return Some(self.handle(conn_id));
+            }
+        }
+        None
+    }
+
+    /// Take every connection that became ready since the last call.
+    pub fn incoming(&mut self) -> impl Iterator<Item = ServerConnection> + '_ {
+        std::iter::from_fn(|| self.accept())
+    }
+
+    /// Handle for an open connection, whether or not it was accepted yet.
+    pub fn connection(&self, conn_id: u64) -> Option<ServerConnection> {
         self.state
             .borrow()
             .connections
-            .get(&conn_id)
-            .map(|info| info.streams.keys().cloned().collect())
-            .unwrap_or_default()
+            .contains_key(&conn_id)
+            .then(|| self.handle(conn_id))
     }
 
-    /// Get readable stream IDs for a connection.
-    pub fn readable_streams(&self, conn_id: u64) -> Vec<u64> {
+    /// Find the connection that has a path to `peer`.
+    ///
+    /// With multipath, one connection is reachable through several resolvers;
+    /// this maps the source of a query back to the connection it belongs to.
+    /// Clients behind the same resolver share its address, so this returns
+    /// any one of them; [`Server::connection_for_packet`] tells them apart.
+    pub fn connection_for_peer(&self, peer: SocketAddr) -> Option<u64> {
         self.state
             .borrow()
             .connections
-            .get(&conn_id)
-            .map(|info| {
-                info.streams
-                    .iter()
-                    .filter(|(_, s)| s.readable)
-                    .map(|(id, _)| *id)
-                    .collect()
-            })
-            .unwrap_or_default()
+            .iter()
+            .find(|(_, info)| info.paths.iter().any(|path| path.remote == peer))
+            .map(|(conn_id, _)| *conn_id)
+    }
+
+    /// Find the connection a QUIC packet belongs to by its destination
+    /// connection ID.
+    ///
+    /// A packet the server received carries one of the connection IDs the
+    /// server issued, and one it sent carries one the client issued. A client
+    /// Initial still carries the ID the client made up, so it matches nothing.
+    pub fn connection_for_packet(&self, packet: &[u8], direction: PacketDirection) -> Option<u64> {
+        let conn_ids: Vec<u64> = self.state.borrow().connections.keys().copied().collect();
+        let mut endpoint = self.endpoint.borrow_mut();
+        conn_ids.into_iter().find(|&conn_id| {
+            let Some(conn) = endpoint.conn_get_mut(conn_id) else {
+                return false;
+            };
+            match direction {
+                PacketDirection::Received => {
+                    conn.scid_iter().any(|item| has_dcid(packet, &item.cid))
+                }
+                PacketDirection::Sent => conn.dcid_iter().any(|item| has_dcid(packet, &item.cid)),
+            }
+        })
+    }
+
+    /// Whether a connection resumed an earlier TLS session.
+    pub fn is_resumed(&self, conn_id: u64) -> bool {
+        self.handle(conn_id).is_resumed()
+    }
+
+    /// Get all stream IDs for a connection.
+    pub fn streams(&self, conn_id: u64) -> Vec<u64> {
+        self.handle(conn_id).streams()
+    }
+
+    /// Get readable stream IDs for a connection.
+    pub fn readable_streams(&self, conn_id: u64) -> Vec<u64> {
+        self.handle(conn_id).readable_streams()
     }
 
     /// Read data from a stream on a connection.
@@ -154,14 +454,32 @@ impl Server {
         stream_id: u64,
         buf: &mut [u8],
     ) -> Result<(usize, bool), Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(conn_id) {
-            conn.stream_read(stream_id, buf)
-                .map_err(|e| Error::Stream(e.to_string()))
-        } else {
-            Err(Error::ConnectionClosed {
-                reason: "connection not found".to_string(),
-            })
-        }
+        self.handle(conn_id).stream_read(stream_id, buf)
+    }
+
+    /// ALPN protocol negotiated on a connection.
+    pub fn connection_alpn(&self, conn_id: u64) -> Option<Vec<u8>> {
+        self.connection(conn_id).map(|conn| conn.alpn())
+    }
+
+    /// Open a new unidirectional stream on a connection.
+    pub fn open_uni(&mut self, conn_id: u64) -> Result<u64, Error> {
+        self.handle(conn_id).open_uni()
+    }
+
+    /// Change the priority of a stream on a connection.
+    ///
+    /// Lower `urgency` (0-7, RFC 9218) is sent first; `incremental` streams of
+    /// equal urgency share the connection round-robin.
+    pub fn set_stream_priority(
+        &mut self,
+        conn_id: u64,
+        stream_id: u64,
+        urgency: u8,
+        incremental: bool,
+    ) -> Result<(), Error> {
+        self.handle(conn_id)
+            .set_stream_priority(stream_id, urgency, incremental)
     }
 
     /// Write data to a stream on a connection.
@@ -172,14 +490,61 @@ impl Server {
         data: &[u8],
         fin: bool,
     ) -> Result<usize, Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(conn_id) {
-            conn.stream_write(stream_id, Bytes::copy_from_slice(data), fin)
-                .map_err(|e| Error::Stream(e.to_string()))
-        } else {
-            Err(Error::ConnectionClosed {
-                reason: "connection not found".to_string(),
-            })
-        }
+        self.handle(conn_id).stream_write(stream_id, data, fin)
+    }
+
+    /// Bytes written to a stream on a connection that the peer has not
+    /// acknowledged yet. See [`ServerConnection::stream_queued_bytes`].
+    pub fn stream_queued_bytes(&self, conn_id: u64, stream_id: u64) -> usize {
+        self.handle(conn_id).stream_queued_bytes(stream_id)
+    }
+
+    /// Label a stream of a connection. See [`ServerConnection::set_stream_label`].
+    pub fn set_stream_label(&self, conn_id: u64, stream_id: u64, label: u64) -> Result<(), Error> {
+        self.handle(conn_id).set_stream_label(stream_id, label)
+    }
+
+    /// Label of a stream of a connection, if it has one.
+    pub fn stream_label(&self, conn_id: u64, stream_id: u64) -> Option<u64> {
+        self.handle(conn_id).stream_label(stream_id)
+    }
+
+    /// Get all established paths of a connection.
+    ///
+    /// The server never opens paths itself, so IDs are assigned in the order the
+    /// client's paths appear; path 0 is the one the handshake arrived on. An ID
+    /// is never reused within a connection.
+    pub fn active_paths(&mut self, conn_id: u64) -> Vec<PathInfo> {
+        self.handle(conn_id).active_paths()
+    }
+
+    /// Get a snapshot of one path of a connection.
+    pub fn path_info(&mut self, conn_id: u64, path_id: PathId) -> Option<PathInfo> {
+        self.handle(conn_id).path_info(path_id)
+    }
+
+    /// Queue a PING frame on one path of a connection.
+    /// See [`ServerConnection::ping_path`].
+    pub fn ping_path(&mut self, conn_id: u64, path_id: PathId) -> Result<(), Error> {
+        self.handle(conn_id).ping_path(path_id)
+    }
+
+    /// Get the traffic and loss counters of a connection.
+    pub fn connection_stats(&mut self, conn_id: u64) -> Option<crate::ConnectionStats> {
+        self.handle(conn_id).stats()
+    }
+
+    /// Drain the path events of a connection since the last call.
+    pub fn drain_path_events(&mut self, conn_id: u64) -> Vec<PathEvent> {
+        self.handle(conn_id).drain_path_events()
+    }
+
+    /// Take the events of every connection raised since the last call, oldest
+    /// first, each with the ID of its connection.
+    ///
+    /// Events of a closed connection are kept until taken, up to its `Closed`.
+    pub fn poll_events(&mut self) -> Vec<(u64, ConnectionEvent)> {
+        self.state.borrow_mut().events.drain()
     }
 
     /// Close a connection.
@@ -189,54 +554,412 @@ impl Server {
         error_code: u64,
         reason: &str,
     ) -> Result<(), Error> {
-        if let Some(conn) = self.endpoint.conn_get_mut(conn_id) {
+        self.handle(conn_id).close(error_code, reason)
+    }
+
+    fn handle(&self, conn_id: u64) -> ServerConnection {
+        ServerConnection {
+            endpoint: self.endpoint.clone(),
+            state: self.state.clone(),
+            conn_id,
+        }
+    }
+}
+
+/// Handle to one connection of a [`Server`].
+///
+/// Handles share the server's state, so they stay valid while the server
+/// processes packets; once the connection is gone, stream calls fail with
+/// [`Error::ConnectionClosed`] and queries return empty results.
+#[derive(Clone)]
+pub struct ServerConnection {
+    endpoint: Rc<RefCell<Endpoint>>,
+    state: Rc<RefCell<ServerState>>,
+    conn_id: u64,
+}
+
+impl ServerConnection {
+    /// Connection ID, as used by the `conn_id` methods of [`Server`].
+    pub fn conn_id(&self) -> u64 {
+        self.conn_id
+    }
+
+    /// Check if the connection is still open.
+    pub fn is_open(&self) -> bool {
+        self.state.borrow().connections.contains_key(&self.conn_id)
+    }
+
+    /// Address the client connected from.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.with_info(|info| info.peer_addr)
+    }
+
+    /// ALPN protocol negotiated on the connection.
+    pub fn alpn(&self) -> Vec<u8> {
+        self.with_info(|info| info.alpn.clone()).unwrap_or_default()
+    }
+
+    /// Transport parameters the client sent, once the handshake is done.
+    ///
+    /// See [`PeerTransportParams`](crate::PeerTransportParams) for which
+    /// fields tquic reports.
+    pub fn peer_transport_params(&self) -> Option<crate::PeerTransportParams> {
+        if !self.with_info(|info| info.ready)? {
+            return None;
+        }
+        self.with_conn(|conn| Ok(crate::PeerTransportParams::from_tquic(conn)))
+            .ok()
+    }
+
+    /// QUIC version the client's packets carry, or `None` until one of its
+    /// Handshake packets arrived.
+    pub fn quic_version(&self) -> Option<u32> {
+        self.with_info(|info| info.version).flatten()
+    }
+
+    /// Whether the connection resumed an earlier TLS session.
+    pub fn is_resumed(&self) -> bool {
+        self.with_info(|info| info.resumed).unwrap_or(false)
+    }
+
+    /// Get all stream IDs.
+    pub fn streams(&self) -> Vec<u64> {
+        self.with_info(|info| info.streams.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Get readable stream IDs.
+    pub fn readable_streams(&self) -> Vec<u64> {
+        self.with_info(|info| {
+            info.streams
+                .iter()
+                .filter(|(_, s)| s.readable)
+                .map(|(id, _)| *id)
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Read data from a stream.
+    ///
+    /// A read that finds no data clears the stream's readable state, so the
+    /// next data raises [`ConnectionEvent::StreamReadable`] again.
+    pub fn stream_read(&self, stream_id: u64, buf: &mut [u8]) -> Result<(usize, bool), Error> {
+        let result = self.with_conn(|conn| Ok(conn.stream_read(stream_id, buf)))?;
+        if let Err(tquic::Error::Done) = result {
+            if let Some(stream) = self
+                .state
+                .borrow_mut()
+                .connections
+                .get_mut(&self.conn_id)
+                .and_then(|info| info.streams.get_mut(&stream_id))
+            {
+                stream.readable = false;
+            }
+        }
+        result.map_err(Error::stream)
+    }
+
+    /// Write data to a stream.
+    pub fn stream_write(&self, stream_id: u64, data: &[u8], fin: bool) -> Result<usize, Error> {
+        let data = Bytes::copy_from_slice(data);
+        let written = self.with_conn(|conn| {
+            conn.stream_write(stream_id, data.clone(), fin)
+                .map_err(Error::stream)
+        })?;
+        if let Some(info) = self.state.borrow_mut().connections.get_mut(&self.conn_id) {
+            info.streams
+                .entry(stream_id)
+                .or_insert_with(StreamState::new)
+                .unacked
+                .record(data, written);
+        }
+        Ok(written)
+    }
+
+    /// Bytes written to a stream that the peer has not acknowledged yet.
+    ///
+    /// Acknowledgements are counted per `stream_write` call, so a write is
+    /// counted in full until all of it is acknowledged. Zero for unknown or
+    /// closed streams.
+    pub fn stream_queued_bytes(&self, stream_id: u64) -> usize {
+        self.state
+            .borrow_mut()
+            .connections
+            .get_mut(&self.conn_id)
+            .and_then(|info| info.streams.get_mut(&stream_id))
+            .map_or(0, |stream| stream.unacked.queued_bytes())
+    }
+
+    /// Label a stream, e.g. one the client opened; later events carry the
+    /// label. The label is opaque, e.g. an index into the caller's own table.
+    pub fn set_stream_label(&self, stream_id: u64, label: u64) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let stream = state
+            .connections
+            .get_mut(&self.conn_id)
+            .and_then(|info| info.streams.get_mut(&stream_id))
+            .ok_or_else(|| Error::Stream {
+                kind: ErrorKind::InvalidState,
+                message: format!("unknown stream {}", stream_id),
+            })?;
+        stream.label = Some(label);
+        Ok(())
+    }
+
+    /// Label of a stream, if it has one.
+    pub fn stream_label(&self, stream_id: u64) -> Option<u64> {
+        self.state
+            .borrow()
+            .connections
+            .get(&self.conn_id)?
+            .streams
+            .get(&stream_id)?
+            .label
+    }
+
+    /// Open a new unidirectional stream.
+    pub fn open_uni(&self) -> Result<u64, Error> {
+        self.with_conn(|conn| {
+            // stream_uni_new(urgency, incremental)
+            conn.stream_uni_new(0, false).map_err(Error::stream)
+        })
+    }
+
+    /// Change the priority of a stream. See [`Server::set_stream_priority`].
+    pub fn set_stream_priority(
+        &self,
+        stream_id: u64,
+        urgency: u8,
+        incremental: bool,
+    ) -> Result<(), Error> {
+        check_urgency(urgency)?;
+        self.with_conn(|conn| {
+            conn.stream_set_priority(stream_id, urgency, incremental)
+                .map_err(Error::stream)
+        })
+    }
+
+    /// Get all established paths. See [`Server::active_paths`].
+    pub fn active_paths(&self) -> Vec<PathInfo> {
+        let tuples: Vec<_> = self
+            .with_info(|info| {
+                info.paths
+                    .iter()
+                    .map(|path| (path.path_id, path.local, path.remote))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut endpoint = self.endpoint.borrow_mut();
+        let Some(conn) = endpoint.conn_get_mut(self.conn_id) else {
+            return Vec::new();
+        };
+        tuples
+            .into_iter()
+            .filter_map(|(path_id, local, remote)| {
+                let stats = conn.get_path_stats(local, remote).ok()?;
+                Some(PathInfo::from_stats(path_id, local, remote, stats))
+            })
+            .collect()
+    }
+
+    /// Get a snapshot of one path, including its tquic statistics.
+    pub fn path_info(&self, path_id: PathId) -> Option<PathInfo> {
+        let (local, remote) = self.with_info(|info| {
+            info.paths
+                .iter()
+                .find(|path| path.path_id == path_id)
+                .map(|path| (path.local, path.remote))
+        })??;
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint.conn_get_mut(self.conn_id)?;
+        let stats = conn.get_path_stats(local, remote).ok()?;
+        Some(PathInfo::from_stats(path_id, local, remote, stats))
+    }
+
+    /// Get traffic and loss counters, in total and for each established path.
+    ///
+    /// Returns `None` once the connection is gone.
+    pub fn stats(&self) -> Option<crate::ConnectionStats> {
+        let tuples: Vec<_> = self.with_info(|info| {
+            info.paths
+                .iter()
+                .map(|path| (path.path_id, path.local, path.remote))
+                .collect()
+        })?;
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint.conn_get_mut(self.conn_id)?;
+        let paths = tuples
+            .into_iter()
+            .filter_map(|(path_id, local, remote)| {
+                let stats = conn.get_path_stats(local, remote).ok()?;
+                Some(crate::PathStats::from_tquic(path_id, remote, stats))
+            })
+            .collect();
+        Some(crate::ConnectionStats::from_tquic(conn.stats(), paths))
+    }
+
+    /// Path whose remote address is `peer`, e.g. the resolver a poll arrived from.
+    ///
+    /// Packets tquic schedules on that path come back from [`Server::poll_send`]
+    /// addressed to `peer`, so they can answer queries from the same resolver.
+    pub fn path_for_peer(&self, peer: SocketAddr) -> Option<PathId> {
+        self.with_info(|info| {
+            info.paths
+                .iter()
+                .find(|path| path.remote == peer)
+                .map(|path| path.path_id)
+        })
+        .flatten()
+    }
+
+    /// Queue a PING frame on a path.
+    ///
+    /// tquic picks the path for stream data itself; a PING is the one thing
+    /// that can be put on a given path, e.g. so a poll from that path's
+    /// resolver has a packet to carry back.
+    pub fn ping_path(&self, path_id: PathId) -> Result<(), Error> {
+        let (local, remote) = self
+            .with_info(|info| {
+                info.paths
+                    .iter()
+                    .find(|path| path.path_id == path_id)
+                    .map(|path| (path.local, path.remote))
+            })
+            .flatten()
+            .ok_or_else(|| Error::Path(format!("unknown path {}", path_id)))?;
+        self.with_conn(|conn| {
+            conn.ping(Some(tquic::FourTuple { local, remote }))
+                .map_err(|e| Error::Path(e.to_string()))?;
+            // tquic 1.6 does not queue the connection for sending on a PING;
+            // this no-op on a stream that cannot exist is a public call that does
+            let _ = conn.stream_want_read(u64::MAX, true);
+            Ok(())
+        })
+    }
+
+    /// Drain path events since the last call.
+    ///
+    /// `Available` is reported when tquic first lists a path the client opened
+    /// and `Deleted` once it no longer does.
+    /// They are taken out of the queue [`Server::poll_events`] returns.
+    pub fn drain_path_events(&self) -> Vec<PathEvent> {
+        let conn_id = self.conn_id;
+        self.state
+            .borrow_mut()
+            .events
+            .extract(|(id, event)| match event {
+                ConnectionEvent::Path(path_event) if *id == conn_id => Some(path_event.clone()),
+                _ => None,
+            })
+    }
+
+    /// Close the connection.
+    pub fn close(&self, error_code: u64, reason: &str) -> Result<(), Error> {
+        if let Some(conn) = self.endpoint.borrow_mut().conn_get_mut(self.conn_id) {
             conn.close(true, error_code, reason.as_bytes())
-                .map_err(|e| Error::Quic(e.to_string()))?;
+                .map_err(Error::quic)?;
         }
-        self.state.borrow_mut().connections.remove(&conn_id);
+        self.state.borrow_mut().connections.remove(&self.conn_id);
         Ok(())
     }
+
+    fn with_info<T>(&self, read: impl FnOnce(&ConnectionInfo) -> T) -> Option<T> {
+        self.state.borrow().connections.get(&self.conn_id).map(read)
+    }
+
+    fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut endpoint = self.endpoint.borrow_mut();
+        let conn = endpoint
+            .conn_get_mut(self.conn_id)
+            .ok_or_else(|| Error::ConnectionClosed {
+                reason: "connection not found".to_string(),
+            })?;
+        f(conn)
+    }
 }
 
 /// Handler for server-side tquic transport events.
 struct ServerHandler {
     state: Rc<RefCell<ServerState>>,
+    qlog_dir: Option<PathBuf>,
+    keylog: Option<Keylog>,
+    /// Owns the TLS context the endpoint's config borrows, if it has one.
+    _tls_context: Option<TlsContext>,
 }
 
 impl TransportHandler for ServerHandler {
     fn on_conn_created(&mut self, conn: &mut Connection) {
         let conn_id = conn.trace_id();
         tracing::debug!("Server connection created: {}", conn_id);
+        self.state.borrow_mut().stats.handshakes_started += 1;
+        if let Some(dir) = &self.qlog_dir {
+            start_qlog(conn, dir, "server");
+        }
+        if let Some(keylog) = &self.keylog {
+            keylog.start(conn);
+        }
     }
 
     fn on_conn_established(&mut self, conn: &mut Connection) {
         let conn_id = conn.index().unwrap_or(0);
-        tracing::info!("Server connection established: {}", conn_id);
+        let resumed = conn.is_resumed();
+        tracing::info!(
+            "Server connection established: {}{}",
+            conn_id,
+            if resumed { " (resumed)" } else { "" }
+        );
 
         let peer = conn.paths_iter().next().map(|p| p.remote);
         let mut state = self.state.borrow_mut();
+        state.stats.handshakes_completed += 1;
+        if resumed {
+            state.stats.handshakes_resumed += 1;
+        }
 
         // Check if connection already exists (from on_stream_created)
         // If so, just update ready flag and peer_addr; otherwise create new entry
+        let alpn = conn.application_proto().to_vec();
         if let Some(conn_info) = state.connections.get_mut(&conn_id) {
+            let was_ready = conn_info.ready;
             conn_info.ready = true;
+            conn_info.resumed = resumed;
             conn_info.peer_addr = peer.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap());
+            conn_info.alpn = alpn;
+            if !was_ready {
+                state.accept_queue.push_back(conn_id);
+            }
         } else {
             state.connections.insert(
                 conn_id,
-                ConnectionInfo {
-                    peer_addr: peer.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap()),
-                    ready: true,
-                    streams: HashMap::new(),
-                },
+                ConnectionInfo::new(
+                    peer.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap()),
+                    true,
+                    resumed,
+                    alpn,
+                ),
             );
+            state.accept_queue.push_back(conn_id);
         }
+        state.events.push((conn_id, ConnectionEvent::Established));
     }
 
     fn on_conn_closed(&mut self, conn: &mut Connection) {
         let conn_id = conn.index().unwrap_or(0);
-        tracing::info!("Server connection closed: {}", conn_id);
-        self.state.borrow_mut().connections.remove(&conn_id);
+        let cause = CloseCause::from_connection(conn);
+        match &cause {
+            Some(cause) => tracing::info!("Server connection closed: {} ({})", conn_id, cause),
+            None => tracing::info!("Server connection closed: {}", conn_id),
+        }
+        let mut state = self.state.borrow_mut();
+        if !conn.is_established() {
+            state.stats.handshakes_failed += 1;
+        }
+        state.connections.remove(&conn_id);
+        state.events.push((conn_id, ConnectionEvent::Closed(cause)));
     }
 
     fn on_stream_created(&mut self, conn: &mut Connection, stream_id: u64) {
@@ -245,30 +968,55 @@ impl TransportHandler for ServerHandler {
 
         let mut state = self.state.borrow_mut();
         // Create connection entry if it doesn't exist (stream events can arrive before conn_established)
+        // Streams opened in 0-RTT data make the connection usable right away; the
+        // ALPN was settled by the resumed session.
+        let early = conn.is_in_early_data();
+        if early && !state.connections.contains_key(&conn_id) {
+            state.accept_queue.push_back(conn_id);
+        }
         let conn_info = state.connections.entry(conn_id).or_insert_with(|| {
             let peer = conn.paths_iter().next().map(|p| p.remote);
-            ConnectionInfo {
-                peer_addr: peer.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap()),
-                ready: false, // Will be set to true by on_conn_established
-                streams: HashMap::new(),
-            }
+            ConnectionInfo::new(
+                peer.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap()),
+                early, // Otherwise set to true by on_conn_established
+                early,
+                if early {
+                    conn.application_proto().to_vec()
+                } else {
+                    Vec::new()
+                },
+            )
         });
-        conn_info.streams.insert(
-            stream_id,
-            StreamState {
-                readable: false,
-                writable: true,
-            },
-        );
+        // A local stream may have seen writes already
+        let label = conn_info
+            .streams
+            .entry(stream_id)
+            .or_insert_with(StreamState::new)
+            .label;
+        state
+            .events
+            .push((conn_id, ConnectionEvent::StreamOpened { stream_id, label }));
     }
 
     fn on_stream_readable(&mut self, conn: &mut Connection, stream_id: u64) {
         let conn_id = conn.index().unwrap_or(0);
         tracing::trace!("Server stream {} readable on conn {}", stream_id, conn_id);
 
-        if let Some(conn_info) = self.state.borrow_mut().connections.get_mut(&conn_id) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        if let Some(conn_info) = state.connections.get_mut(&conn_id) {
             if let Some(stream) = conn_info.streams.get_mut(&stream_id) {
-                stream.readable = true;
+                // Reported after every event while data is buffered
+                if !stream.readable {
+                    stream.readable = true;
+                    state.events.push((
+                        conn_id,
+                        ConnectionEvent::StreamReadable {
+                            stream_id,
+                            label: stream.label,
+                        },
+                    ));
+                }
             }
         }
     }
@@ -288,9 +1036,16 @@ impl TransportHandler for ServerHandler {
         let conn_id = conn.index().unwrap_or(0);
         tracing::debug!("Server stream {} closed on conn {}", stream_id, conn_id);
 
-        if let Some(conn_info) = self.state.borrow_mut().connections.get_mut(&conn_id) {
-            conn_info.streams.remove(&stream_id);
-        }
+        let mut state = self.state.borrow_mut();
+        let label = state
+            .connections
+            .get_mut(&conn_id)
+            .and_then(|conn_info| conn_info.streams.remove(&stream_id))
+            .and_then(|stream| stream.label);
+        state.events.push((
+            conn_id,
+            ConnectionEvent::StreamFinished { stream_id, label },
+        ));
     }
 
     fn on_new_token(&mut self, _conn: &mut Connection, _token: Vec<u8>) {
@@ -298,29 +1053,197 @@ impl TransportHandler for ServerHandler {
     }
 }
 
-/// Packet sender for tquic.
-struct PacketSender {
-    pending_packets: RefCell<Vec<(Vec<u8>, PacketInfo)>>,
+/// Whether `data` is a long-header packet for a version tquic doesn't speak.
+/// Version 0 marks a Version Negotiation packet, which a server never answers.
+/// Check if the first packet of `datagram` is addressed to `cid`.
+///
+/// Short headers do not carry the ID's length, so the ID is matched as a
+/// prefix of what follows the first byte.
+fn has_dcid(datagram: &[u8], cid: &[u8]) -> bool {
+    let Some(&first) = datagram.first() else {
+        return false;
+    };
+    if cid.is_empty() {
+        return false;
+    }
+    if first & 0x80 == 0 {
+        return datagram[1..].starts_with(cid);
+    }
+    let Some(&len) = datagram.get(5) else {
+        return false;
+    };
+    datagram.get(6..6 + len as usize) == Some(cid)
 }
 
-impl PacketSender {
-    fn new() -> Self {
-        Self {
-            pending_packets: RefCell::new(Vec::new()),
+fn needs_version_negotiation(data: &[u8]) -> bool {
+    if data.first().is_none_or(|first| first & 0x80 == 0) {
+        return false;
+    }
+    match data.get(1..5) {
+        Some(version) => {
+            let version = u32::from_be_bytes([version[0], version[1], version[2], version[3]]);
+            version != 0 && !crate::QUIC_VERSIONS.contains(&version)
         }
+        None => false,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        connected, exchange, resolver_addr, server_addr, test_client, test_server,
+    };
 
-    fn take_packets(&self) -> Vec<(Vec<u8>, PacketInfo)> {
-        std::mem::take(&mut *self.pending_packets.borrow_mut())
+    #[test]
+    fn has_dcid_reads_both_header_forms() {
+        let cid = [0xaa, 0xbb, 0xcc, 0xdd];
+        let short = [0x40, 0xaa, 0xbb, 0xcc, 0xdd, 0x01, 0x02];
+        assert!(has_dcid(&short, &cid));
+        assert!(!has_dcid(&short, &[0xbb]));
+        let long = [0xc0, 0, 0, 0, 1, 4, 0xaa, 0xbb, 0xcc, 0xdd, 0x08];
+        assert!(has_dcid(&long, &cid));
+        assert!(!has_dcid(&long, &cid[..3]));
+        assert!(!has_dcid(&long[..8], &cid));
+        assert!(!has_dcid(&short, &[]));
+        assert!(!has_dcid(&[], &cid));
+    }
+
+    #[test]
+    fn packets_map_to_their_connection_behind_one_resolver() {
+        let (server_addr, resolver) = (server_addr(), resolver_addr());
+        let mut server = test_server();
+        let client = test_client();
+        // Both clients reach the server through the same resolver address
+        let mut conn_ids = Vec::new();
+        for _ in 0..2 {
+            let mut conn = client.connect(resolver, server_addr, "localhost").unwrap();
+            let mut last_sent = Vec::new();
+            for _ in 0..10 {
+                for (packet, _) in conn.poll_send() {
+                    server.recv(&packet, resolver).unwrap();
+                    last_sent = packet;
+                }
+                for (packet, _) in server.poll_send() {
+                    // Packets of the earlier client would draw a reset
+                    let to = server.connection_for_packet(&packet, PacketDirection::Sent);
+                    if to.is_none() || !conn_ids.contains(&to) {
+                        conn.recv(&packet, server_addr).unwrap();
+                    }
+                }
+            }
+            assert!(conn.is_ready());
+            conn_ids.push(server.connection_for_packet(&last_sent, PacketDirection::Received));
+        }
+
+        conn_ids.sort();
+        let mut ready: Vec<_> = server.ready_connections().into_iter().map(Some).collect();
+        ready.sort();
+        assert_eq!(conn_ids, ready);
     }
-}
 
-impl PacketSendHandler for PacketSender {
-    fn on_packets_send(&self, pkts: &[(Vec<u8>, PacketInfo)]) -> tquic::Result<usize> {
-        let mut pending = self.pending_packets.borrow_mut();
-        for (data, info) in pkts {
-            pending.push((data.clone(), *info));
+    #[test]
+    fn stream_events_carry_labels() {
+        let (mut server, mut conn) = connected();
+        let conn_id = server.ready_connections()[0];
+
+        let stream_id = conn.open_bi_labeled(7).unwrap();
+        assert_eq!(conn.stream_label(stream_id), Some(7));
+        conn.stream_write(stream_id, b"hello", false).unwrap();
+        assert!(conn.poll_events().iter().any(|event| matches!(
+            event,
+            ConnectionEvent::StreamOpened { stream_id: id, label: Some(7) } if *id == stream_id
+        )));
+        exchange(&mut server, &mut conn);
+
+        // The peer's stream has no label until the server gives it one
+        assert!(server.poll_events().iter().any(|(id, event)| *id == conn_id
+            && matches!(
+                event,
+                ConnectionEvent::StreamOpened { stream_id: id, label: None } if *id == stream_id
+            )));
+        server.set_stream_label(conn_id, stream_id, 3).unwrap();
+        assert_eq!(server.stream_label(conn_id, stream_id), Some(3));
+        let mut buf = [0; 16];
+        while let Ok((n, _)) = server.stream_read(conn_id, stream_id, &mut buf) {
+            if n == 0 {
+                break;
+            }
         }
-        Ok(pkts.len())
+
+        conn.stream_write(stream_id, b"again", true).unwrap();
+        exchange(&mut server, &mut conn);
+        assert!(server.poll_events().iter().any(|(id, event)| *id == conn_id
+            && matches!(
+                event,
+                ConnectionEvent::StreamReadable { stream_id: id, label: Some(3) } if *id == stream_id
+            )));
+        assert!(server.set_stream_label(conn_id, 99, 1).is_err());
+    }
+
+    #[test]
+    fn both_ends_report_the_version_on_the_wire() {
+        let mut server = test_server();
+        let mut conn = test_client()
+            .connect(resolver_addr(), server_addr(), "localhost")
+            .unwrap();
+        assert_eq!(conn.quic_version(), None);
+        exchange(&mut server, &mut conn);
+        let conn_id = server.ready_connections()[0];
+        assert_eq!(conn.quic_version(), Some(tquic::QUIC_VERSION_V1));
+        assert_eq!(
+            server.connection(conn_id).unwrap().quic_version(),
+            Some(tquic::QUIC_VERSION_V1)
+        );
+    }
+
+    #[test]
+    fn peer_transport_params_report_multipath() {
+        let (server, conn) = connected();
+        let conn_id = server.ready_connections()[0];
+        let params = server
+            .connection(conn_id)
+            .unwrap()
+            .peer_transport_params()
+            .unwrap();
+        // Both test configs leave multipath on
+        assert!(params.multipath);
+        assert_eq!(params.to_string(), "multipath=true");
+        assert_eq!(conn.peer_transport_params(), Some(params));
+    }
+
+    #[test]
+    fn pings_go_out_on_the_named_path() {
+        let (mut server, mut conn) = connected();
+        let conn_id = server.ready_connections()[0];
+        exchange(&mut server, &mut conn);
+        assert!(server.poll_send().is_empty());
+
+        let path_id = server
+            .connection(conn_id)
+            .unwrap()
+            .path_for_peer(resolver_addr())
+            .unwrap();
+        server.ping_path(conn_id, path_id).unwrap();
+        let sent = server.poll_send();
+        assert!(!sent.is_empty());
+        assert!(sent.iter().all(|(_, to)| *to == resolver_addr()));
+        assert!(server.ping_path(conn_id, path_id + 1).is_err());
+    }
+
+    #[test]
+    fn recv_reports_failed_sends() {
+        let (mut server, mut conn) = connected();
+        exchange(&mut server, &mut conn);
+
+        let stream_id = conn.open_bi().unwrap();
+        conn.stream_write(stream_id, b"hello", false).unwrap();
+        server.sender.fail_sends.set(true);
+        let results: Vec<_> = conn
+            .poll_send()
+            .iter()
+            .map(|(packet, _)| server.recv(packet, resolver_addr()))
+            .collect();
+        assert!(results.iter().any(Result::is_err));
     }
 }
@@ -0,0 +1,258 @@
+//! TLS session storage for resumption and 0-RTT.
+//!
+//! A client that kept the session ticket from an earlier connection can resume
+//! it, skipping the certificate exchange and, with
+//! early data enabled, sending stream data in its first flight. Over DNS paths
+//! with second-long RTTs that saves most of the reconnect time.
+
+use crate::token::TokenStore;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Resumption state saved from a previous connection to a server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionData {
+    /// Serialized TLS session, including the server's session ticket; empty
+    /// until the server has issued one.
+    pub ticket: Vec<u8>,
+
+    /// Address validation token from a NEW_TOKEN frame.
+    ///
+    /// No longer read or written by the client: tokens belong to a server
+    /// address rather than a name, and live in the [`TokenStore`] given to
+    /// [`Client::with_token_store`](crate::Client::with_token_store).
+    #[deprecated(note = "address tokens are kept in a TokenStore, see Client::with_token_store")]
+    pub token: Option<Vec<u8>>,
+}
+
+/// Storage for session data, keyed by server name.
+///
+/// Implement this to keep sessions somewhere other than memory or a directory.
+pub trait SessionStore {
+    /// Session data saved for `server_name`, if any.
+    fn load(&mut self, server_name: &str) -> Option<SessionData>;
+
+    /// Replace the session data saved for `server_name`.
+    fn save(&mut self, server_name: &str, session: &SessionData);
+}
+
+/// Session store that lives as long as the process.
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    sessions: HashMap<String, SessionData>,
+}
+
+impl MemorySessionStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&mut self, server_name: &str) -> Option<SessionData> {
+        self.sessions.get(server_name).cloned()
+    }
+
+    fn save(&mut self, server_name: &str, session: &SessionData) {
+        self.sessions
+            .insert(server_name.to_string(), session.clone());
+    }
+}
+
+/// Session store that keeps one file per server name in a directory.
+///
+/// It also stores address validation tokens, one file per server name and
+/// address, so one directory holds everything a reconnect can reuse.
+///
+/// Session tickets let whoever holds them resume as this client, so the files
+/// are created readable by the owner only, token files included.
+///
+/// Clones share the directory, so one store can serve as both the session
+/// and the token store of a client.
+#[derive(Debug, Clone)]
+pub struct FileSessionStore {
+    dir: PathBuf,
+}
+
+impl FileSessionStore {
+    /// Store sessions in `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, crate::Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            crate::Error::Config(format!(
+                "Failed to create session directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, server_name: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.session", file_name_part(server_name)))
+    }
+
+    fn token_path(&self, server_name: &str, server_addr: SocketAddr) -> PathBuf {
+        self.dir.join(format!(
+            "{}@{}.token",
+            file_name_part(server_name),
+            file_name_part(&server_addr.to_string())
+        ))
+    }
+}
+
+/// `name` with every character unsafe in a file name replaced by `_`.
+fn file_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl SessionStore for FileSessionStore {
+    fn load(&mut self, server_name: &str) -> Option<SessionData> {
+        let path = self.path(server_name);
+        let bytes = std::fs::read(&path).ok()?;
+        let session = decode_session(&bytes);
+        if session.is_none() {
+            tracing::warn!("Ignoring malformed session file {}", path.display());
+        }
+        session
+    }
+
+    fn save(&mut self, server_name: &str, session: &SessionData) {
+        let path = self.path(server_name);
+        if let Err(e) = write_private(&path, &encode_session(session)) {
+            tracing::warn!("Failed to save session to {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl TokenStore for FileSessionStore {
+    fn load(&mut self, server_name: &str, server_addr: SocketAddr) -> Option<Vec<u8>> {
+        std::fs::read(self.token_path(server_name, server_addr))
+            .ok()
+            .filter(|token| !token.is_empty())
+    }
+
+    fn save(&mut self, server_name: &str, server_addr: SocketAddr, token: &[u8]) {
+        let path = self.token_path(server_name, server_addr);
+        if let Err(e) = write_private(&path, token) {
+            tracing::warn!("Failed to save token to {}: {}", path.display(), e);
+        }
+    }
+
+    fn remove(&mut self, server_name: &str, server_addr: SocketAddr) {
+        let path = self.token_path(server_name, server_addr);
+        if let Err(e) = std::fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove token {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(bytes)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, bytes)
+}
+
+/// File layout: u32 ticket length, ticket, u32 token length, token, all big
+/// endian. A zero token length means no token; the client leaves the token
+/// out, since tokens have files of their own.
+#[allow(deprecated)]
+fn encode_session(session: &SessionData) -> Vec<u8> {
+    let token = session.token.as_deref().unwrap_or_default();
+    let mut out = Vec::with_capacity(8 + session.ticket.len() + token.len());
+    out.extend_from_slice(&(session.ticket.len() as u32).to_be_bytes());
+    out.extend_from_slice(&session.ticket);
+    out.extend_from_slice(&(token.len() as u32).to_be_bytes());
+    out.extend_from_slice(token);
+    out
+}
+
+fn decode_session(bytes: &[u8]) -> Option<SessionData> {
+    fn take<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+        let value = bytes.get(4..4 + len)?;
+        *bytes = &bytes[4 + len..];
+        Some(value)
+    }
+
+    let mut rest = bytes;
+    let ticket = take(&mut rest)?.to_vec();
+    let token = take(&mut rest)?;
+    if !rest.is_empty() {
+        return None;
+    }
+    #[allow(deprecated)]
+    Some(SessionData {
+        ticket,
+        token: (!token.is_empty()).then(|| token.to_vec()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn session_files_keep_the_deprecated_token() {
+        let session = SessionData {
+            ticket: vec![1, 2, 3],
+            token: Some(vec![4, 5]),
+        };
+        assert_eq!(decode_session(&encode_session(&session)), Some(session));
+        let session = SessionData {
+            ticket: vec![1, 2, 3],
+            token: None,
+        };
+        assert_eq!(decode_session(&encode_session(&session)), Some(session));
+    }
+
+    #[test]
+    fn clones_share_the_directory() {
+        let dir = std::env::temp_dir().join(format!("slipstream-sessions-{}", std::process::id()));
+        let mut sessions = FileSessionStore::new(&dir).unwrap();
+        let mut tokens = sessions.clone();
+        let addr: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+        TokenStore::save(&mut tokens, "example.com", addr, b"token");
+        SessionStore::save(
+            &mut sessions,
+            "example.com",
+            &SessionData {
+                ticket: vec![7],
+                ..SessionData::default()
+            },
+        );
+        let mut reopened = FileSessionStore::new(&dir).unwrap();
+        let token = TokenStore::load(&mut reopened, "example.com", addr);
+        let session = SessionStore::load(&mut reopened, "example.com");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(token.as_deref(), Some(&b"token"[..]));
+        assert_eq!(session.map(|session| session.ticket), Some(vec![7]));
+    }
+}
@@ -0,0 +1,400 @@
+//! Client connections and servers that can be used from any thread.
+//!
+//! tquic keeps its endpoint behind `Rc`, so a [`ClientConnection`] and the
+//! streams it hands out stay on the thread that created them. A
+//! [`SharedConnection`] runs the connection on a thread of its own, with an
+//! [`AsyncConnection`] event loop, and passes messages to it. The handle is
+//! `Send + Sync` and cheap to clone, so a multi-threaded tokio runtime can
+//! use it from every worker; streams come back as in-memory pipes that the
+//! connection thread copies to and from the QUIC stream.
+//!
+//! [`SharedServer`] does the same for a [`Server`]. The server does no I/O of
+//! its own, so its thread only runs commands and tquic's timers; packets go
+//! in and out through the handle.
+
+use crate::async_client::AsyncConnection;
+use crate::client::{Client, ClientConnection};
+use crate::error::Error;
+use crate::server::Server;
+use std::net::SocketAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use tokio::io::DuplexStream;
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// Bytes a stream pipe buffers in each direction.
+pub const STREAM_PIPE_BUFFER: usize = 64 * 1024;
+
+type Command = Box<dyn FnOnce(&AsyncConnection) + Send>;
+
+/// Handle to a client connection running on a thread of its own.
+///
+/// The connection closes with error code 0 when the last clone is dropped.
+#[derive(Clone)]
+pub struct SharedConnection {
+    commands: mpsc::UnboundedSender<Command>,
+    closed: watch::Receiver<bool>,
+}
+
+impl SharedConnection {
+    /// Build a client with `make_client` on a new thread, connect it to
+    /// `server_addr` as [`Client::connect_async`] does and wait until streams
+    /// can be opened.
+    ///
+    /// The client is built on the connection thread because its session and
+    /// token stores need not be `Send`.
+    pub async fn connect(
+        make_client: impl FnOnce() -> Result<Client, Error> + Send + 'static,
+        server_addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<Self, Error> {
+        let server_name = server_name.to_string();
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (closed_tx, closed) = watch::channel(false);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        std::thread::Builder::new()
+            .name("slipstream-quic".to_string())
+            .spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_io()
+                    .enable_time()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(Error::Io(e)));
+                        return;
+                    }
+                };
+                let local = tokio::task::LocalSet::new();
+                local.block_on(
+                    &runtime,
+                    run(make_client, server_addr, server_name, ready_tx, command_rx),
+                );
+                let _ = closed_tx.send(true);
+            })?;
+        ready_rx.await.map_err(|_| thread_gone())??;
+        Ok(Self { commands, closed })
+    }
+
+    /// Run `f` on the connection thread and return its result, e.g. for path
+    /// management or statistics.
+    pub async fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut ClientConnection) -> T + Send + 'static,
+    ) -> Result<T, Error> {
+        self.call(move |conn| conn.with_connection(f)).await
+    }
+
+    /// Open a bidirectional stream.
+    ///
+    /// The returned pipe reads what the peer sends on the stream; shutting
+    /// down its write half finishes the stream.
+    pub async fn open_bi(&self) -> Result<DuplexStream, Error> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(Box::new(move |conn: &AsyncConnection| {
+            let _ = reply.send(conn.open_bi().map(|stream| {
+                let (ours, theirs) = tokio::io::duplex(STREAM_PIPE_BUFFER);
+                tokio::task::spawn_local(pump(stream, ours));
+                theirs
+            }));
+        }))?;
+        reply_rx.await.map_err(|_| thread_gone())?
+    }
+
+    /// Check if the handshake has completed.
+    pub async fn is_ready(&self) -> bool {
+        self.call(AsyncConnection::is_ready).await.unwrap_or(false)
+    }
+
+    /// Start closing the connection. Use [`Self::closed`] to wait until it is gone.
+    pub async fn close(&self, error_code: u64, reason: &str) -> Result<(), Error> {
+        let reason = reason.to_string();
+        self.call(move |conn| conn.close(error_code, &reason))
+            .await?
+    }
+
+    /// Check if the connection thread has stopped.
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    /// Wait until the connection has closed and its thread has stopped.
+    pub async fn closed(&self) {
+        let mut closed = self.closed.clone();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    async fn call<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&AsyncConnection) -> T + Send + 'static,
+    ) -> Result<T, Error> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(Box::new(move |conn: &AsyncConnection| {
+            let _ = reply.send(f(conn));
+        }))?;
+        reply_rx.await.map_err(|_| thread_gone())
+    }
+
+    fn send(&self, command: Command) -> Result<(), Error> {
+        self.commands.send(command).map_err(|_| thread_gone())
+    }
+}
+
+type ServerCommand = Box<dyn FnOnce(&mut Server) + Send>;
+
+/// Handle to a [`Server`] running on a thread of its own.
+///
+/// The thread fires tquic's timers between commands. It stops, dropping the
+/// server and its connections, when the last clone is dropped.
+#[derive(Clone)]
+pub struct SharedServer {
+    commands: std::sync::mpsc::Sender<ServerCommand>,
+}
+
+impl SharedServer {
+    /// Build a server with `make_server` on a new thread.
+    ///
+    /// The server is built on its thread because it is not `Send`.
+    pub async fn spawn(
+        make_server: impl FnOnce() -> Result<Server, Error> + Send + 'static,
+    ) -> Result<Self, Error> {
+        let (commands, command_rx) = std::sync::mpsc::channel();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        std::thread::Builder::new()
+            .name("slipstream-quic-server".to_string())
+            .spawn(move || {
+                let server = match make_server() {
+                    Ok(server) => server,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                if ready_tx.send(Ok(())).is_ok() {
+                    run_server(server, command_rx);
+                }
+            })?;
+        ready_rx.await.map_err(|_| thread_gone())??;
+        Ok(Self { commands })
+    }
+
+    /// Feed a packet received from `from` to the server.
+    /// See [`Server::recv`].
+    pub async fn recv(&self, packet: Vec<u8>, from: SocketAddr) -> Result<(), Error> {
+        self.with_server(move |server| server.recv(&packet, from))
+            .await?
+    }
+
+    /// Take the packets the server has to send. See [`Server::poll_send`].
+    pub async fn poll_send(&self) -> Result<Vec<(Vec<u8>, SocketAddr)>, Error> {
+        self.with_server(Server::poll_send).await
+    }
+
+    /// Run `f` on the server thread and return its result, e.g. to accept
+    /// connections or read and write their streams.
+    pub async fn with_server<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Server) -> T + Send + 'static,
+    ) -> Result<T, Error> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.commands
+            .send(Box::new(move |server: &mut Server| {
+                let _ = reply.send(f(server));
+            }))
+            .map_err(|_| thread_gone())?;
+        reply_rx.await.map_err(|_| thread_gone())
+    }
+}
+
+/// Body of the server thread: run commands as they come and tquic's timers
+/// when they expire, until every handle is dropped.
+fn run_server(mut server: Server, commands: std::sync::mpsc::Receiver<ServerCommand>) {
+    loop {
+        let command = match server.timeout() {
+            Some(timeout) => match commands.recv_timeout(timeout) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
+            },
+            None => match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            },
+        };
+        match command {
+            Some(command) => command(&mut server),
+            None => server.on_timeout(),
+        }
+    }
+}
+
+fn thread_gone() -> Error {
+    Error::ConnectionClosed {
+        reason: "connection thread stopped".to_string(),
+    }
+}
+
+/// Body of the connection thread: connect, then run commands until every
+/// handle is dropped or the connection closes.
+async fn run(
+    make_client: impl FnOnce() -> Result<Client, Error>,
+    server_addr: SocketAddr,
+    server_name: String,
+    ready: oneshot::Sender<Result<(), Error>>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+) {
+    let connected = match make_client() {
+        Ok(client) => client.connect_async(server_addr, &server_name).await,
+        Err(e) => Err(e),
+    };
+    let mut conn = match connected {
+        Ok(conn) => conn,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    if ready.send(Ok(())).is_err() {
+        return;
+    }
+    let Some(mut event_loop) = conn.take_event_loop() else {
+        return;
+    };
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => command(&conn),
+                None => break,
+            },
+            _ = &mut event_loop => return,
+        }
+    }
+    if !conn.is_closed() {
+        if let Err(e) = conn.close(0, "") {
+            tracing::debug!("Failed to close connection: {}", e);
+        }
+    }
+    let _ = event_loop.await;
+}
+
+/// Copy between a QUIC stream and the connection thread's end of its pipe.
+async fn pump(mut stream: crate::stream::BiStream, mut pipe: DuplexStream) {
+    if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut pipe).await {
+        tracing::debug!("Stream {} pipe stopped: {}", stream.stream_id(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{resolver_addr, server_addr, test_client, test_server, test_server_at};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UdpSocket;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn handles_are_send_and_sync() {
+        assert_send_sync::<SharedConnection>();
+        assert_send_sync::<SharedServer>();
+    }
+
+    /// Everything the server's connections have received on their streams.
+    async fn read_streams(server: &SharedServer) -> Vec<u8> {
+        server
+            .with_server(|server| {
+                let mut received = Vec::new();
+                let mut buf = [0u8; 64];
+                for conn_id in server.ready_connections() {
+                    for stream_id in server.streams(conn_id) {
+                        while let Ok((n, _)) = server.stream_read(conn_id, stream_id, &mut buf) {
+                            if n == 0 {
+                                break;
+                            }
+                            received.extend_from_slice(&buf[..n]);
+                        }
+                    }
+                }
+                received
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn shared_server_takes_packets_through_the_handle() {
+        let server = SharedServer::spawn(|| Ok(test_server())).await.unwrap();
+        let mut conn = test_client()
+            .connect(resolver_addr(), server_addr(), "localhost")
+            .unwrap();
+        for _ in 0..10 {
+            for (packet, _) in conn.poll_send() {
+                server.recv(packet, resolver_addr()).await.unwrap();
+            }
+            for (packet, _) in server.poll_send().await.unwrap() {
+                conn.recv(&packet, server_addr()).unwrap();
+            }
+        }
+        assert!(conn.is_ready());
+
+        let stream_id = conn.open_bi().unwrap();
+        conn.stream_write(stream_id, b"hello", false).unwrap();
+        for (packet, _) in conn.poll_send() {
+            server.recv(packet, resolver_addr()).await.unwrap();
+        }
+        assert_eq!(read_streams(&server).await, b"hello");
+    }
+
+    #[tokio::test]
+    async fn shared_connection_reaches_a_shared_server() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = SharedServer::spawn(move || Ok(test_server_at(addr)))
+            .await
+            .unwrap();
+        let relay = server.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            loop {
+                tokio::select! {
+                    received = socket.recv_from(&mut buf) => {
+                        let (n, from) = received.unwrap();
+                        let _ = relay.recv(buf[..n].to_vec(), from).await;
+                    }
+                    () = tokio::time::sleep(Duration::from_millis(5)) => {}
+                }
+                let Ok(packets) = relay.poll_send().await else {
+                    return;
+                };
+                for (packet, to) in packets {
+                    socket.send_to(&packet, to).await.unwrap();
+                }
+            }
+        });
+
+        let conn = SharedConnection::connect(|| Ok(test_client()), addr, "localhost")
+            .await
+            .unwrap();
+        assert!(conn.is_ready().await);
+        let mut pipe = conn.open_bi().await.unwrap();
+        pipe.write_all(b"hello").await.unwrap();
+        pipe.flush().await.unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            received.extend(read_streams(&server).await);
+            if received.len() >= 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(received, b"hello");
+
+        conn.close(0, "").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), conn.closed())
+            .await
+            .unwrap();
+        assert!(conn.is_closed());
+    }
+}
@@ -0,0 +1,142 @@
+//! Per-connection traffic and loss counters.
+//!
+//! tquic never resends a lost packet as such: the frames it carried are queued
+//! again and go out in new packets, and no count of those is kept. Lost
+//! packets are therefore the retransmission measure here; every ack-eliciting
+//! one costs at least one more packet.
+
+use crate::multipath::PathId;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Totals of one connection, with a breakdown per path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// QUIC packets sent.
+    pub sent_packets: u64,
+    /// Bytes sent.
+    pub sent_bytes: u64,
+    /// QUIC packets received.
+    pub recv_packets: u64,
+    /// Bytes received.
+    pub recv_bytes: u64,
+    /// Packets declared lost; their frames were retransmitted if they needed to be.
+    pub lost_packets: u64,
+    /// Bytes declared lost.
+    pub lost_bytes: u64,
+    /// Paths tquic still reports, in no particular order.
+    pub paths: Vec<PathStats>,
+}
+
+/// Counters of one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStats {
+    /// Path identifier, as in [`PathInfo`](crate::multipath::PathInfo).
+    pub path_id: PathId,
+    /// Remote address of the path, usually a resolver.
+    pub peer_addr: SocketAddr,
+    /// QUIC packets sent on this path.
+    pub sent_packets: u64,
+    /// Bytes sent on this path.
+    pub sent_bytes: u64,
+    /// QUIC packets received on this path.
+    pub recv_packets: u64,
+    /// Bytes received on this path.
+    pub recv_bytes: u64,
+    /// Packets sent on this path and declared lost.
+    pub lost_packets: u64,
+    /// Bytes sent on this path and declared lost.
+    pub lost_bytes: u64,
+    /// Congestion events: runs of losses the controller reacted to once.
+    pub loss_events: u64,
+    /// Smoothed RTT in microseconds.
+    pub rtt_us: u64,
+}
+
+impl ConnectionStats {
+    pub(crate) fn from_tquic(
+        stats: &tquic::connection::ConnectionStats,
+        paths: Vec<PathStats>,
+    ) -> Self {
+        Self {
+            sent_packets: stats.sent_count,
+            sent_bytes: stats.sent_bytes,
+            recv_packets: stats.recv_count,
+            recv_bytes: stats.recv_bytes,
+            lost_packets: stats.lost_count,
+            lost_bytes: stats.lost_bytes,
+            paths,
+        }
+    }
+
+    /// Fraction of the packets sent that were declared lost.
+    pub fn loss_rate(&self) -> f64 {
+        loss_rate(self.lost_packets, self.sent_packets)
+    }
+}
+
+impl PathStats {
+    pub(crate) fn from_tquic(
+        path_id: PathId,
+        peer_addr: SocketAddr,
+        stats: &tquic::PathStats,
+    ) -> Self {
+        Self {
+            path_id,
+            peer_addr,
+            sent_packets: stats.sent_count,
+            sent_bytes: stats.sent_bytes,
+            recv_packets: stats.recv_count,
+            recv_bytes: stats.recv_bytes,
+            lost_packets: stats.lost_count,
+            lost_bytes: stats.lost_bytes,
+            loss_events: stats.loss_event_count,
+            rtt_us: stats.srtt,
+        }
+    }
+
+    /// Fraction of the packets sent on this path that were declared lost.
+    pub fn loss_rate(&self) -> f64 {
+        loss_rate(self.lost_packets, self.sent_packets)
+    }
+}
+
+fn loss_rate(lost: u64, sent: u64) -> f64 {
+    if sent == 0 {
+        return 0.0;
+    }
+    lost as f64 / sent as f64
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent={} ({} bytes) recv={} ({} bytes) lost={} ({} bytes, {:.1}%)",
+            self.sent_packets,
+            self.sent_bytes,
+            self.recv_packets,
+            self.recv_bytes,
+            self.lost_packets,
+            self.lost_bytes,
+            self.loss_rate() * 100.0
+        )
+    }
+}
+
+impl fmt::Display for PathStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "path={} peer={} sent={} recv={} lost={} ({:.1}%) loss_events={} rtt={}us",
+            self.path_id,
+            self.peer_addr,
+            self.sent_packets,
+            self.recv_packets,
+            self.lost_packets,
+            self.loss_rate() * 100.0,
+            self.loss_events,
+            self.rtt_us
+        )
+    }
+}
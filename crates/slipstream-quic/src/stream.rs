@@ -1,79 +1,196 @@
 //! Stream handling for QUIC connections.
 
-#![allow(dead_code)]
+use crate::client::StreamHandle;
+use crate::error::{Error, ErrorKind};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-use crate::error::Error;
+/// Least urgent stream urgency (RFC 9218); 0 is the most urgent.
+pub const MAX_URGENCY: u8 = 7;
+
+/// Urgency of tunnel streams opened without an explicit priority.
+///
+/// This is the RFC 9218 default, which leaves room above it for interactive
+/// streams. Control streams use 0 so capability messages are never stuck behind data.
+pub const DEFAULT_URGENCY: u8 = 3;
+
+/// Reject urgencies outside the RFC 9218 range.
+pub(crate) fn check_urgency(urgency: u8) -> Result<(), Error> {
+    if urgency > MAX_URGENCY {
+        return Err(Error::Stream {
+            kind: ErrorKind::InvalidOperation,
+            message: format!("stream urgency {} exceeds {}", urgency, MAX_URGENCY),
+        });
+    }
+    Ok(())
+}
+
+/// Writes a stream handed to tquic that the peer has not acknowledged.
+///
+/// tquic keeps no count of acknowledged stream data it would expose, but it
+/// holds each written buffer until every byte of it has been acknowledged.
+/// A write is done once our copy of its buffer is the only one left.
+#[derive(Debug, Default)]
+pub(crate) struct SendLedger {
+    writes: VecDeque<(Bytes, usize)>,
+}
+
+impl SendLedger {
+    /// Note that tquic accepted the first `written` bytes of `data`, and
+    /// forget the writes acknowledged since, so the ledger only ever holds
+    /// what is in flight.
+    pub(crate) fn record(&mut self, data: Bytes, written: usize) {
+        self.prune();
+        if written > 0 {
+            self.writes.push_back((data, written));
+        }
+    }
+
+    /// Bytes written and not yet acknowledged.
+    ///
+    /// Counted per write: a write the peer acknowledged in part still counts
+    /// in full.
+    pub(crate) fn queued_bytes(&mut self) -> usize {
+        self.prune();
+        self.writes.iter().map(|(_, written)| written).sum()
+    }
+
+    fn prune(&mut self) {
+        self.writes.retain(|(data, _)| !data.is_unique());
+    }
+}
 
 /// A send stream for writing data.
 pub struct SendStream {
-    stream_id: u64,
-    // tquic connection and stream state will be added here
+    handle: StreamHandle,
 }
 
 impl SendStream {
     /// Create a new send stream.
-    pub(crate) fn new(stream_id: u64) -> Self {
-        Self { stream_id }
+    pub(crate) fn new(handle: StreamHandle) -> Self {
+        Self { handle }
     }
 
     /// Get the stream ID.
     pub fn stream_id(&self) -> u64 {
-        self.stream_id
+        self.handle.stream_id()
     }
 
-    /// Write data to the stream.
+    /// Write data to the stream, waiting until flow control accepts some of it.
+    ///
+    /// Returns how many bytes were queued, which may be fewer than `data.len()`.
     pub async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
-        // TODO: Implement with tquic
-        Ok(data.len())
+        poll_fn(|cx| self.handle.poll_write(cx, data, false)).await
     }
 
     /// Write all data to the stream.
-    pub async fn write_all(&mut self, _data: &[u8]) -> Result<(), Error> {
-        // TODO: Implement with tquic
+    pub async fn write_all(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let written = self.write(data).await?;
+            data = &data[written..];
+        }
         Ok(())
     }
 
     /// Finish the stream (send FIN).
     pub async fn finish(&mut self) -> Result<(), Error> {
-        // TODO: Implement with tquic
+        poll_fn(|cx| self.handle.poll_write(cx, &[], true)).await?;
         Ok(())
     }
 
     /// Reset the stream with an error code.
-    pub fn reset(&mut self, _error_code: u64) -> Result<(), Error> {
-        // TODO: Implement with tquic
-        Ok(())
+    pub fn reset(&mut self, error_code: u64) -> Result<(), Error> {
+        self.handle.shutdown(tquic::Shutdown::Write, error_code)
+    }
+}
+
+impl AsyncWrite for SendStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.handle
+            .poll_write(cx, buf, false)
+            .map_err(io::Error::other)
+    }
+
+    /// Written data is already queued on the connection.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Finish the stream.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.handle
+            .poll_write(cx, &[], true)
+            .map_ok(|_| ())
+            .map_err(io::Error::other)
     }
 }
 
 /// A receive stream for reading data.
 pub struct RecvStream {
-    stream_id: u64,
-    // tquic connection and stream state will be added here
+    handle: StreamHandle,
+    finished: bool,
 }
 
 impl RecvStream {
     /// Create a new receive stream.
-    pub(crate) fn new(stream_id: u64) -> Self {
-        Self { stream_id }
+    pub(crate) fn new(handle: StreamHandle) -> Self {
+        Self {
+            handle,
+            finished: false,
+        }
     }
 
     /// Get the stream ID.
     pub fn stream_id(&self) -> u64 {
-        self.stream_id
+        self.handle.stream_id()
     }
 
-    /// Read data from the stream.
-    pub async fn read(&mut self, _buf: &mut [u8]) -> Result<Option<usize>, Error> {
-        // TODO: Implement with tquic
-        // Returns None on FIN, Some(n) for data, Err on error
-        Ok(Some(0))
+    /// Read data from the stream, waiting until some arrives.
+    ///
+    /// Returns `None` once the peer has finished the stream and all data was read.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let (read, fin) = poll_fn(|cx| self.handle.poll_read(cx, buf)).await?;
+        self.finished = fin;
+        if read == 0 && fin {
+            return Ok(None);
+        }
+        Ok(Some(read))
     }
 
     /// Stop reading from the stream with an error code.
-    pub fn stop(&mut self, _error_code: u64) -> Result<(), Error> {
-        // TODO: Implement with tquic
-        Ok(())
+    pub fn stop(&mut self, error_code: u64) -> Result<(), Error> {
+        self.handle.shutdown(tquic::Shutdown::Read, error_code)
+    }
+}
+
+impl AsyncRead for RecvStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.finished {
+            return Poll::Ready(Ok(()));
+        }
+        let (read, fin) = ready!(self
+            .handle
+            .poll_read(cx, buf.initialize_unfilled())
+            .map_err(io::Error::other))?;
+        buf.advance(read);
+        self.finished = fin;
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -87,15 +204,235 @@ pub struct BiStream {
 
 impl BiStream {
     /// Create a new bidirectional stream.
-    pub(crate) fn new(stream_id: u64) -> Self {
+    pub(crate) fn new(handle: StreamHandle) -> Self {
         Self {
-            send: SendStream::new(stream_id),
-            recv: RecvStream::new(stream_id),
+            send: SendStream::new(handle.clone()),
+            recv: RecvStream::new(handle),
         }
     }
 
     /// Get the stream ID.
     pub fn stream_id(&self) -> u64 {
-        self.send.stream_id
+        self.send.stream_id()
+    }
+}
+
+impl AsyncRead for BiStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for BiStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// A driver's state for each stream, found by the label the stream carries.
+///
+/// [`insert`](Self::insert) returns the label to give the stream with
+/// `set_stream_label`; stream events and `stream_label` hand it back. Labels
+/// of removed entries are reused, so lookups also take the stream's key, e.g.
+/// its ID, and miss when a newer stream holds the label.
+pub struct StreamTable<K, V> {
+    slots: Vec<Option<(K, V)>>,
+    /// Labels of empty slots.
+    free: Vec<u64>,
+}
+
+impl<K: PartialEq, V> StreamTable<K, V> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Add state for the stream `key` and return its label.
+    pub fn insert(&mut self, key: K, value: V) -> u64 {
+        match self.free.pop() {
+            Some(label) => {
+                self.slots[label as usize] = Some((key, value));
+                label
+            }
+            None => {
+                self.slots.push(Some((key, value)));
+                self.slots.len() as u64 - 1
+            }
+        }
+    }
+
+    /// State of the stream `key`, found by its `label`.
+    pub fn get(&self, label: Option<u64>, key: &K) -> Option<&V> {
+        match self.slots.get(usize::try_from(label?).ok()?)? {
+            Some((entry_key, value)) if entry_key == key => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, label: Option<u64>, key: &K) -> Option<&mut V> {
+        match self.slots.get_mut(usize::try_from(label?).ok()?)? {
+            Some((entry_key, value)) if entry_key == key => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Remove the state of the stream `key`, freeing its label.
+    pub fn remove(&mut self, label: Option<u64>, key: &K) -> Option<V> {
+        let label = label?;
+        let slot = self.slots.get_mut(usize::try_from(label).ok()?)?;
+        if slot.as_ref().is_none_or(|(entry_key, _)| entry_key != key) {
+            return None;
+        }
+        self.free.push(label);
+        slot.take().map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every entry with its label and key.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &K, &V)> {
+        self.slots.iter().enumerate().filter_map(|(label, slot)| {
+            let (key, value) = slot.as_ref()?;
+            Some((label as u64, key, value))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u64, &K, &mut V)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(label, slot)| {
+                let (key, value) = slot.as_mut()?;
+                Some((label as u64, &*key, value))
+            })
+    }
+
+    /// Remove every entry.
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.free.clear();
+        self.slots.drain(..).flatten()
+    }
+}
+
+impl<K: PartialEq, V> Default for StreamTable<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SendLedger, StreamTable};
+    use crate::testing::{connected, exchange};
+    use bytes::Bytes;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use tokio::io::{AsyncRead, ReadBuf};
+
+    #[test]
+    fn ledger_forgets_acknowledged_writes() {
+        let mut ledger = SendLedger::default();
+        // The clones stand in for the copies tquic holds until acknowledged
+        let first = Bytes::from(b"hello".to_vec());
+        let second = Bytes::from(b"world!".to_vec());
+        let held_first = first.clone();
+        let held_second = second.clone();
+        ledger.record(first, 5);
+        ledger.record(second, 4);
+        ledger.record(Bytes::from(b"ignored".to_vec()), 0);
+        assert_eq!(ledger.queued_bytes(), 9);
+
+        drop(held_first);
+        assert_eq!(ledger.queued_bytes(), 4);
+
+        // Recording prunes too, without waiting for a count to be asked for
+        drop(held_second);
+        let third = Bytes::from(b"again".to_vec());
+        let held_third = third.clone();
+        ledger.record(third, 5);
+        assert_eq!(ledger.writes.len(), 1);
+        assert_eq!(ledger.queued_bytes(), 5);
+        drop(held_third);
+        assert_eq!(ledger.queued_bytes(), 0);
+        assert!(ledger.writes.is_empty());
+    }
+
+    #[test]
+    fn table_lookups_need_the_label_and_the_key() {
+        let mut table = StreamTable::new();
+        let first = table.insert(0u64, "first");
+        let second = table.insert(4u64, "second");
+        assert_ne!(first, second);
+        assert_eq!(table.get(Some(first), &0), Some(&"first"));
+        assert_eq!(table.get(Some(first), &4), None);
+        assert_eq!(table.get(None, &0), None);
+        assert_eq!(table.get(Some(99), &0), None);
+
+        // A stale label misses once a newer stream reuses it
+        assert_eq!(table.remove(Some(first), &0), Some("first"));
+        assert_eq!(table.remove(Some(first), &0), None);
+        let third = table.insert(8u64, "third");
+        assert_eq!(third, first);
+        assert_eq!(table.get(Some(first), &0), None);
+        assert_eq!(table.remove(Some(first), &0), None);
+        assert_eq!(table.get_mut(Some(third), &8), Some(&mut "third"));
+
+        let mut entries: Vec<_> = table.iter().map(|(_, key, value)| (*key, *value)).collect();
+        entries.sort();
+        assert_eq!(entries, [(4, "second"), (8, "third")]);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.drain().count(), 2);
+        assert!(table.is_empty());
+        assert_eq!(table.insert(12u64, "fourth"), 0);
+    }
+
+    #[test]
+    fn reads_wait_for_data_instead_of_ending() {
+        let (mut server, mut conn) = connected();
+        let conn_id = server.ready_connections()[0];
+        let mut stream = conn.open_bi_stream().unwrap();
+        let stream_id = stream.stream_id();
+        conn.stream_write(stream_id, b"x", false).unwrap();
+        exchange(&mut server, &mut conn);
+
+        // A read with nothing buffered waits; an empty Ready would mean EOF
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut data = [0; 16];
+        let mut buf = ReadBuf::new(&mut data);
+        let mut recv = Pin::new(&mut stream.recv);
+        assert!(recv.as_mut().poll_read(&mut cx, &mut buf).is_pending());
+
+        server
+            .stream_write(conn_id, stream_id, b"hi", false)
+            .unwrap();
+        exchange(&mut server, &mut conn);
+        let read = recv.as_mut().poll_read(&mut cx, &mut buf);
+        assert!(matches!(read, Poll::Ready(Ok(()))));
+        assert_eq!(buf.filled(), b"hi");
+        assert!(recv.as_mut().poll_read(&mut cx, &mut buf).is_pending());
     }
 }
@@ -0,0 +1,61 @@
+//! A server and client wired back to back, for tests.
+
+use crate::{Client, ClientConnection, Config, Server};
+use std::net::SocketAddr;
+
+pub(crate) const ALPN: &[u8] = b"test";
+
+pub(crate) fn server_addr() -> SocketAddr {
+    "[2001:db8::53]:53".parse().unwrap()
+}
+
+/// The address the server sees queries come from.
+pub(crate) fn resolver_addr() -> SocketAddr {
+    "[2001:db8::1]:5300".parse().unwrap()
+}
+
+pub(crate) fn test_server() -> Server {
+    test_server_at(server_addr())
+}
+
+/// A server bound to `addr`, for tests that put it behind a real socket.
+pub(crate) fn test_server_at(addr: SocketAddr) -> Server {
+    let certs = concat!(env!("CARGO_MANIFEST_DIR"), "/../../fixtures/certs");
+    let config = Config::new().with_alpn(&[ALPN]).with_tls(
+        &format!("{}/cert.pem", certs),
+        &format!("{}/key.pem", certs),
+    );
+    Server::new(addr, config).unwrap()
+}
+
+pub(crate) fn test_client() -> Client {
+    Client::new(
+        Config::new()
+            .with_alpn(&[ALPN])
+            .with_verify_cert_chain(false),
+    )
+    .unwrap()
+}
+
+/// Move packets between `server` and `conn` until both go quiet.
+pub(crate) fn exchange(server: &mut Server, conn: &mut ClientConnection) {
+    for _ in 0..10 {
+        for (packet, _) in conn.poll_send() {
+            server.recv(&packet, resolver_addr()).unwrap();
+        }
+        for (packet, _) in server.poll_send() {
+            conn.recv(&packet, server_addr()).unwrap();
+        }
+    }
+}
+
+/// A server and a client connection that finished the handshake.
+pub(crate) fn connected() -> (Server, ClientConnection) {
+    let mut server = test_server();
+    let mut conn = test_client()
+        .connect(resolver_addr(), server_addr(), "localhost")
+        .unwrap();
+    exchange(&mut server, &mut conn);
+    assert!(conn.is_ready());
+    (server, conn)
+}
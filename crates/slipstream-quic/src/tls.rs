@@ -0,0 +1,337 @@
+//! TLS contexts for the checks tquic's TLS config cannot express.
+//!
+//! tquic only switches peer verification on or off. Pinning a server key
+//! and requiring client certificates need BoringSSL settings it does not
+//! expose, so those configs get an `SSL_CTX` of their own, set up here and
+//! handed to tquic with [`tquic::TlsConfig::new_with_ssl_ctx`]. tquic links
+//! BoringSSL statically, so its symbols are declared directly.
+
+use std::ffi::{c_int, c_long, c_void};
+use std::ptr;
+use std::sync::OnceLock;
+
+const SSL_VERIFY_PEER: c_int = 0x01;
+const SSL_VERIFY_FAIL_IF_NO_PEER_CERT: c_int = 0x02;
+
+/// `ssl_verify_result_t` values.
+const SSL_VERIFY_OK: c_int = 0;
+const SSL_VERIFY_INVALID: c_int = 1;
+
+const SSL_AD_BAD_CERTIFICATE: u8 = 42;
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_CONTEXT_0: u8 = 0xa0;
+
+type ExFree = extern "C" fn(*mut c_void, *mut c_void, *mut c_void, c_int, c_long, *mut c_void);
+type CustomVerify = extern "C" fn(*mut c_void, *mut u8) -> c_int;
+
+extern "C" {
+    fn TLS_method() -> *const c_void;
+    fn SSL_CTX_new(method: *const c_void) -> *mut c_void;
+    fn SSL_CTX_free(ctx: *mut c_void);
+    fn SSL_CTX_set_verify(ctx: *mut c_void, mode: c_int, callback: *const c_void);
+    fn SSL_CTX_set_custom_verify(ctx: *mut c_void, mode: c_int, callback: CustomVerify);
+    fn SSL_CTX_get_ex_new_index(
+        argl: c_long,
+        argp: *mut c_void,
+        unused: *mut c_void,
+        dup: *const c_void,
+        free: ExFree,
+    ) -> c_int;
+    fn SSL_CTX_set_ex_data(ctx: *mut c_void, index: c_int, data: *mut c_void) -> c_int;
+    fn SSL_CTX_get_ex_data(ctx: *const c_void, index: c_int) -> *mut c_void;
+    fn SSL_get_SSL_CTX(ssl: *const c_void) -> *mut c_void;
+    fn SSL_get0_peer_certificates(ssl: *const c_void) -> *const c_void;
+    fn OPENSSL_sk_num(stack: *const c_void) -> usize;
+    fn OPENSSL_sk_value(stack: *const c_void, index: usize) -> *mut c_void;
+    fn CRYPTO_BUFFER_data(buf: *const c_void) -> *const u8;
+    fn CRYPTO_BUFFER_len(buf: *const c_void) -> usize;
+    fn SHA256(data: *const u8, len: usize, out: *mut u8) -> *mut u8;
+}
+
+/// An `SSL_CTX` with settings tquic does not expose.
+///
+/// tquic only borrows the context, so it has to outlive every endpoint built
+/// on it; connections hold references of their own.
+pub(crate) struct TlsContext {
+    ctx: *mut c_void,
+}
+
+impl TlsContext {
+    fn new() -> Result<Self, crate::Error> {
+        let ctx = unsafe { SSL_CTX_new(TLS_method()) };
+        if ctx.is_null() {
+            return Err(crate::Error::Config(
+                "Failed to create TLS context".to_string(),
+            ));
+        }
+        Ok(Self { ctx })
+    }
+
+    /// Accept only servers whose leaf certificate carries the
+    /// SubjectPublicKeyInfo with SHA-256 `pin`, whoever signed it.
+    pub(crate) fn pinned_spki(pin: [u8; 32]) -> Result<Self, crate::Error> {
+        let context = Self::new()?;
+        let pin = Box::into_raw(Box::new(pin));
+        // The context owns the pin from here and frees it with free_pin
+        if unsafe { SSL_CTX_set_ex_data(context.ctx, pin_index(), pin.cast()) } != 1 {
+            drop(unsafe { Box::from_raw(pin) });
+            return Err(crate::Error::Config(
+                "Failed to store the pinned SPKI hash".to_string(),
+            ));
+        }
+        unsafe { SSL_CTX_set_custom_verify(context.ctx, SSL_VERIFY_PEER, verify_pinned_spki) };
+        Ok(context)
+    }
+
+    /// Refuse clients without a certificate that chains to the CA set on
+    /// the TLS config.
+    pub(crate) fn client_auth() -> Result<Self, crate::Error> {
+        let context = Self::new()?;
+        unsafe {
+            SSL_CTX_set_verify(
+                context.ctx,
+                SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT,
+                ptr::null(),
+            )
+        };
+        Ok(context)
+    }
+
+    /// A tquic TLS config on this context.
+    pub(crate) fn tls_config(&self) -> tquic::TlsConfig {
+        tquic::TlsConfig::new_with_ssl_ctx(self.ctx.cast())
+    }
+}
+
+impl Drop for TlsContext {
+    fn drop(&mut self) {
+        unsafe { SSL_CTX_free(self.ctx) }
+    }
+}
+
+/// The `SSL_CTX` ex_data slot holding a boxed SPKI pin.
+fn pin_index() -> c_int {
+    static INDEX: OnceLock<c_int> = OnceLock::new();
+    *INDEX.get_or_init(|| unsafe {
+        SSL_CTX_get_ex_new_index(0, ptr::null_mut(), ptr::null_mut(), ptr::null(), free_pin)
+    })
+}
+
+extern "C" fn free_pin(
+    _parent: *mut c_void,
+    pin: *mut c_void,
+    _ad: *mut c_void,
+    _index: c_int,
+    _argl: c_long,
+    _argp: *mut c_void,
+) {
+    if !pin.is_null() {
+        drop(unsafe { Box::from_raw(pin.cast::<[u8; 32]>()) });
+    }
+}
+
+extern "C" fn verify_pinned_spki(ssl: *mut c_void, out_alert: *mut u8) -> c_int {
+    if unsafe { peer_matches_pin(ssl) } {
+        return SSL_VERIFY_OK;
+    }
+    tracing::warn!("Server public key does not match the pinned SPKI hash");
+    unsafe { *out_alert = SSL_AD_BAD_CERTIFICATE };
+    SSL_VERIFY_INVALID
+}
+
+/// Check the leaf certificate of `ssl`'s peer against its context's pin.
+unsafe fn peer_matches_pin(ssl: *const c_void) -> bool {
+    let pin = SSL_CTX_get_ex_data(SSL_get_SSL_CTX(ssl), pin_index()).cast::<[u8; 32]>();
+    let Some(pin) = pin.as_ref() else {
+        return false;
+    };
+    let certs = SSL_get0_peer_certificates(ssl);
+    if certs.is_null() || OPENSSL_sk_num(certs) == 0 {
+        return false;
+    }
+    let leaf = OPENSSL_sk_value(certs, 0);
+    let der = std::slice::from_raw_parts(CRYPTO_BUFFER_data(leaf), CRYPTO_BUFFER_len(leaf));
+    let Some(spki) = subject_public_key_info(der) else {
+        return false;
+    };
+    let mut digest = [0u8; 32];
+    SHA256(spki.as_ptr(), spki.len(), digest.as_mut_ptr());
+    digest == *pin
+}
+
+/// The DER SubjectPublicKeyInfo of a DER X.509 certificate.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = split_der(cert)?;
+    let (tbs, _) = split_der(certificate.contents)?;
+    let mut rest = tbs.contents;
+    if rest.first() == Some(&DER_CONTEXT_0) {
+        // The explicit version
+        rest = split_der(rest)?.1;
+    }
+    // Serial number, signature algorithm, issuer, validity and subject
+    for _ in 0..5 {
+        rest = split_der(rest)?.1;
+    }
+    let (spki, _) = split_der(rest)?;
+    (spki.tag == DER_SEQUENCE).then_some(spki.bytes)
+}
+
+/// One DER element.
+#[derive(Debug, PartialEq)]
+struct DerElement<'a> {
+    tag: u8,
+    /// The whole element, header included.
+    bytes: &'a [u8],
+    contents: &'a [u8],
+}
+
+/// Split the first DER element off `der`, returning it and what follows.
+fn split_der(der: &[u8]) -> Option<(DerElement<'_>, &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let len = rest[..octets]
+            .iter()
+            .fold(0usize, |len, &byte| len << 8 | usize::from(byte));
+        (len, &rest[octets..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header = der.len() - rest.len();
+    let element = DerElement {
+        tag,
+        bytes: &der[..header + len],
+        contents: &rest[..len],
+    };
+    Some((element, &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_der, DerElement};
+    use crate::testing::{resolver_addr, server_addr, test_server, ALPN};
+    use crate::{Client, Config, Server};
+
+    fn certs() -> &'static str {
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../../fixtures/certs")
+    }
+
+    /// SHA-256 of the SubjectPublicKeyInfo of `fixtures/certs/cert.pem`.
+    const FIXTURE_SPKI: &str = "eb6226ef7aa341d77a78ccf190da1c47edd29ca35e9293d9b9ffb0914bdd1eaa";
+
+    fn fixture_pin() -> [u8; 32] {
+        let mut pin = [0u8; 32];
+        for (i, byte) in pin.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&FIXTURE_SPKI[2 * i..2 * i + 2], 16).unwrap();
+        }
+        pin
+    }
+
+    /// Whether a client built from `config` and `server` both finish a
+    /// handshake; failed ones surface as errors from either side's `recv`.
+    fn handshake(server: &mut Server, config: Config) -> bool {
+        let client = Client::new(config.with_alpn(&[ALPN])).unwrap();
+        let mut conn = client
+            .connect(resolver_addr(), server_addr(), "localhost")
+            .unwrap();
+        for _ in 0..10 {
+            for (packet, _) in conn.poll_send() {
+                if server.recv(&packet, resolver_addr()).is_err() {
+                    return false;
+                }
+            }
+            for (packet, _) in server.poll_send() {
+                if conn.recv(&packet, server_addr()).is_err() {
+                    return false;
+                }
+            }
+        }
+        conn.is_ready() && !server.ready_connections().is_empty()
+    }
+
+    #[test]
+    fn split_der_reads_short_and_long_lengths() {
+        let short = [0x30, 0x02, 0x05, 0x00, 0xff];
+        let (element, rest) = split_der(&short).unwrap();
+        assert_eq!(
+            element,
+            DerElement {
+                tag: 0x30,
+                bytes: &short[..4],
+                contents: &short[2..4],
+            }
+        );
+        assert_eq!(rest, &short[4..]);
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend([7u8; 0x80]);
+        let (element, rest) = split_der(&long).unwrap();
+        assert_eq!(
+            (element.tag, element.bytes.len(), element.contents.len()),
+            (0x04, 0x83, 0x80)
+        );
+        assert!(rest.is_empty());
+        assert_eq!(split_der(&long[..10]), None);
+        assert_eq!(split_der(&[0x30, 0x80]), None);
+    }
+
+    #[test]
+    fn pinned_spki_accepts_only_the_pinned_key() {
+        let mut server = test_server();
+        assert!(handshake(
+            &mut server,
+            Config::new().with_pinned_spki(fixture_pin())
+        ));
+
+        let mut wrong = fixture_pin();
+        wrong[0] ^= 1;
+        let mut server = test_server();
+        assert!(!handshake(
+            &mut server,
+            Config::new().with_pinned_spki(wrong)
+        ));
+    }
+
+    #[test]
+    fn required_client_auth_refuses_clients_without_a_certificate() {
+        let certs = certs();
+        let server_config = || {
+            Config::new()
+                .with_alpn(&[ALPN])
+                .with_tls(
+                    &format!("{}/cert.pem", certs),
+                    &format!("{}/key.pem", certs),
+                )
+                .with_require_client_auth(&format!("{}/alt_cert.pem", certs))
+        };
+
+        let mut server = Server::new(server_addr(), server_config()).unwrap();
+        assert!(handshake(
+            &mut server,
+            Config::new().with_client_cert(
+                &format!("{}/alt_cert.pem", certs),
+                &format!("{}/alt_key.pem", certs),
+            )
+        ));
+
+        let mut server = Server::new(server_addr(), server_config()).unwrap();
+        assert!(!handshake(&mut server, Config::new()));
+
+        // A certificate the CA did not issue is refused too
+        let mut server = Server::new(server_addr(), server_config()).unwrap();
+        assert!(!handshake(
+            &mut server,
+            Config::new().with_client_cert(
+                &format!("{}/cert.pem", certs),
+                &format!("{}/key.pem", certs),
+            )
+        ));
+    }
+}
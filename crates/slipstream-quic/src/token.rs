@@ -0,0 +1,63 @@
+//! Address validation tokens for reconnects.
+//!
+//! A server hands out a token in a NEW_TOKEN frame once the handshake is done.
+//! A client that presents it in the Initial of its next connection counts as
+//! validated at once: the server need not send a Retry and is not held to
+//! three times the bytes it received while the handshake runs, which over a
+//! DNS path is several round trips of polling.
+//!
+//! tquic binds a token to the client address the server saw, which for a
+//! tunnel is the resolver's egress address, so tokens are kept per server
+//! address. A token the server rejects costs a Retry, and Retries fail through
+//! resolvers that query from changing addresses; a connection that presented
+//! a token and never completed its handshake therefore drops it.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Storage for address validation tokens, keyed by server name and address.
+///
+/// Implement this to keep tokens somewhere other than memory or a directory.
+pub trait TokenStore {
+    /// The latest token saved for the server, if any.
+    fn load(&mut self, server_name: &str, server_addr: SocketAddr) -> Option<Vec<u8>>;
+
+    /// Replace the token saved for the server.
+    fn save(&mut self, server_name: &str, server_addr: SocketAddr, token: &[u8]);
+
+    /// Forget the token saved for the server.
+    fn remove(&mut self, server_name: &str, server_addr: SocketAddr);
+}
+
+/// Token store that lives as long as the process.
+///
+/// Every [`Client`](crate::Client) starts with one, so reconnects within a
+/// process reuse tokens without any setup.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: HashMap<(String, SocketAddr), Vec<u8>>,
+}
+
+impl MemoryTokenStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(&mut self, server_name: &str, server_addr: SocketAddr) -> Option<Vec<u8>> {
+        self.tokens
+            .get(&(server_name.to_string(), server_addr))
+            .cloned()
+    }
+
+    fn save(&mut self, server_name: &str, server_addr: SocketAddr, token: &[u8]) {
+        self.tokens
+            .insert((server_name.to_string(), server_addr), token.to_vec());
+    }
+
+    fn remove(&mut self, server_name: &str, server_addr: SocketAddr) {
+        self.tokens.remove(&(server_name.to_string(), server_addr));
+    }
+}
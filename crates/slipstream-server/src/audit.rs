@@ -0,0 +1,112 @@
+//! Per-stream audit trail.
+//!
+//! When enabled, the server appends one JSON line per stream lifetime so
+//! operators can review what was tunneled through it.
+
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Why a stream's lifetime ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CloseReason {
+    /// The client finished its side of the QUIC stream and the target's
+    /// FIN was relayed back.
+    ClientFin,
+    /// The TCP connection to the target could not be established.
+    TargetConnectFailed,
+    /// Writing to the target failed.
+    TargetWriteFailed,
+    /// The server shut down with the stream still open.
+    Shutdown,
+    /// The owning connection hit its lifetime or byte limit.
+    ConnectionLimit,
+}
+
+impl CloseReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::ClientFin => "client_fin",
+            CloseReason::TargetConnectFailed => "target_connect_failed",
+            CloseReason::TargetWriteFailed => "target_write_failed",
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::ConnectionLimit => "connection_limit",
+        }
+    }
+}
+
+/// Summary of one stream, written when it closes.
+pub(crate) struct StreamRecord {
+    pub(crate) conn_id: u64,
+    pub(crate) stream_id: u64,
+    pub(crate) target: SocketAddr,
+    pub(crate) bytes_to_target: u64,
+    pub(crate) bytes_from_target: u64,
+    pub(crate) opened_at: Instant,
+    pub(crate) reason: CloseReason,
+}
+
+pub(crate) struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    /// Open (or create) the audit log for appending.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub(crate) fn record(&mut self, record: &StreamRecord) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let line = json!({
+            "ts": ts,
+            "event": "stream",
+            "conn_id": record.conn_id,
+            "stream_id": record.stream_id,
+            "target": record.target.to_string(),
+            "bytes_to_target": record.bytes_to_target,
+            "bytes_from_target": record.bytes_from_target,
+            "duration_ms": record.opened_at.elapsed().as_millis() as u64,
+            "close_reason": record.reason.as_str(),
+        });
+        if let Err(err) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            tracing::warn!("Failed to write audit record: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditLog, CloseReason, StreamRecord};
+    use slipstream_core::test_util::TempLog;
+    use std::time::Instant;
+
+    #[test]
+    fn writes_one_json_line_per_stream() {
+        let log = TempLog::new("audit");
+        let mut audit = AuditLog::open(log.path()).expect("open audit log");
+        for stream_id in [0u64, 4] {
+            audit.record(&StreamRecord {
+                conn_id: 1,
+                stream_id,
+                target: "127.0.0.1:5201".parse().expect("target"),
+                bytes_to_target: 42,
+                bytes_from_target: 7,
+                opened_at: Instant::now(),
+                reason: CloseReason::ClientFin,
+            });
+        }
+        let lines = log.json_lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["stream_id"], 4);
+        assert_eq!(lines[0]["bytes_to_target"], 42);
+        assert_eq!(lines[0]["close_reason"], "client_fin");
+    }
+}
@@ -0,0 +1,109 @@
+//! Server side of the heartbeat exchange.
+//!
+//! Clients that agreed on heartbeats write them on their second unidirectional
+//! stream. The server answers each one on a stream of its own, opened on first
+//! use, and reports when a client's heartbeats or its acknowledgements of ours
+//! stop arriving.
+
+use slipstream_core::heartbeat::{HeartbeatEvent, HeartbeatMonitor, HeartbeatReader};
+use slipstream_quic::ServerConnection;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+#[derive(Default)]
+struct ClientHeartbeats {
+    reader: HeartbeatReader,
+    /// Created from the interval carried by the first heartbeat.
+    monitor: Option<HeartbeatMonitor>,
+    reply_stream: Option<u64>,
+}
+
+#[derive(Default)]
+pub(crate) struct Heartbeats {
+    conns: HashMap<u64, ClientHeartbeats>,
+}
+
+impl Heartbeats {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget connections that are no longer ready.
+    pub(crate) fn retain(&mut self, ready_conns: &[u64]) {
+        self.conns
+            .retain(|conn_id, _| ready_conns.contains(conn_id));
+    }
+
+    /// Drain a client heartbeat stream and answer every complete heartbeat.
+    ///
+    /// `tx_bytes` and `rx_bytes` are the tunnel data sent and received on the
+    /// connection so far.
+    pub(crate) fn on_control_stream(
+        &mut self,
+        conn: &ServerConnection,
+        stream_id: u64,
+        tx_bytes: u64,
+        rx_bytes: u64,
+        now: Instant,
+    ) {
+        let conn_id = conn.conn_id();
+        let client = self.conns.entry(conn_id).or_default();
+        let mut read_buf = [0u8; 512];
+        let mut received = Vec::new();
+        loop {
+            match conn.stream_read(stream_id, &mut read_buf) {
+                Ok((n, fin)) => {
+                    received.extend(client.reader.push(&read_buf[..n]));
+                    if fin || n == 0 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        "conn {} stream {}: heartbeat stream_read: {}",
+                        conn_id, stream_id, e
+                    );
+                    break;
+                }
+            }
+        }
+
+        for heartbeat in received {
+            let monitor = client.monitor.get_or_insert_with(|| {
+                HeartbeatMonitor::new(
+                    Duration::from_millis(u64::from(heartbeat.interval_ms.max(1))),
+                    now,
+                )
+            });
+            monitor.on_heartbeat(heartbeat, now);
+            let reply = monitor.next(tx_bytes, rx_bytes).encode();
+            let sent = match client.reply_stream {
+                Some(reply_id) => conn.stream_write(reply_id, &reply, false),
+                None => conn.open_uni().and_then(|reply_id| {
+                    client.reply_stream = Some(reply_id);
+                    conn.stream_write(reply_id, &reply, false)
+                }),
+            };
+            if let Err(e) = sent {
+                warn!("conn {}: failed to answer heartbeat: {}", conn_id, e);
+            }
+        }
+    }
+
+    /// Log connections whose heartbeats stalled or recovered since the last check.
+    ///
+    /// `tx_bytes` gives the tunnel data sent on a connection so far.
+    pub(crate) fn check(&mut self, now: Instant, tx_bytes: impl Fn(u64) -> u64) {
+        for (conn_id, client) in self.conns.iter_mut() {
+            let Some(monitor) = client.monitor.as_mut() else {
+                continue;
+            };
+            match monitor.check(tx_bytes(*conn_id), now) {
+                Some(HeartbeatEvent::Stalled(stall)) => warn!("conn {}: {}", conn_id, stall),
+                Some(HeartbeatEvent::Recovered) => info!("conn {}: heartbeats recovered", conn_id),
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Transports that carry DNS queries to the server and responses back.
+//!
+//! The server loop only sees raw DNS messages and the peer they came from, so
+//! a new listener type (TCP, DoH) plugs in by implementing [`DnsIngress`]
+//! without touching the QUIC or stream handling.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener as TokioTcpListener, TcpStream, UdpSocket as TokioUdpSocket};
+use tokio::sync::mpsc;
+
+/// TCP connections served at once; further ones are closed right away.
+const TCP_CONNECTIONS_MAX: usize = 256;
+/// How long a TCP connection may go without a query before it is closed.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Transport a DNS message travels over.
+///
+/// A UDP peer may share its address with a TCP connection, so responses are
+/// routed by the transport their query came in on, not by address alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transport {
+    Udp,
+    /// A stream, where messages may be any size and never need truncating.
+    Tcp,
+}
+
+/// Source of DNS queries and sink for the responses to them.
+pub(crate) trait DnsIngress {
+    /// Wait for the next query, returning its length, the peer that sent it
+    /// and the transport it came over.
+    async fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)>;
+
+    /// Take an already queued query without waiting; `WouldBlock` when none is.
+    fn try_recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)>;
+
+    /// Deliver a message to `peer` over `transport`.
+    async fn send(&self, message: &[u8], peer: SocketAddr, transport: Transport) -> io::Result<()>;
+}
+
+/// Bind a dual-stack socket of `ty` on `[::]:port`.
+///
+/// Linux accepts IPv4 on IPv6 sockets by default, but Windows and some BSDs
+/// default to v6-only, so the flag is cleared explicitly.
+fn bind_dual_stack(port: u16, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0));
+    let socket = Socket::new(Domain::IPV6, ty, Some(protocol))?;
+    socket.set_only_v6(false)?;
+    socket.set_nonblocking(true)?;
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+/// Plain DNS over a dual-stack UDP socket.
+pub(crate) struct UdpIngress {
+    socket: TokioUdpSocket,
+}
+
+impl UdpIngress {
+    /// Bind a dual-stack UDP socket on `[::]`.
+    pub(crate) fn bind(port: u16) -> io::Result<Self> {
+        let socket = bind_dual_stack(port, Type::DGRAM, Protocol::UDP)?;
+        Ok(Self {
+            socket: TokioUdpSocket::from_std(socket.into())?,
+        })
+    }
+}
+
+impl DnsIngress for UdpIngress {
+    async fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        let (len, peer) = self.socket.recv_from(buf).await?;
+        Ok((len, peer, Transport::Udp))
+    }
+
+    fn try_recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        let (len, peer) = self.socket.try_recv_from(buf)?;
+        Ok((len, peer, Transport::Udp))
+    }
+
+    async fn send(
+        &self,
+        message: &[u8],
+        peer: SocketAddr,
+        _transport: Transport,
+    ) -> io::Result<()> {
+        self.socket.send_to(message, peer).await.map(|_| ())
+    }
+}
+
+/// Write handles of the open TCP connections, by peer.
+type TcpPeers = Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>>;
+
+/// DNS over TCP (RFC 7766), for resolvers retrying a truncated answer.
+///
+/// Connections are served on tasks of their own, which read length-prefixed
+/// queries and write back whatever [`DnsIngress::send`] addresses to their
+/// peer, in any order and without waiting for earlier queries.
+pub(crate) struct TcpIngress {
+    queries: mpsc::UnboundedReceiver<Message>,
+    peers: TcpPeers,
+}
+
+impl TcpIngress {
+    /// Listen on a dual-stack TCP socket on `[::]`. Must be called within the
+    /// runtime that serves the connections.
+    pub(crate) fn bind(port: u16) -> io::Result<Self> {
+        let socket = bind_dual_stack(port, Type::STREAM, Protocol::TCP)?;
+        socket.listen(1024)?;
+        let listener = TokioTcpListener::from_std(socket.into())?;
+        Ok(Self::spawn(listener))
+    }
+
+    fn spawn(listener: TokioTcpListener) -> Self {
+        let (query_tx, queries) = mpsc::unbounded_channel();
+        let peers = TcpPeers::default();
+        tokio::spawn(accept_connections(listener, query_tx, peers.clone()));
+        Self { queries, peers }
+    }
+}
+
+async fn accept_connections(
+    listener: TokioTcpListener,
+    queries: mpsc::UnboundedSender<Message>,
+    peers: TcpPeers,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::debug!("TCP accept failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if queries.is_closed() {
+            return;
+        }
+        let (response_tx, responses) = mpsc::unbounded_channel();
+        {
+            let Ok(mut peers) = peers.lock() else {
+                return;
+            };
+            if peers.len() >= TCP_CONNECTIONS_MAX {
+                continue;
+            }
+            peers.insert(peer, response_tx);
+        }
+        let queries = queries.clone();
+        let peers = peers.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, peer, queries, responses).await {
+                tracing::debug!("TCP connection from {} closed: {}", peer, e);
+            }
+            if let Ok(mut peers) = peers.lock() {
+                peers.remove(&peer);
+            }
+        });
+    }
+}
+
+/// Pass the queries `stream` carries on and write back the responses to it,
+/// until either side is done.
+async fn serve_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    queries: mpsc::UnboundedSender<Message>,
+    mut responses: mpsc::UnboundedReceiver<Vec<u8>>,
+) -> io::Result<()> {
+    let _ = stream.set_nodelay(true);
+    let (mut reader, mut writer) = stream.into_split();
+    let read = async {
+        loop {
+            let len = match tokio::time::timeout(TCP_IDLE_TIMEOUT, reader.read_u16()).await {
+                Ok(Ok(len)) => len as usize,
+                Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Ok(()),
+            };
+            let mut query = vec![0u8; len];
+            tokio::time::timeout(TCP_IDLE_TIMEOUT, reader.read_exact(&mut query))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "query cut short"))??;
+            if queries.send((query, peer, Transport::Tcp)).is_err() {
+                return Ok(());
+            }
+        }
+    };
+    let write = async {
+        while let Some(response) = responses.recv().await {
+            let Ok(len) = u16::try_from(response.len()) else {
+                continue;
+            };
+            let mut framed = Vec::with_capacity(2 + response.len());
+            framed.extend_from_slice(&len.to_be_bytes());
+            framed.extend_from_slice(&response);
+            writer.write_all(&framed).await?;
+        }
+        Ok(())
+    };
+    tokio::select! {
+        result = read => result,
+        result = write => result,
+    }
+}
+
+impl DnsIngress for TcpIngress {
+    async fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        match self.queries.recv().await {
+            Some(query) => deliver(buf, query),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn try_recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        match self.queries.try_recv() {
+            Ok(query) => deliver(buf, query),
+            Err(_) => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    /// Queue `message` on `peer`'s connection. A connection that closed
+    /// before its answer was ready loses it, as a datagram would be lost.
+    async fn send(
+        &self,
+        message: &[u8],
+        peer: SocketAddr,
+        _transport: Transport,
+    ) -> io::Result<()> {
+        let sender = self
+            .peers
+            .lock()
+            .ok()
+            .and_then(|peers| peers.get(&peer).cloned());
+        if let Some(sender) = sender {
+            let _ = sender.send(message.to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// UDP for queries, and TCP on the same port for retries of truncated ones.
+///
+/// Responses go back over the transport their query came in on.
+pub(crate) struct UdpTcpIngress {
+    udp: UdpIngress,
+    tcp: TcpIngress,
+}
+
+impl UdpTcpIngress {
+    pub(crate) fn bind(port: u16) -> io::Result<Self> {
+        Ok(Self {
+            udp: UdpIngress::bind(port)?,
+            tcp: TcpIngress::bind(port)?,
+        })
+    }
+}
+
+impl DnsIngress for UdpTcpIngress {
+    async fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        tokio::select! {
+            recv = self.udp.recv(buf) => recv,
+            Some(query) = self.tcp.queries.recv() => deliver(buf, query),
+        }
+    }
+
+    fn try_recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        match self.udp.try_recv(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.tcp.try_recv(buf),
+            recv => recv,
+        }
+    }
+
+    async fn send(&self, message: &[u8], peer: SocketAddr, transport: Transport) -> io::Result<()> {
+        match transport {
+            Transport::Udp => self.udp.send(message, peer, transport).await,
+            Transport::Tcp => self.tcp.send(message, peer, transport).await,
+        }
+    }
+}
+
+/// A DNS message, the peer it came from or goes to and its transport.
+type Message = (Vec<u8>, SocketAddr, Transport);
+
+/// Copy `query` into `buf`, cut to fit.
+fn deliver(
+    buf: &mut [u8],
+    (query, peer, transport): Message,
+) -> io::Result<(usize, SocketAddr, Transport)> {
+    let len = query.len().min(buf.len());
+    buf[..len].copy_from_slice(&query[..len]);
+    Ok((len, peer, transport))
+}
+
+/// In-memory ingress for driving the server from tests.
+#[cfg(test)]
+pub(crate) struct ChannelIngress {
+    queries: tokio::sync::mpsc::UnboundedReceiver<Message>,
+    responses: tokio::sync::mpsc::UnboundedSender<Message>,
+}
+
+#[cfg(test)]
+impl ChannelIngress {
+    /// Returns the ingress, a sender for queries and a receiver for responses.
+    pub(crate) fn new() -> (
+        Self,
+        tokio::sync::mpsc::UnboundedSender<Message>,
+        tokio::sync::mpsc::UnboundedReceiver<Message>,
+    ) {
+        let (query_tx, queries) = tokio::sync::mpsc::unbounded_channel();
+        let (responses, response_rx) = tokio::sync::mpsc::unbounded_channel();
+        (Self { queries, responses }, query_tx, response_rx)
+    }
+}
+
+#[cfg(test)]
+impl DnsIngress for ChannelIngress {
+    async fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        match self.queries.recv().await {
+            Some(query) => deliver(buf, query),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn try_recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Transport)> {
+        match self.queries.try_recv() {
+            Ok(query) => deliver(buf, query),
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+    }
+
+    async fn send(&self, message: &[u8], peer: SocketAddr, transport: Transport) -> io::Result<()> {
+        self.responses
+            .send((message.to_vec(), peer, transport))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelIngress, DnsIngress, TcpIngress, Transport};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn channel_ingress_round_trip() {
+        let (mut ingress, queries, mut responses) = ChannelIngress::new();
+        let peer = "[::1]:5353".parse().unwrap();
+        let mut buf = [0u8; 16];
+
+        assert_eq!(
+            ingress.try_recv(&mut buf).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        queries.send((vec![1, 2, 3], peer, Transport::Udp)).unwrap();
+        assert_eq!(
+            ingress.recv(&mut buf).await.unwrap(),
+            (3, peer, Transport::Udp)
+        );
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+
+        ingress.send(&[4, 5], peer, Transport::Tcp).await.unwrap();
+        assert_eq!(
+            responses.recv().await,
+            Some((vec![4, 5], peer, Transport::Tcp))
+        );
+    }
+
+    #[tokio::test]
+    async fn tcp_ingress_frames_queries_and_routes_responses() {
+        let listener = tokio::net::TcpListener::bind("[::1]:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut ingress = TcpIngress::spawn(listener);
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 16];
+
+        client.write_all(b"\x00\x03abc\x00\x02de").await.unwrap();
+        let (len, peer, transport) = ingress.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"abc");
+        assert_eq!(peer, client.local_addr().unwrap());
+        assert_eq!(transport, Transport::Tcp);
+        let (len, _, _) = ingress.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"de");
+
+        ingress.send(b"xyz", peer, Transport::Tcp).await.unwrap();
+        let mut response = [0u8; 5];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"\x00\x03xyz");
+
+        drop(client);
+        while ingress.peers.lock().unwrap().contains_key(&peer) {
+            tokio::task::yield_now().await;
+        }
+        assert!(ingress.send(b"xyz", peer, Transport::Tcp).await.is_ok());
+    }
+}
@@ -0,0 +1,237 @@
+//! Slipstream DNS tunnel server.
+//!
+//! The `slipstream-server` binary and the combined `slipstream server` subcommand
+//! both parse arguments with [`command`] and hand the matches to [`run`].
+
+mod audit;
+mod heartbeat;
+mod ingress;
+mod limits;
+mod negotiation;
+mod pool;
+mod proxy_protocol;
+mod response_cache;
+mod sandbox;
+mod server;
+mod shaping;
+
+use clap::builder::PossibleValuesParser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use server::{run_server, TquicServerConfig};
+use shaping::RateLimits;
+use slipstream_core::{normalize_domain, parse_host_port, AddressKind, HostPort};
+use slipstream_dns::{FRAGMENT_BUFFER_MAX_BYTES, FRAGMENT_BUFFER_MAX_ENTRIES};
+use slipstream_quic::CONGESTION_CONTROL_NAMES;
+use std::path::PathBuf;
+use tokio::runtime::Builder;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slipstream-server",
+    about = "slipstream-server - A high-performance covert channel over DNS (server)"
+)]
+struct Args {
+    #[arg(long = "dns-listen-port", short = 'l', default_value_t = 53)]
+    dns_listen_port: u16,
+    #[arg(
+        long = "target-address",
+        short = 'a',
+        default_value = "127.0.0.1:5201",
+        value_parser = parse_target_address
+    )]
+    target_address: HostPort,
+    #[arg(long = "cert", short = 'c', value_name = "PATH")]
+    cert: String,
+    #[arg(long = "key", short = 'k', value_name = "PATH")]
+    key: String,
+    #[arg(long = "domain", short = 'd', value_parser = parse_domain, required = true)]
+    domains: Vec<String>,
+    #[arg(long = "max-connections", short = 'm', default_value_t = 256)]
+    max_connections: u32,
+    #[arg(
+        long = "congestion-control",
+        value_parser = PossibleValuesParser::new(CONGESTION_CONTROL_NAMES.iter().copied())
+    )]
+    congestion_control: Option<String>,
+    #[arg(long = "no-multipath")]
+    no_multipath: bool,
+    #[arg(long = "target-pool-size", default_value_t = 0)]
+    target_pool_size: usize,
+    #[arg(long = "target-pool-idle-timeout", default_value_t = 30)]
+    target_pool_idle_timeout: u64,
+    #[arg(long = "proxy-protocol")]
+    proxy_protocol: bool,
+    #[arg(long = "backlog-hints")]
+    backlog_hints: bool,
+    #[arg(long = "no-name-compression")]
+    no_name_compression: bool,
+    #[arg(
+        long = "txt-record-size",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u16).range(64..)
+    )]
+    txt_record_size: Option<u16>,
+    #[arg(long = "pad-responses")]
+    pad_responses: bool,
+    #[arg(long = "dns-tcp")]
+    dns_tcp: bool,
+    #[arg(long = "null-domain", value_name = "DOMAIN", value_parser = parse_domain)]
+    null_domains: Vec<String>,
+    #[arg(long = "address-records")]
+    address_records: bool,
+    #[arg(long = "response-cache-size", default_value_t = 32)]
+    response_cache_size: usize,
+    #[arg(
+        long = "fragment-buffer-entries",
+        default_value_t = FRAGMENT_BUFFER_MAX_ENTRIES
+    )]
+    fragment_buffer_entries: usize,
+    #[arg(
+        long = "fragment-buffer-bytes",
+        value_name = "BYTES",
+        default_value_t = FRAGMENT_BUFFER_MAX_BYTES
+    )]
+    fragment_buffer_bytes: usize,
+    #[arg(long = "audit-log", value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+    #[arg(long = "max-connection-lifetime", default_value_t = 0)]
+    max_connection_lifetime: u64,
+    #[arg(long = "max-connection-bytes", default_value_t = 0)]
+    max_connection_bytes: u64,
+    #[arg(
+        long = "stream-rate-limit",
+        value_name = "BYTES_PER_SEC",
+        default_value_t = 0
+    )]
+    stream_rate_limit: u64,
+    #[arg(
+        long = "connection-rate-limit",
+        value_name = "BYTES_PER_SEC",
+        default_value_t = 0
+    )]
+    connection_rate_limit: u64,
+    #[arg(
+        long = "domain-rate-limit",
+        value_name = "DOMAIN=STREAM,CONNECTION",
+        value_parser = parse_domain_rate_limit
+    )]
+    domain_rate_limits: Vec<(String, RateLimits)>,
+    #[arg(long = "control-secret-file", value_name = "PATH")]
+    control_secret_file: Option<PathBuf>,
+    #[arg(long = "qlog-dir", value_name = "DIR")]
+    qlog_dir: Option<PathBuf>,
+    #[arg(long = "sandbox")]
+    sandbox: bool,
+    #[arg(long = "zero-rtt")]
+    zero_rtt: bool,
+    #[arg(long = "retry")]
+    retry: bool,
+    #[arg(long = "anti-amplification-factor", default_value_t = 3)]
+    anti_amplification_factor: usize,
+    #[arg(
+        long = "handshake-timeout",
+        value_name = "SECONDS",
+        default_value_t = 30
+    )]
+    handshake_timeout: u64,
+    #[arg(long = "session-ticket-key-file", value_name = "PATH")]
+    session_ticket_key_file: Option<PathBuf>,
+    #[arg(long = "debug-streams")]
+    debug_streams: bool,
+    #[arg(long = "debug-commands")]
+    debug_commands: bool,
+}
+
+/// Command-line definition for the server.
+pub fn command() -> clap::Command {
+    Args::command()
+}
+
+/// Run the server with parsed arguments and return the process exit code.
+pub fn run(matches: &ArgMatches) -> i32 {
+    let args = Args::from_arg_matches(matches).unwrap_or_else(|err| err.exit());
+
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+
+    let config = TquicServerConfig {
+        dns_listen_port: args.dns_listen_port,
+        target_address: args.target_address,
+        cert: args.cert,
+        key: args.key,
+        domains: args.domains,
+        max_connections: args.max_connections,
+        congestion_control: args.congestion_control,
+        multipath: !args.no_multipath,
+        target_pool_size: args.target_pool_size,
+        target_pool_idle_timeout: args.target_pool_idle_timeout,
+        proxy_protocol: args.proxy_protocol,
+        backlog_hints: args.backlog_hints,
+        name_compression: !args.no_name_compression,
+        txt_record_size: args.txt_record_size.map(usize::from),
+        pad_responses: args.pad_responses,
+        dns_tcp: args.dns_tcp,
+        null_domains: args.null_domains,
+        address_records: args.address_records,
+        response_cache_size: args.response_cache_size,
+        fragment_buffer_entries: args.fragment_buffer_entries,
+        fragment_buffer_bytes: args.fragment_buffer_bytes,
+        audit_log: args.audit_log,
+        max_connection_lifetime: args.max_connection_lifetime,
+        max_connection_bytes: args.max_connection_bytes,
+        rate_limits: RateLimits {
+            stream_bps: args.stream_rate_limit,
+            connection_bps: args.connection_rate_limit,
+        },
+        domain_rate_limits: args.domain_rate_limits,
+        control_secret_file: args.control_secret_file,
+        qlog_dir: args.qlog_dir,
+        sandbox: args.sandbox,
+        zero_rtt: args.zero_rtt,
+        retry: args.retry,
+        anti_amplification_factor: args.anti_amplification_factor,
+        handshake_timeout: args.handshake_timeout,
+        session_ticket_key_file: args.session_ticket_key_file,
+        debug_streams: args.debug_streams,
+        debug_commands: args.debug_commands,
+    };
+    match runtime.block_on(run_server(&config)) {
+        Ok(code) => code,
+        Err(err) => {
+            tracing::error!("Server error: {}", err);
+            err.exit_code()
+        }
+    }
+}
+
+fn parse_domain(input: &str) -> Result<String, String> {
+    normalize_domain(input).map_err(|err| err.to_string())
+}
+
+fn parse_domain_rate_limit(input: &str) -> Result<(String, RateLimits), String> {
+    let (domain, rates) = input
+        .split_once('=')
+        .ok_or_else(|| "Expected DOMAIN=STREAM,CONNECTION".to_string())?;
+    let (stream, connection) = rates
+        .split_once(',')
+        .ok_or_else(|| "Expected DOMAIN=STREAM,CONNECTION".to_string())?;
+    let parse_rate = |rate: &str| {
+        rate.trim()
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid rate '{}'", rate))
+    };
+    Ok((
+        parse_domain(domain)?,
+        RateLimits {
+            stream_bps: parse_rate(stream)?,
+            connection_bps: parse_rate(connection)?,
+        },
+    ))
+}
+
+fn parse_target_address(input: &str) -> Result<HostPort, String> {
+    parse_host_port(input, 5201, AddressKind::Target).map_err(|err| err.to_string())
+}
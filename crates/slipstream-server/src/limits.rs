@@ -0,0 +1,121 @@
+//! Per-connection policy limits.
+//!
+//! Operators can cap how long a tunnel may live and how many bytes it may carry;
+//! connections past either limit are closed gracefully with a CONNECTION_CLOSE.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ConnectionLimits {
+    pub(crate) max_lifetime: Option<Duration>,
+    pub(crate) max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LimitExceeded {
+    Lifetime,
+    Bytes,
+}
+
+impl LimitExceeded {
+    pub(crate) fn reason(self) -> &'static str {
+        match self {
+            LimitExceeded::Lifetime => "connection lifetime limit reached",
+            LimitExceeded::Bytes => "connection byte quota reached",
+        }
+    }
+}
+
+/// Usage accounted against a connection's limits.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ConnectionUsage {
+    /// When the lifetime limit closes the connection, fixed when it was
+    /// accepted.
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) bytes: u64,
+    /// Target data relayed to the client; not limited, only reported in
+    /// heartbeats.
+    pub(crate) bytes_to_client: u64,
+}
+
+impl ConnectionLimits {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_lifetime.is_some() || self.max_bytes.is_some()
+    }
+
+    /// Start accounting for a connection accepted at `accepted_at`.
+    pub(crate) fn usage(&self, accepted_at: Instant) -> ConnectionUsage {
+        ConnectionUsage {
+            deadline: self
+                .max_lifetime
+                .and_then(|max_lifetime| accepted_at.checked_add(max_lifetime)),
+            bytes: 0,
+            bytes_to_client: 0,
+        }
+    }
+
+    pub(crate) fn check(&self, usage: &ConnectionUsage, now: Instant) -> Option<LimitExceeded> {
+        if usage.deadline.is_some_and(|deadline| now >= deadline) {
+            return Some(LimitExceeded::Lifetime);
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if usage.bytes >= max_bytes {
+                return Some(LimitExceeded::Bytes);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionLimits, LimitExceeded};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn unlimited_by_default() {
+        let limits = ConnectionLimits::default();
+        let start = Instant::now();
+        let mut usage = limits.usage(start);
+        usage.bytes = u64::MAX;
+        assert!(!limits.is_enabled());
+        assert_eq!(
+            limits.check(&usage, start + Duration::from_secs(86_400)),
+            None
+        );
+    }
+
+    #[test]
+    fn enforces_lifetime_and_bytes() {
+        let limits = ConnectionLimits {
+            max_lifetime: Some(Duration::from_secs(60)),
+            max_bytes: Some(1_000),
+        };
+        let start = Instant::now();
+        let mut usage = limits.usage(start);
+        assert_eq!(limits.check(&usage, start + Duration::from_secs(59)), None);
+        assert_eq!(
+            limits.check(&usage, start + Duration::from_secs(60)),
+            Some(LimitExceeded::Lifetime)
+        );
+        usage.bytes = 1_000;
+        assert_eq!(limits.check(&usage, start), Some(LimitExceeded::Bytes));
+    }
+
+    #[test]
+    fn deadlines_are_fixed_when_accepted() {
+        let limits = ConnectionLimits {
+            max_lifetime: Some(Duration::from_secs(60)),
+            max_bytes: None,
+        };
+        let start = Instant::now();
+        let usage = limits.usage(start);
+        assert_eq!(usage.deadline, Some(start + Duration::from_secs(60)));
+        // However late the first check comes, the clock ran from acceptance
+        assert_eq!(
+            limits.check(&usage, start + Duration::from_secs(3_600)),
+            Some(LimitExceeded::Lifetime)
+        );
+        assert_eq!(ConnectionLimits::default().usage(start).deadline, None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Server side of the capability exchange.
+//!
+//! Clients that negotiated the slipstream ALPN send their capabilities on a
+//! unidirectional stream; the server answers with its own on a stream of its
+//! own and remembers the agreed feature set. Legacy clients never open one and
+//! keep the baseline behaviour.
+
+use slipstream_core::capabilities::{Capabilities, CapabilityError, MAX_CAPABILITIES_LEN};
+use slipstream_core::SLIPSTREAM_PROTOCOL_VERSION_ERROR;
+use slipstream_quic::ServerConnection;
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+#[derive(Default)]
+pub(crate) struct Negotiation {
+    pending: HashMap<u64, Vec<u8>>,
+    agreed: HashMap<u64, Capabilities>,
+}
+
+impl Negotiation {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget connections that are no longer ready.
+    pub(crate) fn retain(&mut self, ready_conns: &[u64]) {
+        self.pending
+            .retain(|conn_id, _| ready_conns.contains(conn_id));
+        self.agreed
+            .retain(|conn_id, _| ready_conns.contains(conn_id));
+    }
+
+    /// Feature set agreed with the client, once the exchange has completed.
+    pub(crate) fn agreed(&self, conn_id: u64) -> Option<&Capabilities> {
+        self.agreed.get(&conn_id)
+    }
+
+    /// Drain a client control stream and answer once the message is complete.
+    pub(crate) fn on_control_stream(&mut self, conn: &ServerConnection, stream_id: u64) {
+        let conn_id = conn.conn_id();
+        if self.agreed.contains_key(&conn_id) {
+            return;
+        }
+        let mut read_buf = [0u8; 512];
+        let message = self.pending.entry(conn_id).or_default();
+        let finished = loop {
+            match conn.stream_read(stream_id, &mut read_buf) {
+                Ok((n, fin)) => {
+                    message.extend_from_slice(&read_buf[..n]);
+                    if fin {
+                        break true;
+                    }
+                    if n == 0 {
+                        break false;
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        "conn {} stream {}: control stream_read: {}",
+                        conn_id, stream_id, e
+                    );
+                    break false;
+                }
+            }
+            if message.len() > MAX_CAPABILITIES_LEN {
+                break true;
+            }
+        };
+        if !finished {
+            return;
+        }
+        let Some(message) = self.pending.remove(&conn_id) else {
+            return;
+        };
+
+        match answer(&message) {
+            Ok(agreed) => {
+                info!("conn {}: negotiated {}", conn_id, agreed);
+                let reply = Capabilities::local().encode();
+                let sent = conn
+                    .open_uni()
+                    .and_then(|reply_id| conn.stream_write(reply_id, &reply, true));
+                if let Err(e) = sent {
+                    warn!("conn {}: failed to send capabilities: {}", conn_id, e);
+                }
+                self.agreed.insert(conn_id, agreed);
+            }
+            Err(e) => {
+                warn!("conn {}: capability exchange failed: {}", conn_id, e);
+                if let Err(e) = conn.close(SLIPSTREAM_PROTOCOL_VERSION_ERROR, &e.to_string()) {
+                    warn!("conn {}: close failed: {}", conn_id, e);
+                }
+            }
+        }
+    }
+}
+
+fn answer(message: &[u8]) -> Result<Capabilities, CapabilityError> {
+    if message.len() > MAX_CAPABILITIES_LEN {
+        return Err(CapabilityError::new("capability message too large"));
+    }
+    let peer = Capabilities::decode(message)?;
+    Capabilities::local().negotiate(&peer)
+}
@@ -0,0 +1,170 @@
+//! Warm pool of idle TCP connections to the target.
+//!
+//! Opening a stream normally pays a fresh TCP handshake to the target before the
+//! first byte can be forwarded. When pooling is enabled, the server keeps a few
+//! pre-connected, never-used sockets per destination and hands one out on stream
+//! open, then refills the pool in the background.
+//!
+//! Connections are never returned to the pool after a stream used them: the
+//! target protocol state (and our half-close on stream FIN) makes reuse unsafe.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::debug;
+
+struct IdleConn {
+    stream: TcpStream,
+    connected_at: Instant,
+}
+
+pub(crate) struct TargetPool {
+    max_idle: usize,
+    idle_timeout: Duration,
+    idle: HashMap<SocketAddr, VecDeque<IdleConn>>,
+    connecting: HashMap<SocketAddr, usize>,
+    ready_tx: mpsc::UnboundedSender<(SocketAddr, Option<TcpStream>)>,
+    ready_rx: mpsc::UnboundedReceiver<(SocketAddr, Option<TcpStream>)>,
+}
+
+impl TargetPool {
+    /// Create a pool keeping up to `max_idle` warm connections per destination.
+    /// A `max_idle` of zero disables pooling.
+    pub(crate) fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        let (ready_tx, ready_rx) = mpsc::unbounded_channel();
+        Self {
+            max_idle,
+            idle_timeout,
+            idle: HashMap::new(),
+            connecting: HashMap::new(),
+            ready_tx,
+            ready_rx,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_idle > 0
+    }
+
+    /// Number of warm connections currently held for `addr`.
+    pub(crate) fn idle_count(&self, addr: SocketAddr) -> usize {
+        self.idle.get(&addr).map(VecDeque::len).unwrap_or(0)
+    }
+
+    /// Take a warm connection to `addr`, skipping expired or peer-closed ones.
+    pub(crate) async fn checkout(&mut self, addr: SocketAddr) -> Option<TcpStream> {
+        if !self.is_enabled() {
+            return None;
+        }
+        self.collect_ready();
+        let idle_timeout = self.idle_timeout;
+        let queue = self.idle.get_mut(&addr)?;
+        while let Some(conn) = queue.pop_front() {
+            if conn.connected_at.elapsed() >= idle_timeout {
+                debug!("target pool: dropping expired connection to {}", addr);
+                continue;
+            }
+            if !is_open(&conn.stream).await {
+                debug!("target pool: dropping closed connection to {}", addr);
+                continue;
+            }
+            return Some(conn.stream);
+        }
+        None
+    }
+
+    /// Start background connects until `addr` has `max_idle` warm or in-flight connections.
+    pub(crate) fn replenish(&mut self, addr: SocketAddr) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.collect_ready();
+        let idle = self.idle_count(addr);
+        let connecting = self.connecting.entry(addr).or_insert(0);
+        let missing = self.max_idle.saturating_sub(idle + *connecting);
+        for _ in 0..missing {
+            *connecting += 1;
+            let ready_tx = self.ready_tx.clone();
+            tokio::spawn(async move {
+                let stream = match TcpStream::connect(addr).await {
+                    Ok(stream) => {
+                        let _ = stream.set_nodelay(true);
+                        Some(stream)
+                    }
+                    Err(err) => {
+                        debug!("target pool: connect to {} failed: {}", addr, err);
+                        None
+                    }
+                };
+                let _ = ready_tx.send((addr, stream));
+            });
+        }
+    }
+
+    fn collect_ready(&mut self) {
+        while let Ok((addr, stream)) = self.ready_rx.try_recv() {
+            if let Some(connecting) = self.connecting.get_mut(&addr) {
+                *connecting = connecting.saturating_sub(1);
+            }
+            if let Some(stream) = stream {
+                self.idle.entry(addr).or_default().push_back(IdleConn {
+                    stream,
+                    connected_at: Instant::now(),
+                });
+            }
+        }
+    }
+}
+
+/// Non-consuming liveness probe: a peek that would block means the socket is
+/// still open; EOF or an error means the target already hung up.
+async fn is_open(stream: &TcpStream) -> bool {
+    let mut byte = [0u8; 1];
+    match timeout(Duration::ZERO, stream.peek(&mut byte)).await {
+        Err(_) => true,
+        Ok(Ok(n)) => n > 0,
+        Ok(Err(_)) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TargetPool;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn checkout_returns_warm_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("accept");
+            stream
+        });
+
+        let mut pool = TargetPool::new(1, Duration::from_secs(30));
+        pool.replenish(addr);
+        let _peer = accept.await.expect("accept task");
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+            pool.collect_ready();
+            if pool.idle_count(addr) == 1 {
+                break;
+            }
+        }
+        assert!(pool.checkout(addr).await.is_some());
+        assert!(pool.checkout(addr).await.is_none());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn disabled_pool_never_connects() {
+        let mut pool = TargetPool::new(0, Duration::from_secs(30));
+        let addr = "127.0.0.1:1".parse().expect("addr");
+        pool.replenish(addr);
+        assert_eq!(pool.idle_count(addr), 0);
+        assert!(pool.checkout(addr).await.is_none());
+    }
+}
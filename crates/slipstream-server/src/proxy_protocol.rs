@@ -0,0 +1,104 @@
+//! PROXY protocol v2 headers for target connections.
+//!
+//! With `--proxy-protocol`, every TCP connection to the target starts with a
+//! binary PROXY header (haproxy's proxy-protocol.txt, section 2.2) naming the
+//! tunnel peer as the source and the target as the destination. The QUIC
+//! connection ID goes in a `PP2_TYPE_UNIQUE_ID` TLV so a backend can group the
+//! streams of one tunnel.
+
+use std::net::{IpAddr, SocketAddr};
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Version 2, PROXY command.
+const VERSION_PROXY: u8 = 0x21;
+const FAMILY_TCP4: u8 = 0x11;
+const FAMILY_TCP6: u8 = 0x21;
+const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+
+/// Build the header for a stream of tunnel `conn_id`.
+///
+/// `source` is the address the tunnel's packets arrive from, usually the
+/// client's recursive resolver. Mixed address families are sent as IPv6.
+pub(crate) fn proxy_header_v2(
+    source: SocketAddr,
+    destination: SocketAddr,
+    conn_id: u64,
+) -> Vec<u8> {
+    let source_ip = source.ip().to_canonical();
+    let destination_ip = destination.ip().to_canonical();
+    let unique_id = conn_id.to_string();
+
+    let mut addresses = Vec::with_capacity(36);
+    let family = match (source_ip, destination_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            addresses.extend_from_slice(&src.octets());
+            addresses.extend_from_slice(&dst.octets());
+            FAMILY_TCP4
+        }
+        _ => {
+            addresses.extend_from_slice(&to_v6_octets(source_ip));
+            addresses.extend_from_slice(&to_v6_octets(destination_ip));
+            FAMILY_TCP6
+        }
+    };
+    addresses.extend_from_slice(&source.port().to_be_bytes());
+    addresses.extend_from_slice(&destination.port().to_be_bytes());
+
+    let len = addresses.len() + 3 + unique_id.len();
+    let mut header = Vec::with_capacity(16 + len);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_PROXY);
+    header.push(family);
+    header.extend_from_slice(&(len as u16).to_be_bytes());
+    header.extend_from_slice(&addresses);
+    header.push(PP2_TYPE_UNIQUE_ID);
+    header.extend_from_slice(&(unique_id.len() as u16).to_be_bytes());
+    header.extend_from_slice(unique_id.as_bytes());
+    header
+}
+
+fn to_v6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_header_layout() {
+        let header = proxy_header_v2(
+            "[::ffff:192.0.2.1]:5353".parse().unwrap(),
+            "127.0.0.1:5201".parse().unwrap(),
+            42,
+        );
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(header[12], VERSION_PROXY);
+        assert_eq!(header[13], FAMILY_TCP4);
+        assert_eq!(u16::from_be_bytes([header[14], header[15]]), 12 + 3 + 2);
+        assert_eq!(&header[16..20], &[192, 0, 2, 1]);
+        assert_eq!(&header[20..24], &[127, 0, 0, 1]);
+        assert_eq!(&header[24..26], &5353u16.to_be_bytes());
+        assert_eq!(&header[26..28], &5201u16.to_be_bytes());
+        assert_eq!(&header[28..], &[PP2_TYPE_UNIQUE_ID, 0, 2, b'4', b'2']);
+    }
+
+    #[test]
+    fn mixed_families_use_ipv6() {
+        let header = proxy_header_v2(
+            "[2001:db8::1]:53".parse().unwrap(),
+            "127.0.0.1:80".parse().unwrap(),
+            7,
+        );
+        assert_eq!(header[13], FAMILY_TCP6);
+        assert_eq!(u16::from_be_bytes([header[14], header[15]]), 36 + 3 + 1);
+        assert_eq!(header.len(), 16 + 36 + 3 + 1);
+        assert_eq!(
+            &header[32..48],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 127, 0, 0, 1]
+        );
+    }
+}
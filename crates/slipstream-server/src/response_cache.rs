@@ -0,0 +1,163 @@
+//! Replay of recently prepared responses.
+//!
+//! A QUIC packet placed in a DNS response is gone if that response is lost;
+//! QUIC only resends its contents once loss detection fires, a full RTT or more
+//! later. Resolvers retry unanswered queries, so the server keeps the last
+//! payloads it sent on each connection and answers an identical retry with the
+//! same payload instead of whatever QUIC has queued next.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+/// Identifies a query well enough to recognise a resolver retry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct QueryKey {
+    pub(crate) peer: SocketAddr,
+    pub(crate) id: u16,
+    pub(crate) name: String,
+}
+
+pub(crate) struct ResponseCache {
+    per_connection: usize,
+    /// Cached keys of each connection, oldest first.
+    connections: HashMap<u64, VecDeque<QueryKey>>,
+    payloads: HashMap<QueryKey, Vec<u8>>,
+    hits: u64,
+}
+
+impl ResponseCache {
+    /// Keep up to `per_connection` payloads per connection; 0 disables caching.
+    pub(crate) fn new(per_connection: usize) -> Self {
+        Self {
+            per_connection,
+            connections: HashMap::new(),
+            payloads: HashMap::new(),
+            hits: 0,
+        }
+    }
+
+    /// Payload previously sent in answer to `key`, if it is still cached.
+    pub(crate) fn lookup(&mut self, key: &QueryKey) -> Option<Vec<u8>> {
+        let payload = self.payloads.get(key)?.clone();
+        self.hits += 1;
+        Some(payload)
+    }
+
+    pub(crate) fn insert(&mut self, conn_id: u64, key: QueryKey, payload: Vec<u8>) {
+        if self.per_connection == 0 {
+            return;
+        }
+        if let Some(cached) = self.payloads.get_mut(&key) {
+            *cached = payload;
+            return;
+        }
+        let keys = self.connections.entry(conn_id).or_default();
+        if keys.len() == self.per_connection {
+            if let Some(oldest) = keys.pop_front() {
+                self.payloads.remove(&oldest);
+            }
+        }
+        keys.push_back(key.clone());
+        self.payloads.insert(key, payload);
+    }
+
+    /// Drop the payloads of connections that are no longer open.
+    pub(crate) fn retain(&mut self, open: &[u64]) {
+        let payloads = &mut self.payloads;
+        self.connections.retain(|conn_id, keys| {
+            let keep = open.contains(conn_id);
+            if !keep {
+                for key in keys.iter() {
+                    payloads.remove(key);
+                }
+            }
+            keep
+        });
+    }
+
+    /// Retries answered from the cache so far.
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits
+    }
+}
+
+/// Truncated responses whose payload still awaits the resolver's TCP retry.
+const TRUNCATED_PAYLOADS_MAX: usize = 64;
+
+/// Payloads of responses sent truncated, for the TCP retry to carry.
+///
+/// The retry comes from another socket of the resolver, so it is matched by
+/// DNS ID and name alone.
+#[derive(Default)]
+pub(crate) struct TruncatedPayloads {
+    /// Oldest first.
+    entries: VecDeque<(u16, String, Vec<u8>)>,
+}
+
+impl TruncatedPayloads {
+    pub(crate) fn insert(&mut self, id: u16, name: String, payload: Vec<u8>) {
+        if self.entries.len() == TRUNCATED_PAYLOADS_MAX {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((id, name, payload));
+    }
+
+    /// Remove and return the payload withheld from the response to `id` and
+    /// `name`.
+    pub(crate) fn take(&mut self, id: u16, name: &str) -> Option<Vec<u8>> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(entry_id, entry_name, _)| *entry_id == id && entry_name == name)?;
+        self.entries.remove(index).map(|(_, _, payload)| payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryKey, ResponseCache, TruncatedPayloads, TRUNCATED_PAYLOADS_MAX};
+
+    fn key(id: u16) -> QueryKey {
+        QueryKey {
+            peer: "[::1]:53".parse().unwrap(),
+            id,
+            name: format!("q{}.example.com.", id),
+        }
+    }
+
+    #[test]
+    fn evicts_oldest_per_connection() {
+        let mut cache = ResponseCache::new(2);
+        cache.insert(1, key(1), vec![1]);
+        cache.insert(1, key(2), vec![2]);
+        cache.insert(2, key(3), vec![3]);
+        cache.insert(1, key(4), vec![4]);
+        assert_eq!(cache.lookup(&key(1)), None);
+        assert_eq!(cache.lookup(&key(2)), Some(vec![2]));
+        assert_eq!(cache.lookup(&key(3)), Some(vec![3]));
+        assert_eq!(cache.lookup(&key(4)), Some(vec![4]));
+        assert_eq!(cache.hits(), 3);
+    }
+
+    #[test]
+    fn drops_closed_connections() {
+        let mut cache = ResponseCache::new(4);
+        cache.insert(1, key(1), vec![1]);
+        cache.insert(2, key(2), vec![2]);
+        cache.retain(&[2]);
+        assert_eq!(cache.lookup(&key(1)), None);
+        assert_eq!(cache.lookup(&key(2)), Some(vec![2]));
+    }
+
+    #[test]
+    fn truncated_payloads_are_taken_once_and_bounded() {
+        let mut truncated = TruncatedPayloads::default();
+        for id in 0..=TRUNCATED_PAYLOADS_MAX as u16 {
+            truncated.insert(id, format!("q{}.example.com.", id), vec![id as u8]);
+        }
+        assert_eq!(truncated.take(0, "q0.example.com."), None);
+        assert_eq!(truncated.take(1, "q2.example.com."), None);
+        assert_eq!(truncated.take(1, "q1.example.com."), Some(vec![1]));
+        assert_eq!(truncated.take(1, "q1.example.com."), None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Opt-in process hardening for the server.
+//!
+//! Once the server has read its certificate, key and control secret and bound
+//! its DNS sockets, `--sandbox` locks the process down so a bug in DNS parsing or
+//! QUIC handling has little left to abuse:
+//!
+//! - Landlock denies all filesystem access except creating qlog files, when a
+//!   qlog directory is configured. Files opened earlier (audit log) stay usable.
+//!   Kernels without Landlock only get a warning.
+//! - A seccomp filter limits the process to the syscalls the event loop, TLS
+//!   and target connections need; anything else fails with `EPERM`.
+//!
+//! Landlock only covers the calling thread and threads it creates later, so the
+//! sandbox must be applied from the server loop before any worker thread starts.
+
+use crate::server::TquicServerError;
+use std::path::Path;
+
+/// Apply the sandbox to the current process.
+#[cfg(target_os = "linux")]
+pub(crate) fn apply_sandbox(qlog_dir: Option<&Path>) -> Result<(), TquicServerError> {
+    // Required by both Landlock and unprivileged seccomp.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(last_os_error("Failed to set no_new_privs"));
+    }
+    match landlock::restrict(qlog_dir)? {
+        Some(abi) => tracing::info!("Landlock filesystem restrictions applied (ABI {})", abi),
+        None => tracing::warn!("Landlock is not available; skipping filesystem restrictions"),
+    }
+    seccomp::install()?;
+    tracing::info!("Seccomp syscall filter installed");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn apply_sandbox(_qlog_dir: Option<&Path>) -> Result<(), TquicServerError> {
+    Err(TquicServerError::new(
+        "--sandbox is only supported on Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn last_os_error(context: &str) -> TquicServerError {
+    TquicServerError::new(format!("{}: {}", context, std::io::Error::last_os_error()))
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use super::last_os_error;
+    use crate::server::TquicServerError;
+    use std::fs::File;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::path::Path;
+
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Filesystem rights the kernel knows for a Landlock ABI version.
+    fn handled_access_fs(abi: i64) -> u64 {
+        match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        }
+    }
+
+    /// Deny all filesystem access except writing new files into `qlog_dir`.
+    ///
+    /// Returns the ABI version in use, or `None` when the kernel lacks Landlock.
+    pub(super) fn restrict(qlog_dir: Option<&Path>) -> Result<Option<i64>, TquicServerError> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Ok(None);
+        }
+        let handled = handled_access_fs(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if fd < 0 {
+            return Err(last_os_error("Failed to create Landlock ruleset"));
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+
+        if let Some(dir) = qlog_dir {
+            let dir_file = File::open(dir).map_err(|e| {
+                TquicServerError::new(format!("Failed to open {}: {}", dir.display(), e))
+            })?;
+            let rule = PathBeneathAttr {
+                allowed_access: (ACCESS_FS_WRITE_FILE | ACCESS_FS_MAKE_REG | ACCESS_FS_TRUNCATE)
+                    & handled,
+                parent_fd: dir_file.as_raw_fd(),
+            };
+            let added = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0u32,
+                )
+            };
+            if added != 0 {
+                return Err(last_os_error(
+                    "Failed to add Landlock rule for the qlog directory",
+                ));
+            }
+        }
+
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32) }
+            != 0
+        {
+            return Err(last_os_error("Failed to apply Landlock ruleset"));
+        }
+        Ok(Some(abi))
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod seccomp {
+    use super::last_os_error;
+    use crate::server::TquicServerError;
+
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    const BPF_RET_K: u16 = 0x06;
+
+    /// Offsets into `struct seccomp_data`.
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    const RET_ALLOW: u32 = 0x7fff_0000;
+    const RET_ERRNO: u32 = 0x0005_0000;
+    const RET_KILL_PROCESS: u32 = 0x8000_0000;
+
+    /// Syscalls available on every supported architecture.
+    const COMMON_SYSCALLS: &[libc::c_long] = &[
+        // I/O on already-open descriptors and audit/qlog files
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_close,
+        libc::SYS_lseek,
+        libc::SYS_fstat,
+        libc::SYS_newfstatat,
+        libc::SYS_statx,
+        libc::SYS_openat,
+        libc::SYS_fcntl,
+        libc::SYS_ioctl,
+        libc::SYS_fsync,
+        libc::SYS_fdatasync,
+        // Memory
+        libc::SYS_brk,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        // DNS and target sockets
+        libc::SYS_socket,
+        libc::SYS_connect,
+        libc::SYS_accept4,
+        libc::SYS_sendto,
+        libc::SYS_recvfrom,
+        libc::SYS_sendmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_sendmmsg,
+        libc::SYS_recvmmsg,
+        libc::SYS_shutdown,
+        libc::SYS_getsockname,
+        libc::SYS_getpeername,
+        libc::SYS_setsockopt,
+        libc::SYS_getsockopt,
+        // tokio reactor, timers and threads
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_eventfd2,
+        libc::SYS_ppoll,
+        libc::SYS_futex,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_nanosleep,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_clock_gettime,
+        libc::SYS_gettimeofday,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_prlimit64,
+        libc::SYS_sigaltstack,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_restart_syscall,
+        // TLS randomness and process bookkeeping
+        libc::SYS_getrandom,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        libc::SYS_tgkill,
+        libc::SYS_uname,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+    ];
+
+    /// Legacy syscalls only x86_64 still has; libc may use them there.
+    #[cfg(target_arch = "x86_64")]
+    const ARCH_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_epoll_wait,
+        libc::SYS_poll,
+        libc::SYS_open,
+        libc::SYS_stat,
+        libc::SYS_lstat,
+    ];
+    #[cfg(target_arch = "aarch64")]
+    const ARCH_SYSCALLS: &[libc::c_long] = &[];
+
+    fn stmt(code: u16, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    pub(super) fn install() -> Result<(), TquicServerError> {
+        let mut program = vec![
+            // Refuse syscalls made through another ABI, where the numbers mean something else.
+            stmt(BPF_LD_W_ABS, DATA_ARCH),
+            jump(BPF_JMP_JEQ_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET_K, RET_KILL_PROCESS),
+            stmt(BPF_LD_W_ABS, DATA_NR),
+        ];
+        for nr in COMMON_SYSCALLS.iter().chain(ARCH_SYSCALLS) {
+            program.push(jump(BPF_JMP_JEQ_K, *nr as u32, 0, 1));
+            program.push(stmt(BPF_RET_K, RET_ALLOW));
+        }
+        program.push(stmt(BPF_RET_K, RET_ERRNO | libc::EPERM as u32));
+
+        let fprog = libc::sock_fprog {
+            len: program.len() as libc::c_ushort,
+            filter: program.as_mut_ptr(),
+        };
+        // TSYNC applies the filter to every thread, not just this one.
+        let installed = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_TSYNC,
+                &fprog as *const libc::sock_fprog,
+            )
+        };
+        if installed != 0 {
+            return Err(last_os_error("Failed to install seccomp filter"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+mod seccomp {
+    use crate::server::TquicServerError;
+
+    pub(super) fn install() -> Result<(), TquicServerError> {
+        Err(TquicServerError::new(
+            "--sandbox seccomp filter is only available on x86_64 and aarch64",
+        ))
+    }
+}
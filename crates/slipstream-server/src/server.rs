@@ -9,50 +9,119 @@
 //   - tquic "Done" error means no data available, not fatal
 
 // TODO(congestion-control): Consider congestion control tuning:
-//   - Current: using default CUBIC
-//   - Consider BBR for high-latency DNS tunnel paths
 //   - May need larger initial_max_data for bulk transfers
 
-use slipstream_core::{resolve_host_port, HostPort};
+use crate::audit::{AuditLog, CloseReason, StreamRecord};
+use crate::heartbeat::Heartbeats;
+use crate::ingress::{DnsIngress, Transport, UdpIngress, UdpTcpIngress};
+use crate::limits::{ConnectionLimits, ConnectionUsage};
+use crate::negotiation::Negotiation;
+use crate::pool::TargetPool;
+use crate::proxy_protocol::proxy_header_v2;
+use crate::response_cache::{QueryKey, ResponseCache, TruncatedPayloads};
+use crate::sandbox::apply_sandbox;
+use crate::shaping::{RateLimits, Shaper};
+use slipstream_core::capabilities::{
+    is_client_initiated, is_control_stream, ALPN_LEGACY, ALPN_SLIPSTREAM,
+};
+use slipstream_core::domain::is_subdomain_of;
+use slipstream_core::exit::{EXIT_CONFIG, EXIT_RUNTIME};
+use slipstream_core::logging::suppressed_log_messages;
+use slipstream_core::{
+    log_limited, resolve_host_port, HostPort, SLIPSTREAM_CONNECTION_LIMIT_ERROR,
+};
 use slipstream_dns::{
-    decode_query_with_domains, encode_response, is_fragmented, DecodeQueryError, FragmentBuffer,
-    Question, Rcode, ResponseParams,
+    decode_query_with_record_types, encode_response, encode_truncated_response, fragment_packet,
+    is_fragmented, is_poll, max_response_payload_len, negotiated_udp_payload,
+    response_payload_capacity, verify_control, ControlCommand, DecodeQueryError, DnsError,
+    FragmentBuffer, HealthStatus, Question, Rcode, ResponseParams, CLASSIC_UDP_PAYLOAD, RR_A,
+    RR_AAAA, RR_NULL, RR_TXT,
+};
+use slipstream_quic::multipath::PathEvent;
+use slipstream_quic::{
+    parse_congestion_control, Config as QuicConfig, ConnectionEvent, EndpointStats,
+    PacketDirection, Server, ServerConnection, StreamTable, MIN_UDP_PAYLOAD_SIZE,
 };
-use slipstream_quic::{Config as QuicConfig, Server};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpStream, UdpSocket as TokioUdpSocket};
+use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::time::sleep;
-use tracing::{debug, info, warn};
+use tracing::{debug, info, warn, Level};
 
 // Protocol defaults matching picoquic server
 const DNS_MAX_QUERY_SIZE: usize = 512;
 const IDLE_SLEEP_MS: u64 = 10;
 const MAX_PACKET_SIZE: usize = 1500;
 pub(crate) const STREAM_READ_CHUNK_BYTES: usize = 4096;
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// How long packets wait for a peer that sends no queries, the same as the
+/// QUIC idle timeout: a resolver silent for that long has gone away.
+const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 
 static SHOULD_SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
-extern "C" fn handle_sigterm(_signum: libc::c_int) {
-    SHOULD_SHUTDOWN.store(true, Ordering::Relaxed);
+/// Flag the main loop to shut down once SIGTERM arrives.
+#[cfg(unix)]
+fn spawn_shutdown_listener() {
+    use tokio::signal::unix::{signal, SignalKind};
+    // Registered before spawning so a signal that arrives before the task first runs is kept.
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            warn!("Failed to install SIGTERM handler: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        if sigterm.recv().await.is_some() {
+            SHOULD_SHUTDOWN.store(true, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Flag the main loop to shut down on Ctrl-C, the nearest thing to SIGTERM off Unix.
+#[cfg(not(unix))]
+fn spawn_shutdown_listener() {
+    tokio::spawn(async {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => SHOULD_SHUTDOWN.store(true, Ordering::Relaxed),
+            Err(e) => warn!("Failed to install Ctrl-C handler: {}", e),
+        }
+    });
 }
 
 #[derive(Debug)]
 pub struct TquicServerError {
     message: String,
+    exit_code: i32,
 }
 
 impl TquicServerError {
-    fn new(message: impl Into<String>) -> Self {
+    /// A runtime failure.
+    pub(crate) fn new(message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
+            exit_code: EXIT_RUNTIME,
         }
     }
+
+    pub(crate) fn config(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            exit_code: EXIT_CONFIG,
+        }
+    }
+
+    /// Process exit code for this failure, see [`slipstream_core::exit`].
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
 }
 
 impl fmt::Display for TquicServerError {
@@ -72,6 +141,44 @@ pub struct TquicServerConfig {
     pub key: String,
     pub domains: Vec<String>,
     pub max_connections: u32,
+    pub congestion_control: Option<String>,
+    pub multipath: bool,
+    pub target_pool_size: usize,
+    pub target_pool_idle_timeout: u64,
+    pub proxy_protocol: bool,
+    pub backlog_hints: bool,
+    pub name_compression: bool,
+    /// RDATA bytes one TXT answer may hold before the data spills into more
+    /// answers; `None` keeps it in one.
+    pub txt_record_size: Option<usize>,
+    /// Pad every response to one of a few fixed sizes with EDNS0 Padding.
+    pub pad_responses: bool,
+    /// Also take DNS queries over TCP, so responses too large for a query's
+    /// UDP payload can be truncated and retried.
+    pub dns_tcp: bool,
+    /// Served domains answered with NULL records instead of TXT.
+    pub null_domains: Vec<String>,
+    /// Answer A and AAAA queries with data packed into address records.
+    pub address_records: bool,
+    pub response_cache_size: usize,
+    /// Incomplete fragment reassemblies held at once, across all clients.
+    pub fragment_buffer_entries: usize,
+    /// Fragment bytes those reassemblies may hold.
+    pub fragment_buffer_bytes: usize,
+    pub audit_log: Option<PathBuf>,
+    pub max_connection_lifetime: u64,
+    pub max_connection_bytes: u64,
+    pub rate_limits: RateLimits,
+    pub domain_rate_limits: Vec<(String, RateLimits)>,
+    pub control_secret_file: Option<PathBuf>,
+    pub qlog_dir: Option<PathBuf>,
+    pub sandbox: bool,
+    pub zero_rtt: bool,
+    pub retry: bool,
+    pub anti_amplification_factor: usize,
+    /// Seconds a client's handshake may take before the server drops it.
+    pub handshake_timeout: u64,
+    pub session_ticket_key_file: Option<PathBuf>,
     pub debug_streams: bool,
     pub debug_commands: bool,
 }
@@ -83,6 +190,15 @@ struct StreamState {
     write_tx: mpsc::UnboundedSender<StreamWrite>,
     rx_bytes: u64,
     tx_bytes: u64,
+    opened_at: Instant,
+    /// Bytes read from the target that the QUIC stream has not taken yet.
+    to_client: Vec<u8>,
+    /// The target closed its side of the TCP connection.
+    target_fin: bool,
+    /// FIN has been queued on the QUIC stream.
+    fin_sent: bool,
+    /// The client finished the QUIC stream and the target's write half is shut down.
+    client_fin: bool,
 }
 
 /// Commands for stream management.
@@ -111,26 +227,264 @@ fn sync_shutdown_tcp(tcp: TcpStream) {
 #[allow(dead_code)]
 struct Slot {
     peer: SocketAddr,
+    /// Transport the query came over, and its response goes back over.
+    transport: Transport,
     id: u16,
     rd: bool,
     cd: bool,
     question: Question,
     rcode: Option<Rcode>,
+    /// Connection whose packet the query carried, found by its connection ID.
     conn_id: Option<u64>,
+    /// Answer for an authenticated control query, sent instead of QUIC data.
+    control_reply: Option<Vec<u8>>,
+    /// Response size the query's EDNS0 OPT record allows.
+    udp_payload: usize,
 }
 
-/// Run the server.
+/// QUIC packets waiting for a query from their peer to carry them, and the
+/// fragments of packets too large for the response that took them.
+#[derive(Default)]
+struct PendingFragments {
+    by_peer: HashMap<SocketAddr, PeerQueue>,
+    next_packet_id: u16,
+}
+
+/// Data queued for one peer, and when that peer last took some.
+struct PeerQueue {
+    pending: VecDeque<Pending>,
+    polled_at: Instant,
+}
+
+impl PeerQueue {
+    fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            polled_at: Instant::now(),
+        }
+    }
+}
+
+/// An entry of [`PendingFragments`], with the connection its packet
+/// belongs to when the server could tell.
+enum Pending {
+    /// A whole packet, fragmented once a response with less room takes it.
+    Packet(Option<u64>, Vec<u8>),
+    /// A fragment already cut for an earlier response.
+    Fragment(Option<u64>, Vec<u8>),
+}
+
+impl Pending {
+    fn len(&self) -> usize {
+        match self {
+            Pending::Packet(_, data) | Pending::Fragment(_, data) => data.len(),
+        }
+    }
+
+    fn conn_id(&self) -> Option<u64> {
+        match self {
+            Pending::Packet(conn_id, _) | Pending::Fragment(conn_id, _) => *conn_id,
+        }
+    }
+}
+
+impl PendingFragments {
+    /// Queue `packet` of connection `conn_id` for the next response to `peer`.
+    fn push(&mut self, peer: SocketAddr, conn_id: Option<u64>, packet: Vec<u8>) {
+        self.by_peer
+            .entry(normalize_dual_stack_addr(peer))
+            .or_insert_with(PeerQueue::new)
+            .pending
+            .push_back(Pending::Packet(conn_id, packet));
+    }
+
+    /// Return `packet` if it fits in `capacity` bytes; otherwise fragment it,
+    /// queue all but the first fragment for `peer` and return that one.
+    ///
+    /// Fragments are cut for this response; a later response with less room,
+    /// e.g. to a longer query name, may still exceed its size.
+    fn split(
+        &mut self,
+        peer: SocketAddr,
+        conn_id: Option<u64>,
+        packet: &[u8],
+        capacity: usize,
+    ) -> Vec<u8> {
+        if packet.len() <= capacity {
+            return packet.to_vec();
+        }
+        let mut fragments: VecDeque<Vec<u8>> =
+            fragment_packet(packet, self.next_packet_id, capacity).into();
+        self.next_packet_id = self.next_packet_id.wrapping_add(1);
+        let Some(first) = fragments.pop_front() else {
+            // No room even for a fragment header
+            return packet.to_vec();
+        };
+        if !fragments.is_empty() {
+            // Ahead of queued packets, so the packet completes first
+            let queue = self
+                .by_peer
+                .entry(normalize_dual_stack_addr(peer))
+                .or_insert_with(PeerQueue::new);
+            for fragment in fragments.into_iter().rev() {
+                queue
+                    .pending
+                    .push_front(Pending::Fragment(conn_id, fragment));
+            }
+        }
+        first
+    }
+
+    /// Take the next queued data for `peer` and the connection it belongs to,
+    /// fragmenting a whole packet that does not fit in `capacity` bytes.
+    fn next(&mut self, peer: SocketAddr, capacity: usize) -> Option<(Option<u64>, Vec<u8>)> {
+        let peer = normalize_dual_stack_addr(peer);
+        let queue = self.by_peer.get_mut(&peer)?;
+        queue.polled_at = Instant::now();
+        let pending = queue.pending.pop_front();
+        if queue.pending.is_empty() {
+            self.by_peer.remove(&peer);
+        }
+        match pending? {
+            Pending::Packet(conn_id, packet) if packet.len() <= capacity => Some((conn_id, packet)),
+            Pending::Packet(conn_id, packet) => {
+                Some((conn_id, self.split(peer, conn_id, &packet, capacity)))
+            }
+            Pending::Fragment(conn_id, fragment) => Some((conn_id, fragment)),
+        }
+    }
+
+    /// Whether data of connection `conn_id` waits for any peer.
+    fn holds_connection(&self, conn_id: u64) -> bool {
+        self.by_peer
+            .values()
+            .flat_map(|queue| &queue.pending)
+            .any(|pending| pending.conn_id() == Some(conn_id))
+    }
+
+    /// Drop what waits for peers that took nothing for [`PEER_IDLE_TIMEOUT`],
+    /// such as a resolver the client stopped using; returns the bytes dropped.
+    fn expire_idle(&mut self, now: Instant) -> u64 {
+        let mut dropped = 0;
+        self.by_peer.retain(|_, queue| {
+            if now.saturating_duration_since(queue.polled_at) < PEER_IDLE_TIMEOUT {
+                return true;
+            }
+            dropped += queue
+                .pending
+                .iter()
+                .map(|pending| pending.len() as u64)
+                .sum::<u64>();
+            false
+        });
+        dropped
+    }
+
+    fn queued_bytes(&self, peer: SocketAddr) -> u64 {
+        self.by_peer
+            .get(&normalize_dual_stack_addr(peer))
+            .map_or(0, |queue| {
+                queue
+                    .pending
+                    .iter()
+                    .map(|pending| pending.len() as u64)
+                    .sum()
+            })
+    }
+}
+
+/// Secret and server facts needed to answer control queries.
+struct ControlContext {
+    secret: Vec<u8>,
+    started_at: Instant,
+}
+
+/// Run the server, taking DNS queries on the configured port over UDP, and
+/// over TCP too when enabled.
 pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerError> {
+    if config.dns_tcp {
+        let ingress = UdpTcpIngress::bind(config.dns_listen_port).map_err(map_io)?;
+        serve(config, ingress).await
+    } else {
+        let ingress = UdpIngress::bind(config.dns_listen_port).map_err(map_io)?;
+        serve(config, ingress).await
+    }
+}
+
+/// Run the server loop over any DNS transport.
+async fn serve<I: DnsIngress>(
+    config: &TquicServerConfig,
+    mut ingress: I,
+) -> Result<i32, TquicServerError> {
     let target_addr = resolve_host_port(&config.target_address)
-        .map_err(|e| TquicServerError::new(e.to_string()))?;
+        .map_err(|e| TquicServerError::config(e.to_string()))?;
 
     let (_command_tx, mut command_rx) = mpsc::unbounded_channel::<()>(); // Placeholder for commands
     let debug_streams = config.debug_streams;
 
-    // Create tquic server config with multipath and TLS
-    let quic_config = QuicConfig::new()
-        .with_multipath(true)
-        .with_tls(&config.cert, &config.key);
+    for domain in &config.null_domains {
+        if !config.domains.contains(domain) {
+            return Err(TquicServerError::config(format!(
+                "NULL records set for {}, which is not a served domain",
+                domain
+            )));
+        }
+    }
+    // What the tightest domain's responses hold; tquic packets are never
+    // smaller than 1200 bytes, so larger ones are split across responses
+    let response_payload = response_payload_len(config, |domain| {
+        if config.null_domains.contains(domain) {
+            RR_NULL
+        } else {
+            RR_TXT
+        }
+    })?;
+
+    // Create tquic server config with TLS
+    let mut quic_config = QuicConfig::new()
+        .with_multipath(config.multipath)
+        .with_tls(&config.cert, &config.key)
+        .with_alpn(&[ALPN_SLIPSTREAM, ALPN_LEGACY])
+        // Each packet rides in DNS responses; MTU probes would only grow them
+        .with_pmtud(false);
+    if response_payload < MIN_UDP_PAYLOAD_SIZE {
+        warn!(
+            "DNS responses carry at most {} bytes of QUIC data, but tquic sends packets of up to {} bytes; \
+             the larger packets are fragmented across responses",
+            response_payload, MIN_UDP_PAYLOAD_SIZE
+        );
+    }
+    if config.address_records {
+        let a_payload = response_payload_len(config, |_| RR_A)?;
+        let aaaa_payload = response_payload_len(config, |_| RR_AAAA)?;
+        warn!(
+            "A answers carry at most {} and AAAA answers at most {} bytes of QUIC data; larger packets are fragmented across responses",
+            a_payload, aaaa_payload
+        );
+    }
+    if let Some(name) = config.congestion_control.as_deref() {
+        let algo =
+            parse_congestion_control(name).map_err(|e| TquicServerError::config(e.to_string()))?;
+        quic_config = quic_config.with_congestion_control(algo);
+    }
+    if let Some(dir) = &config.qlog_dir {
+        quic_config = quic_config.with_qlog_dir(dir);
+    }
+    quic_config = quic_config.with_keylog_from_env();
+    if config.zero_rtt {
+        quic_config = quic_config.with_early_data(true);
+    }
+    if config.retry {
+        quic_config = quic_config.with_retry(true);
+    }
+    quic_config = quic_config
+        .with_anti_amplification_factor(config.anti_amplification_factor)
+        .with_handshake_timeout(Duration::from_secs(config.handshake_timeout));
+    if let Some(path) = &config.session_ticket_key_file {
+        quic_config = quic_config.with_session_ticket_key(&read_session_ticket_key(path)?);
+    }
+
+    log_effective_config(config, &quic_config, response_payload);
 
     // Create QUIC server
     let addr = SocketAddr::V6(SocketAddrV6::new(
@@ -140,28 +494,102 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
         0,
     ));
     let mut server = Server::new(addr, quic_config)
-        .map_err(|e| TquicServerError::new(format!("Failed to create QUIC server: {}", e)))?;
+        .map_err(|e| TquicServerError::config(format!("Failed to create QUIC server: {}", e)))?;
     info!("Server listening on {}", addr);
 
-    // Bind UDP socket for DNS
-    let udp = bind_udp_socket(config.dns_listen_port).await?;
     warn_overlapping_domains(&config.domains);
     let domains: Vec<&str> = config.domains.iter().map(String::as_str).collect();
+    let record_types = RecordTypes {
+        null_domains: &config.null_domains,
+        address_records: config.address_records,
+    };
     if domains.is_empty() {
-        return Err(TquicServerError::new(
+        return Err(TquicServerError::config(
             "At least one domain must be configured",
         ));
     }
 
     // Set up signal handler
-    unsafe {
-        libc::signal(libc::SIGTERM, handle_sigterm as usize);
-    }
+    spawn_shutdown_listener();
 
     let mut recv_buf = vec![0u8; DNS_MAX_QUERY_SIZE];
     let _send_buf = vec![0u8; MAX_PACKET_SIZE];
-    let mut streams: HashMap<(u64, u64), StreamState> = HashMap::new();
-    let mut fragment_buffer = FragmentBuffer::new();
+    let mut streams: StreamTable<(u64, u64), StreamState> = StreamTable::new();
+    let mut fragment_buffer = FragmentBuffer::new()
+        .with_limits(config.fragment_buffer_entries, config.fragment_buffer_bytes);
+    let mut target_pool = TargetPool::new(
+        config.target_pool_size,
+        Duration::from_secs(config.target_pool_idle_timeout),
+    );
+    if target_pool.is_enabled() {
+        info!(
+            "Keeping {} warm connection(s) to {}",
+            config.target_pool_size, target_addr
+        );
+        target_pool.replenish(target_addr);
+    }
+    let mut audit_log = match &config.audit_log {
+        Some(path) => Some(AuditLog::open(path).map_err(|e| {
+            TquicServerError::config(format!(
+                "Failed to open audit log {}: {}",
+                path.display(),
+                e
+            ))
+        })?),
+        None => None,
+    };
+    let limits = ConnectionLimits {
+        max_lifetime: (config.max_connection_lifetime > 0)
+            .then_some(Duration::from_secs(config.max_connection_lifetime)),
+        max_bytes: (config.max_connection_bytes > 0).then_some(config.max_connection_bytes),
+    };
+    if limits.is_enabled() {
+        info!(
+            "Connection limits: lifetime {}s, {} bytes (0 = unlimited)",
+            config.max_connection_lifetime, config.max_connection_bytes
+        );
+    }
+    let mut conn_usage: HashMap<u64, ConnectionUsage> = HashMap::new();
+    let mut connections: HashMap<u64, ServerConnection> = HashMap::new();
+    for (domain, _) in &config.domain_rate_limits {
+        if !config.domains.contains(domain) {
+            return Err(TquicServerError::config(format!(
+                "Rate limit set for {}, which is not a served domain",
+                domain
+            )));
+        }
+    }
+    let mut shaper = Shaper::new(config.rate_limits, config.domain_rate_limits.clone());
+    if shaper.is_enabled() {
+        info!(
+            "Rate limits toward the target: {} B/s per stream, {} B/s per connection (0 = unlimited), {} domain override(s)",
+            config.rate_limits.stream_bps,
+            config.rate_limits.connection_bps,
+            config.domain_rate_limits.len()
+        );
+    }
+    let control = match &config.control_secret_file {
+        Some(path) => Some(ControlContext {
+            secret: read_control_secret(path)?,
+            started_at: Instant::now(),
+        }),
+        None => None,
+    };
+    if control.is_some() {
+        info!("Authenticated control queries enabled");
+    }
+    let mut negotiation = Negotiation::new();
+    let mut heartbeats = Heartbeats::new();
+    let mut response_cache = ResponseCache::new(config.response_cache_size);
+    let mut truncated_payloads = TruncatedPayloads::default();
+    let mut pending_fragments = PendingFragments::default();
+    let mut target_buf = vec![0u8; STREAM_READ_CHUNK_BYTES];
+    let mut last_stats_log = Instant::now();
+
+    // Everything that touches the filesystem or binds sockets is done by now
+    if config.sandbox {
+        apply_sandbox(config.qlog_dir.as_deref())?;
+    }
 
     loop {
         if SHOULD_SHUTDOWN.load(Ordering::Relaxed) {
@@ -170,6 +598,13 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
             let stream_count = streams.len();
             info!("Cleaning up {} TCP streams", stream_count);
             for ((conn_id, stream_id), mut state) in streams.drain() {
+                audit_stream(
+                    &mut audit_log,
+                    (conn_id, stream_id),
+                    &state,
+                    target_addr,
+                    CloseReason::Shutdown,
+                );
                 if let Some(tcp) = state.tcp_stream.take() {
                     debug!(
                         "Shutting down TCP stream for conn {} stream {}",
@@ -198,9 +633,47 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                 }
             }
             info!("TCP cleanup complete");
+            info!("{}", format_endpoint_stats(&server.endpoint_stats()));
+            if response_cache.hits() > 0 {
+                info!(
+                    "Answered {} resolver retries from the response cache",
+                    response_cache.hits()
+                );
+            }
+            let suppressed = suppressed_log_messages();
+            if suppressed > 0 {
+                info!(
+                    "{} log messages were suppressed by rate limiting",
+                    suppressed
+                );
+            }
             break;
         }
 
+        if last_stats_log.elapsed() >= STATS_LOG_INTERVAL {
+            debug!("{}", format_endpoint_stats(&server.endpoint_stats()));
+            fragment_buffer.cleanup_stale();
+            let fragments = fragment_buffer.stats();
+            debug!(
+                "Fragment reassembly: pending={} ({} bytes) completed={} dropped={} evicted={}",
+                fragment_buffer.pending_count(),
+                fragment_buffer.buffered_bytes(),
+                fragments.completed,
+                fragments.dropped,
+                fragments.evicted
+            );
+            for (conn_id, conn) in &connections {
+                let Some(stats) = conn.stats() else {
+                    continue;
+                };
+                debug!("Connection {} stats: {}", conn_id, stats);
+                for path in &stats.paths {
+                    debug!("Connection {} {}", conn_id, path);
+                }
+            }
+            last_stats_log = Instant::now();
+        }
+
         let mut slots = Vec::new();
         let timeout = server
             .timeout()
@@ -214,30 +687,36 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                 }
             }
 
-            // Handle incoming UDP packets (DNS queries)
-            recv = udp.recv_from(&mut recv_buf) => {
+            // Handle incoming DNS queries
+            recv = ingress.recv(&mut recv_buf) => {
                 match recv {
-                    Ok((size, peer)) => {
+                    Ok((size, peer, transport)) => {
                         if let Some(slot) = decode_slot_tquic(
                             &recv_buf[..size],
                             peer,
+                            transport,
                             &domains,
+                            &record_types,
                             &mut server,
                             &mut fragment_buffer,
+                            control.as_ref(),
                         )? {
                             slots.push(slot);
                         }
 
                         // Try to receive more packets in burst
                         for _ in 1..64 {
-                            match udp.try_recv_from(&mut recv_buf) {
-                                Ok((size, peer)) => {
+                            match ingress.try_recv(&mut recv_buf) {
+                                Ok((size, peer, transport)) => {
                                     if let Some(slot) = decode_slot_tquic(
                                         &recv_buf[..size],
                                         peer,
+                                        transport,
                                         &domains,
+                                        &record_types,
                                         &mut server,
                                         &mut fragment_buffer,
+                                        control.as_ref(),
                                     )? {
                                         slots.push(slot);
                                     }
@@ -260,44 +739,150 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
             }
         }
 
+        if shaper.is_enabled() {
+            // Polls carry no connection ID; the data queries of the same
+            // client assign its name
+            for slot in slots.iter().filter(|slot| slot.rcode.is_none()) {
+                if let Some(conn_id) = slot.conn_id {
+                    shaper.assign(conn_id, &slot.question.name);
+                }
+            }
+        }
+
+        log_connection_events(&mut server);
+
         // Process ready connections
-        let ready_conns = server.ready_connections();
+        for conn in server.incoming() {
+            conn_usage.insert(conn.conn_id(), limits.usage(Instant::now()));
+            connections.insert(conn.conn_id(), conn);
+        }
+        connections.retain(|_, conn| conn.is_open());
+        let ready_conns: Vec<u64> = connections.keys().copied().collect();
         if !ready_conns.is_empty() {
             debug!("Processing {} ready connections", ready_conns.len());
         }
-        for conn_id in ready_conns {
+        conn_usage.retain(|conn_id, _| ready_conns.contains(conn_id));
+        negotiation.retain(&ready_conns);
+        heartbeats.retain(&ready_conns);
+        response_cache.retain(&ready_conns);
+        shaper.retain(&ready_conns, streams.iter().map(|(_, key, _)| *key));
+        for (&conn_id, conn) in &connections {
             let mut read_buf = vec![0u8; STREAM_READ_CHUNK_BYTES];
 
             // Try to read from all known streams for this connection
-            let stream_ids = server.streams(conn_id);
+            let stream_ids = conn.streams();
             if !stream_ids.is_empty() {
                 debug!("conn {}: {} streams to check", conn_id, stream_ids.len());
             }
             for stream_id in stream_ids {
+                if is_control_stream(stream_id) {
+                    if !is_client_initiated(stream_id) {
+                        continue;
+                    }
+                    match negotiation.agreed(conn_id) {
+                        None => negotiation.on_control_stream(conn, stream_id),
+                        Some(agreed) if agreed.heartbeat => {
+                            let (tx_bytes, rx_bytes) = conn_usage
+                                .get(&conn_id)
+                                .map_or((0, 0), |usage| (usage.bytes_to_client, usage.bytes));
+                            heartbeats.on_control_stream(
+                                conn,
+                                stream_id,
+                                tx_bytes,
+                                rx_bytes,
+                                Instant::now(),
+                            );
+                        }
+                        Some(_) => {}
+                    }
+                    continue;
+                }
                 // Read in a loop until all buffered data is drained
                 let mut read_count = 0;
                 loop {
-                    match server.stream_read(conn_id, stream_id, &mut read_buf) {
+                    let allowance = shaper.allowance(conn_id, stream_id, Instant::now());
+                    if allowance == 0 {
+                        // Over its rate: the data waits in the QUIC receive buffer
+                        break;
+                    }
+                    let chunk = allowance.min(read_buf.len());
+                    match conn.stream_read(stream_id, &mut read_buf[..chunk]) {
                         Ok((n, fin)) if n > 0 => {
                             read_count += 1;
-                            debug!(
+                            log_limited!(
+                                Level::DEBUG,
                                 "conn {} stream {}: read {} bytes (iteration {}), fin={}",
-                                conn_id, stream_id, n, read_count, fin
+                                conn_id,
+                                stream_id,
+                                n,
+                                read_count,
+                                fin
                             );
 
                             // Get or create TCP connection for this stream
                             let stream_key = (conn_id, stream_id);
-                            let (write_tx, _) = mpsc::unbounded_channel();
-                            let state = streams.entry(stream_key).or_insert_with(|| StreamState {
-                                tcp_stream: None,
-                                write_tx,
-                                rx_bytes: 0,
-                                tx_bytes: 0,
-                            });
+                            let mut label = conn.stream_label(stream_id);
+                            if streams.get(label, &stream_key).is_none() {
+                                let (write_tx, _) = mpsc::unbounded_channel();
+                                let new_label = streams.insert(
+                                    stream_key,
+                                    StreamState {
+                                        tcp_stream: None,
+                                        write_tx,
+                                        rx_bytes: 0,
+                                        tx_bytes: 0,
+                                        opened_at: Instant::now(),
+                                        to_client: Vec::new(),
+                                        target_fin: false,
+                                        fin_sent: false,
+                                        client_fin: false,
+                                    },
+                                );
+                                if let Err(e) = conn.set_stream_label(stream_id, new_label) {
+                                    debug!(
+                                        "conn {} stream {}: failed to label stream: {}",
+                                        conn_id, stream_id, e
+                                    );
+                                }
+                                label = Some(new_label);
+                            }
+                            let Some(state) = streams.get_mut(label, &stream_key) else {
+                                break;
+                            };
 
                             // Open TCP connection if not already connected
                             if state.tcp_stream.is_none() {
-                                match TcpStream::connect(target_addr).await {
+                                let connected = match target_pool.checkout(target_addr).await {
+                                    Some(tcp) => {
+                                        debug!(
+                                            "conn {} stream {}: using pooled TCP connection",
+                                            conn_id, stream_id
+                                        );
+                                        Ok(tcp)
+                                    }
+                                    None => TcpStream::connect(target_addr).await,
+                                };
+                                target_pool.replenish(target_addr);
+                                let connected = match connected {
+                                    Ok(mut tcp) if config.proxy_protocol => {
+                                        match conn.peer_addr() {
+                                            Some(source) => tcp
+                                                .write_all(&proxy_header_v2(
+                                                    source,
+                                                    target_addr,
+                                                    conn_id,
+                                                ))
+                                                .await
+                                                .map(|_| tcp),
+                                            None => Err(std::io::Error::new(
+                                                std::io::ErrorKind::NotConnected,
+                                                "tunnel peer address unknown",
+                                            )),
+                                        }
+                                    }
+                                    other => other,
+                                };
+                                match connected {
                                     Ok(tcp) => {
                                         // Disable Nagle's algorithm to ensure immediate delivery
                                         if let Err(e) = tcp.set_nodelay(true) {
@@ -317,7 +902,14 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                                             "conn {} stream {}: TCP connect failed: {}",
                                             conn_id, stream_id, e
                                         );
-                                        streams.remove(&stream_key);
+                                        close_stream(
+                                            &mut streams,
+                                            label,
+                                            stream_key,
+                                            target_addr,
+                                            CloseReason::TargetConnectFailed,
+                                            &mut audit_log,
+                                        );
                                         break; // Exit read loop for this stream
                                     }
                                 }
@@ -330,7 +922,14 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                                         "conn {} stream {}: TCP write failed: {}",
                                         conn_id, stream_id, e
                                     );
-                                    streams.remove(&stream_key);
+                                    close_stream(
+                                        &mut streams,
+                                        label,
+                                        stream_key,
+                                        target_addr,
+                                        CloseReason::TargetWriteFailed,
+                                        &mut audit_log,
+                                    );
                                     break; // Exit read loop for this stream
                                 } else {
                                     // Flush to ensure data is actually sent
@@ -339,10 +938,21 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                                             "conn {} stream {}: TCP flush failed: {}",
                                             conn_id, stream_id, e
                                         );
-                                        streams.remove(&stream_key);
+                                        close_stream(
+                                            &mut streams,
+                                            label,
+                                            stream_key,
+                                            target_addr,
+                                            CloseReason::TargetWriteFailed,
+                                            &mut audit_log,
+                                        );
                                         break;
                                     }
                                     state.tx_bytes += n as u64;
+                                    shaper.consume(conn_id, stream_id, n);
+                                    if let Some(usage) = conn_usage.get_mut(&conn_id) {
+                                        usage.bytes += n as u64;
+                                    }
                                     debug!(
                                         "conn {} stream {}: TCP wrote {} bytes (total: {})",
                                         conn_id, stream_id, n, state.tx_bytes
@@ -352,30 +962,36 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
 
                             // Handle stream finish
                             if fin {
-                                if let Some(mut state) = streams.remove(&stream_key) {
-                                    if let Some(tcp) = state.tcp_stream.take() {
-                                        sync_shutdown_tcp(tcp);
-                                    }
-                                }
+                                finish_to_target(
+                                    &mut streams,
+                                    label,
+                                    stream_key,
+                                    target_addr,
+                                    &mut audit_log,
+                                );
                                 break; // Stream finished, exit loop
                             }
                         }
                         Ok((0, fin)) if fin => {
                             // Stream finished with no data
-                            let stream_key = (conn_id, stream_id);
-                            if let Some(mut state) = streams.remove(&stream_key) {
-                                if let Some(tcp) = state.tcp_stream.take() {
-                                    sync_shutdown_tcp(tcp);
-                                }
-                            }
+                            finish_to_target(
+                                &mut streams,
+                                conn.stream_label(stream_id),
+                                (conn_id, stream_id),
+                                target_addr,
+                                &mut audit_log,
+                            );
                             break;
                         }
                         Ok((0, _)) => {
                             // No more data available, exit loop
                             if read_count > 0 {
-                                debug!(
+                                log_limited!(
+                                    Level::DEBUG,
                                     "conn {} stream {}: no more data after {} reads",
-                                    conn_id, stream_id, read_count
+                                    conn_id,
+                                    stream_id,
+                                    read_count
                                 );
                             }
                             break;
@@ -383,12 +999,13 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                         Ok((_, true)) => {
                             // Stream finished with no data
                             debug!("conn {} stream {}: stream finished", conn_id, stream_id);
-                            let stream_key = (conn_id, stream_id);
-                            if let Some(mut state) = streams.remove(&stream_key) {
-                                if let Some(tcp) = state.tcp_stream.take() {
-                                    sync_shutdown_tcp(tcp);
-                                }
-                            }
+                            finish_to_target(
+                                &mut streams,
+                                conn.stream_label(stream_id),
+                                (conn_id, stream_id),
+                                target_addr,
+                                &mut audit_log,
+                            );
                             break;
                         }
                         Ok(_) => {
@@ -400,13 +1017,15 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                             break;
                         }
                         Err(e) => {
-                            let err_str = e.to_string();
-                            // "Done" means no more data available - this is normal, not an error
-                            if err_str.contains("Done") {
+                            // No more data available - this is normal, not an error
+                            if e.would_block() {
                                 if read_count > 0 {
-                                    debug!(
+                                    log_limited!(
+                                        Level::DEBUG,
                                         "conn {} stream {}: no more data after {} reads (Done)",
-                                        conn_id, stream_id, read_count
+                                        conn_id,
+                                        stream_id,
+                                        read_count
                                     );
                                 }
                             } else {
@@ -421,30 +1040,121 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                     }
                 }
             }
+
+            if let Some(exceeded) = conn_usage
+                .get(&conn_id)
+                .and_then(|usage| limits.check(usage, Instant::now()))
+            {
+                info!("conn {}: closing, {}", conn_id, exceeded.reason());
+                let stream_keys: Vec<(u64, (u64, u64))> = streams
+                    .iter()
+                    .filter(|(_, (id, _), _)| *id == conn_id)
+                    .map(|(label, key, _)| (label, *key))
+                    .collect();
+                for (label, stream_key) in stream_keys {
+                    if let Some(mut state) = close_stream(
+                        &mut streams,
+                        Some(label),
+                        stream_key,
+                        target_addr,
+                        CloseReason::ConnectionLimit,
+                        &mut audit_log,
+                    ) {
+                        if let Some(tcp) = state.tcp_stream.take() {
+                            sync_shutdown_tcp(tcp);
+                        }
+                    }
+                }
+                if let Err(e) = conn.close(SLIPSTREAM_CONNECTION_LIMIT_ERROR, exceeded.reason()) {
+                    warn!("conn {}: close failed: {}", conn_id, e);
+                }
+                conn_usage.remove(&conn_id);
+            }
+        }
+        heartbeats.check(Instant::now(), |conn_id| {
+            conn_usage
+                .get(&conn_id)
+                .map_or(0, |usage| usage.bytes_to_client)
+        });
+        let expired = pending_fragments.expire_idle(Instant::now());
+        if expired > 0 {
+            debug!(
+                "Dropped {} bytes queued for peers that stopped polling",
+                expired
+            );
         }
+        relay_from_targets(
+            &connections,
+            &mut streams,
+            &mut conn_usage,
+            &mut target_buf,
+            target_addr,
+            &mut audit_log,
+        );
 
         // Send DNS responses
         for slot in slots.iter_mut() {
             // Get QUIC packet to send
             let mut quic_payload = None;
+            let mut backlog_bytes = 0u64;
 
-            if slot.rcode.is_none() {
-                // Poll for outgoing packet
-                let packets = server.poll_send();
-                for (packet_data, dest) in packets {
-                    if normalize_dual_stack_addr(dest) == normalize_dual_stack_addr(slot.peer) {
-                        quic_payload = Some(packet_data);
-                        break;
+            if slot.rcode.is_none() && slot.control_reply.is_none() {
+                let key = QueryKey {
+                    peer: slot.peer,
+                    id: slot.id,
+                    name: slot.question.name.clone(),
+                };
+                let withheld = if slot.transport == Transport::Tcp {
+                    truncated_payloads.take(slot.id, &slot.question.name)
+                } else {
+                    None
+                };
+                if let Some(payload) = withheld {
+                    // A TCP retry of a truncated response gets what it withheld
+                    log_limited!(Level::DEBUG, "Answering TCP retry from {}", slot.peer);
+                    quic_payload = Some(payload);
+                } else if let Some(payload) = response_cache.lookup(&key) {
+                    // A resolver retry: resend what the lost response carried
+                    log_limited!(Level::DEBUG, "Replaying cached response to {}", slot.peer);
+                    quic_payload = Some(payload);
+                } else {
+                    let capacity = response_payload_capacity(
+                        &slot.question,
+                        config.name_compression,
+                        config.txt_record_size,
+                        slot.udp_payload,
+                    );
+                    // QUIC packets only reach a peer inside responses to its
+                    // queries, so queue each for its destination
+                    for (packet_data, dest) in server.poll_send() {
+                        let conn_id =
+                            server.connection_for_packet(&packet_data, PacketDirection::Sent);
+                        pending_fragments.push(dest, conn_id, packet_data);
+                    }
+                    if pending_fragments.queued_bytes(slot.peer) == 0
+                        && ping_arrival_path(&server, &pending_fragments, slot.peer)
+                    {
+                        for (packet_data, dest) in server.poll_send() {
+                            let conn_id =
+                                server.connection_for_packet(&packet_data, PacketDirection::Sent);
+                            pending_fragments.push(dest, conn_id, packet_data);
+                        }
                     }
-                    // Send other packets
-                    if let Err(e) = udp.send_to(&packet_data, dest).await {
-                        warn!("Failed to send packet to {}: {}", dest, e);
+                    // Earlier packets and the rest of a packet an earlier
+                    // response could not hold go first
+                    let next = pending_fragments.next(slot.peer, capacity);
+                    backlog_bytes = pending_fragments.queued_bytes(slot.peer);
+                    if let Some((Some(conn_id), payload)) = &next {
+                        response_cache.insert(*conn_id, key, payload.clone());
                     }
+                    quic_payload = next.map(|(_, payload)| payload);
                 }
             }
 
             // Encode DNS response
-            let (payload, rcode) = if let Some(ref data) = quic_payload {
+            let (payload, rcode) = if let Some(ref reply) = slot.control_reply {
+                (Some(reply.as_slice()), None)
+            } else if let Some(ref data) = quic_payload {
                 (Some(data.as_slice()), slot.rcode)
             } else if slot.rcode.is_none() {
                 (None, Some(Rcode::Ok))
@@ -452,69 +1162,486 @@ pub async fn run_server(config: &TquicServerConfig) -> Result<i32, TquicServerEr
                 (None, slot.rcode)
             };
 
-            let response = encode_response(&ResponseParams {
+            let params = ResponseParams {
                 id: slot.id,
                 rd: slot.rd,
                 cd: slot.cd,
                 question: &slot.question,
                 payload,
                 rcode,
-            })
-            .map_err(|e| TquicServerError::new(e.to_string()))?;
+                backlog_hint: (config.backlog_hints && slot.rcode.is_none())
+                    .then_some(backlog_bytes.min(u32::MAX as u64) as u32),
+                compress_names: config.name_compression,
+                txt_record_size: config.txt_record_size,
+                padding_limit: config.pad_responses.then_some(slot.udp_payload),
+            };
+            // A response too large for the resolver to take over UDP, or to
+            // encode at all, gets TC so it asks again over TCP
+            let udp_limit =
+                (config.dns_tcp && slot.transport == Transport::Udp).then_some(slot.udp_payload);
+            let response = match encode_or_truncate(&params, udp_limit) {
+                Ok((response, truncated)) => {
+                    if truncated {
+                        // Keep the data for the TCP retry
+                        log_limited!(Level::DEBUG, "Truncating response to {}", slot.peer);
+                        if let Some(payload) = quic_payload.filter(|_| slot.control_reply.is_none())
+                        {
+                            truncated_payloads.insert(slot.id, slot.question.name.clone(), payload);
+                        }
+                    }
+                    response
+                }
+                Err(e) => {
+                    // One bad response must not take the server down; the
+                    // QUIC data it held is resent once found lost
+                    log_limited!(
+                        Level::WARN,
+                        "Failed to encode response to {}: {}",
+                        slot.peer,
+                        e
+                    );
+                    match encode_servfail(&params) {
+                        Ok(response) => response,
+                        Err(_) => continue,
+                    }
+                }
+            };
 
             let peer = normalize_dual_stack_addr(slot.peer);
-            udp.send_to(&response, peer).await.map_err(map_io)?;
+            ingress
+                .send(&response, peer, slot.transport)
+                .await
+                .map_err(map_io)?;
         }
 
-        // Poll and send any remaining packets
-        let packets = server.poll_send();
-        for (packet_data, dest) in packets {
-            // Encode as DNS response (for unsolicited data)
-            // In a full implementation, we'd need to track pending queries
-            if let Err(e) = udp.send_to(&packet_data, dest).await {
-                warn!("Failed to send packet: {}", e);
-            }
+        // Keep packets no query asked for until their peer polls
+        for (packet_data, dest) in server.poll_send() {
+            let conn_id = server.connection_for_packet(&packet_data, PacketDirection::Sent);
+            pending_fragments.push(dest, conn_id, packet_data);
         }
     }
 
     Ok(0)
 }
 
+/// Encode the response `params` describe, or with `udp_limit` set a TC one
+/// in its place when it is larger or cannot be encoded; the flag tells
+/// whether it was truncated.
+fn encode_or_truncate(
+    params: &ResponseParams<'_>,
+    udp_limit: Option<usize>,
+) -> Result<(Vec<u8>, bool), DnsError> {
+    match (encode_response(params), udp_limit) {
+        (Ok(response), Some(limit)) if response.len() > limit => {}
+        (Ok(response), _) => return Ok((response, false)),
+        (Err(_), Some(_)) => {}
+        (Err(e), None) => return Err(e),
+    }
+    encode_truncated_response(params).map(|response| (response, true))
+}
+
+/// SERVFAIL answer to the query `params` answers, for when its response
+/// cannot be encoded.
+fn encode_servfail(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError> {
+    encode_response(&ResponseParams {
+        payload: None,
+        rcode: Some(Rcode::ServerFailure),
+        backlog_hint: None,
+        padding_limit: None,
+        ..params.clone()
+    })
+}
+
+/// Bytes of QUIC data that fit in a response for every domain, answered
+/// with records of the type `qtype_of` picks for it.
+fn response_payload_len(
+    config: &TquicServerConfig,
+    qtype_of: impl Fn(&String) -> u16,
+) -> Result<usize, TquicServerError> {
+    let mut smallest = usize::MAX;
+    for domain in &config.domains {
+        let len = max_response_payload_len(
+            domain,
+            qtype_of(domain),
+            config.name_compression,
+            config.txt_record_size,
+        )
+        .map_err(|e| TquicServerError::config(format!("domain {}: {}", domain, e)))?;
+        smallest = smallest.min(len);
+    }
+    Ok(smallest)
+}
+
+/// Log the settings the server actually runs with, in one line for bug reports.
+fn log_effective_config(
+    config: &TquicServerConfig,
+    quic_config: &QuicConfig,
+    response_payload: usize,
+) {
+    info!(
+        "Effective configuration: version={} runtime=tquic dns_port={} domains=[{}] null_domains=[{}] address_records={} response_payload={} quic_packet_size={} target={}:{} proxy_protocol={} cc={} multipath={} max_connections={} retry={} anti_amplification_factor={} handshake_timeout_s={} zero_rtt={} response_cache={} fragment_buffer={}/{} backlog_hints={} name_compression={} txt_record_size={} pad_responses={} dns_tcp={} sandbox={}",
+        env!("CARGO_PKG_VERSION"),
+        config.dns_listen_port,
+        config.domains.join(","),
+        config.null_domains.join(","),
+        config.address_records,
+        response_payload,
+        quic_config
+            .max_packet_size()
+            .map_or_else(|| "pmtud".to_string(), |size| size.to_string()),
+        config.target_address.host,
+        config.target_address.port,
+        config.proxy_protocol,
+        quic_config.congestion_control.name(),
+        config.multipath,
+        config.max_connections,
+        config.retry,
+        config.anti_amplification_factor,
+        config.handshake_timeout,
+        config.zero_rtt,
+        config.response_cache_size,
+        config.fragment_buffer_entries,
+        config.fragment_buffer_bytes,
+        config.backlog_hints,
+        config.name_compression,
+        config
+            .txt_record_size
+            .map_or_else(|| "unlimited".to_string(), |size| size.to_string()),
+        config.pad_responses,
+        config.dns_tcp,
+        config.sandbox
+    );
+}
+
+/// Reply on the path a query arrived on when tquic put nothing there but
+/// has packets of the same connection waiting on another resolver's path.
+///
+/// tquic schedules stream data itself and cannot be told which path to use.
+/// A PING on the arrival path gives this response a packet to carry, and the
+/// RTT samples it yields steer tquic toward the resolvers that poll.
+fn ping_arrival_path(server: &Server, pending: &PendingFragments, peer: SocketAddr) -> bool {
+    let Some(conn) = server
+        .connection_for_peer(peer)
+        .and_then(|conn_id| server.connection(conn_id))
+    else {
+        return false;
+    };
+    if !pending.holds_connection(conn.conn_id()) {
+        return false;
+    }
+    let Some(path_id) = conn.path_for_peer(peer) else {
+        return false;
+    };
+    match conn.ping_path(path_id) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!(
+                "conn {}: failed to ping path {}: {}",
+                conn.conn_id(),
+                path_id,
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Log what happened to connections since the last pass, including paths
+/// the client opened or abandoned through other resolvers.
+fn log_connection_events(server: &mut Server) {
+    for (conn_id, event) in server.poll_events() {
+        match event {
+            ConnectionEvent::Path(PathEvent::Available(path_id)) => {
+                let peer = server
+                    .connection(conn_id)
+                    .and_then(|conn| conn.path_info(path_id))
+                    .map(|path| path.peer_addr.to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                debug!("conn {}: path {} available via {}", conn_id, path_id, peer);
+            }
+            ConnectionEvent::Path(PathEvent::Deleted(path_id)) => {
+                debug!("conn {}: path {} removed", conn_id, path_id);
+            }
+            ConnectionEvent::Path(PathEvent::Loss {
+                path_id,
+                lost_packets,
+            }) => {
+                log_limited!(
+                    Level::DEBUG,
+                    "conn {}: {} packets lost on path {}",
+                    conn_id,
+                    lost_packets,
+                    path_id
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Move whatever the targets have sent onto their QUIC streams.
+///
+/// Runs once per loop pass rather than on its own wakeup: data only leaves
+/// in answers to queries, and every query wakes the loop. Streams whose
+/// client already finished close once the target's FIN is relayed.
+fn relay_from_targets(
+    connections: &HashMap<u64, ServerConnection>,
+    streams: &mut StreamTable<(u64, u64), StreamState>,
+    conn_usage: &mut HashMap<u64, ConnectionUsage>,
+    buf: &mut [u8],
+    target: SocketAddr,
+    audit_log: &mut Option<AuditLog>,
+) {
+    let mut finished = Vec::new();
+    for (label, &(conn_id, stream_id), state) in streams.iter_mut() {
+        let (Some(conn), Some(tcp)) = (connections.get(&conn_id), state.tcp_stream.as_ref()) else {
+            continue;
+        };
+        loop {
+            if state.to_client.is_empty() && !state.target_fin {
+                match tcp.try_read(buf) {
+                    Ok(0) => state.target_fin = true,
+                    Ok(n) => {
+                        state.rx_bytes += n as u64;
+                        state.to_client.extend_from_slice(&buf[..n]);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        debug!(
+                            "conn {} stream {}: target read error: {}",
+                            conn_id, stream_id, e
+                        );
+                        state.target_fin = true;
+                    }
+                }
+            }
+            if state.to_client.is_empty() {
+                break;
+            }
+            match conn.stream_write(stream_id, &state.to_client, false) {
+                Ok(n) => {
+                    state.to_client.drain(..n);
+                    if let Some(usage) = conn_usage.get_mut(&conn_id) {
+                        usage.bytes_to_client += n as u64;
+                    }
+                    if !state.to_client.is_empty() {
+                        // Stream flow control is full; retry on the next pass
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        "conn {} stream {}: stream_write error: {}",
+                        conn_id, stream_id, e
+                    );
+                    break;
+                }
+            }
+        }
+        if state.target_fin && state.to_client.is_empty() && !state.fin_sent {
+            match conn.stream_write(stream_id, &[], true) {
+                Ok(_) => state.fin_sent = true,
+                Err(e) => debug!(
+                    "conn {} stream {}: failed to send FIN: {}",
+                    conn_id, stream_id, e
+                ),
+            }
+        }
+        if state.fin_sent && state.client_fin {
+            finished.push((label, (conn_id, stream_id)));
+        }
+    }
+    for (label, key) in finished {
+        close_stream(
+            streams,
+            Some(label),
+            key,
+            target,
+            CloseReason::ClientFin,
+            audit_log,
+        );
+    }
+}
+
+/// Pass the client's FIN on by shutting down the write half of the target
+/// connection.
+///
+/// The target may still reply after a half-close, so the stream stays open
+/// until the target's own FIN has been relayed back.
+fn finish_to_target(
+    streams: &mut StreamTable<(u64, u64), StreamState>,
+    label: Option<u64>,
+    key: (u64, u64),
+    target: SocketAddr,
+    audit_log: &mut Option<AuditLog>,
+) {
+    let Some(state) = streams.get_mut(label, &key) else {
+        return;
+    };
+    state.client_fin = true;
+    if let Some(tcp) = state.tcp_stream.as_ref() {
+        if let Err(e) = socket2::SockRef::from(tcp).shutdown(std::net::Shutdown::Write) {
+            debug!(
+                "conn {} stream {}: target shutdown failed: {}",
+                key.0, key.1, e
+            );
+        }
+    }
+    if state.fin_sent {
+        close_stream(
+            streams,
+            label,
+            key,
+            target,
+            CloseReason::ClientFin,
+            audit_log,
+        );
+    }
+}
+
+/// Remove a stream from the table, writing its audit record if auditing is enabled.
+fn close_stream(
+    streams: &mut StreamTable<(u64, u64), StreamState>,
+    label: Option<u64>,
+    key: (u64, u64),
+    target: SocketAddr,
+    reason: CloseReason,
+    audit_log: &mut Option<AuditLog>,
+) -> Option<StreamState> {
+    let state = streams.remove(label, &key)?;
+    audit_stream(audit_log, key, &state, target, reason);
+    Some(state)
+}
+
+fn audit_stream(
+    audit_log: &mut Option<AuditLog>,
+    (conn_id, stream_id): (u64, u64),
+    state: &StreamState,
+    target: SocketAddr,
+    reason: CloseReason,
+) {
+    if let Some(audit_log) = audit_log.as_mut() {
+        audit_log.record(&StreamRecord {
+            conn_id,
+            stream_id,
+            target,
+            bytes_to_target: state.tx_bytes,
+            bytes_from_target: state.rx_bytes,
+            opened_at: state.opened_at,
+            reason,
+        });
+    }
+}
+
+/// Record types the server answers with.
+struct RecordTypes<'a> {
+    /// Domains answered with NULL records instead of TXT.
+    null_domains: &'a [String],
+    /// Answer A and AAAA queries under any domain with address records.
+    address_records: bool,
+}
+
+impl RecordTypes<'_> {
+    /// Query types the decoder lets through.
+    fn qtypes(&self) -> &'static [u16] {
+        if self.address_records {
+            &[RR_TXT, RR_NULL, RR_A, RR_AAAA]
+        } else {
+            &[RR_TXT, RR_NULL]
+        }
+    }
+
+    /// Check if a query for `qtype` records under `name` gets data.
+    ///
+    /// Packets are sized for the domain's record type, NULL under a NULL
+    /// domain and TXT elsewhere, so the other one would not fit; the longest
+    /// matching domain decides. Address records never hold a full packet and
+    /// are taken on any domain.
+    fn accepts(&self, qtype: u16, name: &str, domains: &[&str]) -> bool {
+        if qtype == RR_A || qtype == RR_AAAA {
+            return self.address_records;
+        }
+        let null_domain = domains
+            .iter()
+            .filter(|domain| is_subdomain_of(name, domain))
+            .max_by_key(|domain| domain.len())
+            .is_some_and(|domain| self.null_domains.iter().any(|null| null == domain));
+        qtype == if null_domain { RR_NULL } else { RR_TXT }
+    }
+}
+
 /// Decode a DNS query slot using tquic (mirrors decode_slot from server.rs).
+#[allow(clippy::too_many_arguments)]
 fn decode_slot_tquic(
     packet: &[u8],
     peer: SocketAddr,
+    transport: Transport,
     domains: &[&str],
+    record_types: &RecordTypes<'_>,
     server: &mut Server,
     fragment_buffer: &mut FragmentBuffer,
+    control: Option<&ControlContext>,
 ) -> Result<Option<Slot>, TquicServerError> {
-    match decode_query_with_domains(packet, domains) {
+    match decode_query_with_record_types(packet, domains, record_types.qtypes()) {
+        // The name exists but holds no records of this type: NODATA
+        Ok(query) if !record_types.accepts(query.question.qtype, &query.question.name, domains) => {
+            Ok(Some(Slot {
+                peer: normalize_dual_stack_addr(peer),
+                transport,
+                id: query.id,
+                rd: query.rd,
+                cd: query.cd,
+                question: query.question,
+                rcode: Some(Rcode::Ok),
+                conn_id: None,
+                control_reply: None,
+                udp_payload: negotiated_udp_payload(query.edns_udp_payload),
+            }))
+        }
         Ok(query) => {
-            // Check if this is a fragmented packet (has magic byte header)
-            if is_fragmented(&query.payload) {
+            let control_reply = control.and_then(|control| {
+                answer_control(control, &query.payload, server.ready_connections().len())
+            });
+            let mut conn_id = None;
+            if control_reply.is_some() {
+                log_limited!(Level::DEBUG, "Control query from {}", peer);
+            } else if is_poll(&query.payload) {
+                // Polls carry no QUIC data; they only give us a slot to answer with
+                log_limited!(Level::DEBUG, "Poll query from {}", peer);
+            } else if is_fragmented(&query.payload) {
                 // Try to reassemble fragment
                 if let Some(complete_packet) = fragment_buffer.receive_fragment(&query.payload) {
                     // Complete packet - feed to tquic
                     if let Err(e) = server.recv(&complete_packet, peer) {
-                        debug!("Failed to process QUIC packet: {}", e);
+                        log_limited!(Level::DEBUG, "Failed to process QUIC packet: {}", e);
                     }
+                    conn_id =
+                        server.connection_for_packet(&complete_packet, PacketDirection::Received);
                 }
                 // If fragment is incomplete, wait for more pieces
             } else {
                 // Raw QUIC packet (no fragment header) - pass directly to tquic
                 if let Err(e) = server.recv(&query.payload, peer) {
-                    debug!("Failed to process QUIC packet (direct): {}", e);
+                    log_limited!(
+                        Level::DEBUG,
+                        "Failed to process QUIC packet (direct): {}",
+                        e
+                    );
                 }
+                conn_id = server.connection_for_packet(&query.payload, PacketDirection::Received);
             }
 
             Ok(Some(Slot {
                 peer: normalize_dual_stack_addr(peer),
+                transport,
                 id: query.id,
                 rd: query.rd,
                 cd: query.cd,
                 question: query.question,
                 rcode: None,
-                conn_id: None, // Will be populated by ready_connections
+                conn_id,
+                control_reply,
+                udp_payload: negotiated_udp_payload(query.edns_udp_payload),
             }))
         }
         Err(DecodeQueryError::Drop) => Ok(None),
@@ -531,20 +1658,92 @@ fn decode_slot_tquic(
             };
             Ok(Some(Slot {
                 peer: normalize_dual_stack_addr(peer),
+                transport,
                 id,
                 rd,
                 cd,
                 question,
                 rcode: Some(rcode),
                 conn_id: None,
+                control_reply: None,
+                udp_payload: CLASSIC_UDP_PAYLOAD as usize,
             }))
         }
     }
 }
 
-async fn bind_udp_socket(port: u16) -> Result<TokioUdpSocket, TquicServerError> {
-    let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0));
-    TokioUdpSocket::bind(addr).await.map_err(map_io)
+/// Answer a control query if it verifies; anything else is left to the tunnel.
+fn answer_control(control: &ControlContext, payload: &[u8], connections: usize) -> Option<Vec<u8>> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    match verify_control(&control.secret, payload, now)? {
+        ControlCommand::Health => Some(
+            HealthStatus {
+                uptime_secs: control.started_at.elapsed().as_secs().min(u32::MAX as u64) as u32,
+                connections: connections.min(u32::MAX as usize) as u32,
+            }
+            .encode(),
+        ),
+    }
+}
+
+fn format_endpoint_stats(stats: &EndpointStats) -> String {
+    format!(
+        "Endpoint stats: packets in={} ({} bytes) out={} ({} bytes) recv_errors={} \
+         handshakes started={} completed={} resumed={} failed={} \
+         version_negotiations={} active_connections={} oversized_packets={}",
+        stats.packets_in,
+        stats.bytes_in,
+        stats.packets_out,
+        stats.bytes_out,
+        stats.recv_errors,
+        stats.handshakes_started,
+        stats.handshakes_completed,
+        stats.handshakes_resumed,
+        stats.handshakes_failed,
+        stats.version_negotiations,
+        stats.active_connections,
+        stats.oversized_packets
+    )
+}
+
+fn read_control_secret(path: &std::path::Path) -> Result<Vec<u8>, TquicServerError> {
+    let contents = std::fs::read(path).map_err(|e| {
+        TquicServerError::config(format!(
+            "Failed to read control secret {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let secret = contents.trim_ascii().to_vec();
+    if secret.is_empty() {
+        return Err(TquicServerError::config(format!(
+            "Control secret {} is empty",
+            path.display()
+        )));
+    }
+    Ok(secret)
+}
+
+/// Read a raw 48- or 80-byte session ticket key.
+fn read_session_ticket_key(path: &std::path::Path) -> Result<Vec<u8>, TquicServerError> {
+    let key = std::fs::read(path).map_err(|e| {
+        TquicServerError::config(format!(
+            "Failed to read session ticket key {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    if key.len() != 48 && key.len() != 80 {
+        return Err(TquicServerError::config(format!(
+            "Session ticket key {} must be 48 or 80 bytes, got {}",
+            path.display(),
+            key.len()
+        )));
+    }
+    Ok(key)
 }
 
 fn normalize_dual_stack_addr(addr: SocketAddr) -> SocketAddr {
@@ -584,7 +1783,7 @@ fn warn_overlapping_domains(domains: &[String]) {
                 continue;
             }
 
-            if is_label_suffix(left, right) || is_label_suffix(right, left) {
+            if is_subdomain_of(left, right) || is_subdomain_of(right, left) {
                 tracing::warn!(
                     "Configured domains overlap; longest suffix wins: '{}' and '{}'",
                     domains[i],
@@ -595,12 +1794,569 @@ fn warn_overlapping_domains(domains: &[String]) {
     }
 }
 
-fn is_label_suffix(domain: &str, suffix: &str) -> bool {
-    if domain.len() <= suffix.len() {
-        return false;
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_or_truncate, encode_servfail, serve, PendingFragments, TquicServerConfig,
+        PEER_IDLE_TIMEOUT,
+    };
+    use crate::ingress::{ChannelIngress, Transport};
+    use crate::shaping::RateLimits;
+    use slipstream_core::capabilities::ALPN_SLIPSTREAM;
+    use slipstream_core::test_util::TempLog;
+    use slipstream_core::{AddressFamily, HostPort};
+    use slipstream_dns::{
+        build_qname, decode_response, encode_query, encode_response, fragment_packet,
+        is_fragmented, is_truncated, max_payload_len_for_domain, poll_payload, response_rcode,
+        FragmentBuffer, QueryParams, Question, Rcode, ResponseParams, CLASS_IN,
+        FRAGMENT_BUFFER_MAX_BYTES, FRAGMENT_BUFFER_MAX_ENTRIES, RR_A, RR_TXT,
+    };
+    use slipstream_quic::{Client, ClientConnection, Config as QuicConfig};
+    use std::net::SocketAddr;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+    type Message = (Vec<u8>, SocketAddr, Transport);
+
+    fn test_config() -> TquicServerConfig {
+        let certs = concat!(env!("CARGO_MANIFEST_DIR"), "/../../fixtures/certs");
+        TquicServerConfig {
+            dns_listen_port: 0,
+            target_address: HostPort {
+                host: "127.0.0.1".to_string(),
+                port: 9,
+                family: AddressFamily::V4,
+            },
+            cert: format!("{}/cert.pem", certs),
+            key: format!("{}/key.pem", certs),
+            domains: vec!["example.com".to_string()],
+            max_connections: 256,
+            congestion_control: None,
+            multipath: false,
+            target_pool_size: 0,
+            target_pool_idle_timeout: 30,
+            proxy_protocol: false,
+            backlog_hints: false,
+            name_compression: true,
+            txt_record_size: None,
+            pad_responses: false,
+            dns_tcp: true,
+            null_domains: Vec::new(),
+            address_records: false,
+            response_cache_size: 32,
+            fragment_buffer_entries: FRAGMENT_BUFFER_MAX_ENTRIES,
+            fragment_buffer_bytes: FRAGMENT_BUFFER_MAX_BYTES,
+            audit_log: None,
+            max_connection_lifetime: 0,
+            max_connection_bytes: 0,
+            rate_limits: RateLimits {
+                stream_bps: 0,
+                connection_bps: 0,
+            },
+            domain_rate_limits: Vec::new(),
+            control_secret_file: None,
+            qlog_dir: None,
+            sandbox: false,
+            zero_rtt: false,
+            retry: false,
+            anti_amplification_factor: 3,
+            handshake_timeout: 30,
+            session_ticket_key_file: None,
+            debug_streams: false,
+            debug_commands: false,
+        }
     }
-    if !domain.ends_with(suffix) {
-        return false;
+
+    /// TXT query carrying `payload` under example.com, advertising
+    /// `udp_payload` bytes of room for the response.
+    fn query(id: u16, payload: &[u8], udp_payload: u16) -> Vec<u8> {
+        let qname = build_qname(payload, "example.com").unwrap();
+        encode_query(&QueryParams {
+            id,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns_udp_payload: udp_payload,
+        })
+        .unwrap()
+    }
+
+    /// Send every packet the client has queued, then a poll, as queries over
+    /// TCP, and feed the answers back to the client.
+    async fn round_trip(
+        conn: &mut ClientConnection,
+        queries: &UnboundedSender<Message>,
+        responses: &mut UnboundedReceiver<Message>,
+        peer: SocketAddr,
+        id: &mut u16,
+        fragments: &mut FragmentBuffer,
+    ) {
+        let max_payload = max_payload_len_for_domain("example.com").unwrap();
+        let mut payloads = Vec::new();
+        for (packet, _) in conn.poll_send() {
+            *id += 1;
+            payloads.extend(fragment_packet(&packet, *id, max_payload));
+        }
+        payloads.push(poll_payload(u32::from(*id)).to_vec());
+        for payload in payloads {
+            *id += 1;
+            queries
+                .send((query(*id, &payload, 4096), peer, Transport::Tcp))
+                .unwrap();
+            let (response, _, _) = responses.recv().await.unwrap();
+            let Some(payload) = decode_response(&response) else {
+                continue;
+            };
+            let packet = if is_fragmented(&payload) {
+                fragments.receive_fragment(&payload)
+            } else {
+                Some(payload)
+            };
+            if let Some(packet) = packet {
+                conn.recv(&packet, "[2001:db8::53]:53".parse().unwrap())
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn pending_packets_are_found_by_connection() {
+        let (resolver, other) = (
+            "[2001:db8::1]:5300".parse().unwrap(),
+            "[2001:db8::2]:5300".parse().unwrap(),
+        );
+        let mut pending = PendingFragments::default();
+        pending.push(other, Some(3), vec![0u8; 100]);
+        pending.push(other, None, vec![0u8; 100]);
+        assert_eq!(pending.queued_bytes(resolver), 0);
+        assert!(pending.holds_connection(3));
+        assert!(!pending.holds_connection(4));
+
+        // What is left of a split packet still counts
+        pending.next(other, 40).unwrap();
+        assert!(pending.holds_connection(3));
+        while pending.next(other, 40).is_some() {}
+        assert!(!pending.holds_connection(3));
+    }
+
+    #[test]
+    fn split_packets_complete_before_the_next_one() {
+        let peer = "[2001:db8::1]:5300".parse().unwrap();
+        let first: Vec<u8> = (0..250).map(|i| i as u8).collect();
+        let mut pending = PendingFragments::default();
+        pending.push(peer, Some(1), first.clone());
+        pending.push(peer, Some(2), vec![7u8; 60]);
+        assert_eq!(pending.queued_bytes(peer), 310);
+
+        let mut fragments = FragmentBuffer::new();
+        let mut packet = None;
+        while packet.is_none() {
+            let (conn_id, payload) = pending.next(peer, 100).unwrap();
+            assert_eq!(conn_id, Some(1));
+            assert!(payload.len() <= 100 && is_fragmented(&payload));
+            packet = fragments.receive_fragment(&payload);
+        }
+        assert_eq!(packet.unwrap(), first);
+        assert_eq!(pending.next(peer, 100), Some((Some(2), vec![7u8; 60])));
+        assert_eq!(pending.next(peer, 100), None);
+    }
+
+    #[test]
+    fn peers_that_stop_polling_are_expired() {
+        let (polling, gone) = (
+            "[2001:db8::1]:5300".parse().unwrap(),
+            "[2001:db8::2]:5300".parse().unwrap(),
+        );
+        let mut pending = PendingFragments::default();
+        pending.push(polling, Some(1), vec![0u8; 100]);
+        pending.push(polling, Some(1), vec![0u8; 100]);
+        pending.push(gone, Some(1), vec![0u8; 80]);
+        assert_eq!(pending.expire_idle(Instant::now()), 0);
+
+        // Packets pushed later do not keep a silent peer alive; a poll does
+        let later = Instant::now() + PEER_IDLE_TIMEOUT;
+        pending.push(gone, Some(1), vec![0u8; 20]);
+        pending.by_peer.get_mut(&polling).unwrap().polled_at = later;
+        assert_eq!(pending.expire_idle(later), 100);
+        assert_eq!(pending.queued_bytes(gone), 0);
+        assert_eq!(pending.queued_bytes(polling), 200);
+    }
+
+    #[test]
+    fn unencodable_responses_become_servfail() {
+        let question = Question {
+            name: "abc.example.com.".to_string(),
+            qtype: RR_A,
+            qclass: 1,
+        };
+        // More than 256 A records' worth of data
+        let payload = vec![0u8; 1200];
+        let params = ResponseParams {
+            id: 7,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: Some(1),
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        assert!(encode_response(&params).is_err());
+        let response = encode_servfail(&params).unwrap();
+        assert_eq!(&response[..2], &[0, 7]);
+        assert_eq!(response_rcode(&response), Some(Rcode::ServerFailure));
+    }
+
+    #[test]
+    fn udp_responses_that_do_not_fit_are_truncated() {
+        let question = Question {
+            name: "abc.example.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: 1,
+        };
+        let payload = vec![0u8; 600];
+        let params = ResponseParams {
+            id: 9,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+            backlog_hint: None,
+            compress_names: true,
+            txt_record_size: None,
+            padding_limit: None,
+        };
+        let (full, truncated) = encode_or_truncate(&params, None).unwrap();
+        assert!(!truncated);
+        let (response, truncated) = encode_or_truncate(&params, Some(full.len())).unwrap();
+        assert!(!truncated);
+        assert_eq!(response, full);
+
+        let (response, truncated) = encode_or_truncate(&params, Some(512)).unwrap();
+        assert!(truncated);
+        assert_ne!(response[2] & 0x02, 0);
+        assert_eq!(&response[6..8], &[0, 0]);
+
+        // Data no record layout holds is truncated too, rather than refused
+        let payload = vec![0u8; u16::MAX as usize];
+        let params = ResponseParams {
+            payload: Some(&payload),
+            ..params
+        };
+        assert!(encode_or_truncate(&params, None).is_err());
+        let (response, truncated) = encode_or_truncate(&params, Some(512)).unwrap();
+        assert!(truncated);
+        assert_eq!(response_rcode(&response), Some(Rcode::Ok));
+    }
+
+    #[tokio::test]
+    async fn names_outside_served_domains_get_nxdomain() {
+        let config = test_config();
+        let (ingress, queries, mut responses) = ChannelIngress::new();
+        let peer: SocketAddr = "[2001:db8::1]:5300".parse().unwrap();
+        let qname = build_qname(b"payload", "example.org").unwrap();
+        let request = encode_query(&QueryParams {
+            id: 42,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns_udp_payload: 1232,
+        })
+        .unwrap();
+        let exchange = async {
+            queries.send((request, peer, Transport::Udp)).unwrap();
+            let (response, to, transport) = responses.recv().await.unwrap();
+            assert_eq!((to, transport), (peer, Transport::Udp));
+            assert_eq!(&response[..2], &[0, 42]);
+            assert_eq!(response_rcode(&response), Some(Rcode::NameError));
+        };
+        tokio::select! {
+            result = serve(&config, ingress) => panic!("server exited: {:?}", result.err()),
+            () = exchange => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn other_record_types_on_a_null_domain_get_nodata() {
+        let mut config = test_config();
+        config.null_domains = vec!["example.com".to_string()];
+        let (ingress, queries, mut responses) = ChannelIngress::new();
+        let peer: SocketAddr = "[2001:db8::1]:5300".parse().unwrap();
+        let qname = build_qname(b"payload", "example.com").unwrap();
+        let request = encode_query(&QueryParams {
+            id: 42,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns_udp_payload: 1232,
+        })
+        .unwrap();
+        let exchange = async {
+            queries.send((request, peer, Transport::Udp)).unwrap();
+            let (response, _, _) = responses.recv().await.unwrap();
+            assert_eq!(&response[..2], &[0, 42]);
+            // The name exists, it just holds no TXT records
+            assert_eq!(response_rcode(&response), Some(Rcode::Ok));
+            assert_eq!(&response[6..8], &[0, 0]);
+        };
+        tokio::select! {
+            result = serve(&config, ingress) => panic!("server exited: {:?}", result.err()),
+            () = exchange => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_retries_get_what_truncated_responses_withheld() {
+        let config = test_config();
+        let (ingress, queries, mut responses) = ChannelIngress::new();
+        let peer: SocketAddr = "[2001:db8::1]:5300".parse().unwrap();
+        let exchange = async {
+            let client = Client::new(
+                QuicConfig::new()
+                    .with_alpn(&[ALPN_SLIPSTREAM])
+                    .with_verify_cert_chain(false),
+            )
+            .unwrap();
+            let mut conn = client
+                .connect(peer, "[2001:db8::53]:53".parse().unwrap(), "localhost")
+                .unwrap();
+            let max_payload = max_payload_len_for_domain("example.com").unwrap();
+
+            // Send the Initial in queries whose responses have room for part
+            // of a packet, so the server's first one is cut to that room
+            let mut id = 0;
+            for (packet, _) in conn.poll_send() {
+                for fragment in fragment_packet(&packet, 1, max_payload) {
+                    id += 1;
+                    queries
+                        .send((query(id, &fragment, 900), peer, Transport::Udp))
+                        .unwrap();
+                    responses.recv().await.unwrap();
+                }
+            }
+
+            // A classic 512-byte response has no room for the next fragment
+            let poll = query(id + 1, &poll_payload(1), 512);
+            queries.send((poll.clone(), peer, Transport::Udp)).unwrap();
+            let (response, to, transport) = responses.recv().await.unwrap();
+            assert_eq!((to, transport), (peer, Transport::Udp));
+            assert!(is_truncated(&response));
+            assert_eq!(decode_response(&response), None);
+
+            // The retry over TCP gets the fragment the truncated one withheld
+            queries.send((poll, peer, Transport::Tcp)).unwrap();
+            let (response, to, transport) = responses.recv().await.unwrap();
+            assert_eq!((to, transport), (peer, Transport::Tcp));
+            assert!(!is_truncated(&response));
+            assert!(is_fragmented(&decode_response(&response).unwrap()));
+        };
+        tokio::select! {
+            result = serve(&config, ingress) => panic!("server exited: {:?}", result.err()),
+            () = exchange => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn target_replies_reach_the_client_and_the_audit_log() {
+        let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_port = target.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut tcp, _) = target.accept().await.unwrap();
+            let mut request = [0u8; 4];
+            tcp.read_exact(&mut request).await.unwrap();
+            tcp.write_all(b"pong").await.unwrap();
+        });
+        let audit = TempLog::new("relay-audit");
+        let mut config = test_config();
+        config.target_address.port = target_port;
+        config.audit_log = Some(audit.path().to_path_buf());
+
+        let (ingress, queries, mut responses) = ChannelIngress::new();
+        let peer: SocketAddr = "[2001:db8::1]:5300".parse().unwrap();
+        let exchange = async {
+            let client = Client::new(
+                QuicConfig::new()
+                    .with_alpn(&[ALPN_SLIPSTREAM])
+                    .with_verify_cert_chain(false),
+            )
+            .unwrap();
+            let mut conn = client
+                .connect(peer, "[2001:db8::53]:53".parse().unwrap(), "localhost")
+                .unwrap();
+            let mut id = 0;
+            let mut fragments = FragmentBuffer::new();
+            while !conn.is_ready() {
+                round_trip(
+                    &mut conn,
+                    &queries,
+                    &mut responses,
+                    peer,
+                    &mut id,
+                    &mut fragments,
+                )
+                .await;
+            }
+
+            let stream_id = conn.open_bi().unwrap();
+            conn.stream_write(stream_id, b"ping", false).unwrap();
+            let mut reply = Vec::new();
+            let mut buf = [0u8; 64];
+            loop {
+                round_trip(
+                    &mut conn,
+                    &queries,
+                    &mut responses,
+                    peer,
+                    &mut id,
+                    &mut fragments,
+                )
+                .await;
+                match conn.stream_read(stream_id, &mut buf) {
+                    Ok((n, fin)) => {
+                        reply.extend_from_slice(&buf[..n]);
+                        if fin {
+                            break;
+                        }
+                    }
+                    Err(_) => tokio::time::sleep(std::time::Duration::from_millis(5)).await,
+                }
+            }
+            assert_eq!(reply, b"pong");
+
+            conn.stream_write(stream_id, &[], true).unwrap();
+            loop {
+                round_trip(
+                    &mut conn,
+                    &queries,
+                    &mut responses,
+                    peer,
+                    &mut id,
+                    &mut fragments,
+                )
+                .await;
+                if let Some(record) = audit.json_lines().first() {
+                    assert_eq!(record["bytes_to_target"], 4);
+                    assert_eq!(record["bytes_from_target"], 4);
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            result = serve(&config, ingress) => panic!("server exited: {:?}", result.err()),
+            result = tokio::time::timeout(std::time::Duration::from_secs(10), exchange) => {
+                result.expect("no reply from the target")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn targets_reply_after_the_client_half_closes() {
+        let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_port = target.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut tcp, _) = target.accept().await.unwrap();
+            // Answer only once the client's side is done
+            let mut request = Vec::new();
+            tcp.read_to_end(&mut request).await.unwrap();
+            tcp.write_all(&request.repeat(2)).await.unwrap();
+        });
+        let audit = TempLog::new("half-close-audit");
+        let mut config = test_config();
+        config.target_address.port = target_port;
+        config.audit_log = Some(audit.path().to_path_buf());
+
+        let (ingress, queries, mut responses) = ChannelIngress::new();
+        let peer: SocketAddr = "[2001:db8::1]:5300".parse().unwrap();
+        let exchange = async {
+            let client = Client::new(
+                QuicConfig::new()
+                    .with_alpn(&[ALPN_SLIPSTREAM])
+                    .with_verify_cert_chain(false),
+            )
+            .unwrap();
+            let mut conn = client
+                .connect(peer, "[2001:db8::53]:53".parse().unwrap(), "localhost")
+                .unwrap();
+            let mut id = 0;
+            let mut fragments = FragmentBuffer::new();
+            while !conn.is_ready() {
+                round_trip(
+                    &mut conn,
+                    &queries,
+                    &mut responses,
+                    peer,
+                    &mut id,
+                    &mut fragments,
+                )
+                .await;
+            }
+
+            let stream_id = conn.open_bi().unwrap();
+            conn.stream_write(stream_id, b"ping", true).unwrap();
+            let mut reply = Vec::new();
+            let mut buf = [0u8; 64];
+            loop {
+                round_trip(
+                    &mut conn,
+                    &queries,
+                    &mut responses,
+                    peer,
+                    &mut id,
+                    &mut fragments,
+                )
+                .await;
+                match conn.stream_read(stream_id, &mut buf) {
+                    Ok((n, fin)) => {
+                        reply.extend_from_slice(&buf[..n]);
+                        if fin {
+                            break;
+                        }
+                    }
+                    Err(_) => tokio::time::sleep(std::time::Duration::from_millis(5)).await,
+                }
+            }
+            assert_eq!(reply, b"pingping");
+
+            // Both FINs have passed, so the stream is closed and audited
+            loop {
+                round_trip(
+                    &mut conn,
+                    &queries,
+                    &mut responses,
+                    peer,
+                    &mut id,
+                    &mut fragments,
+                )
+                .await;
+                if let Some(record) = audit.json_lines().first() {
+                    assert_eq!(record["bytes_to_target"], 4);
+                    assert_eq!(record["bytes_from_target"], 8);
+                    assert_eq!(record["close_reason"], "client_fin");
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            result = serve(&config, ingress) => panic!("server exited: {:?}", result.err()),
+            result = tokio::time::timeout(std::time::Duration::from_secs(10), exchange) => {
+                result.expect("no reply from the target")
+            }
+        }
     }
-    domain.as_bytes()[domain.len() - suffix.len() - 1] == b'.'
 }
@@ -0,0 +1,189 @@
+//! Bandwidth shaping of data forwarded toward targets.
+//!
+//! Each stream and each connection draws from a token bucket refilled at its
+//! configured rate. A stream whose bucket is empty is simply not read: its data
+//! stays in the QUIC receive buffer and flow control pushes back on the client,
+//! so one bulk transfer cannot starve interactive streams sharing the tunnel.
+
+use slipstream_core::domain::is_subdomain_of;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// Rates in bytes per second; 0 leaves that level unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RateLimits {
+    pub(crate) stream_bps: u64,
+    pub(crate) connection_bps: u64,
+}
+
+impl RateLimits {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.stream_bps > 0 || self.connection_bps > 0
+    }
+}
+
+/// Token bucket holding at most one second of traffic.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn available(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.rate as f64);
+        self.refilled_at = now;
+        self.tokens as u64
+    }
+
+    fn consume(&mut self, bytes: u64) {
+        self.tokens = (self.tokens - bytes as f64).max(0.0);
+    }
+}
+
+pub(crate) struct Shaper {
+    default: RateLimits,
+    /// Overrides for connections tunnelled through a given domain.
+    domains: Vec<(String, RateLimits)>,
+    /// Limits of connections whose domain is known.
+    assigned: HashMap<u64, RateLimits>,
+    connections: HashMap<u64, TokenBucket>,
+    streams: HashMap<(u64, u64), TokenBucket>,
+}
+
+impl Shaper {
+    pub(crate) fn new(default: RateLimits, domains: Vec<(String, RateLimits)>) -> Self {
+        Self {
+            default,
+            domains,
+            assigned: HashMap::new(),
+            connections: HashMap::new(),
+            streams: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.default.is_enabled() || self.domains.iter().any(|(_, limits)| limits.is_enabled())
+    }
+
+    /// Pick the limits of a connection from the query name it was seen with.
+    ///
+    /// The first name seen decides; the longest matching domain wins.
+    pub(crate) fn assign(&mut self, conn_id: u64, name: &str) {
+        if self.domains.is_empty() || self.assigned.contains_key(&conn_id) {
+            return;
+        }
+        let limits = self
+            .domains
+            .iter()
+            .filter(|(domain, _)| is_subdomain_of(name, domain))
+            .max_by_key(|(domain, _)| domain.len())
+            .map_or(self.default, |(_, limits)| *limits);
+        self.assigned.insert(conn_id, limits);
+    }
+
+    fn limits(&self, conn_id: u64) -> RateLimits {
+        self.assigned.get(&conn_id).copied().unwrap_or(self.default)
+    }
+
+    /// Bytes the stream may forward right now.
+    pub(crate) fn allowance(&mut self, conn_id: u64, stream_id: u64, now: Instant) -> usize {
+        let limits = self.limits(conn_id);
+        let mut allowance = u64::MAX;
+        if limits.stream_bps > 0 {
+            let bucket = self
+                .streams
+                .entry((conn_id, stream_id))
+                .or_insert_with(|| TokenBucket::new(limits.stream_bps, now));
+            allowance = allowance.min(bucket.available(now));
+        }
+        if limits.connection_bps > 0 {
+            let bucket = self
+                .connections
+                .entry(conn_id)
+                .or_insert_with(|| TokenBucket::new(limits.connection_bps, now));
+            allowance = allowance.min(bucket.available(now));
+        }
+        usize::try_from(allowance).unwrap_or(usize::MAX)
+    }
+
+    /// Charge forwarded bytes to the stream and its connection.
+    pub(crate) fn consume(&mut self, conn_id: u64, stream_id: u64, bytes: usize) {
+        if let Some(bucket) = self.streams.get_mut(&(conn_id, stream_id)) {
+            bucket.consume(bytes as u64);
+        }
+        if let Some(bucket) = self.connections.get_mut(&conn_id) {
+            bucket.consume(bytes as u64);
+        }
+    }
+
+    /// Forget connections that are no longer open and streams that are no
+    /// longer forwarding.
+    pub(crate) fn retain(
+        &mut self,
+        open: &[u64],
+        forwarding: impl IntoIterator<Item = (u64, u64)>,
+    ) {
+        self.assigned.retain(|conn_id, _| open.contains(conn_id));
+        self.connections.retain(|conn_id, _| open.contains(conn_id));
+        let forwarding: HashSet<_> = forwarding.into_iter().collect();
+        self.streams.retain(|key, _| forwarding.contains(key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimits, Shaper};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn stream_and_connection_buckets_both_apply() {
+        let limits = RateLimits {
+            stream_bps: 1_000,
+            connection_bps: 1_500,
+        };
+        let mut shaper = Shaper::new(limits, Vec::new());
+        let start = Instant::now();
+        assert_eq!(shaper.allowance(1, 0, start), 1_000);
+        shaper.consume(1, 0, 1_000);
+        assert_eq!(shaper.allowance(1, 0, start), 0);
+        // The second stream is capped by what the connection has left
+        assert_eq!(shaper.allowance(1, 4, start), 500);
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(shaper.allowance(1, 0, later), 500);
+        assert_eq!(shaper.allowance(2, 0, later), 1_000);
+    }
+
+    #[test]
+    fn longest_domain_override_wins() {
+        let slow = RateLimits {
+            stream_bps: 100,
+            connection_bps: 0,
+        };
+        let mut shaper = Shaper::new(
+            RateLimits::default(),
+            vec![
+                ("example.com".to_string(), RateLimits::default()),
+                ("tenant.example.com".to_string(), slow),
+            ],
+        );
+        assert!(shaper.is_enabled());
+        let now = Instant::now();
+        shaper.assign(1, "abc.tenant.example.com.");
+        shaper.assign(2, "abc.example.com.");
+        assert_eq!(shaper.allowance(1, 0, now), 100);
+        assert_eq!(shaper.allowance(2, 0, now), usize::MAX);
+    }
+}
@@ -0,0 +1,64 @@
+//! Combined `slipstream` binary: `slipstream client …` and `slipstream server …`
+//! accept exactly the arguments of the standalone binaries.
+
+use clap::Command;
+use slipstream_core::logging::init_logging;
+
+fn main() {
+    init_logging();
+    let matches = command().get_matches();
+    let code = match matches.subcommand() {
+        Some(("client", matches)) => slipstream_client::run(matches),
+        Some(("server", matches)) => slipstream_server::run(matches),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    std::process::exit(code);
+}
+
+fn command() -> Command {
+    Command::new("slipstream")
+        .about("slipstream - A high-performance covert channel over DNS")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(slipstream_client::command().name("client"))
+        .subcommand(slipstream_server::command().name("server"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command;
+
+    #[test]
+    fn command_is_consistent() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn dispatches_subcommands() {
+        let matches = command()
+            .try_get_matches_from([
+                "slipstream",
+                "client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+            ])
+            .expect("client args should parse");
+        assert_eq!(matches.subcommand_name(), Some("client"));
+
+        let matches = command()
+            .try_get_matches_from([
+                "slipstream",
+                "server",
+                "--domain",
+                "example.com",
+                "--cert",
+                "cert.pem",
+                "--key",
+                "key.pem",
+            ])
+            .expect("server args should parse");
+        assert_eq!(matches.subcommand_name(), Some("server"));
+    }
+}
@@ -30,6 +30,7 @@ NETEM_DIST="${NETEM_DIST:-normal}"
 NETEM_SUDO="${NETEM_SUDO:-1}"
 NETEM_ACTIVE=0
 PROXY_DELAY_MS="${PROXY_DELAY_MS:-}"
+PROXY_SCENARIO="${PROXY_SCENARIO:-}"
 PROXY_JITTER_MS="${PROXY_JITTER_MS:-}"
 PROXY_DIST="${PROXY_DIST:-normal}"
 PROXY_PORT="${PROXY_PORT:-}"
@@ -444,7 +445,7 @@ run_case() {
     target_preface_args=(--preface-bytes "${PREFACE_BYTES}")
   fi
 
-  if [[ -n "${PROXY_DELAY_MS}" ]]; then
+  if [[ -n "${PROXY_DELAY_MS}" || -n "${PROXY_SCENARIO}" ]]; then
     local proxy_port="${PROXY_PORT:-$((DNS_LISTEN_PORT + 1))}"
     if [[ "${proxy_port}" -eq "${DNS_LISTEN_PORT}" ]]; then
       echo "Proxy port ${proxy_port} conflicts with DNS_LISTEN_PORT." >&2
@@ -453,10 +454,15 @@ run_case() {
     local proxy_args=(
       --listen "127.0.0.1:${proxy_port}"
       --upstream "127.0.0.1:${DNS_LISTEN_PORT}"
-      --delay-ms "${PROXY_DELAY_MS}"
       --dist "${PROXY_DIST}"
       --log "${case_dir}/dns_proxy.jsonl"
     )
+    if [[ -n "${PROXY_SCENARIO}" ]]; then
+      proxy_args+=(--scenario "${PROXY_SCENARIO}")
+    fi
+    if [[ -n "${PROXY_DELAY_MS}" ]]; then
+      proxy_args+=(--delay-ms "${PROXY_DELAY_MS}")
+    fi
     if [[ -n "${PROXY_JITTER_MS}" ]]; then
       proxy_args+=(--jitter-ms "${PROXY_JITTER_MS}")
     fi
@@ -539,7 +545,7 @@ run_case() {
 
 run_mixed() {
   local use_proxy="${USE_PROXY}"
-  if [[ -n "${PROXY_DELAY_MS}" ]]; then
+  if [[ -n "${PROXY_DELAY_MS}" || -n "${PROXY_SCENARIO}" ]]; then
     use_proxy=1
   fi
 
@@ -556,7 +562,7 @@ run_mixed() {
     return 1
   fi
 
-  if [[ -z "${PROXY_DELAY_MS}" ]]; then
+  if [[ -z "${PROXY_DELAY_MS}" && -z "${PROXY_SCENARIO}" ]]; then
     setup_netem
   fi
 
@@ -580,6 +586,10 @@ run_mixed() {
       --upstream "127.0.0.1:${DNS_LISTEN_PORT}"
       --log "${RUN_DIR}/dns_authoritative.jsonl"
     )
+    if [[ -n "${PROXY_SCENARIO}" ]]; then
+      proxy_recursive_args+=(--scenario "${PROXY_SCENARIO}")
+      proxy_authoritative_args+=(--scenario "${PROXY_SCENARIO}")
+    fi
     if [[ -n "${PROXY_DELAY_MS}" ]]; then
       proxy_recursive_args+=(--delay-ms "${PROXY_DELAY_MS}" --dist "${PROXY_DIST}")
       proxy_authoritative_args+=(--delay-ms "${PROXY_DELAY_MS}" --dist "${PROXY_DIST}")
@@ -660,7 +670,7 @@ if [[ "${RESOLVER_MODE}" == "mixed" ]]; then
   exit $?
 fi
 
-if [[ -z "${PROXY_DELAY_MS}" ]]; then
+if [[ -z "${PROXY_DELAY_MS}" && -z "${PROXY_SCENARIO}" ]]; then
   setup_netem
 fi
 